@@ -1,14 +1,12 @@
-use crate::CircuitBreakerConfig;
+use crate::clock::{Clock, SystemClock};
+use crate::metrics::Histogram;
+use crate::{CircuitBreakerConfig, HalfOpenClose};
 use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 
-fn current_timestamp_ms() -> u64 {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
-}
+/// Most recent inter-failure intervals kept per breaker
+pub const MAX_FAILURE_INTERVALS: usize = 1024;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CircuitState {
@@ -17,13 +15,145 @@ pub enum CircuitState {
     HalfOpen,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CircuitBreakerState {
     pub state: CircuitState,
     pub failure_count: u32,
+    /// How many of `failure_count` were slow successes rather than errors
+    pub soft_failure_count: u32,
     pub success_count: u32,
     pub last_failure_at_ms: u64,
+    /// First millisecond an Open circuit lets a probe through (inclusive)
     pub next_attempt_at_ms: u64,
+    /// Failed probes in the current half-open window (`SuccessRatio` only;
+    /// `Consecutive` reopens on the first)
+    #[serde(default)]
+    pub probe_failure_count: u32,
+    /// Until this millisecond (exclusive), reaching the failure threshold
+    /// once leaves a Closed circuit closed; 0 when there's no grace left
+    #[serde(default)]
+    pub suppress_reopen_until_ms: u64,
+    /// Requests turned away since the circuit last opened
+    #[serde(default)]
+    pub rejected_count: u32,
+    /// When the circuit entered its current state
+    #[serde(default)]
+    pub state_entered_at_ms: u64,
+    /// Time spent in each state since `time_in_state_since_ms`, not counting
+    /// the current stretch
+    #[serde(default)]
+    pub time_in_state: TimeInState,
+    /// When time-in-state tracking started or was last reset
+    #[serde(default)]
+    pub time_in_state_since_ms: u64,
+    /// Why the circuit opened; `None` unless it's Open
+    #[serde(default)]
+    pub open_reason: Option<OpenReason>,
+    /// When each success in the current half-open stretch happened; only
+    /// tracked with `success_decay_ms` set
+    #[serde(default)]
+    pub half_open_successes_ms: Vec<u64>,
+    /// Paused for PSP maintenance: requests are turned away and outcomes
+    /// ignored, so the circuit picks up where it was once resumed
+    #[serde(default)]
+    pub paused: bool,
+}
+
+/// What tripped a circuit open
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OpenReason {
+    /// Consecutive failures reached the failure threshold
+    FailureThreshold,
+    /// The failure that reached the threshold was a success slower than the
+    /// latency threshold
+    LatencyThreshold,
+    /// Half-open probes failed
+    ProbeFailed,
+    /// An operator forced it open
+    Forced,
+}
+
+/// Milliseconds a circuit has spent in each state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeInState {
+    pub closed_ms: u64,
+    pub open_ms: u64,
+    pub half_open_ms: u64,
+}
+
+impl TimeInState {
+    fn add(&mut self, state: CircuitState, ms: u64) {
+        let bucket = match state {
+            CircuitState::Closed => &mut self.closed_ms,
+            CircuitState::Open => &mut self.open_ms,
+            CircuitState::HalfOpen => &mut self.half_open_ms,
+        };
+        *bucket = bucket.saturating_add(ms);
+    }
+}
+
+/// What an Open circuit is doing, to tell an outage still being hit from
+/// one nobody has tried since
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenActivity {
+    /// Within its timeout and has turned requests away
+    Rejecting,
+    /// Within its timeout with no requests since it opened
+    CoolingDown,
+    /// Past its timeout with no requests since; the next one probes the PSP
+    ReadyToProbe,
+}
+
+impl CircuitBreakerState {
+    /// What the circuit is doing at `now_ms`, or `None` unless it's Open
+    pub fn open_activity(&self, now_ms: u64) -> Option<OpenActivity> {
+        if self.state != CircuitState::Open {
+            return None;
+        }
+        Some(if now_ms >= self.next_attempt_at_ms {
+            OpenActivity::ReadyToProbe
+        } else if self.rejected_count > 0 {
+            OpenActivity::Rejecting
+        } else {
+            OpenActivity::CoolingDown
+        })
+    }
+
+    /// Time spent in each state up to `now_ms`, including the current stretch
+    /// if tracking has started
+    pub fn time_in_state_at(&self, now_ms: u64) -> TimeInState {
+        let mut time_in_state = self.time_in_state;
+        if self.state_entered_at_ms > 0 {
+            time_in_state.add(self.state, now_ms.saturating_sub(self.state_entered_at_ms));
+        }
+        time_in_state
+    }
+
+    /// Move to `to` at `now_ms`, crediting the stretch just ended to the
+    /// state being left
+    fn enter(&mut self, to: CircuitState, now_ms: u64) {
+        let spent_ms = now_ms.saturating_sub(self.state_entered_at_ms);
+        self.time_in_state.add(self.state, spent_ms);
+        self.state = to;
+        self.state_entered_at_ms = now_ms;
+        if to != CircuitState::Open {
+            self.open_reason = None;
+        }
+        self.half_open_successes_ms.clear();
+    }
+
+    /// Move to Open at `now_ms` because of `reason`
+    fn open(&mut self, reason: OpenReason, now_ms: u64) {
+        self.enter(CircuitState::Open, now_ms);
+        self.open_reason = Some(reason);
+    }
+
+    /// Start counting time in state afresh from `now_ms`
+    fn restart_time_in_state(&mut self, now_ms: u64) {
+        self.time_in_state = TimeInState::default();
+        self.time_in_state_since_ms = now_ms;
+        self.state_entered_at_ms = now_ms;
+    }
 }
 
 impl Default for CircuitBreakerState {
@@ -31,9 +161,19 @@ impl Default for CircuitBreakerState {
         Self {
             state: CircuitState::Closed,
             failure_count: 0,
+            soft_failure_count: 0,
             success_count: 0,
             last_failure_at_ms: 0,
             next_attempt_at_ms: 0,
+            probe_failure_count: 0,
+            suppress_reopen_until_ms: 0,
+            rejected_count: 0,
+            state_entered_at_ms: 0,
+            time_in_state: TimeInState::default(),
+            time_in_state_since_ms: 0,
+            open_reason: None,
+            half_open_successes_ms: Vec::new(),
+            paused: false,
         }
     }
 }
@@ -42,38 +182,157 @@ impl Default for CircuitBreakerState {
 pub struct CircuitBreaker {
     config: CircuitBreakerConfig,
     state: Arc<Mutex<CircuitBreakerState>>,
+    clock: Arc<dyn Clock>,
+    /// Milliseconds between consecutive recorded failures, oldest first.
+    /// Always locked after `state`.
+    failure_intervals: Arc<Mutex<VecDeque<u64>>>,
+    /// Shadow breaker fed the same checks and outcomes as this one
+    canary: Arc<Mutex<Option<CircuitCanary>>>,
+    /// Time in state started counting when this breaker was built, so a new
+    /// clock should restart it
+    tracking_started_on_build: bool,
+}
+
+/// A shadow breaker running a candidate config alongside a live one
+///
+/// It sees every check and outcome the live breaker does but never blocks
+/// anything; it only counts how often the candidate would have opened.
+/// Outcomes that arrive while the candidate is open are dropped, as it would
+/// have blocked those requests. Once the live circuit opens the canary stops
+/// seeing traffic too.
+#[derive(Clone)]
+pub struct CircuitCanary {
+    breaker: CircuitBreaker,
+    /// Times the candidate went to Open; also serializes `observe`
+    would_have_opened: Arc<Mutex<u64>>,
+}
+
+impl CircuitCanary {
+    fn new(config: CircuitBreakerConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            breaker: CircuitBreaker::new(config).with_clock(clock),
+            would_have_opened: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    fn observe(&self, outcome: impl FnOnce(&CircuitBreaker)) {
+        let mut would_have_opened = self.would_have_opened.lock().unwrap();
+        // An open candidate would have blocked the request behind this outcome
+        if self.breaker.get_state().state == CircuitState::Open {
+            return;
+        }
+        outcome(&self.breaker);
+        if self.breaker.get_state().state == CircuitState::Open {
+            *would_have_opened += 1;
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        self.breaker.config()
+    }
+
+    /// The candidate's own state
+    pub fn get_state(&self) -> CircuitBreakerState {
+        self.breaker.get_state()
+    }
+
+    pub fn would_have_opened(&self) -> u64 {
+        *self.would_have_opened.lock().unwrap()
+    }
 }
 
 impl CircuitBreaker {
     pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self::with_state(config, CircuitBreakerState::default())
+    }
+
+    /// A breaker resuming from `state`; one that never tracked time in
+    /// state starts now
+    pub fn with_state(config: CircuitBreakerConfig, mut state: CircuitBreakerState) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let tracking_started_on_build = state.time_in_state_since_ms == 0;
+        if tracking_started_on_build {
+            state.restart_time_in_state(clock.now_ms());
+        }
         Self {
             config,
-            state: Arc::new(Mutex::new(CircuitBreakerState::default())),
+            state: Arc::new(Mutex::new(state)),
+            clock,
+            failure_intervals: Arc::new(Mutex::new(VecDeque::new())),
+            canary: Arc::new(Mutex::new(None)),
+            tracking_started_on_build,
         }
     }
 
-    pub fn with_state(config: CircuitBreakerConfig, state: CircuitBreakerState) -> Self {
+    /// Use a custom time source for timeouts, failure intervals and time in
+    /// state. Time in state that started when the breaker was built restarts
+    /// on the new clock; a restored count carries on.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        if self.tracking_started_on_build {
+            self.state
+                .lock()
+                .unwrap()
+                .restart_time_in_state(clock.now_ms());
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// A new breaker with `config`, starting from this one's state, clock,
+    /// failure interval history and canary
+    pub fn reconfigured(&self, config: CircuitBreakerConfig) -> Self {
         Self {
             config,
-            state: Arc::new(Mutex::new(state)),
+            state: Arc::new(Mutex::new(self.get_state())),
+            clock: self.clock.clone(),
+            failure_intervals: Arc::new(Mutex::new(self.failure_intervals.lock().unwrap().clone())),
+            canary: self.canary.clone(),
+            tracking_started_on_build: false,
+        }
+    }
+
+    /// Start shadowing this breaker with a fresh, closed breaker running
+    /// `config`, replacing any existing canary; `None` stops it
+    pub fn set_canary(&self, config: Option<CircuitBreakerConfig>) -> Option<CircuitCanary> {
+        let canary = config.map(|config| CircuitCanary::new(config, self.clock.clone()));
+        *self.canary.lock().unwrap() = canary.clone();
+        canary
+    }
+
+    pub fn canary(&self) -> Option<CircuitCanary> {
+        self.canary.lock().unwrap().clone()
+    }
+
+    fn feed_canary(&self, outcome: impl FnOnce(&CircuitBreaker)) {
+        if self.is_paused() {
+            return;
+        }
+        if let Some(canary) = self.canary() {
+            canary.observe(outcome);
         }
     }
 
     /// Check if a request can proceed
     pub fn can_proceed(&self) -> bool {
+        if let Some(canary) = self.canary() {
+            // Lets an expired open candidate go half-open
+            canary.breaker.can_proceed();
+        }
         let mut state = self.state.lock().unwrap();
-        let now = current_timestamp_ms();
+        if state.paused {
+            return false;
+        }
 
         match state.state {
             CircuitState::Closed => true,
             CircuitState::Open => {
                 // Check if timeout has expired
-                if now >= state.next_attempt_at_ms {
-                    // Transition to half-open
-                    state.state = CircuitState::HalfOpen;
-                    state.success_count = 0;
+                let now = self.clock.now_ms();
+                if self.timeout_elapsed(&state, now) {
+                    self.half_open(&mut state, now);
                     true
                 } else {
+                    state.rejected_count = state.rejected_count.saturating_add(1);
                     false
                 }
             }
@@ -81,63 +340,322 @@ impl CircuitBreaker {
         }
     }
 
+    /// Move an Open circuit whose timeout has run out to HalfOpen, as the
+    /// next request would, returning whether it did
+    pub fn half_open_if_timed_out(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = self.clock.now_ms();
+        if state.state != CircuitState::Open || !self.timeout_elapsed(&state, now) {
+            return false;
+        }
+        self.half_open(&mut state, now);
+        true
+    }
+
+    fn half_open(&self, state: &mut CircuitBreakerState, now: u64) {
+        state.enter(CircuitState::HalfOpen, now);
+        state.success_count = 0;
+        state.probe_failure_count = 0;
+    }
+
+    /// Check if a request could proceed, without promoting an expired Open
+    /// circuit to HalfOpen
+    pub fn would_proceed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        if state.paused {
+            return false;
+        }
+        match state.state {
+            CircuitState::Closed | CircuitState::HalfOpen => true,
+            CircuitState::Open => self.timeout_elapsed(&state, self.clock.now_ms()),
+        }
+    }
+
+    /// When a circuit opening at `now` admits its first probe: after its
+    /// timeout, but never before `min_open_ms`
+    fn probe_due_at(&self, now: u64) -> u64 {
+        let open_ms = self.config.timeout_duration_ms.max(self.config.min_open_ms);
+        now.saturating_add(open_ms)
+    }
+
+    /// Whether an Open circuit's timeout has run out. Inclusive, so with a
+    /// zero timeout the circuit admits a probe in the same millisecond it
+    /// opened.
+    fn timeout_elapsed(&self, state: &CircuitBreakerState, now: u64) -> bool {
+        now >= state.next_attempt_at_ms
+    }
+
     /// Record a successful operation
     pub fn record_success(&self) {
+        self.feed_canary(CircuitBreaker::record_success);
+        self.succeed();
+    }
+
+    fn succeed(&self) {
         let mut state = self.state.lock().unwrap();
+        self.succeed_at(&mut state, self.clock.now_ms());
+    }
 
+    fn succeed_at(&self, state: &mut CircuitBreakerState, now: u64) {
+        if state.paused {
+            return;
+        }
         match state.state {
             CircuitState::Closed => {
                 // Reset failure count on success
                 state.failure_count = 0;
+                state.soft_failure_count = 0;
             }
             CircuitState::HalfOpen => {
-                state.success_count += 1;
-                // If we reach success threshold, close the circuit
-                if state.success_count >= self.config.success_threshold {
-                    state.state = CircuitState::Closed;
-                    state.failure_count = 0;
-                    state.success_count = 0;
+                match self.config.success_decay_ms {
+                    0 => state.success_count += 1,
+                    decay_ms => {
+                        // Only successes within the decay window still count
+                        state.half_open_successes_ms.push(now);
+                        state
+                            .half_open_successes_ms
+                            .retain(|at| now.saturating_sub(*at) < decay_ms);
+                        state.success_count = state.half_open_successes_ms.len() as u32;
+                    }
+                }
+                // If the probes meet the close criterion, close the circuit
+                if self.half_open_verdict(state) == Some(CircuitState::Closed) {
+                    self.close_half_open(state, now);
                 }
             }
             CircuitState::Open => {
                 // Should not happen, but reset if it does
-                state.state = CircuitState::Closed;
+                state.enter(CircuitState::Closed, now);
                 state.failure_count = 0;
+                state.soft_failure_count = 0;
                 state.success_count = 0;
             }
         }
     }
 
+    /// Record a successful operation that took `latency_ms`
+    ///
+    /// With a latency threshold configured, a success slower than it is
+    /// recorded as a soft failure instead.
+    pub fn record_success_with_latency(&self, latency_ms: u64) {
+        // The canary judges the latency against its own threshold
+        self.feed_canary(|shadow| shadow.record_success_with_latency(latency_ms));
+        let threshold = self.config.latency_threshold_ms;
+        if threshold > 0 && latency_ms > threshold {
+            self.fail(true);
+        } else {
+            self.succeed();
+        }
+    }
+
     /// Record a failed operation
     pub fn record_failure(&self) {
+        self.feed_canary(CircuitBreaker::record_failure);
+        self.fail(false);
+    }
+
+    fn fail(&self, soft: bool) {
         let mut state = self.state.lock().unwrap();
-        let now = current_timestamp_ms();
+        self.fail_at(&mut state, self.clock.now_ms(), soft);
+    }
 
+    fn fail_at(&self, state: &mut CircuitBreakerState, now: u64, soft: bool) {
+        if state.paused {
+            return;
+        }
+        if state.last_failure_at_ms > 0 {
+            let mut intervals = self.failure_intervals.lock().unwrap();
+            if intervals.len() == MAX_FAILURE_INTERVALS {
+                intervals.pop_front();
+            }
+            intervals.push_back(now.saturating_sub(state.last_failure_at_ms));
+        }
         state.last_failure_at_ms = now;
 
         match state.state {
             CircuitState::Closed => {
                 state.failure_count += 1;
-                // If we reach failure threshold, open the circuit
+                if soft {
+                    state.soft_failure_count += 1;
+                }
+                // If we reach failure threshold, open the circuit, unless it
+                // only just recovered and this is its one failure of grace
                 if state.failure_count >= self.config.failure_threshold {
-                    state.state = CircuitState::Open;
-                    state.next_attempt_at_ms = now + self.config.timeout_duration_ms;
+                    if now < state.suppress_reopen_until_ms {
+                        state.suppress_reopen_until_ms = 0;
+                    } else {
+                        let reason = if soft {
+                            OpenReason::LatencyThreshold
+                        } else {
+                            OpenReason::FailureThreshold
+                        };
+                        state.open(reason, now);
+                        state.rejected_count = 0;
+                        state.next_attempt_at_ms = self.probe_due_at(now);
+                    }
                 }
             }
             CircuitState::HalfOpen => {
-                // Any failure in half-open state reopens the circuit
-                state.state = CircuitState::Open;
+                state.probe_failure_count += 1;
+                match self.half_open_verdict(state) {
+                    Some(CircuitState::Open) => {}
+                    Some(_) => {
+                        // A failure can still complete a window that meets the ratio
+                        self.close_half_open(state, now);
+                        return;
+                    }
+                    None => return,
+                }
+                // Too many failed probes, so reopen the circuit
+                state.open(OpenReason::ProbeFailed, now);
                 state.failure_count = self.config.failure_threshold;
+                state.soft_failure_count = if soft {
+                    self.config.failure_threshold
+                } else {
+                    0
+                };
                 state.success_count = 0;
-                state.next_attempt_at_ms = now + self.config.timeout_duration_ms;
+                state.probe_failure_count = 0;
+                state.rejected_count = 0;
+                state.next_attempt_at_ms = self.probe_due_at(now);
             }
             CircuitState::Open => {
                 // Already open, just update timestamp
-                state.next_attempt_at_ms = now + self.config.timeout_duration_ms;
+                state.next_attempt_at_ms = self.probe_due_at(now);
+            }
+        }
+    }
+
+    /// Apply `(at_ms, success)` outcomes in order under a single lock, each
+    /// judged at its own timestamp rather than the clock's, returning the
+    /// resulting state. For warming a breaker from the event log or a batch
+    /// of reported outcomes.
+    ///
+    /// An outcome at or after an Open circuit's timeout is taken as the probe
+    /// it would have been, so the circuit goes half-open first. The canary
+    /// isn't fed.
+    pub fn record_batch(&self, outcomes: &[(u64, bool)]) -> CircuitBreakerState {
+        let mut state = self.state.lock().unwrap();
+        for &(at_ms, success) in outcomes {
+            if state.state == CircuitState::Open && self.timeout_elapsed(&state, at_ms) {
+                self.half_open(&mut state, at_ms);
+            }
+            if success {
+                self.succeed_at(&mut state, at_ms);
+            } else {
+                self.fail_at(&mut state, at_ms, false);
+            }
+        }
+        state.clone()
+    }
+
+    /// Pause the breaker for PSP maintenance, turning requests away and
+    /// ignoring outcomes until `resume`; returns whether it was running
+    pub fn pause(&self) -> bool {
+        !std::mem::replace(&mut self.state.lock().unwrap().paused, true)
+    }
+
+    /// Resume a paused breaker in the state it was paused in; returns
+    /// whether it was paused
+    pub fn resume(&self) -> bool {
+        std::mem::replace(&mut self.state.lock().unwrap().paused, false)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    /// Open the circuit on an operator's say-so, whatever its failures,
+    /// until its timeout runs out as if it had tripped now
+    pub fn force_open(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = self.clock.now_ms();
+        if state.state != CircuitState::Open {
+            state.open(OpenReason::Forced, now);
+            state.success_count = 0;
+            state.probe_failure_count = 0;
+            state.rejected_count = 0;
+        } else {
+            state.open_reason = Some(OpenReason::Forced);
+        }
+        state.next_attempt_at_ms = self.probe_due_at(now);
+    }
+
+    /// Clear stale failures on a scheduled reset
+    ///
+    /// A closed circuit just forgets its failures. An open one goes to
+    /// half-open rather than closed, so the next request probes the PSP and a
+    /// single failure reopens it if the outage is still going.
+    pub fn scheduled_reset(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failure_count = 0;
+        state.soft_failure_count = 0;
+        state.success_count = 0;
+        state.probe_failure_count = 0;
+        if state.state != CircuitState::Closed {
+            state.enter(CircuitState::HalfOpen, self.clock.now_ms());
+            state.next_attempt_at_ms = 0;
+        }
+    }
+
+    /// Close a half-open circuit whose probes passed, starting its reopen
+    /// suppression window
+    fn close_half_open(&self, state: &mut CircuitBreakerState, now: u64) {
+        state.enter(CircuitState::Closed, now);
+        state.failure_count = 0;
+        state.soft_failure_count = 0;
+        state.success_count = 0;
+        state.probe_failure_count = 0;
+        state.suppress_reopen_until_ms = match self.config.reopen_suppression_ms {
+            0 => 0,
+            window_ms => now.saturating_add(window_ms),
+        };
+    }
+
+    /// Where the half-open probes so far leave the circuit: `Closed` or
+    /// `Open` once decided, `None` while more probes are needed
+    fn half_open_verdict(&self, state: &CircuitBreakerState) -> Option<CircuitState> {
+        match self.config.half_open_close {
+            HalfOpenClose::Consecutive => {
+                if state.probe_failure_count > 0 {
+                    Some(CircuitState::Open)
+                } else if state.success_count >= self.config.success_threshold {
+                    Some(CircuitState::Closed)
+                } else {
+                    None
+                }
+            }
+            HalfOpenClose::SuccessRatio {
+                window,
+                min_success_percent,
+            } => {
+                let window = u64::from(window);
+                let required = (window * u64::from(min_success_percent)).div_ceil(100);
+                let failures = u64::from(state.probe_failure_count);
+                if failures > window.saturating_sub(required) {
+                    Some(CircuitState::Open)
+                } else if u64::from(state.success_count) + failures >= window {
+                    Some(CircuitState::Closed)
+                } else {
+                    None
+                }
             }
         }
     }
 
+    /// Time between consecutive failures, in seconds, bucketed by `bounds`
+    /// (ascending, in seconds). Covers the last `MAX_FAILURE_INTERVALS`
+    /// intervals.
+    pub fn failure_interval_histogram(&self, bounds: &[f64]) -> Histogram {
+        let intervals = self.failure_intervals.lock().unwrap();
+        Histogram::from_samples(bounds, intervals.iter().map(|ms| *ms as f64 / 1000.0))
+    }
+
+    /// Get the config this breaker was built with
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
     /// Get current state
     pub fn get_state(&self) -> CircuitBreakerState {
         self.state.lock().unwrap().clone()
@@ -147,12 +665,20 @@ impl CircuitBreaker {
     pub fn reset(&self) {
         let mut state = self.state.lock().unwrap();
         *state = CircuitBreakerState::default();
+        state.restart_time_in_state(self.clock.now_ms());
+    }
+
+    /// Zero the time spent in each state, keeping the circuit as it is
+    pub fn reset_time_in_state(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.restart_time_in_state(self.clock.now_ms());
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{Preset, MAX_PROBE_WINDOW};
 
     #[test]
     fn test_circuit_starts_closed() {
@@ -169,6 +695,7 @@ mod tests {
             failure_threshold: 3,
             success_threshold: 2,
             timeout_duration_ms: 1000,
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -192,6 +719,7 @@ mod tests {
             failure_threshold: 2,
             success_threshold: 2,
             timeout_duration_ms: 10000,
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -209,7 +737,8 @@ mod tests {
         let config = CircuitBreakerConfig {
             failure_threshold: 2,
             success_threshold: 2,
-            timeout_duration_ms: 0, // Immediate timeout for testing
+            timeout_duration_ms: 0, // Half-open straight away
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -218,9 +747,6 @@ mod tests {
         cb.record_failure();
         assert_eq!(cb.get_state().state, CircuitState::Open);
 
-        // Wait for timeout (immediate in this case)
-        std::thread::sleep(std::time::Duration::from_millis(10));
-
         // Should transition to half-open
         assert!(cb.can_proceed());
         assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
@@ -239,6 +765,7 @@ mod tests {
             failure_threshold: 2,
             success_threshold: 2,
             timeout_duration_ms: 0,
+            ..Default::default()
         };
         let cb = CircuitBreaker::new(config);
 
@@ -247,7 +774,6 @@ mod tests {
         cb.record_failure();
 
         // Transition to half-open
-        std::thread::sleep(std::time::Duration::from_millis(10));
         assert!(cb.can_proceed());
         assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
 
@@ -255,4 +781,577 @@ mod tests {
         cb.record_failure();
         assert_eq!(cb.get_state().state, CircuitState::Open);
     }
+
+    #[test]
+    fn test_would_proceed_does_not_change_state() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 1,
+            success_threshold: 1,
+            timeout_duration_ms: 0,
+            ..Default::default()
+        };
+        let cb = CircuitBreaker::new(config);
+        cb.record_failure();
+
+        assert!(cb.would_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_slow_successes_open_circuit() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 3,
+            success_threshold: 1,
+            timeout_duration_ms: 10000,
+            latency_threshold_ms: 500,
+            ..Default::default()
+        };
+        let cb = CircuitBreaker::new(config);
+
+        // Fast successes don't count
+        cb.record_success_with_latency(120);
+        assert_eq!(cb.get_state().failure_count, 0);
+
+        cb.record_failure();
+        cb.record_success_with_latency(800);
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+        cb.record_success_with_latency(1500);
+
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Open);
+        assert_eq!(state.failure_count, 3);
+        assert_eq!(state.soft_failure_count, 2);
+        assert_eq!(state.open_reason, Some(OpenReason::LatencyThreshold));
+        assert!(!cb.can_proceed());
+    }
+
+    #[test]
+    fn test_latency_ignored_when_disabled() {
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            ..Default::default()
+        });
+
+        cb.record_success_with_latency(u64::MAX);
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+        assert_eq!(cb.get_state().failure_count, 0);
+    }
+
+    #[test]
+    fn test_scheduled_reset_reevaluates_open_circuit() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 3,
+            success_threshold: 1,
+            timeout_duration_ms: 60000,
+            ..Default::default()
+        };
+        let cb = CircuitBreaker::new(config);
+        for _ in 0..3 {
+            cb.record_failure();
+        }
+
+        cb.scheduled_reset();
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+        assert_eq!(cb.get_state().failure_count, 0);
+        assert!(cb.can_proceed());
+
+        // Still failing: one failure reopens it
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+
+        let closed = CircuitBreaker::new(CircuitBreakerConfig::default());
+        closed.record_failure();
+        closed.scheduled_reset();
+        assert_eq!(closed.get_state().state, CircuitState::Closed);
+        assert_eq!(closed.get_state().failure_count, 0);
+    }
+
+    #[test]
+    fn test_failure_interval_histogram() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 100,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+
+        // The first failure has nothing to measure from
+        cb.record_failure();
+        for interval_ms in [500, 2_000, 10_000, 120_000] {
+            clock.advance(interval_ms);
+            cb.record_failure();
+        }
+
+        let histogram = cb.failure_interval_histogram(&[1.0, 5.0, 60.0]);
+        assert_eq!(histogram.buckets, vec![(1.0, 1), (5.0, 2), (60.0, 3)]);
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.sum, 132.5);
+
+        // Other bucketing over the same intervals
+        let histogram = cb.failure_interval_histogram(&[0.1, 1000.0]);
+        assert_eq!(histogram.buckets, vec![(0.1, 0), (1000.0, 4)]);
+
+        // Reconfiguring keeps the history
+        let reconfigured = cb.reconfigured(CircuitBreakerConfig::default());
+        assert_eq!(reconfigured.failure_interval_histogram(&[1.0]).count, 4);
+    }
+
+    #[test]
+    fn test_failure_intervals_are_bounded() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig::default()).with_clock(clock.clone());
+
+        for _ in 0..MAX_FAILURE_INTERVALS + 10 {
+            clock.advance(1_000);
+            cb.record_failure();
+        }
+        cb.reset();
+        clock.advance(1_000);
+        cb.record_failure();
+
+        let histogram = cb.failure_interval_histogram(&[]);
+        assert_eq!(histogram.count, MAX_FAILURE_INTERVALS as u64);
+    }
+
+    fn ratio_breaker(outcomes: &[bool]) -> CircuitBreaker {
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            timeout_duration_ms: 0,
+            half_open_close: HalfOpenClose::SuccessRatio {
+                window: 5,
+                min_success_percent: 80,
+            },
+            ..Default::default()
+        });
+        cb.record_failure();
+        assert!(cb.can_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+
+        for success in outcomes {
+            if *success {
+                cb.record_success();
+            } else {
+                cb.record_failure();
+            }
+        }
+        cb
+    }
+
+    #[test]
+    fn test_success_ratio_closes_on_four_of_five() {
+        // One failed probe doesn't reopen it, and the window closes it
+        let cb = ratio_breaker(&[true, false, true, true]);
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+        assert_eq!(cb.get_state().probe_failure_count, 1);
+        cb.record_success();
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+
+        // Even when the last probe of the window is the failure
+        let cb = ratio_breaker(&[true, true, true, true, false]);
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_success_ratio_reopens_on_three_of_five() {
+        let cb = ratio_breaker(&[true, false, true, true, false]);
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Open);
+        assert_eq!(state.probe_failure_count, 0);
+
+        // It reopens as soon as 80% is out of reach, not at the end of the window
+        let cb = ratio_breaker(&[false, false]);
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_success_ratio_config_validation() {
+        let config = |window, min_success_percent| CircuitBreakerConfig {
+            half_open_close: HalfOpenClose::SuccessRatio {
+                window,
+                min_success_percent,
+            },
+            ..Default::default()
+        };
+        assert!(config(5, 80).validate().is_ok());
+        assert!(config(0, 80).validate().is_err());
+        assert!(config(5, 0).validate().is_err());
+        assert!(config(5, 101).validate().is_err());
+        assert!(config(MAX_PROBE_WINDOW, 100).validate().is_ok());
+        assert!(config(MAX_PROBE_WINDOW + 1, 80).validate().is_err());
+        assert!(config(u32::MAX, 100).validate().is_err());
+        assert!(HalfOpenClose::from_parts(0, 80).is_err());
+        assert_eq!(
+            HalfOpenClose::from_parts(0, 0).unwrap(),
+            HalfOpenClose::Consecutive
+        );
+    }
+
+    #[test]
+    fn test_success_ratio_largest_window_does_not_overflow() {
+        // Even past the validated bound, a window times a percentage fits
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            timeout_duration_ms: 0,
+            half_open_close: HalfOpenClose::SuccessRatio {
+                window: u32::MAX,
+                min_success_percent: 100,
+            },
+            ..Default::default()
+        });
+        cb.record_failure();
+        assert!(cb.can_proceed());
+        cb.record_success();
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_canary_counts_openings_without_blocking() {
+        let live = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 5,
+            ..Default::default()
+        });
+        live.set_canary(Some(CircuitBreakerConfig {
+            failure_threshold: 2,
+            timeout_duration_ms: 60_000,
+            ..Default::default()
+        }));
+
+        for _ in 0..4 {
+            assert!(live.can_proceed());
+            live.record_failure();
+        }
+        let canary = live.canary().unwrap();
+        assert_eq!(canary.get_state().state, CircuitState::Open);
+        assert_eq!(canary.would_have_opened(), 1);
+        assert_eq!(live.get_state().state, CircuitState::Closed);
+        assert_eq!(live.get_state().failure_count, 4);
+
+        // Outlives a config change, and stops when cleared
+        let reconfigured = live.reconfigured(CircuitBreakerConfig::default());
+        assert_eq!(reconfigured.canary().unwrap().would_have_opened(), 1);
+        reconfigured.set_canary(None);
+        assert!(live.canary().is_none());
+    }
+
+    #[test]
+    fn test_zero_timeout_promotes_in_the_same_millisecond() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            timeout_duration_ms: 0,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+        assert_eq!(cb.get_state().next_attempt_at_ms, 1_000);
+        assert!(cb.would_proceed());
+        assert!(cb.can_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+
+        // With a timeout, the probe is due exactly when it runs out
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            timeout_duration_ms: 100,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        cb.record_failure();
+        clock.advance(99);
+        assert!(!cb.can_proceed());
+        clock.advance(1);
+        assert!(cb.can_proceed());
+    }
+
+    #[test]
+    fn test_reopen_suppression_absorbs_one_failure_after_recovery() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            success_threshold: 1,
+            timeout_duration_ms: 100,
+            reopen_suppression_ms: 5_000,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        let recover = || {
+            clock.advance(100);
+            assert!(cb.can_proceed());
+            cb.record_success();
+            assert_eq!(cb.get_state().state, CircuitState::Closed);
+        };
+
+        // Without a recovery to suppress, the first failure opens it
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+
+        // Within the window a failure is counted but the circuit stays closed
+        recover();
+        clock.advance(4_999);
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+        assert_eq!(cb.get_state().failure_count, 1);
+        // Only one failure gets that grace
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+
+        // Once the window has passed a failure opens it as usual
+        recover();
+        clock.advance(5_000);
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_min_open_ms_holds_a_zero_timeout_open() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            timeout_duration_ms: 0,
+            min_open_ms: 100,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        cb.record_failure();
+        assert_eq!(cb.get_state().next_attempt_at_ms, 1_100);
+        assert!(!cb.can_proceed());
+        clock.advance(99);
+        assert!(!cb.would_proceed());
+        assert!(!cb.can_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+
+        clock.advance(1);
+        assert!(cb.can_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+
+        // A failed probe isolates the PSP for the minimum again
+        cb.record_failure();
+        assert_eq!(cb.get_state().next_attempt_at_ms, 1_200);
+        assert!(!cb.can_proceed());
+    }
+
+    #[test]
+    fn test_time_in_state_accumulates_across_transitions() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            success_threshold: 1,
+            timeout_duration_ms: 500,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+
+        clock.advance(1_000);
+        cb.record_failure();
+        clock.advance(700);
+        assert!(cb.can_proceed());
+        clock.advance(50);
+        // The probe fails, so the circuit is open again
+        cb.record_failure();
+        clock.advance(300);
+
+        let time_in_state = cb.get_state().time_in_state_at(clock.now_ms());
+        assert_eq!(time_in_state.closed_ms, 1_000);
+        assert_eq!(time_in_state.open_ms, 1_000);
+        assert_eq!(time_in_state.half_open_ms, 50);
+
+        clock.advance(200);
+        assert!(cb.can_proceed());
+        cb.record_success();
+        clock.advance(100);
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Closed);
+        assert_eq!(state.time_in_state_at(clock.now_ms()).open_ms, 1_200);
+        assert_eq!(state.time_in_state_at(clock.now_ms()).closed_ms, 1_100);
+
+        // A reset starts the count again without touching the circuit
+        cb.reset_time_in_state();
+        clock.advance(40);
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Closed);
+        assert_eq!(state.time_in_state_since_ms, 3_350);
+        assert_eq!(
+            state.time_in_state_at(clock.now_ms()),
+            TimeInState {
+                closed_ms: 40,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_open_reason_tells_failures_from_force() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            success_threshold: 1,
+            timeout_duration_ms: 1000,
+            ..Default::default()
+        };
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+
+        let tripped = CircuitBreaker::new(config.clone()).with_clock(clock.clone());
+        assert_eq!(tripped.get_state().open_reason, None);
+        tripped.record_failure();
+        tripped.record_failure();
+        let state = tripped.get_state();
+        assert_eq!(state.state, CircuitState::Open);
+        assert_eq!(state.open_reason, Some(OpenReason::FailureThreshold));
+
+        let forced = CircuitBreaker::new(config).with_clock(clock.clone());
+        forced.force_open();
+        let state = forced.get_state();
+        assert_eq!(state.state, CircuitState::Open);
+        assert_eq!(state.failure_count, 0);
+        assert_eq!(state.open_reason, Some(OpenReason::Forced));
+        assert!(!forced.can_proceed());
+
+        // A failed probe reopens with its own reason, and leaving Open clears it
+        clock.advance(1_000);
+        assert!(tripped.can_proceed());
+        assert_eq!(tripped.get_state().open_reason, None);
+        tripped.record_failure();
+        assert_eq!(
+            tripped.get_state().open_reason,
+            Some(OpenReason::ProbeFailed)
+        );
+    }
+
+    #[test]
+    fn test_decayed_half_open_successes_stop_counting() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000));
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            success_threshold: 3,
+            timeout_duration_ms: 0,
+            success_decay_ms: 1_000,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        cb.record_failure();
+        assert!(cb.can_proceed());
+        assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+
+        // A trickle of successes further apart than the decay window never adds up
+        for _ in 0..5 {
+            cb.record_success();
+            assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
+            assert_eq!(cb.get_state().success_count, 1);
+            clock.advance(1_500);
+        }
+
+        // A sustained recovery inside the window closes the circuit
+        cb.record_success();
+        clock.advance(300);
+        cb.record_success();
+        assert_eq!(cb.get_state().success_count, 2);
+        clock.advance(300);
+        cb.record_success();
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Closed);
+        assert!(state.half_open_successes_ms.is_empty());
+
+        // Without decay the successes are only counted, not timestamped
+        let counting = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 1,
+            success_threshold: 3,
+            timeout_duration_ms: 0,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        counting.record_failure();
+        assert!(counting.can_proceed());
+        counting.record_success();
+        counting.record_success();
+        let state = counting.get_state();
+        assert_eq!(state.success_count, 2);
+        assert!(state.half_open_successes_ms.is_empty());
+    }
+
+    #[test]
+    fn test_record_batch_replays_outcomes_at_their_timestamps() {
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            success_threshold: 2,
+            timeout_duration_ms: 1_000,
+            ..Default::default()
+        })
+        .with_clock(Arc::new(crate::clock::ManualClock::new(500)));
+
+        // Two failures open it at 1_100, and a failure while it's open
+        // pushes the probe back to 2_500
+        let state = cb.record_batch(&[(1_000, false), (1_100, false), (1_500, false)]);
+        assert_eq!(state.state, CircuitState::Open);
+        assert_eq!(state.next_attempt_at_ms, 2_500);
+
+        // The first outcome after the timeout probes, and two successes close it
+        let state = cb.record_batch(&[(2_500, true), (2_600, true)]);
+        assert_eq!(state.state, CircuitState::Closed);
+        assert_eq!(state.state_entered_at_ms, 2_600);
+        assert_eq!(state.last_failure_at_ms, 1_500);
+        assert_eq!(
+            state.time_in_state_at(2_600),
+            TimeInState {
+                closed_ms: 600,
+                open_ms: 1_400,
+                half_open_ms: 100,
+            }
+        );
+        assert_eq!(cb.get_state(), state);
+    }
+
+    #[test]
+    fn test_paused_breaker_blocks_and_ignores_outcomes() {
+        let cb = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 2,
+            ..Default::default()
+        });
+        cb.record_failure();
+        assert!(cb.pause());
+        assert!(!cb.pause());
+        assert!(!cb.can_proceed());
+        assert!(!cb.would_proceed());
+
+        // Failures during maintenance don't trip the circuit
+        cb.record_failure();
+        cb.record_failure();
+        let state = cb.get_state();
+        assert_eq!(state.state, CircuitState::Closed);
+        assert_eq!(state.failure_count, 1);
+
+        assert!(cb.resume());
+        assert!(!cb.resume());
+        assert!(cb.can_proceed());
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
+
+    #[test]
+    fn test_presets_validate_and_order_their_thresholds() {
+        for preset in [Preset::Conservative, Preset::Balanced, Preset::Aggressive] {
+            assert!(
+                CircuitBreakerConfig::preset(preset).validate().is_ok(),
+                "{preset:?}"
+            );
+        }
+        assert_eq!(
+            CircuitBreakerConfig::preset(Preset::Balanced),
+            CircuitBreakerConfig::default()
+        );
+        let aggressive = CircuitBreakerConfig::preset(Preset::Aggressive);
+        let conservative = CircuitBreakerConfig::preset(Preset::Conservative);
+        assert!(aggressive.failure_threshold < conservative.failure_threshold);
+        assert!(aggressive.success_threshold < conservative.success_threshold);
+        assert!(aggressive.timeout_duration_ms < conservative.timeout_duration_ms);
+
+        // The aggressive breaker trips on its third failure
+        let cb = CircuitBreaker::new(aggressive);
+        cb.record_failure();
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Closed);
+        cb.record_failure();
+        assert_eq!(cb.get_state().state, CircuitState::Open);
+    }
 }
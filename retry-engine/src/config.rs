@@ -0,0 +1,1283 @@
+use crate::dlq::{DlqConfig, OverflowPolicy, PspQuota, ReplayThrottle};
+use crate::metrics::{HealthScoreConfig, OutcomeConfig, OutcomeTimeoutAction};
+use crate::persistence::{PersistenceConfig, PersistenceMode, SerializationFormat};
+use crate::retry_budget::RetryBudgetConfig;
+use crate::{
+    AttemptNumbering, AttemptOverflow, BlankIds, CircuitBreakerConfig, HalfOpenClose, JitterConfig,
+    JitterDistribution, JitterStrategy, Preset, PspAccessPolicy, PspNaming, PspOverflow,
+    RetryConfig, ServerConfig, DEFAULT_JITTER_STDDEV_FACTOR,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use toml_edit::{Document, Item, TableLike};
+
+/// Names the config file; when set, the file must exist
+pub const CONFIG_PATH_VAR: &str = "RETRY_ENGINE_CONFIG";
+
+/// Read when `RETRY_ENGINE_CONFIG` is unset, if present
+pub const DEFAULT_CONFIG_PATH: &str = "retry-engine.toml";
+
+/// Everything the engine needs at startup
+///
+/// Built from the defaults, overlaid with a TOML file, overlaid with
+/// `RETRY_ENGINE_*` environment variables:
+///
+/// ```toml
+/// [retry]
+/// max_attempts = 5
+/// jitter_strategy = "equal_jitter"
+///
+/// [circuit_breaker]
+/// failure_threshold = 5
+///
+/// # Only the listed fields differ from [circuit_breaker], or for jitter
+/// # keys, from [retry]
+/// [psp_overrides.stripe]
+/// failure_threshold = 10
+/// jitter_strategy = "equal_jitter"
+///
+/// # Omit allowlist to allow every PSP not denylisted
+/// [psp_access]
+/// allowlist = ["stripe", "adyen"]
+/// denylist = ["sanctioned-psp"]
+///
+/// # Replay dead letters by themselves after a cooldown, and expire old ones
+/// [dlq]
+/// soft_cooldown_ms = 300000
+/// min_replay_interval_ms = 200
+/// max_age_ms = 2592000000
+/// max_replays = 3
+///
+/// # At most this many DLQ entries for one PSP
+/// [dlq.psp_quotas.stripe]
+/// max_entries = 10000
+/// overflow_policy = "evict_oldest"
+///
+/// # Count a scheduled retry as failed if no outcome is reported in time
+/// [outcomes]
+/// timeout_ms = 30000
+/// timeout_action = "fail"
+///
+/// # Weights of the per-PSP health score
+/// [health_score]
+/// success_rate_weight = 0.5
+/// latency_ceiling_ms = 5000
+///
+/// # Retries per minute for priority 0, 1 and 2+; higher tiers can borrow
+/// # what lower ones leave unused
+/// [retry_budget]
+/// window_ms = 60000
+/// tier_allowances = [1000, 200, 50]
+///
+/// [persistence]
+/// path = "/var/lib/retry-engine/dlq"
+/// format = "bincode"
+/// mode = "strict"
+/// wal_sync_interval_ms = 1000
+///
+/// [server]
+/// request_timeout_ms = 5000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineConfig {
+    pub retry: RetryConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Per-PSP circuit configs that replace `circuit_breaker` for that PSP
+    pub psp_overrides: HashMap<String, CircuitBreakerConfig>,
+    /// Per-PSP jitter settings that replace those in `retry` for that PSP,
+    /// from the jitter keys of its `psp_overrides` table
+    pub psp_jitter: HashMap<String, JitterConfig>,
+    pub psp_access: PspAccessPolicy,
+    pub dlq: DlqConfig,
+    pub outcomes: OutcomeConfig,
+    pub health_score: HealthScoreConfig,
+    pub retry_budget: RetryBudgetConfig,
+    pub persistence: PersistenceConfig,
+    pub server: ServerConfig,
+}
+
+impl EngineConfig {
+    /// Load the file named by `RETRY_ENGINE_CONFIG` (or `retry-engine.toml`
+    /// if it exists), then apply environment overrides
+    pub fn load() -> Result<Self, String> {
+        let default_path = Path::new(DEFAULT_CONFIG_PATH);
+        let config = match std::env::var_os(CONFIG_PATH_VAR) {
+            Some(path) => Self::from_file(Path::new(&path))?,
+            None if default_path.exists() => Self::from_file(default_path)?,
+            None => Self::default(),
+        };
+        config.with_env(|name| std::env::var(name).ok())
+    }
+
+    /// Parse and validate a TOML file; errors are prefixed with its path
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Parse and validate TOML; missing sections and keys keep their defaults
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        let doc = Document::parse(text).map_err(|e| e.to_string())?;
+        let root: &dyn TableLike = doc.as_table();
+        check_keys(
+            root,
+            "",
+            &[
+                "retry",
+                "circuit_breaker",
+                "psp_overrides",
+                "psp_access",
+                "dlq",
+                "outcomes",
+                "health_score",
+                "retry_budget",
+                "persistence",
+                "server",
+            ],
+        )?;
+
+        let mut config = Self::default();
+        if let Some(table) = section(root, "retry")? {
+            read_retry(table, "retry", &mut config.retry)?;
+        }
+        if let Some(table) = section(root, "circuit_breaker")? {
+            check_keys(table, "circuit_breaker", CIRCUIT_KEYS)?;
+            read_circuit(table, "circuit_breaker", &mut config.circuit_breaker)?;
+        }
+        if let Some(overrides) = section(root, "psp_overrides")? {
+            for (psp_name, _) in overrides.iter() {
+                let name = format!("psp_overrides.{}", psp_name);
+                let table = section(overrides, psp_name)?
+                    .ok_or_else(|| format!("{} must be a table", name))?;
+                check_keys(table, &name, &[CIRCUIT_KEYS, JITTER_KEYS].concat())?;
+                // A table setting only jitter leaves the PSP's circuit alone
+                let jitter_only = table.iter().all(|(key, _)| JITTER_KEYS.contains(&key));
+                if !jitter_only || table.is_empty() {
+                    let mut circuit = config.circuit_breaker.clone();
+                    read_circuit(table, &name, &mut circuit)?;
+                    config.psp_overrides.insert(psp_name.to_string(), circuit);
+                }
+                if JITTER_KEYS.iter().any(|key| table.contains_key(key)) {
+                    let mut retry = config.retry.clone();
+                    read_jitter(table, &name, &mut retry)?;
+                    config
+                        .psp_jitter
+                        .insert(psp_name.to_string(), JitterConfig::of(&retry));
+                }
+            }
+        }
+        if let Some(table) = section(root, "psp_access")? {
+            read_psp_access(table, &mut config.psp_access)?;
+        }
+        if let Some(table) = section(root, "dlq")? {
+            read_dlq(table, &mut config.dlq)?;
+        }
+        if let Some(table) = section(root, "outcomes")? {
+            read_outcomes(table, &mut config.outcomes)?;
+        }
+        if let Some(table) = section(root, "health_score")? {
+            read_health_score(table, &mut config.health_score)?;
+        }
+        if let Some(table) = section(root, "retry_budget")? {
+            read_retry_budget(table, &mut config.retry_budget)?;
+        }
+        if let Some(table) = section(root, "persistence")? {
+            read_persistence(table, &mut config.persistence)?;
+        }
+        if let Some(table) = section(root, "server")? {
+            read_server(table, &mut config.server)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Apply `RETRY_ENGINE_*` overrides looked up through `var`, then validate
+    ///
+    /// Besides the server and persistence variables, `RETRY_ENGINE_MAX_ATTEMPTS`,
+    /// `RETRY_ENGINE_INITIAL_DELAY_MS`, `RETRY_ENGINE_MAX_DELAY_MS`,
+    /// `RETRY_ENGINE_FAILURE_THRESHOLD`, `RETRY_ENGINE_SUCCESS_THRESHOLD` and
+    /// `RETRY_ENGINE_CIRCUIT_TIMEOUT_MS` override the global retry and circuit
+    /// settings. Per-PSP overrides only come from the file.
+    pub fn with_env(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        fn number<T: std::str::FromStr>(
+            var: &impl Fn(&str) -> Option<String>,
+            name: &str,
+            target: &mut T,
+        ) -> Result<(), String> {
+            if let Some(value) = var(name) {
+                *target = value.parse().map_err(|_| {
+                    format!("{} must be a non-negative integer, got {:?}", name, value)
+                })?;
+            }
+            Ok(())
+        }
+
+        number(
+            &var,
+            "RETRY_ENGINE_MAX_ATTEMPTS",
+            &mut self.retry.max_attempts,
+        )?;
+        number(
+            &var,
+            "RETRY_ENGINE_INITIAL_DELAY_MS",
+            &mut self.retry.initial_delay_ms,
+        )?;
+        number(
+            &var,
+            "RETRY_ENGINE_MAX_DELAY_MS",
+            &mut self.retry.max_delay_ms,
+        )?;
+        number(
+            &var,
+            "RETRY_ENGINE_FAILURE_THRESHOLD",
+            &mut self.circuit_breaker.failure_threshold,
+        )?;
+        number(
+            &var,
+            "RETRY_ENGINE_SUCCESS_THRESHOLD",
+            &mut self.circuit_breaker.success_threshold,
+        )?;
+        number(
+            &var,
+            "RETRY_ENGINE_CIRCUIT_TIMEOUT_MS",
+            &mut self.circuit_breaker.timeout_duration_ms,
+        )?;
+        self.server = self.server.with_env(&var)?;
+        self.persistence = self.persistence.with_env(&var)?;
+
+        self.validate()?;
+        Ok(self)
+    }
+
+    /// Check every section, naming the one at fault
+    pub fn validate(&self) -> Result<(), String> {
+        self.retry.validate().map_err(|e| format!("retry: {}", e))?;
+        self.circuit_breaker
+            .validate()
+            .map_err(|e| format!("circuit_breaker: {}", e))?;
+        for (psp_name, config) in &self.psp_overrides {
+            config
+                .validate()
+                .map_err(|e| format!("psp_overrides.{}: {}", psp_name, e))?;
+        }
+        for (psp_name, jitter) in &self.psp_jitter {
+            jitter
+                .apply_to(&self.retry)
+                .validate()
+                .map_err(|e| format!("psp_overrides.{}: {}", psp_name, e))?;
+        }
+        self.dlq.validate().map_err(|e| format!("dlq.{}", e))?;
+        self.health_score
+            .validate()
+            .map_err(|e| format!("health_score: {}", e))?;
+        self.retry_budget
+            .validate()
+            .map_err(|e| format!("retry_budget: {}", e))?;
+        self.server
+            .validate()
+            .map_err(|e| format!("server: {}", e))?;
+        // A clamped attempt number must still be able to reach the last attempt
+        if self.server.max_attempt_number < self.retry.max_attempts {
+            return Err(format!(
+                "server: max_attempt_number ({}) must be at least retry.max_attempts ({})",
+                self.server.max_attempt_number, self.retry.max_attempts
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn read_retry(table: &dyn TableLike, name: &str, retry: &mut RetryConfig) -> Result<(), String> {
+    check_keys(
+        table,
+        name,
+        &[
+            "preset",
+            "max_attempts",
+            "initial_delay_ms",
+            "max_delay_ms",
+            "backoff_multiplier",
+            "jitter",
+            "jitter_strategy",
+            "min_delay_ms",
+            "max_elapsed_ms",
+            "load_stretch",
+            "immediate_retries",
+            "max_jitter_ms",
+            "attempt_numbering",
+            "jitter_distribution",
+            "jitter_stddev_factor",
+            "attempt_timeout_ms",
+            "attempt_timeout_multiplier",
+            "max_attempt_timeout_ms",
+            "backoff_offset",
+        ],
+    )?;
+    // The preset goes first, so the table's other keys override it
+    if let Some(preset) = read_preset(table, name)? {
+        *retry = RetryConfig::preset(preset);
+    }
+    read_u32(table, name, "max_attempts", &mut retry.max_attempts)?;
+    read_u32(
+        table,
+        name,
+        "immediate_retries",
+        &mut retry.immediate_retries,
+    )?;
+    read_u64(table, name, "initial_delay_ms", &mut retry.initial_delay_ms)?;
+    read_u64(table, name, "max_delay_ms", &mut retry.max_delay_ms)?;
+    read_u32(table, name, "backoff_offset", &mut retry.backoff_offset)?;
+    if let Some(item) = table.get("backoff_multiplier") {
+        retry.backoff_multiplier = item
+            .as_float()
+            .or_else(|| item.as_integer().map(|v| v as f64))
+            .ok_or_else(|| invalid(name, "backoff_multiplier", "a number", item))?;
+    }
+    if let Some(item) = table.get("load_stretch") {
+        retry.load_stretch = item
+            .as_float()
+            .or_else(|| item.as_integer().map(|v| v as f64))
+            .ok_or_else(|| invalid(name, "load_stretch", "a number", item))?;
+    }
+    if let Some(item) = table.get("attempt_numbering") {
+        retry.attempt_numbering = match item.as_str() {
+            Some("sequential") => AttemptNumbering::Sequential,
+            Some("literal") => AttemptNumbering::Literal,
+            _ => {
+                return Err(invalid(
+                    name,
+                    "attempt_numbering",
+                    "\"sequential\" or \"literal\"",
+                    item,
+                ))
+            }
+        };
+    }
+    read_jitter(table, name, retry)?;
+    read_u64(table, name, "min_delay_ms", &mut retry.min_delay_ms)?;
+    read_u64(table, name, "max_elapsed_ms", &mut retry.max_elapsed_ms)?;
+    read_u64(
+        table,
+        name,
+        "attempt_timeout_ms",
+        &mut retry.attempt_timeout_ms,
+    )?;
+    read_f64(
+        table,
+        name,
+        "attempt_timeout_multiplier",
+        &mut retry.attempt_timeout_multiplier,
+    )?;
+    read_u64(
+        table,
+        name,
+        "max_attempt_timeout_ms",
+        &mut retry.max_attempt_timeout_ms,
+    )
+}
+
+/// Keys of a `[retry]` table that make up its `JitterConfig`, which a PSP
+/// override can also set
+const JITTER_KEYS: &[&str] = &[
+    "jitter",
+    "jitter_strategy",
+    "jitter_distribution",
+    "jitter_stddev_factor",
+    "max_jitter_ms",
+];
+
+fn read_jitter(table: &dyn TableLike, name: &str, retry: &mut RetryConfig) -> Result<(), String> {
+    if let Some(item) = table.get("jitter") {
+        retry.jitter = item
+            .as_bool()
+            .ok_or_else(|| invalid(name, "jitter", "true or false", item))?;
+    }
+    if let Some(item) = table.get("jitter_strategy") {
+        retry.jitter_strategy = match item.as_str() {
+            Some("proportional") => JitterStrategy::Proportional,
+            Some("equal_jitter") => JitterStrategy::EqualJitter,
+            Some("full_jitter") => JitterStrategy::Full,
+            _ => {
+                return Err(invalid(
+                    name,
+                    "jitter_strategy",
+                    "\"proportional\", \"equal_jitter\" or \"full_jitter\"",
+                    item,
+                ))
+            }
+        };
+    }
+    if let Some(item) = table.get("jitter_distribution") {
+        retry.jitter_distribution = match item.as_str() {
+            Some("uniform") => JitterDistribution::Uniform,
+            Some("normal") => JitterDistribution::Normal {
+                stddev_factor: DEFAULT_JITTER_STDDEV_FACTOR,
+            },
+            _ => {
+                return Err(invalid(
+                    name,
+                    "jitter_distribution",
+                    "\"uniform\" or \"normal\"",
+                    item,
+                ))
+            }
+        };
+    }
+    if table.contains_key("jitter_stddev_factor") {
+        let JitterDistribution::Normal { stddev_factor } = &mut retry.jitter_distribution else {
+            return Err(format!(
+                "{}.jitter_stddev_factor only applies with jitter_distribution = \"normal\"",
+                name
+            ));
+        };
+        read_f64(table, name, "jitter_stddev_factor", stddev_factor)?;
+    }
+    read_u64(table, name, "max_jitter_ms", &mut retry.max_jitter_ms)
+}
+
+const CIRCUIT_KEYS: &[&str] = &[
+    "preset",
+    "failure_threshold",
+    "success_threshold",
+    "timeout_duration_ms",
+    "latency_threshold_ms",
+    "probe_window",
+    "min_success_percent",
+    "reopen_suppression_ms",
+    "min_open_ms",
+    "success_decay_ms",
+];
+
+/// Read a circuit config; the caller checks for unknown keys
+fn read_circuit(
+    table: &dyn TableLike,
+    name: &str,
+    circuit: &mut CircuitBreakerConfig,
+) -> Result<(), String> {
+    if let Some(preset) = read_preset(table, name)? {
+        *circuit = CircuitBreakerConfig::preset(preset);
+    }
+    read_u32(
+        table,
+        name,
+        "failure_threshold",
+        &mut circuit.failure_threshold,
+    )?;
+    read_u32(
+        table,
+        name,
+        "success_threshold",
+        &mut circuit.success_threshold,
+    )?;
+    read_u64(
+        table,
+        name,
+        "timeout_duration_ms",
+        &mut circuit.timeout_duration_ms,
+    )?;
+    read_u64(
+        table,
+        name,
+        "latency_threshold_ms",
+        &mut circuit.latency_threshold_ms,
+    )?;
+    read_u64(
+        table,
+        name,
+        "reopen_suppression_ms",
+        &mut circuit.reopen_suppression_ms,
+    )?;
+    read_u64(table, name, "min_open_ms", &mut circuit.min_open_ms)?;
+    read_u64(
+        table,
+        name,
+        "success_decay_ms",
+        &mut circuit.success_decay_ms,
+    )?;
+    let (mut window, mut min_success_percent) = circuit.half_open_close.parts();
+    read_u32(table, name, "probe_window", &mut window)?;
+    read_u32(table, name, "min_success_percent", &mut min_success_percent)?;
+    circuit.half_open_close = HalfOpenClose::from_parts(window, min_success_percent)
+        .map_err(|e| format!("{}: {}", name, e))?;
+    Ok(())
+}
+
+fn read_psp_access(table: &dyn TableLike, access: &mut PspAccessPolicy) -> Result<(), String> {
+    let name = "psp_access";
+    check_keys(table, name, &["allowlist", "denylist"])?;
+    if let Some(allowlist) = read_strings(table, name, "allowlist")? {
+        access.allowlist = Some(allowlist);
+    }
+    if let Some(denylist) = read_strings(table, name, "denylist")? {
+        access.denylist = denylist;
+    }
+    Ok(())
+}
+
+fn read_dlq(table: &dyn TableLike, dlq: &mut DlqConfig) -> Result<(), String> {
+    let name = "dlq";
+    check_keys(
+        table,
+        name,
+        &[
+            "soft_cooldown_ms",
+            "min_replay_interval_ms",
+            "replay_throttle",
+            "max_age_ms",
+            "max_replays",
+            "psp_quotas",
+        ],
+    )?;
+    if table.contains_key("soft_cooldown_ms") {
+        let mut cooldown_ms = 0;
+        read_u64(table, name, "soft_cooldown_ms", &mut cooldown_ms)?;
+        dlq.soft_cooldown_ms = Some(cooldown_ms);
+    }
+    if table.contains_key("max_age_ms") {
+        let mut max_age_ms = 0;
+        read_u64(table, name, "max_age_ms", &mut max_age_ms)?;
+        dlq.max_age_ms = Some(max_age_ms);
+    }
+    read_u64(
+        table,
+        name,
+        "min_replay_interval_ms",
+        &mut dlq.min_replay_interval_ms,
+    )?;
+    read_u32(table, name, "max_replays", &mut dlq.max_replays)?;
+    if let Some(item) = table.get("replay_throttle") {
+        dlq.replay_throttle = item
+            .as_str()
+            .and_then(ReplayThrottle::parse)
+            .ok_or_else(|| invalid(name, "replay_throttle", "\"queue\" or \"reject\"", item))?;
+    }
+    if let Some(quotas) = section(table, "psp_quotas")? {
+        for (psp_name, _) in quotas.iter() {
+            let name = format!("dlq.psp_quotas.{}", psp_name);
+            let table =
+                section(quotas, psp_name)?.ok_or_else(|| format!("{} must be a table", name))?;
+            check_keys(table, &name, &["max_entries", "overflow_policy"])?;
+            if !table.contains_key("max_entries") {
+                return Err(format!("{}.max_entries is required", name));
+            }
+            let mut max_entries = 0;
+            read_u64(table, &name, "max_entries", &mut max_entries)?;
+            let overflow_policy = match table.get("overflow_policy") {
+                Some(item) => item
+                    .as_str()
+                    .and_then(OverflowPolicy::parse)
+                    .ok_or_else(|| {
+                        invalid(
+                            &name,
+                            "overflow_policy",
+                            "\"reject\" or \"evict_oldest\"",
+                            item,
+                        )
+                    })?,
+                None => OverflowPolicy::Reject,
+            };
+            dlq.psp_quotas.insert(
+                psp_name.to_string(),
+                PspQuota {
+                    max_entries: max_entries as usize,
+                    overflow_policy,
+                },
+            );
+        }
+    }
+    Ok(())
+}
+
+fn read_outcomes(table: &dyn TableLike, outcomes: &mut OutcomeConfig) -> Result<(), String> {
+    let name = "outcomes";
+    check_keys(table, name, &["timeout_ms", "timeout_action"])?;
+    if table.contains_key("timeout_ms") {
+        let mut timeout_ms = 0;
+        read_u64(table, name, "timeout_ms", &mut timeout_ms)?;
+        outcomes.timeout_ms = Some(timeout_ms);
+    }
+    if let Some(item) = table.get("timeout_action") {
+        outcomes.timeout_action = item
+            .as_str()
+            .and_then(OutcomeTimeoutAction::parse)
+            .ok_or_else(|| invalid(name, "timeout_action", "\"fail\" or \"ignore\"", item))?;
+    }
+    Ok(())
+}
+
+fn read_retry_budget(table: &dyn TableLike, budget: &mut RetryBudgetConfig) -> Result<(), String> {
+    let name = "retry_budget";
+    check_keys(table, name, &["window_ms", "tier_allowances"])?;
+    read_u64(table, name, "window_ms", &mut budget.window_ms)?;
+    if let Some(item) = table.get("tier_allowances") {
+        budget.tier_allowances = item
+            .as_array()
+            .and_then(|array| {
+                array
+                    .iter()
+                    .map(|value| value.as_integer().and_then(|v| u64::try_from(v).ok()))
+                    .collect()
+            })
+            .ok_or_else(|| {
+                invalid(
+                    name,
+                    "tier_allowances",
+                    "an array of non-negative integers",
+                    item,
+                )
+            })?;
+    }
+    Ok(())
+}
+
+fn read_health_score(table: &dyn TableLike, health: &mut HealthScoreConfig) -> Result<(), String> {
+    let name = "health_score";
+    check_keys(
+        table,
+        name,
+        &[
+            "success_rate_weight",
+            "latency_weight",
+            "dlq_weight",
+            "latency_ceiling_ms",
+            "dlq_ceiling",
+        ],
+    )?;
+    read_f64(
+        table,
+        name,
+        "success_rate_weight",
+        &mut health.success_rate_weight,
+    )?;
+    read_f64(table, name, "latency_weight", &mut health.latency_weight)?;
+    read_f64(table, name, "dlq_weight", &mut health.dlq_weight)?;
+    read_u64(
+        table,
+        name,
+        "latency_ceiling_ms",
+        &mut health.latency_ceiling_ms,
+    )?;
+    read_u64(table, name, "dlq_ceiling", &mut health.dlq_ceiling)?;
+    Ok(())
+}
+
+fn read_persistence(
+    table: &dyn TableLike,
+    persistence: &mut PersistenceConfig,
+) -> Result<(), String> {
+    let name = "persistence";
+    check_keys(
+        table,
+        name,
+        &[
+            "path",
+            "format",
+            "mode",
+            "wal_sync_interval_ms",
+            "checkpoint_interval_ms",
+            "persist_payloads",
+        ],
+    )?;
+    if let Some(item) = table.get("path") {
+        let path = item
+            .as_str()
+            .ok_or_else(|| invalid(name, "path", "a string", item))?;
+        persistence.path = Some(PathBuf::from(path));
+    }
+    if let Some(item) = table.get("format") {
+        persistence.format = item
+            .as_str()
+            .and_then(SerializationFormat::parse)
+            .ok_or_else(|| invalid(name, "format", "\"json\" or \"bincode\"", item))?;
+    }
+    if let Some(item) = table.get("mode") {
+        persistence.mode = item
+            .as_str()
+            .and_then(PersistenceMode::parse)
+            .ok_or_else(|| invalid(name, "mode", "\"strict\" or \"lenient\"", item))?;
+    }
+    if table.contains_key("wal_sync_interval_ms") {
+        let mut sync_interval_ms = 0;
+        read_u64(table, name, "wal_sync_interval_ms", &mut sync_interval_ms)?;
+        persistence.wal_sync_interval_ms = Some(sync_interval_ms);
+    }
+    if let Some(item) = table.get("persist_payloads") {
+        persistence.persist_payloads = item
+            .as_bool()
+            .ok_or_else(|| invalid(name, "persist_payloads", "true or false", item))?;
+    }
+    read_u64(
+        table,
+        name,
+        "checkpoint_interval_ms",
+        &mut persistence.checkpoint_interval_ms,
+    )
+}
+
+fn read_server(table: &dyn TableLike, server: &mut ServerConfig) -> Result<(), String> {
+    let name = "server";
+    check_keys(
+        table,
+        name,
+        &[
+            "keepalive_interval_ms",
+            "keepalive_timeout_ms",
+            "request_timeout_ms",
+            "min_schedule_interval_ms",
+            "psp_names",
+            "max_psp_breakers",
+            "psp_overflow",
+            "max_attempt_number",
+            "attempt_overflow",
+            "blank_ids",
+        ],
+    )?;
+    read_u64(
+        table,
+        name,
+        "keepalive_interval_ms",
+        &mut server.keepalive_interval_ms,
+    )?;
+    read_u64(
+        table,
+        name,
+        "keepalive_timeout_ms",
+        &mut server.keepalive_timeout_ms,
+    )?;
+    read_u64(
+        table,
+        name,
+        "request_timeout_ms",
+        &mut server.request_timeout_ms,
+    )?;
+    read_u64(
+        table,
+        name,
+        "min_schedule_interval_ms",
+        &mut server.min_schedule_interval_ms,
+    )?;
+    if let Some(item) = table.get("psp_names") {
+        server.psp_naming = item
+            .as_str()
+            .and_then(PspNaming::parse)
+            .ok_or_else(|| invalid(name, "psp_names", "\"raw\" or \"trim_lowercase\"", item))?;
+    }
+    if table.contains_key("max_psp_breakers") {
+        let mut max_psp_breakers = 0;
+        read_u64(table, name, "max_psp_breakers", &mut max_psp_breakers)?;
+        server.max_psp_breakers = max_psp_breakers as usize;
+    }
+    if let Some(item) = table.get("psp_overflow") {
+        server.psp_overflow = item
+            .as_str()
+            .and_then(PspOverflow::parse)
+            .ok_or_else(|| invalid(name, "psp_overflow", "\"shared\" or \"reject\"", item))?;
+    }
+    read_u32(
+        table,
+        name,
+        "max_attempt_number",
+        &mut server.max_attempt_number,
+    )?;
+    if let Some(item) = table.get("attempt_overflow") {
+        server.attempt_overflow = item
+            .as_str()
+            .and_then(AttemptOverflow::parse)
+            .ok_or_else(|| invalid(name, "attempt_overflow", "\"reject\" or \"clamp\"", item))?;
+    }
+    if let Some(item) = table.get("blank_ids") {
+        server.blank_ids = item
+            .as_str()
+            .and_then(BlankIds::parse)
+            .ok_or_else(|| invalid(name, "blank_ids", "\"reject\" or \"allow\"", item))?;
+    }
+    Ok(())
+}
+
+/// The table's `preset`, if it names one
+fn read_preset(table: &dyn TableLike, name: &str) -> Result<Option<Preset>, String> {
+    table
+        .get("preset")
+        .map(|item| {
+            item.as_str().and_then(Preset::parse).ok_or_else(|| {
+                invalid(
+                    name,
+                    "preset",
+                    "\"conservative\", \"balanced\" or \"aggressive\"",
+                    item,
+                )
+            })
+        })
+        .transpose()
+}
+
+/// A sub-table of `table`, if present
+fn section<'a>(table: &'a dyn TableLike, key: &str) -> Result<Option<&'a dyn TableLike>, String> {
+    match table.get(key) {
+        Some(item) => item
+            .as_table_like()
+            .map(Some)
+            .ok_or_else(|| format!("{} must be a table, got {}", key, item.type_name())),
+        None => Ok(None),
+    }
+}
+
+/// Reject keys the engine doesn't know, so typos don't silently fall back to defaults
+fn check_keys(table: &dyn TableLike, name: &str, known: &[&str]) -> Result<(), String> {
+    match table.iter().find(|(key, _)| !known.contains(key)) {
+        Some((key, _)) if name.is_empty() => Err(format!("unknown section {}", key)),
+        Some((key, _)) => Err(format!("unknown key {}.{}", name, key)),
+        None => Ok(()),
+    }
+}
+
+fn read_u64(table: &dyn TableLike, name: &str, key: &str, target: &mut u64) -> Result<(), String> {
+    if let Some(item) = table.get(key) {
+        *target = item
+            .as_integer()
+            .and_then(|v| u64::try_from(v).ok())
+            .ok_or_else(|| invalid(name, key, "a non-negative integer", item))?;
+    }
+    Ok(())
+}
+
+fn read_f64(table: &dyn TableLike, name: &str, key: &str, target: &mut f64) -> Result<(), String> {
+    if let Some(item) = table.get(key) {
+        *target = item
+            .as_float()
+            .or_else(|| item.as_integer().map(|v| v as f64))
+            .ok_or_else(|| invalid(name, key, "a number", item))?;
+    }
+    Ok(())
+}
+
+fn read_u32(table: &dyn TableLike, name: &str, key: &str, target: &mut u32) -> Result<(), String> {
+    if let Some(item) = table.get(key) {
+        *target = item
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| invalid(name, key, "a non-negative integer", item))?;
+    }
+    Ok(())
+}
+
+fn read_strings(
+    table: &dyn TableLike,
+    name: &str,
+    key: &str,
+) -> Result<Option<BTreeSet<String>>, String> {
+    match table.get(key) {
+        Some(item) => item
+            .as_array()
+            .and_then(|array| {
+                array
+                    .iter()
+                    .map(|value| value.as_str().map(str::to_string))
+                    .collect()
+            })
+            .map(Some)
+            .ok_or_else(|| invalid(name, key, "an array of strings", item)),
+        None => Ok(None),
+    }
+}
+
+fn invalid(name: &str, key: &str, expected: &str, item: &Item) -> String {
+    let found = match (item.as_integer(), item.as_float(), item.as_str()) {
+        (Some(v), _, _) => v.to_string(),
+        (_, Some(v), _) => v.to_string(),
+        (_, _, Some(v)) => format!("{:?}", v),
+        _ => item.type_name().to_string(),
+    };
+    format!("{}.{} must be {}, got {}", name, key, expected, found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+[retry]
+max_attempts = 7
+initial_delay_ms = 500
+max_delay_ms = 30000
+backoff_multiplier = 1.5
+jitter = false
+jitter_strategy = "equal_jitter"
+min_delay_ms = 100
+max_elapsed_ms = 600000
+immediate_retries = 1
+max_jitter_ms = 2000
+attempt_numbering = "literal"
+jitter_distribution = "normal"
+jitter_stddev_factor = 0.25
+attempt_timeout_ms = 5000
+attempt_timeout_multiplier = 1.5
+max_attempt_timeout_ms = 20000
+backoff_offset = 0
+
+[circuit_breaker]
+failure_threshold = 4
+timeout_duration_ms = 15000
+reopen_suppression_ms = 10000
+min_open_ms = 1000
+success_decay_ms = 60000
+
+[psp_overrides.stripe]
+failure_threshold = 10
+
+[psp_overrides.adyen]
+latency_threshold_ms = 2000
+probe_window = 10
+min_success_percent = 90
+
+[psp_overrides.worldpay]
+jitter = true
+jitter_strategy = "proportional"
+
+[psp_access]
+denylist = ["sanctioned-psp"]
+
+[dlq]
+soft_cooldown_ms = 300000
+min_replay_interval_ms = 200
+replay_throttle = "reject"
+max_age_ms = 2592000000
+max_replays = 3
+
+[dlq.psp_quotas.noisy]
+max_entries = 100
+overflow_policy = "evict_oldest"
+
+[outcomes]
+timeout_ms = 30000
+timeout_action = "ignore"
+
+[health_score]
+latency_weight = 1
+dlq_ceiling = 20
+
+[retry_budget]
+window_ms = 1000
+tier_allowances = [50, 10]
+
+[persistence]
+path = "/var/lib/retry-engine/dlq"
+format = "bincode"
+mode = "strict"
+wal_sync_interval_ms = 1000
+persist_payloads = false
+
+[server]
+request_timeout_ms = 5000
+min_schedule_interval_ms = 100
+psp_names = "trim_lowercase"
+max_psp_breakers = 500
+psp_overflow = "reject"
+max_attempt_number = 100
+attempt_overflow = "clamp"
+blank_ids = "allow"
+"#;
+
+    #[test]
+    fn test_sample_toml_parses() {
+        let config = EngineConfig::from_toml(SAMPLE).unwrap();
+
+        assert_eq!(
+            config.retry,
+            RetryConfig {
+                max_attempts: 7,
+                initial_delay_ms: 500,
+                max_delay_ms: 30000,
+                backoff_multiplier: 1.5,
+                jitter: false,
+                jitter_strategy: JitterStrategy::EqualJitter,
+                min_delay_ms: 100,
+                max_elapsed_ms: 600000,
+                load_stretch: 1.0,
+                immediate_retries: 1,
+                max_jitter_ms: 2000,
+                attempt_numbering: AttemptNumbering::Literal,
+                jitter_distribution: JitterDistribution::Normal {
+                    stddev_factor: 0.25,
+                },
+                attempt_timeout_ms: 5000,
+                attempt_timeout_multiplier: 1.5,
+                max_attempt_timeout_ms: 20000,
+                backoff_offset: 0,
+            }
+        );
+        let circuit = CircuitBreakerConfig {
+            failure_threshold: 4,
+            timeout_duration_ms: 15000,
+            reopen_suppression_ms: 10000,
+            min_open_ms: 1000,
+            success_decay_ms: 60000,
+            ..Default::default()
+        };
+        assert_eq!(config.circuit_breaker, circuit);
+
+        // Overrides inherit the unlisted fields from [circuit_breaker]
+        assert_eq!(config.psp_overrides.len(), 2);
+        assert_eq!(
+            config.psp_overrides["stripe"],
+            CircuitBreakerConfig {
+                failure_threshold: 10,
+                ..circuit.clone()
+            }
+        );
+        assert_eq!(
+            config.psp_overrides["adyen"],
+            CircuitBreakerConfig {
+                latency_threshold_ms: 2000,
+                half_open_close: HalfOpenClose::SuccessRatio {
+                    window: 10,
+                    min_success_percent: 90,
+                },
+                ..circuit
+            }
+        );
+        // Jitter keys inherit the rest from [retry], and alone leave the
+        // PSP's circuit alone
+        assert_eq!(
+            config.psp_jitter,
+            HashMap::from([(
+                "worldpay".to_string(),
+                JitterConfig {
+                    jitter: true,
+                    jitter_strategy: JitterStrategy::Proportional,
+                    jitter_distribution: JitterDistribution::Normal {
+                        stddev_factor: 0.25,
+                    },
+                    max_jitter_ms: 2000,
+                }
+            )])
+        );
+
+        assert_eq!(
+            config.psp_access,
+            PspAccessPolicy {
+                allowlist: None,
+                denylist: BTreeSet::from(["sanctioned-psp".to_string()]),
+            }
+        );
+
+        assert_eq!(config.dlq.soft_cooldown_ms, Some(300000));
+        assert_eq!(config.dlq.min_replay_interval_ms, 200);
+        assert_eq!(config.dlq.max_age_ms, Some(2_592_000_000));
+        assert_eq!(config.dlq.max_replays, 3);
+        assert_eq!(config.dlq.replay_throttle, ReplayThrottle::Reject);
+        assert_eq!(
+            config.outcomes,
+            OutcomeConfig {
+                timeout_ms: Some(30000),
+                timeout_action: OutcomeTimeoutAction::Ignore,
+            }
+        );
+        assert_eq!(
+            config.health_score,
+            HealthScoreConfig {
+                latency_weight: 1.0,
+                dlq_ceiling: 20,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            config.retry_budget,
+            RetryBudgetConfig {
+                window_ms: 1000,
+                tier_allowances: vec![50, 10],
+            }
+        );
+        assert_eq!(
+            config.dlq.psp_quotas["noisy"],
+            PspQuota {
+                max_entries: 100,
+                overflow_policy: OverflowPolicy::EvictOldest,
+            }
+        );
+
+        assert_eq!(
+            config.persistence,
+            PersistenceConfig {
+                path: Some(PathBuf::from("/var/lib/retry-engine/dlq")),
+                format: SerializationFormat::Bincode,
+                mode: PersistenceMode::Strict,
+                wal_sync_interval_ms: Some(1000),
+                checkpoint_interval_ms: 60000,
+                persist_payloads: false,
+            }
+        );
+        assert_eq!(
+            config.server,
+            ServerConfig {
+                request_timeout_ms: 5000,
+                min_schedule_interval_ms: 100,
+                psp_naming: PspNaming::TrimLowercase,
+                max_psp_breakers: 500,
+                psp_overflow: PspOverflow::Reject,
+                max_attempt_number: 100,
+                attempt_overflow: AttemptOverflow::Clamp,
+                blank_ids: BlankIds::Allow,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_toml_keeps_defaults() {
+        assert_eq!(
+            EngineConfig::from_toml("").unwrap(),
+            EngineConfig::default()
+        );
+    }
+
+    #[test]
+    fn test_invalid_toml_is_rejected_with_context() {
+        let cases = [
+            ("[retry\nmax_attempts = 1", "TOML parse error at line 1"),
+            (
+                "[retry]\nmax_attempts = \"five\"",
+                "retry.max_attempts must be a non-negative integer, got \"five\"",
+            ),
+            (
+                "[circuit_breaker]\nfailure_threshold = -1",
+                "circuit_breaker.failure_threshold must be a non-negative integer, got -1",
+            ),
+            ("[retry]\nmax_attemps = 3", "unknown key retry.max_attemps"),
+            ("[metrics]\nenabled = true", "unknown section metrics"),
+            (
+                "[persistence]\nformat = \"yaml\"",
+                "persistence.format must be \"json\" or \"bincode\"",
+            ),
+            (
+                "[psp_overrides.stripe]\nsuccess_threshold = 0",
+                "psp_overrides.stripe: success_threshold must be at least 1",
+            ),
+            (
+                "[retry]\nmin_delay_ms = 5000\nmax_delay_ms = 1000",
+                "retry: min_delay_ms (5000) must not exceed max_delay_ms (1000)",
+            ),
+            ("retry = 3", "retry must be a table"),
+            (
+                "[dlq.psp_quotas.stripe]\noverflow_policy = \"reject\"",
+                "dlq.psp_quotas.stripe.max_entries is required",
+            ),
+            (
+                "[dlq.psp_quotas.stripe]\nmax_entries = 0",
+                "dlq.psp_quotas.stripe: max_entries must be at least 1",
+            ),
+            (
+                "[psp_access]\ndenylist = \"stripe\"",
+                "psp_access.denylist must be an array of strings, got \"stripe\"",
+            ),
+            (
+                "[retry]\nbackoff_multiplier = 0.5",
+                "retry: backoff_multiplier must be a finite number of at least 1.0",
+            ),
+            (
+                "[health_score]\nsuccess_rate_weight = 0\nlatency_weight = 0\ndlq_weight = 0",
+                "health_score: at least one weight must be above 0.0",
+            ),
+            (
+                "[retry]\nmax_attempts = 20\n[server]\nmax_attempt_number = 10",
+                "server: max_attempt_number (10) must be at least retry.max_attempts (20)",
+            ),
+        ];
+        for (text, expected) in cases {
+            let err = EngineConfig::from_toml(text).unwrap_err();
+            assert!(
+                err.contains(expected),
+                "{:?}: expected {:?} in {:?}",
+                text,
+                expected,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_env_overrides_file_values() {
+        let env: HashMap<&str, &str> = [
+            ("RETRY_ENGINE_MAX_ATTEMPTS", "9"),
+            ("RETRY_ENGINE_FAILURE_THRESHOLD", "2"),
+            ("RETRY_ENGINE_DLQ_FORMAT", "json"),
+            ("RETRY_ENGINE_REQUEST_TIMEOUT_MS", "2500"),
+        ]
+        .into_iter()
+        .collect();
+
+        let config = EngineConfig::from_toml(SAMPLE)
+            .unwrap()
+            .with_env(|name| env.get(name).map(|v| v.to_string()))
+            .unwrap();
+
+        assert_eq!(config.retry.max_attempts, 9);
+        assert_eq!(config.retry.initial_delay_ms, 500);
+        assert_eq!(config.circuit_breaker.failure_threshold, 2);
+        assert_eq!(config.persistence.format, SerializationFormat::Json);
+        assert_eq!(config.persistence.mode, PersistenceMode::Strict);
+        assert_eq!(config.server.request_timeout_ms, 2500);
+
+        let err = EngineConfig::default()
+            .with_env(|name| (name == "RETRY_ENGINE_MAX_ATTEMPTS").then(|| "lots".to_string()))
+            .unwrap_err();
+        assert!(err.contains("RETRY_ENGINE_MAX_ATTEMPTS"));
+    }
+
+    #[test]
+    fn test_from_file_names_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("retry-engine-config-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "[server]\nrequest_timeout_ms = 0\n").unwrap();
+
+        let err = EngineConfig::from_file(&path).unwrap_err();
+        assert!(err.contains(&path.display().to_string()));
+        assert!(err.contains("server: request_timeout_ms must be at least 1"));
+
+        std::fs::write(&path, SAMPLE).unwrap();
+        assert_eq!(
+            EngineConfig::from_file(&path).unwrap(),
+            EngineConfig::from_toml(SAMPLE).unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(EngineConfig::from_file(&path)
+            .unwrap_err()
+            .starts_with("Failed to read config"));
+    }
+
+    #[test]
+    fn test_preset_is_applied_before_explicit_keys() {
+        let config = EngineConfig::from_toml(
+            "[retry]\npreset = \"aggressive\"\nmax_attempts = 5\n\n\
+             [circuit_breaker]\npreset = \"conservative\"\n\n\
+             [psp_overrides.stripe]\npreset = \"aggressive\"\nfailure_threshold = 4",
+        )
+        .unwrap();
+        assert_eq!(
+            config.retry,
+            RetryConfig {
+                max_attempts: 5,
+                ..RetryConfig::preset(Preset::Aggressive)
+            }
+        );
+        assert_eq!(
+            config.circuit_breaker,
+            CircuitBreakerConfig::preset(Preset::Conservative)
+        );
+        assert_eq!(
+            config.psp_overrides["stripe"],
+            CircuitBreakerConfig {
+                failure_threshold: 4,
+                ..CircuitBreakerConfig::preset(Preset::Aggressive)
+            }
+        );
+
+        assert_eq!(
+            EngineConfig::from_toml("[retry]\npreset = \"reckless\"").unwrap_err(),
+            "retry.preset must be \"conservative\", \"balanced\" or \"aggressive\", got \"reckless\""
+        );
+    }
+}
@@ -1,8 +1,12 @@
+use crate::persistence::{DlqStore, PersistenceError};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use tracing::warn;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DLQEntry {
     pub transaction_id: String,
     pub psp_name: String,
@@ -10,23 +14,879 @@ pub struct DLQEntry {
     pub attempt_count: u32,
     pub last_error: String,
     pub timestamp_ms: u64,
+    /// Derived context attached by the queue's `EntryEnricher`
+    #[serde(default)]
+    pub metadata: HashMap<String, String>,
+    /// Set while the entry has been replayed back into the retry pipeline
+    #[serde(default)]
+    pub replaying: bool,
+    /// PSP the entry was re-homed from by a replay, if any
+    #[serde(default)]
+    pub rerouted_from: Option<String>,
+    #[serde(default)]
+    pub status: DlqEntryStatus,
+    /// Queue revision at which this entry was last added or modified
+    #[serde(default)]
+    pub last_modified_revision: u64,
+    /// Labels for operating on a group of transactions together
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Times an operator has replayed the entry, kept across re-dead-lettering
+    #[serde(default)]
+    pub replay_count: u32,
+    /// Why the entry is parked for a manual data fix; while set it's left out
+    /// of bulk replay, purges and budget eviction
+    #[serde(default)]
+    pub parked_note: Option<String>,
+    /// Soft DLQ: when the entry goes back into the retry pipeline by itself,
+    /// once its PSP's circuit is closed. 0 leaves replay to an operator.
+    #[serde(default)]
+    pub retry_after_ms: u64,
+    /// Failures the entry was dead-lettered with before each replay, oldest
+    /// first and capped at `MAX_PREVIOUS_FAILURES`
+    #[serde(default)]
+    pub previous_failures: Vec<PreviousFailure>,
+    /// MIME type of `payload`, so tools reading the entry know how to parse it
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    /// Transfer encoding applied to `payload` on top of its content type, if
+    /// any (e.g. `gzip`)
+    #[serde(default)]
+    pub encoding: Option<String>,
+    /// Why the transaction was dead-lettered
+    #[serde(default)]
+    pub reason: DlqReason,
+    /// The payload wasn't persisted, so a replay must supply it again
+    #[serde(default)]
+    pub payload_stripped: bool,
+}
+
+/// Content type of payloads nobody described: opaque bytes
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+fn default_content_type() -> String {
+    DEFAULT_CONTENT_TYPE.to_string()
+}
+
+/// Most earlier failures an entry remembers; older ones are dropped
+pub const MAX_PREVIOUS_FAILURES: usize = 10;
+
+/// How an entry was dead-lettered before being replayed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreviousFailure {
+    pub psp_name: String,
+    pub attempt_count: u32,
+    pub last_error: String,
+    pub timestamp_ms: u64,
+}
+
+/// An entry's failure against the one it had before its last replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureComparison {
+    /// Never replayed and dead-lettered again
+    FirstFailure,
+    /// The replay failed with the same error, so whatever changed didn't
+    /// fix it
+    SameError,
+    /// The replay failed differently
+    ErrorChanged,
+}
+
+impl DLQEntry {
+    pub fn is_parked(&self) -> bool {
+        self.parked_note.is_some()
+    }
+
+    /// The entry as written to disk when payloads aren't persisted
+    pub fn without_payload(&self) -> Self {
+        let mut entry = self.clone();
+        if !entry.payload.is_empty() {
+            entry.payload.clear();
+            entry.payload_stripped = true;
+        }
+        entry
+    }
+
+    /// Whether the error changed since the failure before the last replay
+    pub fn compare_to_previous(&self) -> FailureComparison {
+        match self.previous_failures.last() {
+            None => FailureComparison::FirstFailure,
+            Some(previous) if previous.last_error == self.last_error => {
+                FailureComparison::SameError
+            }
+            Some(_) => FailureComparison::ErrorChanged,
+        }
+    }
+
+    /// Describe a payload nobody described as opaque bytes
+    fn fill_default_content_type(&mut self) {
+        if self.content_type.is_empty() {
+            self.content_type = default_content_type();
+        }
+    }
+
+    /// Take over the history of `previous`, the replayed entry this one
+    /// replaces, with its own failure appended
+    fn inherit_failures(&mut self, previous: &DLQEntry) {
+        let mut failures = previous.previous_failures.clone();
+        failures.push(PreviousFailure {
+            psp_name: previous.psp_name.clone(),
+            attempt_count: previous.attempt_count,
+            last_error: previous.last_error.clone(),
+            timestamp_ms: previous.timestamp_ms,
+        });
+        let excess = failures.len().saturating_sub(MAX_PREVIOUS_FAILURES);
+        failures.drain(..excess);
+        self.previous_failures = failures;
+    }
+}
+
+/// Triage state of a dead letter
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DlqEntryStatus {
+    #[default]
+    New,
+    InReview,
+    Resolved,
+    Discarded,
+}
+
+impl DlqEntryStatus {
+    /// Entries only move forward: New → InReview → Resolved/Discarded, with New
+    /// allowed to skip straight to a terminal state. Setting the current
+    /// status again is a no-op.
+    pub fn can_transition_to(self, next: DlqEntryStatus) -> bool {
+        use DlqEntryStatus::*;
+        self == next
+            || matches!(
+                (self, next),
+                (New, InReview)
+                    | (New, Resolved)
+                    | (New, Discarded)
+                    | (InReview, Resolved)
+                    | (InReview, Discarded)
+            )
+    }
+
+    pub fn is_terminal(self) -> bool {
+        matches!(self, DlqEntryStatus::Resolved | DlqEntryStatus::Discarded)
+    }
+}
+
+/// Why a transaction was dead-lettered
+///
+/// Each reason has a stable numeric code, so downstream systems can branch
+/// on it rather than parse `last_error`. Codes are never reused; a new
+/// reason gets the next one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DlqReason {
+    /// Not recorded: added directly or before reasons were tracked
+    #[default]
+    Unspecified,
+    /// Failed on its last allowed attempt
+    MaxAttemptsExceeded,
+    /// The next attempt would have been due after `max_elapsed_ms`
+    MaxElapsedExceeded,
+    /// `max_attempts` was lowered to at or below its attempt count
+    MaxAttemptsLowered,
+    /// An operator dead-lettered it with `ForceToDlq`
+    ForcedByOperator,
+    /// Another transaction in its group ran out of retries
+    GroupMemberExhausted,
+}
+
+impl DlqReason {
+    pub fn code(self) -> u32 {
+        match self {
+            DlqReason::Unspecified => 0,
+            DlqReason::MaxAttemptsExceeded => 1,
+            DlqReason::MaxElapsedExceeded => 2,
+            DlqReason::MaxAttemptsLowered => 3,
+            DlqReason::ForcedByOperator => 4,
+            DlqReason::GroupMemberExhausted => 5,
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(DlqReason::Unspecified),
+            1 => Some(DlqReason::MaxAttemptsExceeded),
+            2 => Some(DlqReason::MaxElapsedExceeded),
+            3 => Some(DlqReason::MaxAttemptsLowered),
+            4 => Some(DlqReason::ForcedByOperator),
+            5 => Some(DlqReason::GroupMemberExhausted),
+            _ => None,
+        }
+    }
+}
+
+/// Hook for attaching derived metadata (reason, severity, owner, ...) to an
+/// entry before it is stored. Returned keys are merged into `metadata`.
+pub trait EntryEnricher: Send + Sync {
+    fn enrich(&self, entry: &DLQEntry) -> Result<HashMap<String, String>, String>;
+}
+
+/// Enricher that attaches nothing
+pub struct NoopEnricher;
+
+impl EntryEnricher for NoopEnricher {
+    fn enrich(&self, _entry: &DLQEntry) -> Result<HashMap<String, String>, String> {
+        Ok(HashMap::new())
+    }
+}
+
+/// Called with each entry `purge_expired` is about to remove, e.g. to archive
+/// it elsewhere; an error keeps the entry in the queue
+pub type ExpiryHook = Arc<dyn Fn(&DLQEntry) -> Result<(), String> + Send + Sync>;
+
+/// Puts every change made to the queue on disk, fsynced, returning whether
+/// there's anywhere to put it; `flush` runs it on a blocking task
+pub type FlushHook = Arc<dyn Fn(&DeadLetterQueue) -> Result<bool, PersistenceError> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DlqError {
+    /// Storing the payload would push total stored payload bytes over the ceiling
+    PayloadBudgetExceeded {
+        requested_bytes: u64,
+        max_bytes: u64,
+    },
+    /// The PSP already holds as many entries as its quota allows
+    PspQuotaExceeded {
+        psp_name: String,
+        max_entries: usize,
+    },
+    NotFound(String),
+    InvalidTransition {
+        from: DlqEntryStatus,
+        to: DlqEntryStatus,
+    },
+    /// Another worker holds an unexpired lease on the entry
+    Leased {
+        transaction_id: String,
+        expires_at_ms: u64,
+    },
+    /// The lease given has expired or was never held
+    LeaseNotHeld(String),
+}
+
+impl fmt::Display for DlqError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DlqError::PayloadBudgetExceeded {
+                requested_bytes,
+                max_bytes,
+            } => write!(
+                f,
+                "storing {} payload bytes would exceed the {} byte ceiling",
+                requested_bytes, max_bytes
+            ),
+            DlqError::PspQuotaExceeded {
+                psp_name,
+                max_entries,
+            } => write!(
+                f,
+                "PSP {} already holds its quota of {} DLQ entries",
+                psp_name, max_entries
+            ),
+            DlqError::NotFound(transaction_id) => {
+                write!(f, "transaction {} is not in the DLQ", transaction_id)
+            }
+            DlqError::InvalidTransition { from, to } => {
+                write!(f, "cannot move a DLQ entry from {:?} to {:?}", from, to)
+            }
+            DlqError::Leased {
+                transaction_id,
+                expires_at_ms,
+            } => write!(
+                f,
+                "transaction {} is leased until {}",
+                transaction_id, expires_at_ms
+            ),
+            DlqError::LeaseNotHeld(transaction_id) => {
+                write!(f, "no such lease on transaction {}", transaction_id)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DlqError {}
+
+/// What to do when storing a payload would exceed the byte ceiling
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    /// Refuse the new payload
+    Reject,
+    /// Evict the oldest unparked DLQ entries until the new payload fits
+    EvictOldest,
+}
+
+impl OverflowPolicy {
+    /// Parse the config spelling: `reject` or `evict_oldest`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "evict_oldest" => Some(Self::EvictOldest),
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a replay due sooner than `min_replay_interval_ms` after
+/// the previous replay to its PSP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplayThrottle {
+    /// Push the replayed attempt back until the interval has passed
+    #[default]
+    Queue,
+    /// Refuse the replay
+    Reject,
+}
+
+impl ReplayThrottle {
+    /// Parse the config spelling: `queue` or `reject`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queue" => Some(Self::Queue),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Cap on how many DLQ entries one PSP can hold, so a noisy PSP can't crowd
+/// the others out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PspQuota {
+    pub max_entries: usize,
+    /// What a new entry for a PSP at its quota does; eviction only takes that
+    /// PSP's own entries
+    pub overflow_policy: OverflowPolicy,
+}
+
+/// Running total of payload bytes held by the DLQ and the retry states,
+/// bounded by a ceiling
+pub struct PayloadBudget {
+    max_bytes: u64,
+    overflow_policy: OverflowPolicy,
+    used_bytes: AtomicU64,
+}
+
+impl PayloadBudget {
+    pub fn new(max_bytes: u64, overflow_policy: OverflowPolicy) -> Self {
+        Self {
+            max_bytes,
+            overflow_policy,
+            used_bytes: AtomicU64::new(0),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(u64::MAX, OverflowPolicy::Reject)
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.used_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Reserve bytes if they fit under the ceiling
+    fn try_reserve(&self, bytes: u64) -> bool {
+        self.used_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes)
+                    .filter(|total| *total <= self.max_bytes)
+            })
+            .is_ok()
+    }
+
+    /// Reserve bytes regardless of the ceiling, for restoring previously held payloads
+    fn force_reserve(&self, bytes: u64) {
+        self.used_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    fn release(&self, bytes: u64) {
+        let _ = self
+            .used_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+}
+
+impl Default for PayloadBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+/// DLQ settings from the `[dlq]` section of the engine config
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DlqConfig {
+    /// Soft DLQ cooldown (see `RetryEngineService::with_soft_dlq`); `None`
+    /// leaves every replay to an operator
+    pub soft_cooldown_ms: Option<u64>,
+    /// Age past which dead letters are expired (see
+    /// `RetryEngineService::with_dlq_expiry`); `None` keeps them until removed
+    pub max_age_ms: Option<u64>,
+    /// Per-PSP caps on entry count
+    pub psp_quotas: HashMap<String, PspQuota>,
+    /// Least time between the attempts of two replays to one PSP; 0 for no
+    /// limit
+    pub min_replay_interval_ms: u64,
+    pub replay_throttle: ReplayThrottle,
+    /// Replays allowed per entry before `force` is required (see
+    /// `RetryEngineService::with_max_replays`); 0 for no limit
+    pub max_replays: u32,
+}
+
+impl DlqConfig {
+    /// Check that every quota leaves room for at least one entry
+    pub fn validate(&self) -> Result<(), String> {
+        for (psp_name, quota) in &self.psp_quotas {
+            if quota.max_entries == 0 {
+                return Err(format!(
+                    "psp_quotas.{}: max_entries must be at least 1",
+                    psp_name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn payload_size(entry: &DLQEntry) -> u64 {
+    entry.payload.len() as u64
+}
+
+/// A worker's claim on an entry to reprocess, until `expires_at_ms`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DlqLease {
+    pub lease_id: String,
+    pub expires_at_ms: u64,
+}
+
+/// What the DLQ holds for one PSP, without the entries themselves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DlqPspSummary {
+    pub entries: usize,
+    pub parked: usize,
+    pub replaying: usize,
+    pub payload_bytes: u64,
+}
+
+/// Removals remembered for `changes_since`; older ones are compacted away
+const MAX_TOMBSTONES: usize = 10_000;
+
+/// What changed in the DLQ since a given revision
+#[derive(Debug, Clone, Default)]
+pub struct DlqChanges {
+    /// Entries added or modified since the revision
+    pub added: Vec<DLQEntry>,
+    /// Transactions removed since the revision and not re-added
+    pub removed: Vec<String>,
+    /// Revision to pass on the next call
+    pub revision: u64,
+    /// The revision was too old or from another numbering, so `added` is the
+    /// full contents of the queue and the caller should replace its copy
+    pub reset: bool,
+}
+
+/// Revision counter plus the removals needed to diff against past revisions
+#[derive(Default)]
+struct ChangeLog {
+    revision: u64,
+    tombstones: VecDeque<(u64, String)>,
+    /// Removals at or before this revision are no longer known
+    compacted_through: u64,
+}
+
+impl ChangeLog {
+    fn bump(&mut self) -> u64 {
+        self.revision += 1;
+        self.revision
+    }
+
+    fn record_removal(&mut self, transaction_id: &str) {
+        let revision = self.bump();
+        self.tombstones
+            .push_back((revision, transaction_id.to_string()));
+        if self.tombstones.len() > MAX_TOMBSTONES {
+            if let Some((dropped, _)) = self.tombstones.pop_front() {
+                self.compacted_through = dropped;
+            }
+        }
+    }
+}
+
+/// Transaction ids by tag, and entry counts by PSP
+#[derive(Default)]
+struct EntryIndex {
+    by_tag: HashMap<String, HashSet<String>>,
+    per_psp: HashMap<String, usize>,
+}
+
+impl EntryIndex {
+    fn insert(&mut self, entry: &DLQEntry) {
+        *self.per_psp.entry(entry.psp_name.clone()).or_default() += 1;
+        for tag in &entry.tags {
+            self.by_tag
+                .entry(tag.clone())
+                .or_default()
+                .insert(entry.transaction_id.clone());
+        }
+    }
+
+    fn remove(&mut self, entry: &DLQEntry) {
+        if let Some(count) = self.per_psp.get_mut(&entry.psp_name) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_psp.remove(&entry.psp_name);
+            }
+        }
+        for tag in &entry.tags {
+            if let Some(ids) = self.by_tag.get_mut(tag) {
+                ids.remove(&entry.transaction_id);
+                if ids.is_empty() {
+                    self.by_tag.remove(tag);
+                }
+            }
+        }
+    }
 }
 
 pub struct DeadLetterQueue {
     entries: Arc<Mutex<HashMap<String, DLQEntry>>>,
+    /// Always locked after `entries`
+    changes: Arc<Mutex<ChangeLog>>,
+    /// Always locked after `entries`
+    index: Arc<Mutex<EntryIndex>>,
+    enricher: Arc<dyn EntryEnricher>,
+    budget: Arc<PayloadBudget>,
+    /// Per-PSP caps on entry count, on top of the payload budget. Always
+    /// locked after `entries`.
+    quotas: Arc<Mutex<HashMap<String, PspQuota>>>,
+    /// Reprocessing leases by transaction ID, expired ones included until the
+    /// next `lease_next`. Always locked after `entries`.
+    leases: Arc<Mutex<HashMap<String, DlqLease>>>,
+    on_expire: Option<ExpiryHook>,
+    /// Where `flush` puts the queue; `None` keeps it in memory only
+    on_flush: Option<FlushHook>,
 }
 
 impl DeadLetterQueue {
     pub fn new() -> Self {
+        Self::with_enricher(Arc::new(NoopEnricher))
+    }
+
+    pub fn with_enricher(enricher: Arc<dyn EntryEnricher>) -> Self {
         Self {
             entries: Arc::new(Mutex::new(HashMap::new())),
+            changes: Arc::new(Mutex::new(ChangeLog::default())),
+            index: Arc::new(Mutex::new(EntryIndex::default())),
+            enricher,
+            budget: Arc::new(PayloadBudget::unlimited()),
+            quotas: Arc::new(Mutex::new(HashMap::new())),
+            leases: Arc::new(Mutex::new(HashMap::new())),
+            on_expire: None,
+            on_flush: None,
         }
     }
 
-    /// Add an entry to the DLQ
+    /// Have `purge_expired` pass each entry to `hook` before removing it
+    pub fn on_expire(mut self, hook: ExpiryHook) -> Self {
+        self.set_on_expire(hook);
+        self
+    }
+
+    pub fn set_on_expire(&mut self, hook: ExpiryHook) {
+        self.on_expire = Some(hook);
+    }
+
+    /// Have `flush` persist the queue with `hook`, or with `None` make it a
+    /// no-op for a queue kept in memory only
+    pub fn set_on_flush(&mut self, hook: Option<FlushHook>) {
+        self.on_flush = hook;
+    }
+
+    /// Durability barrier: return once every change made before the call is
+    /// on disk and fsynced, and whether there was a disk to put it on. In
+    /// memory only it succeeds straight away with false. The writes and fsync
+    /// run on a blocking task, so the runtime isn't held up by a slow disk.
+    pub async fn flush(self: &Arc<Self>) -> Result<bool, PersistenceError> {
+        let Some(hook) = self.on_flush.clone() else {
+            return Ok(false);
+        };
+        let dlq = self.clone();
+        tokio::task::spawn_blocking(move || hook(&dlq))
+            .await
+            .map_err(|e| PersistenceError::from(std::io::Error::other(e)))?
+    }
+
+    /// Bound the total payload bytes held by this queue and anything else
+    /// reserving through `reserve_payload`
+    pub fn with_payload_budget(mut self, budget: Arc<PayloadBudget>) -> Self {
+        self.set_payload_budget(budget);
+        self
+    }
+
+    /// Switch to `budget`, carrying over the bytes already held so entries
+    /// and reservations made under the old one are still counted
+    pub fn set_payload_budget(&mut self, budget: Arc<PayloadBudget>) {
+        budget.force_reserve(self.budget.used_bytes());
+        self.budget = budget;
+    }
+
+    pub fn payload_budget(&self) -> &PayloadBudget {
+        &self.budget
+    }
+
+    /// Cap the entries held for `psp_name`, or lift its cap with `None`
+    ///
+    /// Entries already over a new quota stay; it applies to the next add.
+    pub fn set_psp_quota(&self, psp_name: &str, quota: Option<PspQuota>) {
+        let mut quotas = self.quotas.lock().unwrap();
+        match quota {
+            Some(quota) => quotas.insert(psp_name.to_string(), quota),
+            None => quotas.remove(psp_name),
+        };
+    }
+
+    pub fn psp_quota(&self, psp_name: &str) -> Option<PspQuota> {
+        self.quotas.lock().unwrap().get(psp_name).copied()
+    }
+
+    /// How many entries the DLQ holds for `psp_name`
+    pub fn psp_count(&self, psp_name: &str) -> usize {
+        let index = self.index.lock().unwrap();
+        index.per_psp.get(psp_name).copied().unwrap_or(0)
+    }
+
+    /// Add an entry to the DLQ, logging it if the payload budget refuses it
     pub fn add_entry(&self, entry: DLQEntry) {
+        let transaction_id = entry.transaction_id.clone();
+        if let Err(e) = self.try_add_entry(entry) {
+            warn!("Dropped DLQ entry {}: {}", transaction_id, e);
+        }
+    }
+
+    /// Add an entry to the DLQ, applying the payload budget's overflow policy
+    ///
+    /// Enrichment failures are logged and the entry is stored without the
+    /// extra metadata rather than being dropped.
+    pub fn try_add_entry(&self, mut entry: DLQEntry) -> Result<(), DlqError> {
+        self.enrich(&mut entry);
         let mut entries = self.entries.lock().unwrap();
+        self.add_locked(&mut entries, entry)
+    }
+
+    /// Add a batch of entries under a single lock, returning the ones the
+    /// payload budget refused
+    ///
+    /// Entries are added in order, like repeated `try_add_entry` calls. When
+    /// the batch holds the same transaction more than once only the last one
+    /// is added, so earlier copies never take budget or a revision.
+    pub fn add_entries(&self, batch: Vec<DLQEntry>) -> Vec<(String, DlqError)> {
+        let mut last_index = HashMap::new();
+        for (index, entry) in batch.iter().enumerate() {
+            last_index.insert(entry.transaction_id.clone(), index);
+        }
+        let mut batch: Vec<DLQEntry> = batch
+            .into_iter()
+            .enumerate()
+            .filter(|(index, entry)| last_index[&entry.transaction_id] == *index)
+            .map(|(_, entry)| entry)
+            .collect();
+        for entry in &mut batch {
+            self.enrich(entry);
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        let mut refused = Vec::new();
+        for entry in batch {
+            let transaction_id = entry.transaction_id.clone();
+            if let Err(e) = self.add_locked(&mut entries, entry) {
+                refused.push((transaction_id, e));
+            }
+        }
+        refused
+    }
+
+    /// Enrichment failures are logged and the entry is kept as is
+    fn enrich(&self, entry: &mut DLQEntry) {
+        match self.enricher.enrich(entry) {
+            Ok(metadata) => entry.metadata.extend(metadata),
+            Err(e) => warn!("Failed to enrich DLQ entry {}: {}", entry.transaction_id, e),
+        }
+    }
+
+    fn add_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        mut entry: DLQEntry,
+    ) -> Result<(), DlqError> {
+        entry.fill_default_content_type();
+        let previous = entries.remove(&entry.transaction_id);
+        if let Some(previous) = &previous {
+            self.budget.release(payload_size(previous));
+            self.index.lock().unwrap().remove(previous);
+            // Dead-lettered again after a replay
+            if previous.replaying {
+                entry.inherit_failures(previous);
+            }
+        }
+
+        // Evictions only stand if the entry is admitted in the end
+        let mut evicted = Vec::new();
+        let admitted = self
+            .make_room_in_quota(entries, &entry.psp_name, &mut evicted)
+            .and_then(|()| self.reserve_locked(entries, payload_size(&entry), &mut evicted));
+        if let Err(e) = admitted {
+            for (evicted, _) in evicted {
+                self.budget.force_reserve(payload_size(&evicted));
+                self.index.lock().unwrap().insert(&evicted);
+                entries.insert(evicted.transaction_id.clone(), evicted);
+            }
+            if let Some(previous) = previous {
+                self.budget.force_reserve(payload_size(&previous));
+                self.index.lock().unwrap().insert(&previous);
+                entries.insert(previous.transaction_id.clone(), previous);
+            }
+            return Err(e);
+        }
+
+        for (evicted, reason) in evicted {
+            warn!(
+                "Evicted DLQ entry {} to stay within {}",
+                evicted.transaction_id, reason
+            );
+            self.changes
+                .lock()
+                .unwrap()
+                .record_removal(&evicted.transaction_id);
+            // A later entry for the transaction starts out unleased
+            self.leases.lock().unwrap().remove(&evicted.transaction_id);
+        }
+        entry.last_modified_revision = self.changes.lock().unwrap().bump();
+        self.index.lock().unwrap().insert(&entry);
         entries.insert(entry.transaction_id.clone(), entry);
+        Ok(())
+    }
+
+    /// Apply the PSP's quota to a new entry for it: evict its oldest entries
+    /// into `evicted` until one more fits, or refuse
+    fn make_room_in_quota(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        psp_name: &str,
+        evicted: &mut Vec<(DLQEntry, String)>,
+    ) -> Result<(), DlqError> {
+        let Some(quota) = self.psp_quota(psp_name) else {
+            return Ok(());
+        };
+        let exceeded = DlqError::PspQuotaExceeded {
+            psp_name: psp_name.to_string(),
+            max_entries: quota.max_entries,
+        };
+
+        while self
+            .index
+            .lock()
+            .unwrap()
+            .per_psp
+            .get(psp_name)
+            .copied()
+            .unwrap_or(0)
+            >= quota.max_entries
+        {
+            if quota.overflow_policy == OverflowPolicy::Reject {
+                return Err(exceeded);
+            }
+            let reason = format!("the {} quota", psp_name);
+            if !self.evict_oldest_locked(entries, &reason, evicted, |entry| {
+                entry.psp_name == psp_name
+            }) {
+                return Err(exceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the oldest entry `eligible` accepts, not parked or being replayed,
+    /// out of the queue and into `evicted`, returning false if there is none.
+    /// The caller either records the removal or puts the entry back.
+    fn evict_oldest_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        reason: &str,
+        evicted: &mut Vec<(DLQEntry, String)>,
+        eligible: impl Fn(&DLQEntry) -> bool,
+    ) -> bool {
+        let oldest = entries
+            .values()
+            .filter(|entry| !entry.is_parked() && !entry.replaying && eligible(entry))
+            .min_by_key(|entry| entry.timestamp_ms)
+            .map(|entry| entry.transaction_id.clone());
+        match oldest.and_then(|id| entries.remove(&id)) {
+            Some(entry) => {
+                self.budget.release(payload_size(&entry));
+                self.index.lock().unwrap().remove(&entry);
+                evicted.push((entry, reason.to_string()));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reserve payload bytes held outside the DLQ (e.g. by retry states)
+    ///
+    /// Bytes that don't fit are refused whatever the overflow policy: dead
+    /// letters are never evicted to make room for anything but other dead
+    /// letters.
+    pub fn reserve_payload(&self, bytes: u64) -> Result<(), DlqError> {
+        if self.budget.try_reserve(bytes) {
+            Ok(())
+        } else {
+            Err(DlqError::PayloadBudgetExceeded {
+                requested_bytes: bytes,
+                max_bytes: self.budget.max_bytes,
+            })
+        }
+    }
+
+    /// Release bytes previously taken with `reserve_payload`
+    pub fn release_payload(&self, bytes: u64) {
+        self.budget.release(bytes);
+    }
+
+    /// Reserve `bytes` for a new entry, evicting into `evicted` if the policy
+    /// allows
+    fn reserve_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        bytes: u64,
+        evicted: &mut Vec<(DLQEntry, String)>,
+    ) -> Result<(), DlqError> {
+        let exceeded = DlqError::PayloadBudgetExceeded {
+            requested_bytes: bytes,
+            max_bytes: self.budget.max_bytes,
+        };
+        if bytes > self.budget.max_bytes {
+            return Err(exceeded);
+        }
+
+        while !self.budget.try_reserve(bytes) {
+            if self.budget.overflow_policy == OverflowPolicy::Reject {
+                return Err(exceeded);
+            }
+
+            if !self.evict_oldest_locked(entries, "the payload budget", evicted, |_| true) {
+                return Err(exceeded);
+            }
+        }
+        Ok(())
     }
 
     /// Check if a transaction is in the DLQ
@@ -41,6 +901,123 @@ impl DeadLetterQueue {
         entries.get(transaction_id).cloned()
     }
 
+    /// Modify an entry in place, returning the updated copy
+    pub fn update_entry(
+        &self,
+        transaction_id: &str,
+        update: impl FnOnce(&mut DLQEntry),
+    ) -> Option<DLQEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        self.update_locked(&mut entries, transaction_id, update)
+    }
+
+    /// `update_entry` for a caller holding `lease_id` ("" for none): an entry
+    /// leased to anyone else is left alone
+    pub fn update_leased_entry(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+        update: impl FnOnce(&mut DLQEntry),
+    ) -> Result<DLQEntry, DlqError> {
+        let mut entries = self.entries.lock().unwrap();
+        self.check_lease_locked(transaction_id, lease_id, now_ms)?;
+        self.update_locked(&mut entries, transaction_id, update)
+            .ok_or_else(|| DlqError::NotFound(transaction_id.to_string()))
+    }
+
+    fn update_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        transaction_id: &str,
+        update: impl FnOnce(&mut DLQEntry),
+    ) -> Option<DLQEntry> {
+        let entry = entries.get_mut(transaction_id)?;
+        let before = payload_size(entry);
+        let mut index = self.index.lock().unwrap();
+        index.remove(entry);
+        update(entry);
+        index.insert(entry);
+        // Keep the byte accounting right if the payload was swapped
+        self.budget.release(before);
+        self.budget.force_reserve(payload_size(entry));
+        entry.last_modified_revision = self.changes.lock().unwrap().bump();
+        Some(entry.clone())
+    }
+
+    /// Move an entry to a new triage status, enforcing valid transitions
+    pub fn update_status(
+        &self,
+        transaction_id: &str,
+        status: DlqEntryStatus,
+    ) -> Result<DLQEntry, DlqError> {
+        let mut entries = self.entries.lock().unwrap();
+        self.update_status_locked(&mut entries, transaction_id, status)
+    }
+
+    /// `update_status` for a caller holding `lease_id` ("" for none): an
+    /// entry leased to anyone else is left alone
+    pub fn update_leased_status(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+        status: DlqEntryStatus,
+    ) -> Result<DLQEntry, DlqError> {
+        let mut entries = self.entries.lock().unwrap();
+        self.check_lease_locked(transaction_id, lease_id, now_ms)?;
+        self.update_status_locked(&mut entries, transaction_id, status)
+    }
+
+    fn update_status_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        transaction_id: &str,
+        status: DlqEntryStatus,
+    ) -> Result<DLQEntry, DlqError> {
+        let entry = entries
+            .get_mut(transaction_id)
+            .ok_or_else(|| DlqError::NotFound(transaction_id.to_string()))?;
+        if !entry.status.can_transition_to(status) {
+            return Err(DlqError::InvalidTransition {
+                from: entry.status,
+                to: status,
+            });
+        }
+        if entry.status != status {
+            entry.status = status;
+            entry.last_modified_revision = self.changes.lock().unwrap().bump();
+        }
+        Ok(entry.clone())
+    }
+
+    /// Park an entry that needs a manual data fix, with a note on what's wrong
+    pub fn park(&self, transaction_id: &str, note: &str) -> Result<DLQEntry, DlqError> {
+        self.update_entry(transaction_id, |entry| {
+            entry.parked_note = Some(note.to_string())
+        })
+        .ok_or_else(|| DlqError::NotFound(transaction_id.to_string()))
+    }
+
+    /// Return a parked entry to normal handling
+    pub fn unpark(&self, transaction_id: &str) -> Result<DLQEntry, DlqError> {
+        self.update_entry(transaction_id, |entry| entry.parked_note = None)
+            .ok_or_else(|| DlqError::NotFound(transaction_id.to_string()))
+    }
+
+    /// Get the entries carrying `tag`, via the tag index
+    pub fn entries_with_tag(&self, tag: &str) -> Vec<DLQEntry> {
+        let entries = self.entries.lock().unwrap();
+        let index = self.index.lock().unwrap();
+        index
+            .by_tag
+            .get(tag)
+            .into_iter()
+            .flatten()
+            .filter_map(|transaction_id| entries.get(transaction_id).cloned())
+            .collect()
+    }
+
     /// Get all entries
     pub fn get_all_entries(&self) -> Vec<DLQEntry> {
         let entries = self.entries.lock().unwrap();
@@ -50,7 +1027,273 @@ impl DeadLetterQueue {
     /// Remove an entry from the DLQ
     pub fn remove_entry(&self, transaction_id: &str) -> Option<DLQEntry> {
         let mut entries = self.entries.lock().unwrap();
-        entries.remove(transaction_id)
+        self.remove_locked(&mut entries, transaction_id)
+    }
+
+    /// `remove_entry` for a caller holding `lease_id` ("" for none): an entry
+    /// leased to anyone else is kept
+    pub fn remove_leased_entry(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+    ) -> Result<DLQEntry, DlqError> {
+        let mut entries = self.entries.lock().unwrap();
+        self.check_lease_locked(transaction_id, lease_id, now_ms)?;
+        self.remove_locked(&mut entries, transaction_id)
+            .ok_or_else(|| DlqError::NotFound(transaction_id.to_string()))
+    }
+
+    fn remove_locked(
+        &self,
+        entries: &mut HashMap<String, DLQEntry>,
+        transaction_id: &str,
+    ) -> Option<DLQEntry> {
+        let removed = entries.remove(transaction_id);
+        if let Some(entry) = &removed {
+            self.budget.release(payload_size(entry));
+            self.index.lock().unwrap().remove(entry);
+            self.changes.lock().unwrap().record_removal(transaction_id);
+            // A later entry for the transaction starts out unleased
+            self.leases.lock().unwrap().remove(transaction_id);
+        }
+        removed
+    }
+
+    /// Remove the entries dead-lettered more than `max_age_ms` before
+    /// `now_ms`, returning them ordered by transaction ID. Parked entries,
+    /// leased ones and ones being replayed are kept.
+    ///
+    /// The `on_expire` hook sees each entry first, outside the queue's locks.
+    /// An entry it fails on is kept and logged, as is one modified while the
+    /// hook ran, so nothing is dropped without having been handed over.
+    pub fn purge_expired(&self, max_age_ms: u64, now_ms: u64) -> Vec<DLQEntry> {
+        let cutoff_ms = now_ms.saturating_sub(max_age_ms);
+        let expired: Vec<DLQEntry> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.timestamp_ms < cutoff_ms)
+            .filter(|entry| !entry.is_parked() && !entry.replaying)
+            .filter(|entry| {
+                self.check_lease_locked(&entry.transaction_id, "", now_ms)
+                    .is_ok()
+            })
+            .cloned()
+            .collect();
+
+        let mut purged = Vec::new();
+        for entry in expired {
+            if let Some(hook) = &self.on_expire {
+                if let Err(e) = hook(&entry) {
+                    warn!(
+                        "Kept expired DLQ entry {}, on_expire failed: {}",
+                        entry.transaction_id, e
+                    );
+                    continue;
+                }
+            }
+            let mut entries = self.entries.lock().unwrap();
+            let unchanged = entries.get(&entry.transaction_id).is_some_and(|stored| {
+                stored.last_modified_revision == entry.last_modified_revision
+            });
+            if !unchanged
+                || self
+                    .check_lease_locked(&entry.transaction_id, "", now_ms)
+                    .is_err()
+            {
+                warn!(
+                    "Kept expired DLQ entry {}, modified or leased while being expired",
+                    entry.transaction_id
+                );
+                continue;
+            }
+            purged.extend(self.remove_locked(&mut entries, &entry.transaction_id));
+        }
+        purged.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+        purged
+    }
+
+    /// Current revision, bumped on every add, modification and removal
+    pub fn revision(&self) -> u64 {
+        self.changes.lock().unwrap().revision
+    }
+
+    /// Diff the queue against the state it had at `revision`
+    ///
+    /// A revision ahead of the queue's (e.g. from before a restart that
+    /// renumbered revisions) or older than the retained removal history can't
+    /// be diffed, so the whole queue is returned with `reset` set.
+    pub fn changes_since(&self, revision: u64) -> DlqChanges {
+        let entries = self.entries.lock().unwrap();
+        let changes = self.changes.lock().unwrap();
+
+        if revision > changes.revision || revision < changes.compacted_through {
+            return DlqChanges {
+                added: entries.values().cloned().collect(),
+                removed: Vec::new(),
+                revision: changes.revision,
+                reset: true,
+            };
+        }
+
+        let added = entries
+            .values()
+            .filter(|entry| entry.last_modified_revision > revision)
+            .cloned()
+            .collect();
+        let mut seen = HashSet::new();
+        let removed = changes
+            .tombstones
+            .iter()
+            .filter(|(removed_at, id)| *removed_at > revision && !entries.contains_key(id))
+            .filter(|(_, id)| seen.insert(id.as_str()))
+            .map(|(_, id)| id.clone())
+            .collect();
+
+        DlqChanges {
+            added,
+            removed,
+            revision: changes.revision,
+            reset: false,
+        }
+    }
+
+    /// Per-PSP counts, ordered by PSP name
+    pub fn summary_by_psp(&self) -> BTreeMap<String, DlqPspSummary> {
+        let entries = self.entries.lock().unwrap();
+        let mut summary: BTreeMap<String, DlqPspSummary> = BTreeMap::new();
+        for entry in entries.values() {
+            let psp = summary.entry(entry.psp_name.clone()).or_default();
+            psp.entries += 1;
+            psp.parked += entry.is_parked() as usize;
+            psp.replaying += entry.replaying as usize;
+            psp.payload_bytes += payload_size(entry);
+        }
+        summary
+    }
+
+    /// Lease the next entry to reprocess until `now_ms + lease_ms`, or `None`
+    /// if every candidate is leased
+    ///
+    /// Candidates are the entries that are not parked, being replayed or in a
+    /// terminal status, optionally only for `psp_name`. The ones replayed the
+    /// fewest times go first, so entries that keep failing don't starve fresh
+    /// ones, then the oldest. A lease that expires puts its entry back among
+    /// the candidates.
+    pub fn lease_next(
+        &self,
+        psp_name: Option<&str>,
+        now_ms: u64,
+        lease_ms: u64,
+    ) -> Option<(DLQEntry, DlqLease)> {
+        let entries = self.entries.lock().unwrap();
+        let mut leases = self.leases.lock().unwrap();
+        leases.retain(|transaction_id, lease| {
+            lease.expires_at_ms > now_ms && entries.contains_key(transaction_id)
+        });
+
+        let entry = entries
+            .values()
+            .filter(|entry| psp_name.is_none_or(|psp_name| entry.psp_name == psp_name))
+            .filter(|entry| !entry.is_parked() && !entry.replaying && !entry.status.is_terminal())
+            .filter(|entry| !leases.contains_key(&entry.transaction_id))
+            .min_by(|a, b| {
+                (a.replay_count, a.timestamp_ms, &a.transaction_id).cmp(&(
+                    b.replay_count,
+                    b.timestamp_ms,
+                    &b.transaction_id,
+                ))
+            })?
+            .clone();
+        let lease = DlqLease {
+            lease_id: uuid::Uuid::new_v4().to_string(),
+            expires_at_ms: now_ms.saturating_add(lease_ms),
+        };
+        leases.insert(entry.transaction_id.clone(), lease.clone());
+        Some((entry, lease))
+    }
+
+    /// The unexpired lease on an entry, if any
+    pub fn lease(&self, transaction_id: &str, now_ms: u64) -> Option<DlqLease> {
+        let leases = self.leases.lock().unwrap();
+        leases
+            .get(transaction_id)
+            .filter(|lease| lease.expires_at_ms > now_ms)
+            .cloned()
+    }
+
+    /// Refuse a change to an entry someone other than `lease_id`'s holder
+    /// has leased; "" holds no lease
+    pub fn check_lease(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+    ) -> Result<(), DlqError> {
+        let _entries = self.entries.lock().unwrap();
+        self.check_lease_locked(transaction_id, lease_id, now_ms)
+    }
+
+    fn check_lease_locked(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+    ) -> Result<(), DlqError> {
+        let leases = self.leases.lock().unwrap();
+        match leases.get(transaction_id) {
+            Some(lease) if lease.expires_at_ms > now_ms && lease.lease_id != lease_id => {
+                Err(DlqError::Leased {
+                    transaction_id: transaction_id.to_string(),
+                    expires_at_ms: lease.expires_at_ms,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// End the unexpired lease `lease_id` on an entry, putting the entry back
+    /// among the `lease_next` candidates
+    pub fn release_lease(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+    ) -> Result<DlqLease, DlqError> {
+        let _entries = self.entries.lock().unwrap();
+        let mut leases = self.leases.lock().unwrap();
+        match leases.get(transaction_id) {
+            Some(lease) if lease.expires_at_ms > now_ms && lease.lease_id == lease_id => {
+                Ok(leases.remove(transaction_id).unwrap())
+            }
+            _ => Err(DlqError::LeaseNotHeld(transaction_id.to_string())),
+        }
+    }
+
+    /// Move a leased entry to `status` on behalf of the holder of the
+    /// unexpired lease `lease_id`, ending the lease
+    pub fn complete_lease(
+        &self,
+        transaction_id: &str,
+        lease_id: &str,
+        now_ms: u64,
+        status: DlqEntryStatus,
+    ) -> Result<DLQEntry, DlqError> {
+        let mut entries = self.entries.lock().unwrap();
+        let held = self
+            .leases
+            .lock()
+            .unwrap()
+            .get(transaction_id)
+            .is_some_and(|lease| lease.expires_at_ms > now_ms && lease.lease_id == lease_id);
+        if !held {
+            return Err(DlqError::LeaseNotHeld(transaction_id.to_string()));
+        }
+        let entry = self.update_status_locked(&mut entries, transaction_id, status)?;
+        self.leases.lock().unwrap().remove(transaction_id);
+        Ok(entry)
     }
 
     /// Get the count of entries
@@ -58,6 +1301,61 @@ impl DeadLetterQueue {
         let entries = self.entries.lock().unwrap();
         entries.len()
     }
+
+    /// Write every entry to the store as a single snapshot
+    pub fn save_to(&self, store: &DlqStore) -> Result<(), PersistenceError> {
+        let entries = self.get_all_entries();
+        store.save(&entries)
+    }
+
+    /// Store an entry exactly as given, e.g. when rebuilding from an event log
+    ///
+    /// Like `load_from`, this bypasses the enricher and the payload ceiling,
+    /// and keeps the entry's revision.
+    pub fn restore_entry(&self, entry: DLQEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        self.restore_locked(&mut entries, entry);
+    }
+
+    fn restore_locked(&self, entries: &mut HashMap<String, DLQEntry>, mut entry: DLQEntry) {
+        // Persisted before payloads had a content type
+        entry.fill_default_content_type();
+        let mut changes = self.changes.lock().unwrap();
+        changes.revision = changes.revision.max(entry.last_modified_revision);
+        let mut index = self.index.lock().unwrap();
+        if let Some(previous) = entries.remove(&entry.transaction_id) {
+            self.budget.release(payload_size(&previous));
+            index.remove(&previous);
+        }
+        self.budget.force_reserve(payload_size(&entry));
+        index.insert(&entry);
+        entries.insert(entry.transaction_id.clone(), entry);
+    }
+
+    /// Restore persisted entries, returning how many were loaded
+    ///
+    /// Loaded entries were already enriched and admitted when first added, so
+    /// they bypass the enricher and count against the payload budget even if
+    /// that takes it over the ceiling. Revisions continue from the highest one
+    /// loaded, but removals before the load aren't known, so callers diffing
+    /// from an earlier revision get a reset.
+    pub fn load_from(&self, store: &DlqStore) -> Result<usize, PersistenceError> {
+        Ok(self.restore_all(store.load()?))
+    }
+
+    /// Restore recovered entries the way `load_from` does, returning how many
+    /// there were
+    pub fn restore_all(&self, loaded: Vec<DLQEntry>) -> usize {
+        let count = loaded.len();
+        let mut entries = self.entries.lock().unwrap();
+        for entry in loaded {
+            self.restore_locked(&mut entries, entry);
+        }
+        let mut changes = self.changes.lock().unwrap();
+        let revision = changes.bump();
+        changes.compacted_through = revision;
+        count
+    }
 }
 
 impl Default for DeadLetterQueue {
@@ -80,6 +1378,7 @@ mod tests {
             attempt_count: 5,
             last_error: "Connection timeout".to_string(),
             timestamp_ms: 1000,
+            ..Default::default()
         };
 
         dlq.add_entry(entry.clone());
@@ -100,6 +1399,7 @@ mod tests {
             attempt_count: 3,
             last_error: "PSP error".to_string(),
             timestamp_ms: 2000,
+            ..Default::default()
         };
 
         dlq.add_entry(entry);
@@ -110,4 +1410,616 @@ mod tests {
         assert_eq!(dlq.count(), 0);
         assert!(!dlq.contains("txn_456"));
     }
+
+    struct SeverityEnricher;
+
+    impl EntryEnricher for SeverityEnricher {
+        fn enrich(&self, entry: &DLQEntry) -> Result<HashMap<String, String>, String> {
+            let severity = if entry.attempt_count >= 5 {
+                "high"
+            } else {
+                "low"
+            };
+            Ok(HashMap::from([(
+                "severity".to_string(),
+                severity.to_string(),
+            )]))
+        }
+    }
+
+    struct FailingEnricher;
+
+    impl EntryEnricher for FailingEnricher {
+        fn enrich(&self, _entry: &DLQEntry) -> Result<HashMap<String, String>, String> {
+            Err("owner lookup unavailable".to_string())
+        }
+    }
+
+    #[test]
+    fn test_enricher_stamps_metadata() {
+        let dlq = DeadLetterQueue::with_enricher(Arc::new(SeverityEnricher));
+        dlq.add_entry(DLQEntry {
+            transaction_id: "txn_789".to_string(),
+            psp_name: "stripe".to_string(),
+            attempt_count: 5,
+            ..Default::default()
+        });
+
+        let stored = dlq.get_entry("txn_789").unwrap();
+        assert_eq!(stored.metadata.get("severity"), Some(&"high".to_string()));
+    }
+
+    #[test]
+    fn test_enricher_failure_is_not_fatal() {
+        let dlq = DeadLetterQueue::with_enricher(Arc::new(FailingEnricher));
+        dlq.add_entry(DLQEntry {
+            transaction_id: "txn_999".to_string(),
+            psp_name: "stripe".to_string(),
+            ..Default::default()
+        });
+
+        let stored = dlq.get_entry("txn_999").unwrap();
+        assert!(stored.metadata.is_empty());
+    }
+
+    fn entry_with_payload(transaction_id: &str, bytes: usize, timestamp_ms: u64) -> DLQEntry {
+        DLQEntry {
+            transaction_id: transaction_id.to_string(),
+            psp_name: "stripe".to_string(),
+            payload: vec![0; bytes],
+            timestamp_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_payload_budget_rejects_over_ceiling() {
+        let budget = Arc::new(PayloadBudget::new(100, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+
+        for i in 0..4 {
+            dlq.try_add_entry(entry_with_payload(&format!("txn_{}", i), 25, i))
+                .unwrap();
+        }
+        assert_eq!(budget.used_bytes(), 100);
+
+        let result = dlq.try_add_entry(entry_with_payload("txn_4", 1, 4));
+        assert!(matches!(
+            result,
+            Err(DlqError::PayloadBudgetExceeded { .. })
+        ));
+        assert_eq!(dlq.count(), 4);
+
+        // Removing an entry frees its bytes
+        dlq.remove_entry("txn_0");
+        assert_eq!(budget.used_bytes(), 75);
+        dlq.try_add_entry(entry_with_payload("txn_4", 25, 4))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_payload_budget_evicts_oldest() {
+        let budget = Arc::new(PayloadBudget::new(100, OverflowPolicy::EvictOldest));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+
+        for i in 0..4 {
+            dlq.try_add_entry(entry_with_payload(&format!("txn_{}", i), 25, i))
+                .unwrap();
+        }
+        dlq.try_add_entry(entry_with_payload("txn_4", 40, 4))
+            .unwrap();
+
+        assert!(!dlq.contains("txn_0"));
+        assert!(!dlq.contains("txn_1"));
+        assert!(dlq.contains("txn_2"));
+        assert!(dlq.contains("txn_4"));
+        assert_eq!(budget.used_bytes(), 90);
+
+        // Reservations from outside the queue never evict dead letters
+        assert!(matches!(
+            dlq.reserve_payload(20),
+            Err(DlqError::PayloadBudgetExceeded { .. })
+        ));
+        assert_eq!(dlq.count(), 3);
+        dlq.reserve_payload(10).unwrap();
+        assert_eq!(budget.used_bytes(), 100);
+    }
+
+    #[test]
+    fn test_new_payload_budget_keeps_held_bytes() {
+        let mut dlq = DeadLetterQueue::new();
+        dlq.set_psp_quota(
+            "stripe",
+            Some(PspQuota {
+                max_entries: 5,
+                overflow_policy: OverflowPolicy::Reject,
+            }),
+        );
+        dlq.try_add_entry(entry_with_payload("txn_1", 30, 1))
+            .unwrap();
+
+        let budget = Arc::new(PayloadBudget::new(50, OverflowPolicy::Reject));
+        dlq.set_payload_budget(budget.clone());
+        assert_eq!(budget.used_bytes(), 30);
+        assert!(dlq.psp_quota("stripe").is_some());
+        assert!(dlq
+            .try_add_entry(entry_with_payload("txn_2", 30, 2))
+            .is_err());
+    }
+
+    #[test]
+    fn test_replacing_entry_reuses_its_bytes() {
+        let budget = Arc::new(PayloadBudget::new(50, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+
+        dlq.try_add_entry(entry_with_payload("txn_1", 40, 1))
+            .unwrap();
+        dlq.try_add_entry(entry_with_payload("txn_1", 45, 2))
+            .unwrap();
+        assert_eq!(budget.used_bytes(), 45);
+
+        // A rejected replacement keeps the original entry
+        assert!(dlq
+            .try_add_entry(entry_with_payload("txn_1", 60, 3))
+            .is_err());
+        assert_eq!(dlq.get_entry("txn_1").unwrap().payload.len(), 45);
+        assert_eq!(budget.used_bytes(), 45);
+    }
+
+    #[test]
+    fn test_update_entry() {
+        let budget = Arc::new(PayloadBudget::new(100, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1));
+
+        let updated = dlq
+            .update_entry("txn_1", |entry| {
+                entry.psp_name = "adyen".to_string();
+                entry.payload = vec![0; 20];
+            })
+            .unwrap();
+
+        assert_eq!(updated.psp_name, "adyen");
+        assert_eq!(dlq.get_entry("txn_1").unwrap().psp_name, "adyen");
+        assert_eq!(budget.used_bytes(), 20);
+        assert!(dlq.update_entry("missing", |_| {}).is_none());
+    }
+
+    #[test]
+    fn test_save_and_load_snapshot() {
+        use crate::persistence::SerializationFormat;
+
+        let path = std::env::temp_dir().join(format!("dlq-snapshot-{}", uuid::Uuid::new_v4()));
+        let store = DlqStore::new(&path, SerializationFormat::Bincode);
+
+        let dlq = DeadLetterQueue::new();
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1));
+        dlq.add_entry(entry_with_payload("txn_2", 20, 2));
+        dlq.save_to(&store).unwrap();
+
+        let budget = Arc::new(PayloadBudget::unlimited());
+        let restored = DeadLetterQueue::new().with_payload_budget(budget.clone());
+        assert_eq!(restored.load_from(&store).unwrap(), 2);
+        assert_eq!(restored.get_entry("txn_2").unwrap().payload.len(), 20);
+        assert_eq!(budget.used_bytes(), 30);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        let dlq = DeadLetterQueue::new();
+        dlq.add_entry(entry_with_payload("txn_1", 0, 1));
+        assert_eq!(dlq.get_entry("txn_1").unwrap().status, DlqEntryStatus::New);
+
+        dlq.update_status("txn_1", DlqEntryStatus::InReview)
+            .unwrap();
+        dlq.update_status("txn_1", DlqEntryStatus::InReview)
+            .unwrap();
+        let resolved = dlq
+            .update_status("txn_1", DlqEntryStatus::Resolved)
+            .unwrap();
+        assert_eq!(resolved.status, DlqEntryStatus::Resolved);
+
+        for rejected in [
+            DlqEntryStatus::New,
+            DlqEntryStatus::InReview,
+            DlqEntryStatus::Discarded,
+        ] {
+            assert_eq!(
+                dlq.update_status("txn_1", rejected).unwrap_err(),
+                DlqError::InvalidTransition {
+                    from: DlqEntryStatus::Resolved,
+                    to: rejected,
+                }
+            );
+        }
+        assert!(matches!(
+            dlq.update_status("missing", DlqEntryStatus::Resolved),
+            Err(DlqError::NotFound(_))
+        ));
+    }
+
+    fn sorted_ids(entries: &[DLQEntry]) -> Vec<&str> {
+        let mut ids: Vec<&str> = entries.iter().map(|e| e.transaction_id.as_str()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_changes_since_across_snapshots() {
+        let dlq = DeadLetterQueue::new();
+        dlq.add_entry(entry_with_payload("txn_1", 0, 1000));
+        dlq.add_entry(entry_with_payload("txn_2", 0, 1001));
+
+        let first = dlq.changes_since(0);
+        assert!(!first.reset);
+        assert_eq!(sorted_ids(&first.added), vec!["txn_1", "txn_2"]);
+        assert!(first.removed.is_empty());
+
+        dlq.remove_entry("txn_1");
+        dlq.add_entry(entry_with_payload("txn_3", 0, 1002));
+        dlq.update_status("txn_2", DlqEntryStatus::InReview)
+            .unwrap();
+        // Removed and re-added shows up only as added
+        dlq.add_entry(entry_with_payload("txn_4", 0, 1003));
+        dlq.remove_entry("txn_4");
+        dlq.add_entry(entry_with_payload("txn_4", 0, 1004));
+
+        let second = dlq.changes_since(first.revision);
+        assert!(!second.reset);
+        assert_eq!(sorted_ids(&second.added), vec!["txn_2", "txn_3", "txn_4"]);
+        assert_eq!(second.removed, vec!["txn_1".to_string()]);
+        assert_eq!(second.revision, dlq.revision());
+
+        let nothing = dlq.changes_since(second.revision);
+        assert!(nothing.added.is_empty() && nothing.removed.is_empty());
+    }
+
+    #[test]
+    fn test_changes_since_unknown_revision_resets() {
+        use crate::persistence::SerializationFormat;
+
+        let dlq = DeadLetterQueue::new();
+        dlq.add_entry(entry_with_payload("txn_1", 0, 1000));
+
+        // A revision from a previous numbering is ahead of this queue
+        let changes = dlq.changes_since(dlq.revision() + 100);
+        assert!(changes.reset);
+        assert_eq!(sorted_ids(&changes.added), vec!["txn_1"]);
+
+        // Removal history from before a load isn't known
+        let path = std::env::temp_dir().join(format!("dlq-revisions-{}", uuid::Uuid::new_v4()));
+        let store = DlqStore::new(&path, SerializationFormat::Json);
+        dlq.save_to(&store).unwrap();
+        let restored = DeadLetterQueue::new();
+        restored.load_from(&store).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert!(restored.revision() > dlq.revision());
+        assert!(restored.changes_since(dlq.revision()).reset);
+        assert!(!restored.changes_since(restored.revision()).reset);
+    }
+
+    #[test]
+    fn test_tag_index_follows_entries() {
+        let tagged = |id: &str, tags: &[&str]| DLQEntry {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            ..entry_with_payload(id, 0, 1000)
+        };
+        let dlq = DeadLetterQueue::new();
+        dlq.add_entry(tagged("txn_1", &["fraud-ring"]));
+        dlq.add_entry(tagged("txn_2", &["fraud-ring", "vip"]));
+        dlq.add_entry(tagged("txn_3", &[]));
+
+        assert_eq!(
+            sorted_ids(&dlq.entries_with_tag("fraud-ring")),
+            vec!["txn_1", "txn_2"]
+        );
+
+        dlq.remove_entry("txn_1");
+        dlq.update_entry("txn_2", |entry| entry.tags = vec!["vip".to_string()]);
+        dlq.add_entry(tagged("txn_3", &["fraud-ring"]));
+
+        assert_eq!(
+            sorted_ids(&dlq.entries_with_tag("fraud-ring")),
+            vec!["txn_3"]
+        );
+        assert_eq!(sorted_ids(&dlq.entries_with_tag("vip")), vec!["txn_2"]);
+        assert!(dlq.entries_with_tag("unknown").is_empty());
+    }
+
+    #[test]
+    fn test_eviction_skips_parked_entries() {
+        let dlq = DeadLetterQueue::new().with_payload_budget(Arc::new(PayloadBudget::new(
+            20,
+            OverflowPolicy::EvictOldest,
+        )));
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1000));
+        dlq.add_entry(entry_with_payload("txn_2", 10, 2000));
+        dlq.park("txn_1", "card number needs correcting").unwrap();
+
+        dlq.add_entry(entry_with_payload("txn_3", 10, 3000));
+        assert!(dlq.contains("txn_1"));
+        assert!(!dlq.contains("txn_2"));
+        assert!(dlq.contains("txn_3"));
+
+        dlq.unpark("txn_1").unwrap();
+        dlq.add_entry(entry_with_payload("txn_4", 10, 4000));
+        assert!(!dlq.contains("txn_1"));
+        assert!(matches!(
+            dlq.park("txn_1", "gone"),
+            Err(DlqError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_eviction_skips_entries_being_replayed() {
+        let dlq = DeadLetterQueue::new().with_payload_budget(Arc::new(PayloadBudget::new(
+            20,
+            OverflowPolicy::EvictOldest,
+        )));
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1000));
+        dlq.add_entry(entry_with_payload("txn_2", 10, 2000));
+        dlq.update_entry("txn_1", |entry| entry.replaying = true);
+
+        dlq.add_entry(entry_with_payload("txn_3", 10, 3000));
+        assert!(dlq.contains("txn_1"));
+        assert!(!dlq.contains("txn_2"));
+        assert!(dlq.contains("txn_3"));
+
+        // The same goes for a PSP's quota
+        dlq.set_psp_quota(
+            "stripe",
+            Some(PspQuota {
+                max_entries: 2,
+                overflow_policy: OverflowPolicy::EvictOldest,
+            }),
+        );
+        dlq.add_entry(entry_with_payload("txn_4", 0, 4000));
+        assert!(dlq.contains("txn_1"));
+        assert!(!dlq.contains("txn_3"));
+        assert!(dlq.contains("txn_4"));
+    }
+
+    #[test]
+    fn test_eviction_drops_the_evicted_entrys_lease() {
+        let dlq = DeadLetterQueue::new().with_payload_budget(Arc::new(PayloadBudget::new(
+            20,
+            OverflowPolicy::EvictOldest,
+        )));
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1000));
+        dlq.add_entry(entry_with_payload("txn_2", 10, 2000));
+        let (leased, lease) = dlq.lease_next(None, 5000, 60_000).unwrap();
+        assert_eq!(leased.transaction_id, "txn_1");
+
+        dlq.add_entry(entry_with_payload("txn_3", 10, 3000));
+        assert!(!dlq.contains("txn_1"));
+        assert_eq!(dlq.lease("txn_1", 5000), None);
+
+        // Dead-lettered again, the transaction isn't the old holder's
+        dlq.add_entry(entry_with_payload("txn_1", 10, 4000));
+        assert_eq!(dlq.lease("txn_1", 5000), None);
+        assert!(matches!(
+            dlq.complete_lease("txn_1", &lease.lease_id, 5000, DlqEntryStatus::Resolved),
+            Err(DlqError::LeaseNotHeld(_))
+        ));
+        assert_eq!(dlq.get_entry("txn_1").unwrap().status, DlqEntryStatus::New);
+    }
+
+    #[tokio::test]
+    async fn test_flush_runs_the_flush_hook() {
+        let dlq = Arc::new(DeadLetterQueue::new());
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1000));
+        assert!(!dlq.flush().await.unwrap());
+
+        let flushed = Arc::new(Mutex::new(Vec::new()));
+        let mut dlq = DeadLetterQueue::new();
+        dlq.set_on_flush(Some({
+            let flushed = flushed.clone();
+            Arc::new(
+                move |dlq: &DeadLetterQueue| -> Result<bool, PersistenceError> {
+                    flushed.lock().unwrap().extend(dlq.get_all_entries());
+                    Ok(true)
+                },
+            )
+        }));
+        let dlq = Arc::new(dlq);
+        dlq.add_entry(entry_with_payload("txn_1", 10, 1000));
+        assert!(dlq.flush().await.unwrap());
+        assert_eq!(flushed.lock().unwrap().len(), 1);
+
+        let mut failing = DeadLetterQueue::new();
+        failing.set_on_flush(Some(Arc::new(
+            |_: &DeadLetterQueue| -> Result<bool, PersistenceError> {
+                Err(PersistenceError::Io(std::io::Error::other("disk full")))
+            },
+        )));
+        assert!(Arc::new(failing).flush().await.is_err());
+    }
+
+    #[test]
+    fn test_add_entries_last_duplicate_wins() {
+        let budget = Arc::new(PayloadBudget::new(100, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+
+        // The first txn_1 alone would leave no room for the rest of the batch
+        let refused = dlq.add_entries(vec![
+            entry_with_payload("txn_1", 90, 1),
+            entry_with_payload("txn_2", 20, 2),
+            entry_with_payload("txn_1", 30, 3),
+            entry_with_payload("txn_3", 60, 4),
+        ]);
+
+        assert_eq!(refused.len(), 1);
+        assert_eq!(refused[0].0, "txn_3");
+        assert_eq!(dlq.count(), 2);
+        let txn_1 = dlq.get_entry("txn_1").unwrap();
+        assert_eq!(txn_1.payload.len(), 30);
+        assert_eq!(txn_1.timestamp_ms, 3);
+        assert_eq!(budget.used_bytes(), 50);
+        assert_eq!(dlq.changes_since(0).added.len(), 2);
+    }
+
+    fn psp_entry(transaction_id: &str, psp_name: &str, timestamp_ms: u64) -> DLQEntry {
+        DLQEntry {
+            psp_name: psp_name.to_string(),
+            ..entry_with_payload(transaction_id, 10, timestamp_ms)
+        }
+    }
+
+    #[test]
+    fn test_psp_quota_leaves_other_psps_accepting() {
+        let budget = Arc::new(PayloadBudget::new(1_000, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+        dlq.set_psp_quota(
+            "noisy",
+            Some(PspQuota {
+                max_entries: 2,
+                overflow_policy: OverflowPolicy::Reject,
+            }),
+        );
+
+        dlq.try_add_entry(psp_entry("noisy_1", "noisy", 1)).unwrap();
+        dlq.try_add_entry(psp_entry("noisy_2", "noisy", 2)).unwrap();
+        assert_eq!(
+            dlq.try_add_entry(psp_entry("noisy_3", "noisy", 3)),
+            Err(DlqError::PspQuotaExceeded {
+                psp_name: "noisy".to_string(),
+                max_entries: 2,
+            })
+        );
+        // Re-adding a transaction already held doesn't need a new slot
+        dlq.try_add_entry(psp_entry("noisy_2", "noisy", 4)).unwrap();
+
+        for i in 0..5 {
+            dlq.try_add_entry(psp_entry(&format!("quiet_{}", i), "quiet", i))
+                .unwrap();
+        }
+        assert_eq!(dlq.psp_count("noisy"), 2);
+        assert_eq!(dlq.psp_count("quiet"), 5);
+        assert_eq!(budget.used_bytes(), 70);
+
+        // The global budget still applies on top of the quotas
+        let tight = DeadLetterQueue::new()
+            .with_payload_budget(Arc::new(PayloadBudget::new(15, OverflowPolicy::Reject)));
+        tight
+            .try_add_entry(psp_entry("quiet_1", "quiet", 1))
+            .unwrap();
+        assert!(matches!(
+            tight.try_add_entry(psp_entry("quiet_2", "quiet", 2)),
+            Err(DlqError::PayloadBudgetExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_psp_quota_evicts_only_that_psps_entries() {
+        let dlq = DeadLetterQueue::new();
+        dlq.set_psp_quota(
+            "noisy",
+            Some(PspQuota {
+                max_entries: 2,
+                overflow_policy: OverflowPolicy::EvictOldest,
+            }),
+        );
+        dlq.add_entry(psp_entry("quiet_old", "quiet", 0));
+        for i in 1..=3 {
+            dlq.try_add_entry(psp_entry(&format!("noisy_{}", i), "noisy", i))
+                .unwrap();
+        }
+
+        assert!(!dlq.contains("noisy_1"));
+        assert!(dlq.contains("noisy_3"));
+        assert!(dlq.contains("quiet_old"));
+        assert_eq!(dlq.psp_count("noisy"), 2);
+
+        // Rerouting an entry moves its slot to the new PSP
+        dlq.update_entry("noisy_3", |entry| entry.psp_name = "quiet".to_string());
+        assert_eq!(dlq.psp_count("noisy"), 1);
+        assert_eq!(dlq.psp_count("quiet"), 2);
+    }
+
+    #[test]
+    fn test_quota_evictions_are_undone_when_the_budget_refuses() {
+        let budget = Arc::new(PayloadBudget::new(25, OverflowPolicy::Reject));
+        let dlq = DeadLetterQueue::new().with_payload_budget(budget.clone());
+        dlq.set_psp_quota(
+            "noisy",
+            Some(PspQuota {
+                max_entries: 2,
+                overflow_policy: OverflowPolicy::EvictOldest,
+            }),
+        );
+        dlq.try_add_entry(psp_entry("noisy_1", "noisy", 1)).unwrap();
+        dlq.try_add_entry(psp_entry("noisy_2", "noisy", 2)).unwrap();
+        let revision = dlq.revision();
+
+        let oversized = DLQEntry {
+            psp_name: "noisy".to_string(),
+            ..entry_with_payload("noisy_3", 20, 3)
+        };
+        assert!(matches!(
+            dlq.try_add_entry(oversized),
+            Err(DlqError::PayloadBudgetExceeded { .. })
+        ));
+        assert!(dlq.contains("noisy_1"));
+        assert!(!dlq.contains("noisy_3"));
+        assert_eq!(dlq.psp_count("noisy"), 2);
+        assert_eq!(budget.used_bytes(), 20);
+        assert_eq!(dlq.revision(), revision);
+
+        // An entry that fits still evicts
+        dlq.try_add_entry(psp_entry("noisy_3", "noisy", 3)).unwrap();
+        assert!(!dlq.contains("noisy_1"));
+        assert_eq!(
+            dlq.changes_since(revision).removed,
+            vec!["noisy_1".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_purge_expired_hands_entries_to_on_expire_first() {
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let hook: ExpiryHook = {
+            let archived = archived.clone();
+            Arc::new(move |entry: &DLQEntry| {
+                if entry.transaction_id == "txn_unarchivable" {
+                    return Err("cold storage unavailable".to_string());
+                }
+                archived.lock().unwrap().push(entry.transaction_id.clone());
+                Ok(())
+            })
+        };
+        let dlq = DeadLetterQueue::new().on_expire(hook);
+        for (transaction_id, timestamp_ms) in [
+            ("txn_old_1", 1_000),
+            ("txn_old_2", 5_000),
+            ("txn_unarchivable", 2_000),
+            ("txn_parked", 1_000),
+            ("txn_recent", 50_000),
+        ] {
+            dlq.add_entry(DLQEntry {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                timestamp_ms,
+                ..Default::default()
+            });
+        }
+        dlq.park("txn_parked", "awaiting data fix").unwrap();
+
+        let purged: Vec<String> = dlq
+            .purge_expired(40_000, 60_000)
+            .into_iter()
+            .map(|entry| entry.transaction_id)
+            .collect();
+        assert_eq!(purged, vec!["txn_old_1", "txn_old_2"]);
+        let mut archived = archived.lock().unwrap().clone();
+        archived.sort();
+        assert_eq!(archived, purged);
+        // The entry the hook failed on wasn't lost
+        assert!(dlq.contains("txn_unarchivable"));
+        assert!(dlq.contains("txn_parked"));
+        assert!(dlq.contains("txn_recent"));
+        assert_eq!(dlq.count(), 3);
+    }
 }
@@ -0,0 +1,306 @@
+use crate::circuit_breaker::CircuitBreakerState;
+use crate::dlq::DLQEntry;
+use crate::{CircuitBreakerConfig, PspAccessPolicy, RetryConfig};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// A state-changing operation on the engine. Applying a log's events in order
+/// to a fresh engine rebuilds its breakers, retry states and DLQ.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EngineEvent {
+    /// A transaction's retry state was created or replaced
+    RetryStateStored {
+        transaction_id: String,
+        psp_name: String,
+        attempt_count: u32,
+        last_error: String,
+        last_attempt_at_ms: u64,
+        next_retry_at_ms: u64,
+        payload: Vec<u8>,
+        /// `payload` was left out when the event was written
+        #[serde(default)]
+        payload_stripped: bool,
+        #[serde(default)]
+        content_type: String,
+        #[serde(default)]
+        encoding: Option<String>,
+        tags: Vec<String>,
+        #[serde(default)]
+        first_scheduled_at_ms: u64,
+        #[serde(default)]
+        group_id: String,
+        #[serde(default)]
+        due_at_ms: u64,
+        #[serde(default)]
+        drift_ms: u64,
+    },
+    RetryStateRemoved {
+        transaction_id: String,
+    },
+    /// A PSP's breaker was created or changed state
+    CircuitStateChanged {
+        psp_name: String,
+        state: CircuitBreakerState,
+    },
+    CircuitConfigSet {
+        psp_name: String,
+        config: CircuitBreakerConfig,
+    },
+    /// A DLQ entry was added or modified
+    DlqEntryStored {
+        entry: DLQEntry,
+    },
+    DlqEntryRemoved {
+        transaction_id: String,
+    },
+    /// The DLQ's changes couldn't be diffed, so here is all of it
+    DlqSnapshot {
+        entries: Vec<DLQEntry>,
+    },
+    EnginePaused {
+        paused: bool,
+    },
+    RetryConfigSet {
+        config: RetryConfig,
+    },
+    PspAccessSet {
+        policy: PspAccessPolicy,
+    },
+}
+
+impl EngineEvent {
+    /// A copy with any payloads cleared and flagged as stripped
+    pub fn without_payloads(&self) -> Self {
+        let mut event = self.clone();
+        match &mut event {
+            EngineEvent::RetryStateStored {
+                payload,
+                payload_stripped,
+                ..
+            } if !payload.is_empty() => {
+                payload.clear();
+                *payload_stripped = true;
+            }
+            EngineEvent::DlqEntryStored { entry } => *entry = entry.without_payload(),
+            EngineEvent::DlqSnapshot { entries } => {
+                for entry in entries.iter_mut() {
+                    *entry = entry.without_payload();
+                }
+            }
+            _ => {}
+        }
+        event
+    }
+}
+
+/// Append-only sink for engine events
+pub trait EventLog: Send + Sync {
+    fn append(&self, event: &EngineEvent) -> io::Result<()>;
+    /// Every event appended so far, oldest first
+    fn events(&self) -> io::Result<Vec<EngineEvent>>;
+}
+
+/// Event log held in memory
+#[derive(Default)]
+pub struct InMemoryEventLog {
+    events: Mutex<Vec<EngineEvent>>,
+}
+
+impl InMemoryEventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl EventLog for InMemoryEventLog {
+    fn append(&self, event: &EngineEvent) -> io::Result<()> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+
+    fn events(&self) -> io::Result<Vec<EngineEvent>> {
+        Ok(self.events.lock().unwrap().clone())
+    }
+}
+
+/// Event log appended to a file, one JSON event per line
+pub struct FileEventLog {
+    path: PathBuf,
+    file: Mutex<File>,
+    persist_payloads: bool,
+}
+
+impl FileEventLog {
+    /// Open the log at `path`, creating it if missing and appending otherwise
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            persist_payloads: true,
+        })
+    }
+
+    /// Leave payloads out of what's written, so a replayed engine has to be
+    /// given them again
+    pub fn with_persist_payloads(mut self, persist_payloads: bool) -> Self {
+        self.persist_payloads = persist_payloads;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn persist_payloads(&self) -> bool {
+        self.persist_payloads
+    }
+}
+
+impl EventLog for FileEventLog {
+    fn append(&self, event: &EngineEvent) -> io::Result<()> {
+        let mut line = if self.persist_payloads {
+            serde_json::to_vec(event)?
+        } else {
+            serde_json::to_vec(&event.without_payloads())?
+        };
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.flush()
+    }
+
+    fn events(&self) -> io::Result<Vec<EngineEvent>> {
+        // Hold the append lock so a half-written line is never read
+        let _file = self.file.lock().unwrap();
+        BufReader::new(fs::File::open(&self.path)?)
+            .lines()
+            .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|line| {
+                serde_json::from_str(&line?)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_log_round_trip() {
+        let path = std::env::temp_dir().join(format!("event-log-{}", uuid::Uuid::new_v4()));
+        let events = vec![
+            EngineEvent::EnginePaused { paused: true },
+            EngineEvent::RetryStateRemoved {
+                transaction_id: "txn_1".to_string(),
+            },
+        ];
+
+        let log = FileEventLog::open(&path).unwrap();
+        for event in &events {
+            log.append(event).unwrap();
+        }
+        drop(log);
+
+        // Reopening appends rather than truncating
+        let log = FileEventLog::open(&path).unwrap();
+        log.append(&EngineEvent::EnginePaused { paused: false })
+            .unwrap();
+        let read = log.events().unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[..2], events[..]);
+
+        fs::remove_file(log.path()).unwrap();
+    }
+
+    #[test]
+    fn test_file_log_can_leave_out_payloads() {
+        let path = std::env::temp_dir().join(format!("event-log-{}", uuid::Uuid::new_v4()));
+        let log = FileEventLog::open(&path)
+            .unwrap()
+            .with_persist_payloads(false);
+        log.append(&EngineEvent::RetryStateStored {
+            transaction_id: "txn_1".to_string(),
+            psp_name: "stripe".to_string(),
+            attempt_count: 1,
+            last_error: "timeout".to_string(),
+            last_attempt_at_ms: 0,
+            next_retry_at_ms: 0,
+            payload: b"4111111111111111".to_vec(),
+            payload_stripped: false,
+            content_type: String::new(),
+            encoding: None,
+            tags: Vec::new(),
+            first_scheduled_at_ms: 0,
+            group_id: String::new(),
+            due_at_ms: 0,
+            drift_ms: 0,
+        })
+        .unwrap();
+        log.append(&EngineEvent::DlqEntryStored {
+            entry: DLQEntry {
+                transaction_id: "txn_2".to_string(),
+                payload: b"4111111111111111".to_vec(),
+                ..Default::default()
+            },
+        })
+        .unwrap();
+
+        let written = fs::read_to_string(log.path()).unwrap();
+        let card_bytes = serde_json::to_string(&b"4111111111111111".to_vec()).unwrap();
+        assert!(!written.contains(&card_bytes[1..card_bytes.len() - 1]));
+        let read = log.events().unwrap();
+        assert!(matches!(
+            &read[0],
+            EngineEvent::RetryStateStored { payload, payload_stripped: true, .. }
+                if payload.is_empty()
+        ));
+        assert!(matches!(
+            &read[1],
+            EngineEvent::DlqEntryStored { entry } if entry.payload.is_empty() && entry.payload_stripped
+        ));
+
+        fs::remove_file(log.path()).unwrap();
+    }
+
+    #[test]
+    fn test_retry_config_set_from_an_older_engine_loads() {
+        // Fields added to `RetryConfig` since `RetryConfigSet` was first logged
+        let added = [
+            "max_elapsed_ms",
+            "load_stretch",
+            "immediate_retries",
+            "max_jitter_ms",
+            "attempt_numbering",
+        ];
+        let mut event = serde_json::to_value(EngineEvent::RetryConfigSet {
+            config: RetryConfig::default(),
+        })
+        .unwrap();
+        let config = event["RetryConfigSet"]["config"].as_object_mut().unwrap();
+        for field in added {
+            config.remove(field);
+        }
+        let path = std::env::temp_dir().join(format!("event-log-{}", uuid::Uuid::new_v4()));
+        fs::write(&path, format!("{}\n", event)).unwrap();
+
+        let log = FileEventLog::open(&path).unwrap();
+        let read = log.events().unwrap();
+        let EngineEvent::RetryConfigSet { config } = &read[0] else {
+            panic!("expected RetryConfigSet, got {:?}", read[0]);
+        };
+        assert_eq!(config.max_elapsed_ms, 0);
+        assert_eq!(config.load_stretch, 0.0);
+        assert_eq!(config.immediate_retries, 0);
+        assert_eq!(config.max_jitter_ms, 0);
+        assert_eq!(config.attempt_numbering, crate::AttemptNumbering::Literal);
+
+        fs::remove_file(log.path()).unwrap();
+    }
+}
@@ -0,0 +1,342 @@
+use crate::clock::{Clock, ManualClock};
+use crate::server::retry::retry_engine_server::RetryEngine;
+use crate::server::retry::{
+    BulkReplayDlqRequest, BulkReplayDlqResponse, CancelRetryRequest, CancelRetryResponse,
+    CircuitRequest, CircuitResponse, DlqEntrySummary, ForceToDlqRequest, ListDlqEntriesRequest,
+    ListDlqEntriesResponse, ReplayDlqEntryRequest, ReplayDlqEntryResponse, ReportOutcomeRequest,
+    ReportOutcomeResponse, RetryRequest, RetryResponse, RetryStatusRequest, RetryStatusResponse,
+};
+use crate::server::RetryEngineService;
+use crate::{CircuitBreakerConfig, RetryConfig};
+use std::sync::Arc;
+use tonic::{Request, Response, Status};
+
+/// Where the simulated clock starts, so timestamps are plausible but fixed
+pub const HARNESS_START_MS: u64 = 1_700_000_000_000;
+
+/// In-process driver for end-to-end scenarios
+///
+/// Wraps a `RetryEngineService` on a `ManualClock` and calls its RPC handlers
+/// directly, so a scenario can run outage, recovery and replay steps without
+/// a server or client, moving simulated time between them. Circuit breakers,
+/// scheduled resets, retry due times, the DLQ and outcome timeouts all follow
+/// the simulated clock. Built for tests and with the `test-harness` feature.
+pub struct TestHarness {
+    service: RetryEngineService,
+    clock: Arc<ManualClock>,
+}
+
+impl TestHarness {
+    pub fn new(retry_config: RetryConfig, circuit_config: CircuitBreakerConfig) -> Self {
+        Self::with_service(|clock| {
+            RetryEngineService::new(retry_config, circuit_config).with_clock(clock)
+        })
+    }
+
+    /// A harness around a service built by `build`, which must apply the
+    /// clock it is given with `with_clock`
+    pub fn with_service(build: impl FnOnce(Arc<dyn Clock>) -> RetryEngineService) -> Self {
+        let clock = Arc::new(ManualClock::new(HARNESS_START_MS));
+        Self {
+            service: build(clock.clone()),
+            clock,
+        }
+    }
+
+    /// The service, for RPCs without a helper here
+    pub fn service(&self) -> &RetryEngineService {
+        &self.service
+    }
+
+    pub fn now_ms(&self) -> u64 {
+        self.clock.now_ms()
+    }
+
+    /// Move simulated time on, then run the background work that would have
+    /// come due, as the service's periodic tasks would
+    pub fn advance(&self, by_ms: u64) {
+        self.clock.advance(by_ms);
+        self.run_due_tasks();
+    }
+
+    /// Run due circuit resets, soft DLQ replays and outcome timeouts
+    pub fn run_due_tasks(&self) {
+        self.service.run_due_circuit_resets();
+        self.service.replay_due_soft_dlq();
+        self.service.expire_unreported_outcomes();
+    }
+
+    pub async fn schedule_retry(&self, request: RetryRequest) -> Result<RetryResponse, Status> {
+        self.service
+            .schedule_retry(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn report_outcome(
+        &self,
+        request: ReportOutcomeRequest,
+    ) -> Result<ReportOutcomeResponse, Status> {
+        self.service
+            .report_outcome(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn get_circuit_status(&self, psp_name: &str) -> Result<CircuitResponse, Status> {
+        let request = CircuitRequest {
+            psp_name: psp_name.to_string(),
+        };
+        self.service
+            .get_circuit_status(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn get_retry_status(
+        &self,
+        transaction_id: &str,
+    ) -> Result<RetryStatusResponse, Status> {
+        let request = RetryStatusRequest {
+            transaction_id: transaction_id.to_string(),
+        };
+        self.service
+            .get_retry_status(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn cancel_retry(
+        &self,
+        request: CancelRetryRequest,
+    ) -> Result<CancelRetryResponse, Status> {
+        self.service
+            .cancel_retry(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn force_to_dlq(
+        &self,
+        request: ForceToDlqRequest,
+    ) -> Result<DlqEntrySummary, Status> {
+        self.service
+            .force_to_dlq(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn list_dlq_entries(
+        &self,
+        request: ListDlqEntriesRequest,
+    ) -> Result<ListDlqEntriesResponse, Status> {
+        self.service
+            .list_dlq_entries(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn replay_dlq_entry(
+        &self,
+        request: ReplayDlqEntryRequest,
+    ) -> Result<ReplayDlqEntryResponse, Status> {
+        self.service
+            .replay_dlq_entry(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+
+    pub async fn bulk_replay_dlq(
+        &self,
+        request: BulkReplayDlqRequest,
+    ) -> Result<BulkReplayDlqResponse, Status> {
+        self.service
+            .bulk_replay_dlq(Request::new(request))
+            .await
+            .map(Response::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::retry::CircuitState as ProtoCircuitState;
+
+    fn retry(transaction_id: &str, attempt_number: i32) -> RetryRequest {
+        RetryRequest {
+            transaction_id: transaction_id.to_string(),
+            psp_name: "stripe".to_string(),
+            attempt_number,
+            ..Default::default()
+        }
+    }
+
+    fn outcome(transaction_id: &str, attempt_number: i32, success: bool) -> ReportOutcomeRequest {
+        ReportOutcomeRequest {
+            transaction_id: transaction_id.to_string(),
+            psp_name: "stripe".to_string(),
+            attempt_number,
+            success,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_psp_outage_and_recovery() {
+        let harness = TestHarness::new(
+            RetryConfig {
+                jitter: false,
+                ..Default::default()
+            },
+            CircuitBreakerConfig {
+                failure_threshold: 3,
+                success_threshold: 2,
+                timeout_duration_ms: 30_000,
+                ..Default::default()
+            },
+        );
+
+        // The outage: every attempt fails until the circuit opens
+        for id in ["txn_1", "txn_2", "txn_3"] {
+            assert!(
+                harness
+                    .schedule_retry(retry(id, 1))
+                    .await
+                    .unwrap()
+                    .scheduled
+            );
+            harness.report_outcome(outcome(id, 2, false)).await.unwrap();
+        }
+        let circuit = harness.get_circuit_status("stripe").await.unwrap();
+        assert_eq!(circuit.state, ProtoCircuitState::Open as i32);
+        assert!(
+            !harness
+                .schedule_retry(retry("txn_4", 1))
+                .await
+                .unwrap()
+                .scheduled
+        );
+
+        // An operator gives up on the stuck transactions for now
+        for id in ["txn_1", "txn_2", "txn_3"] {
+            let request = ForceToDlqRequest {
+                transaction_id: id.to_string(),
+                reason: "stripe outage".to_string(),
+                ..Default::default()
+            };
+            harness.force_to_dlq(request).await.unwrap();
+        }
+        let dlq = harness
+            .list_dlq_entries(ListDlqEntriesRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(dlq.entries.len(), 3);
+
+        // Recovery: once the timeout passes, probes go through and close it
+        harness.advance(29_999);
+        assert!(
+            !harness
+                .schedule_retry(retry("txn_4", 1))
+                .await
+                .unwrap()
+                .scheduled
+        );
+        harness.advance(1);
+        assert!(
+            harness
+                .schedule_retry(retry("txn_4", 1))
+                .await
+                .unwrap()
+                .scheduled
+        );
+        let circuit = harness.get_circuit_status("stripe").await.unwrap();
+        assert_eq!(circuit.state, ProtoCircuitState::HalfOpen as i32);
+        harness
+            .report_outcome(outcome("txn_4", 2, true))
+            .await
+            .unwrap();
+        assert!(
+            harness
+                .schedule_retry(retry("txn_5", 1))
+                .await
+                .unwrap()
+                .scheduled
+        );
+        harness
+            .report_outcome(outcome("txn_5", 2, true))
+            .await
+            .unwrap();
+        let circuit = harness.get_circuit_status("stripe").await.unwrap();
+        assert_eq!(circuit.state, ProtoCircuitState::Closed as i32);
+        assert_eq!(circuit.time_in_state_ms.unwrap().open_ms, 30_000);
+
+        // The dead letters go back into the pipeline
+        let replayed = harness
+            .bulk_replay_dlq(BulkReplayDlqRequest {
+                psp_name: "stripe".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed.replayed_count, 3);
+        for id in ["txn_1", "txn_2", "txn_3"] {
+            let status = harness.get_retry_status(id).await.unwrap();
+            assert_eq!(status.status, "RETRYING");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_max_elapsed_follows_simulated_time() {
+        let harness = TestHarness::new(
+            RetryConfig {
+                jitter: false,
+                max_elapsed_ms: 10_000,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+
+        let first = harness.schedule_retry(retry("txn_1", 1)).await.unwrap();
+        assert!(first.scheduled);
+        assert_eq!(first.next_retry_at_ms as u64, HARNESS_START_MS + 1000);
+
+        harness.advance(3_600_000);
+        let late = harness.schedule_retry(retry("txn_1", 2)).await.unwrap();
+        assert!(!late.scheduled);
+        let status = harness.get_retry_status("txn_1").await.unwrap();
+        assert_eq!(status.status, "IN_DLQ");
+    }
+
+    #[tokio::test]
+    async fn test_dlq_expiry_follows_simulated_time() {
+        let harness = TestHarness::with_service(|clock| {
+            RetryEngineService::new(
+                RetryConfig {
+                    max_attempts: 2,
+                    ..Default::default()
+                },
+                CircuitBreakerConfig::default(),
+            )
+            .with_dlq_expiry(60_000)
+            .with_clock(clock)
+        });
+
+        assert!(
+            !harness
+                .schedule_retry(retry("txn_1", 2))
+                .await
+                .unwrap()
+                .scheduled
+        );
+        let dlq = harness
+            .list_dlq_entries(ListDlqEntriesRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(dlq.entries[0].timestamp_ms as u64, HARNESS_START_MS);
+
+        harness.advance(60_000);
+        assert!(harness.service().expire_dlq().is_empty());
+        harness.advance(86_400_000);
+        assert_eq!(harness.service().expire_dlq(), vec!["txn_1".to_string()]);
+    }
+}
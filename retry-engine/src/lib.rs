@@ -1,17 +1,209 @@
 pub mod circuit_breaker;
+pub mod clock;
+pub mod config;
 pub mod retry_policy;
 pub mod dlq;
+pub mod event_log;
+#[cfg(any(test, feature = "test-harness"))]
+pub mod harness;
+pub mod metrics;
+pub mod persistence;
+pub mod rate_limit;
+pub mod retry_budget;
+pub mod server;
+pub mod sharding;
+pub mod single_flight;
+pub mod wal;
 
+use std::collections::BTreeSet;
+use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Largest delay `RetryConfig` accepts (30 days); anything longer is a
+/// misconfiguration rather than a backoff
+pub const MAX_RETRY_DELAY_MS: u64 = 30 * 24 * 60 * 60 * 1000;
+
+/// Largest half-open probe window `CircuitBreakerConfig` accepts; a breaker
+/// waiting on more probes than this would in practice never close
+pub const MAX_PROBE_WINDOW: u32 = 10_000;
+
+/// Per-attempt timeout suggested to clients when none is configured
+pub const DEFAULT_ATTEMPT_TIMEOUT_MS: u64 = 30_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RetryConfig {
     pub max_attempts: u32,
     pub initial_delay_ms: u64,
     pub max_delay_ms: u64,
+    /// Growth factor between attempts; 1.0 keeps every delay at
+    /// `initial_delay_ms`, and anything below 1.0 is rejected by `validate`
     pub backoff_multiplier: f64,
     pub jitter: bool,
+    /// How jitter is applied when `jitter` is enabled
+    pub jitter_strategy: JitterStrategy,
+    /// Floor applied to every non-zero delay, after jitter (0 disables it)
+    pub min_delay_ms: u64,
+    /// Dead-letter a transaction rather than schedule an attempt more than
+    /// this long after its first scheduled retry (0 disables it)
+    #[serde(default)]
+    pub max_elapsed_ms: u64,
+    /// How far engine load stretches delays: under `load_factor` each one is
+    /// multiplied by `1 + load_factor * load_stretch` (0 ignores load)
+    #[serde(default)]
+    pub load_stretch: f64,
+    /// Retries made with no delay before backoff starts: attempts 1 to N are
+    /// immediate and attempt N + 1 waits `initial_delay_ms` (0 backs off from
+    /// the first retry)
+    #[serde(default)]
+    pub immediate_retries: u32,
+    /// Cap on how far jitter moves a delay, whatever the strategy's share of
+    /// it (0 leaves it uncapped)
+    #[serde(default)]
+    pub max_jitter_ms: u64,
+    /// Whether a client's attempt number is taken as sent. Configs logged
+    /// before this existed ran with literal numbers, so they replay that way.
+    #[serde(default = "default_replayed_attempt_numbering")]
+    pub attempt_numbering: AttemptNumbering,
+    /// How jitter is spread within the strategy's range
+    #[serde(default)]
+    pub jitter_distribution: JitterDistribution,
+    /// How long a client should wait on the first retry before counting it
+    /// failed (0 suggests no timeout)
+    #[serde(default = "default_attempt_timeout_ms")]
+    pub attempt_timeout_ms: u64,
+    /// Growth of the suggested timeout with each later retry; 1.0 keeps it
+    /// fixed, and anything below 1.0 is rejected by `validate`
+    #[serde(default = "default_attempt_timeout_multiplier")]
+    pub attempt_timeout_multiplier: f64,
+    /// Cap on the suggested timeout as it grows (0 leaves only the 30-day
+    /// `MAX_RETRY_DELAY_MS` ceiling)
+    #[serde(default)]
+    pub max_attempt_timeout_ms: u64,
+    /// Subtracted from a backoff attempt's number to give its exponent,
+    /// which never drops below 0: 1 starts the delays at `initial_delay_ms`,
+    /// 0 a step further along at `initial_delay_ms * backoff_multiplier`
+    #[serde(default = "default_backoff_offset")]
+    pub backoff_offset: u32,
+}
+
+fn default_attempt_timeout_ms() -> u64 {
+    DEFAULT_ATTEMPT_TIMEOUT_MS
+}
+
+fn default_attempt_timeout_multiplier() -> f64 {
+    1.0
+}
+
+fn default_backoff_offset() -> u32 {
+    1
+}
+
+fn default_replayed_attempt_numbering() -> AttemptNumbering {
+    AttemptNumbering::Literal
+}
+
+/// Vetted starting points for `RetryConfig::preset` and
+/// `CircuitBreakerConfig::preset`, for when it's unclear what to tune
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+    /// Few, widely spaced retries and a breaker slow to trip or close: for
+    /// PSPs that charge per attempt or rate-limit hard
+    Conservative,
+    /// The defaults: for most PSPs
+    #[default]
+    Balanced,
+    /// Many quick retries and a breaker that trips early and probes soon: for
+    /// fast PSPs where an attempt is cheap and idempotent
+    Aggressive,
+}
+
+impl Preset {
+    /// Parse the config spelling: `conservative`, `balanced` or `aggressive`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "conservative" => Some(Self::Conservative),
+            "balanced" => Some(Self::Balanced),
+            "aggressive" => Some(Self::Aggressive),
+            _ => None,
+        }
+    }
+}
+
+/// How the engine numbers a transaction's attempts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttemptNumbering {
+    /// Count attempts itself, so a client that skips a number (e.g. after a
+    /// lost response) doesn't jump ahead in the backoff schedule: a number
+    /// past the one after the pending retry's is taken as that one. Numbers
+    /// at or below it, such as a resent request, are used as sent, as is any
+    /// number for a transaction with no pending retry.
+    #[default]
+    Sequential,
+    /// Use the client's attempt number as sent
+    Literal,
+}
+
+/// How jitter is drawn from the range its `JitterStrategy` allows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum JitterDistribution {
+    /// Any amount in the range is equally likely
+    #[default]
+    Uniform,
+    /// Most delays land near the backoff delay with a light tail: the
+    /// amount is normally distributed around it, with a standard deviation
+    /// of `stddev_factor` times the range, and clamped to the range
+    Normal { stddev_factor: f64 },
+}
+
+/// A PSP's own jitter settings, replacing those of the `RetryConfig` for it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JitterConfig {
+    pub jitter: bool,
+    pub jitter_strategy: JitterStrategy,
+    pub jitter_distribution: JitterDistribution,
+    pub max_jitter_ms: u64,
+}
+
+impl JitterConfig {
+    /// The jitter settings of `config`
+    pub fn of(config: &RetryConfig) -> Self {
+        Self {
+            jitter: config.jitter,
+            jitter_strategy: config.jitter_strategy,
+            jitter_distribution: config.jitter_distribution,
+            max_jitter_ms: config.max_jitter_ms,
+        }
+    }
+
+    /// `config` with its jitter settings replaced by these
+    pub fn apply_to(&self, config: &RetryConfig) -> RetryConfig {
+        RetryConfig {
+            jitter: self.jitter,
+            jitter_strategy: self.jitter_strategy,
+            jitter_distribution: self.jitter_distribution,
+            max_jitter_ms: self.max_jitter_ms,
+            ..config.clone()
+        }
+    }
+}
+
+/// `JitterDistribution::Normal` spread when a config picks the normal
+/// distribution without giving one
+pub const DEFAULT_JITTER_STDDEV_FACTOR: f64 = 0.5;
+
+/// Shape of the random jitter applied to a backoff delay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JitterStrategy {
+    /// Up to ±20% of the delay
+    #[default]
+    Proportional,
+    /// AWS "equal jitter": half the delay plus a random amount up to the other
+    /// half, so the wait never drops below half the delay
+    EqualJitter,
+    /// AWS "full jitter": a random amount up to the whole delay, which
+    /// spreads retries widest but can retry straight away
+    Full,
 }
 
 impl Default for RetryConfig {
@@ -22,15 +214,172 @@ impl Default for RetryConfig {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: true,
+            jitter_strategy: JitterStrategy::Proportional,
+            min_delay_ms: 0,
+            max_elapsed_ms: 0,
+            load_stretch: 1.0,
+            immediate_retries: 0,
+            max_jitter_ms: 0,
+            attempt_numbering: AttemptNumbering::Sequential,
+            jitter_distribution: JitterDistribution::Uniform,
+            attempt_timeout_ms: DEFAULT_ATTEMPT_TIMEOUT_MS,
+            attempt_timeout_multiplier: 1.0,
+            max_attempt_timeout_ms: 0,
+            backoff_offset: 1,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl RetryConfig {
+    /// The retry settings of `preset`; the rest are the defaults
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            // 2s, 6s, 18s
+            Preset::Conservative => Self {
+                max_attempts: 4,
+                initial_delay_ms: 2000,
+                max_delay_ms: 300000,
+                backoff_multiplier: 3.0,
+                ..Default::default()
+            },
+            Preset::Balanced => Self::default(),
+            // 200ms, 300ms, 450ms, ... up to 10s
+            Preset::Aggressive => Self {
+                max_attempts: 8,
+                initial_delay_ms: 200,
+                max_delay_ms: 10000,
+                backoff_multiplier: 1.5,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Check that the delay bounds are consistent and delays never shrink
+    pub fn validate(&self) -> Result<(), String> {
+        if self.backoff_multiplier.is_nan()
+            || self.backoff_multiplier.is_infinite()
+            || self.backoff_multiplier < 1.0
+        {
+            return Err(format!(
+                "backoff_multiplier must be a finite number of at least 1.0 (1.0 gives a constant delay), got {}",
+                self.backoff_multiplier
+            ));
+        }
+        if !self.attempt_timeout_multiplier.is_finite() || self.attempt_timeout_multiplier < 1.0 {
+            return Err(format!(
+                "attempt_timeout_multiplier must be a finite number of at least 1.0 (1.0 gives a fixed timeout), got {}",
+                self.attempt_timeout_multiplier
+            ));
+        }
+        if let JitterDistribution::Normal { stddev_factor } = self.jitter_distribution {
+            if !stddev_factor.is_finite() || stddev_factor <= 0.0 {
+                return Err(format!(
+                    "jitter stddev_factor must be a finite number above 0.0, got {}",
+                    stddev_factor
+                ));
+            }
+        }
+        if !self.load_stretch.is_finite() || self.load_stretch < 0.0 {
+            return Err(format!(
+                "load_stretch must be a finite number of at least 0.0, got {}",
+                self.load_stretch
+            ));
+        }
+        for (name, delay_ms) in [
+            ("initial_delay_ms", self.initial_delay_ms),
+            ("max_delay_ms", self.max_delay_ms),
+            ("min_delay_ms", self.min_delay_ms),
+            ("max_jitter_ms", self.max_jitter_ms),
+            ("attempt_timeout_ms", self.attempt_timeout_ms),
+            ("max_attempt_timeout_ms", self.max_attempt_timeout_ms),
+        ] {
+            if delay_ms > MAX_RETRY_DELAY_MS {
+                return Err(format!(
+                    "{} must not exceed {} (30 days), got {}",
+                    name, MAX_RETRY_DELAY_MS, delay_ms
+                ));
+            }
+        }
+        if self.min_delay_ms > self.max_delay_ms {
+            return Err(format!(
+                "min_delay_ms ({}) must not exceed max_delay_ms ({})",
+                self.min_delay_ms, self.max_delay_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct CircuitBreakerConfig {
     pub failure_threshold: u32,
     pub success_threshold: u32,
+    /// How long an open circuit blocks before admitting a probe; 0 admits
+    /// the next request straight away
     pub timeout_duration_ms: u64,
+    /// Successes slower than this count as soft failures toward opening the
+    /// circuit (0 disables latency tripping)
+    pub latency_threshold_ms: u64,
+    /// When a half-open circuit closes again
+    #[serde(default)]
+    pub half_open_close: HalfOpenClose,
+    /// For this long after a half-open circuit closes, the first failure
+    /// that reaches `failure_threshold` is counted but doesn't reopen it, so
+    /// one stray failure from a recovering PSP doesn't flap the circuit
+    /// (0 disables)
+    #[serde(default)]
+    pub reopen_suppression_ms: u64,
+    /// Floor on how long an open circuit blocks, whatever
+    /// `timeout_duration_ms` says, so a zero timeout can't leave a failing
+    /// PSP unisolated (0 disables)
+    #[serde(default)]
+    pub min_open_ms: u64,
+    /// Half-open successes older than this stop counting toward
+    /// `success_threshold` or the probe window, so only a sustained recovery
+    /// closes the circuit (0 disables)
+    #[serde(default)]
+    pub success_decay_ms: u64,
+}
+
+/// Rule for closing a half-open circuit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HalfOpenClose {
+    /// After `success_threshold` successful probes; any failure reopens it
+    #[default]
+    Consecutive,
+    /// After a window of `window` probes of which at least
+    /// `min_success_percent`% succeeded; reopens as soon as too many have
+    /// failed for the window to reach that
+    SuccessRatio {
+        window: u32,
+        min_success_percent: u32,
+    },
+}
+
+impl HalfOpenClose {
+    /// Build from a probe window and success percentage, where a zero window
+    /// means `Consecutive`
+    pub fn from_parts(window: u32, min_success_percent: u32) -> Result<Self, String> {
+        match (window, min_success_percent) {
+            (0, 0) => Ok(Self::Consecutive),
+            (0, _) => Err("min_success_percent requires a probe_window".to_string()),
+            (window, min_success_percent) => Ok(Self::SuccessRatio {
+                window,
+                min_success_percent,
+            }),
+        }
+    }
+
+    /// `(window, min_success_percent)`, both zero for `Consecutive`
+    pub fn parts(&self) -> (u32, u32) {
+        match *self {
+            Self::Consecutive => (0, 0),
+            Self::SuccessRatio {
+                window,
+                min_success_percent,
+            } => (window, min_success_percent),
+        }
+    }
 }
 
 impl Default for CircuitBreakerConfig {
@@ -39,7 +388,364 @@ impl Default for CircuitBreakerConfig {
             failure_threshold: 5,
             success_threshold: 3,
             timeout_duration_ms: 30000,
+            latency_threshold_ms: 0,
+            half_open_close: HalfOpenClose::Consecutive,
+            reopen_suppression_ms: 0,
+            min_open_ms: 0,
+            success_decay_ms: 0,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// The breaker settings of `preset`; the rest are the defaults
+    pub fn preset(preset: Preset) -> Self {
+        match preset {
+            Preset::Conservative => Self {
+                failure_threshold: 10,
+                success_threshold: 5,
+                timeout_duration_ms: 60000,
+                min_open_ms: 10000,
+                ..Default::default()
+            },
+            Preset::Balanced => Self::default(),
+            Preset::Aggressive => Self {
+                failure_threshold: 3,
+                success_threshold: 2,
+                timeout_duration_ms: 5000,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Check that the thresholds can actually be reached
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_threshold == 0 {
+            return Err("failure_threshold must be at least 1".to_string());
+        }
+        if self.success_threshold == 0 {
+            return Err("success_threshold must be at least 1".to_string());
+        }
+        if let HalfOpenClose::SuccessRatio {
+            window,
+            min_success_percent,
+        } = self.half_open_close
+        {
+            if window == 0 {
+                return Err("probe_window must be at least 1".to_string());
+            }
+            if window > MAX_PROBE_WINDOW {
+                return Err(format!(
+                    "probe_window must be at most {}, got {}",
+                    MAX_PROBE_WINDOW, window
+                ));
+            }
+            if !(1..=100).contains(&min_success_percent) {
+                return Err(format!(
+                    "min_success_percent must be between 1 and 100, got {}",
+                    min_success_percent
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Recurring time at which a PSP's circuit breaker is reset, e.g. daily at a
+/// low-traffic hour: resets happen at every `offset_ms + n * interval_ms`
+/// since the Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitResetSchedule {
+    pub interval_ms: u64,
+    pub offset_ms: u64,
+}
+
+impl CircuitResetSchedule {
+    /// Once a day at `offset_ms` past midnight UTC
+    pub fn daily(offset_ms: u64) -> Self {
+        Self {
+            interval_ms: 24 * 60 * 60 * 1000,
+            offset_ms,
+        }
+    }
+
+    /// Check that the interval is non-zero and the offset falls inside it
+    pub fn validate(&self) -> Result<(), String> {
+        if self.interval_ms == 0 {
+            return Err("interval_ms must be at least 1".to_string());
+        }
+        if self.offset_ms >= self.interval_ms {
+            return Err(format!(
+                "offset_ms ({}) must be less than interval_ms ({})",
+                self.offset_ms, self.interval_ms
+            ));
+        }
+        Ok(())
+    }
+
+    /// First reset time strictly after `now_ms`
+    pub fn next_after(&self, now_ms: u64) -> u64 {
+        let next = now_ms - now_ms % self.interval_ms + self.offset_ms;
+        if next > now_ms {
+            next
+        } else {
+            next + self.interval_ms
+        }
+    }
+}
+
+/// Which PSPs retries may be scheduled for, whatever their circuit state
+///
+/// A denylisted PSP is always blocked; with an allowlist set, so is every PSP
+/// not on it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PspAccessPolicy {
+    /// When set, the only PSPs allowed, e.g. during a controlled rollout
+    pub allowlist: Option<BTreeSet<String>>,
+    /// PSPs blocked outright, e.g. for a sanctioned region
+    pub denylist: BTreeSet<String>,
+}
+
+impl PspAccessPolicy {
+    pub fn allows(&self, psp_name: &str) -> bool {
+        !self.denylist.contains(psp_name)
+            && self
+                .allowlist
+                .as_ref()
+                .is_none_or(|allowlist| allowlist.contains(psp_name))
+    }
+}
+
+/// Turns a PSP name from a request or config into the key its breaker,
+/// overrides, quotas and DLQ entries are kept under. Must give the same key
+/// when applied to a key it returned.
+pub type PspNormalizer = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// How PSP names are matched up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PspNaming {
+    /// Names are used exactly as sent, so "Stripe" and "stripe " are
+    /// different PSPs
+    #[default]
+    Raw,
+    /// Surrounding whitespace is trimmed and the name lowercased, so
+    /// "Stripe" and "stripe " are both "stripe"
+    TrimLowercase,
+}
+
+impl PspNaming {
+    /// Parse the config spelling: `raw` or `trim_lowercase`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "raw" => Some(Self::Raw),
+            "trim_lowercase" => Some(Self::TrimLowercase),
+            _ => None,
+        }
+    }
+
+    /// The normalizer to give the service; `None` for raw names
+    pub fn normalizer(self) -> Option<PspNormalizer> {
+        match self {
+            PspNaming::Raw => None,
+            PspNaming::TrimLowercase => {
+                Some(Arc::new(|psp_name: &str| psp_name.trim().to_lowercase()))
+            }
+        }
+    }
+}
+
+/// What a PSP gets once `max_psp_breakers` PSPs already have a breaker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PspOverflow {
+    /// Every PSP past the cap shares a single overflow breaker
+    #[default]
+    Shared,
+    /// Requests for a PSP past the cap are refused
+    Reject,
+}
+
+impl PspOverflow {
+    /// Parse the config spelling: `shared` or `reject`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "shared" => Some(Self::Shared),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+}
+
+/// Highest attempt number a request may carry by default; far past any
+/// sensible `max_attempts`, so only a buggy or hostile client reaches it
+pub const DEFAULT_MAX_ATTEMPT_NUMBER: u32 = 10_000;
+
+fn default_max_attempt_number() -> u32 {
+    DEFAULT_MAX_ATTEMPT_NUMBER
+}
+
+/// What a request gets for an attempt number above `max_attempt_number`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AttemptOverflow {
+    /// Refused with `INVALID_ARGUMENT`
+    #[default]
+    Reject,
+    /// Treated as `max_attempt_number`
+    Clamp,
+}
+
+impl AttemptOverflow {
+    /// Parse the config spelling: `reject` or `clamp`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "clamp" => Some(Self::Clamp),
+            _ => None,
+        }
+    }
+}
+
+/// What a request gets for an empty or whitespace-only `transaction_id` or
+/// `psp_name`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlankIds {
+    /// Refused with `INVALID_ARGUMENT`
+    #[default]
+    Reject,
+    /// Accepted as sent, with a warning logged
+    Allow,
+}
+
+impl BlankIds {
+    /// Parse the config spelling: `reject` or `allow`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(Self::Reject),
+            "allow" => Some(Self::Allow),
+            _ => None,
+        }
+    }
+}
+
+/// Connection and request limits for the gRPC server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    /// How often to send HTTP/2 keepalive pings on idle connections
+    pub keepalive_interval_ms: u64,
+    /// How long to wait for a keepalive ack before dropping the connection
+    pub keepalive_timeout_ms: u64,
+    /// Requests still running after this long are cancelled
+    pub request_timeout_ms: u64,
+    /// `ScheduleRetry` calls for one transaction closer together than this
+    /// are rejected; 0 disables the limit
+    #[serde(default)]
+    pub min_schedule_interval_ms: u64,
+    /// Bearer token required by admin RPCs (`DumpState`, `ForceOpenCircuit`);
+    /// they are refused while it's unset. Only read from the environment.
+    pub admin_token: Option<String>,
+    /// How PSP names from requests and config are matched up
+    #[serde(default)]
+    pub psp_naming: PspNaming,
+    /// Most PSPs given their own circuit breaker, so a flood of made-up PSP
+    /// names can't grow the breaker map without bound; 0 for no limit
+    #[serde(default)]
+    pub max_psp_breakers: usize,
+    /// What PSPs past `max_psp_breakers` get
+    #[serde(default)]
+    pub psp_overflow: PspOverflow,
+    /// Highest `attempt_number` accepted in a request
+    #[serde(default = "default_max_attempt_number")]
+    pub max_attempt_number: u32,
+    /// What attempt numbers above `max_attempt_number` get
+    #[serde(default)]
+    pub attempt_overflow: AttemptOverflow,
+    /// What blank transaction IDs and PSP names get
+    #[serde(default)]
+    pub blank_ids: BlankIds,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            keepalive_interval_ms: 30000,
+            keepalive_timeout_ms: 10000,
+            request_timeout_ms: 10000,
+            min_schedule_interval_ms: 0,
+            admin_token: None,
+            psp_naming: PspNaming::Raw,
+            max_psp_breakers: 0,
+            psp_overflow: PspOverflow::Shared,
+            max_attempt_number: DEFAULT_MAX_ATTEMPT_NUMBER,
+            attempt_overflow: AttemptOverflow::Reject,
+            blank_ids: BlankIds::Reject,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Build from `RETRY_ENGINE_KEEPALIVE_INTERVAL_MS`,
+    /// `RETRY_ENGINE_KEEPALIVE_TIMEOUT_MS`, `RETRY_ENGINE_REQUEST_TIMEOUT_MS`,
+    /// `RETRY_ENGINE_MIN_SCHEDULE_INTERVAL_MS` and `RETRY_ENGINE_ADMIN_TOKEN`,
+    /// using the defaults for any that are unset
+    pub fn from_env() -> Result<Self, String> {
+        Self::default().with_env(|name| std::env::var(name).ok())
+    }
+
+    /// Replace each field whose variable `var` returns a value for, then
+    /// validate the result
+    pub fn with_env(self, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let ms = |name: &str, current: u64| -> Result<u64, String> {
+            match var(name) {
+                Some(value) => value.parse().map_err(|_| {
+                    format!("{} must be a number of milliseconds, got {:?}", name, value)
+                }),
+                None => Ok(current),
+            }
+        };
+
+        let config = Self {
+            keepalive_interval_ms: ms(
+                "RETRY_ENGINE_KEEPALIVE_INTERVAL_MS",
+                self.keepalive_interval_ms,
+            )?,
+            keepalive_timeout_ms: ms(
+                "RETRY_ENGINE_KEEPALIVE_TIMEOUT_MS",
+                self.keepalive_timeout_ms,
+            )?,
+            request_timeout_ms: ms("RETRY_ENGINE_REQUEST_TIMEOUT_MS", self.request_timeout_ms)?,
+            min_schedule_interval_ms: ms(
+                "RETRY_ENGINE_MIN_SCHEDULE_INTERVAL_MS",
+                self.min_schedule_interval_ms,
+            )?,
+            admin_token: var("RETRY_ENGINE_ADMIN_TOKEN")
+                .filter(|token| !token.is_empty())
+                .or(self.admin_token),
+            psp_naming: self.psp_naming,
+            max_psp_breakers: self.max_psp_breakers,
+            psp_overflow: self.psp_overflow,
+            max_attempt_number: self.max_attempt_number,
+            attempt_overflow: self.attempt_overflow,
+            blank_ids: self.blank_ids,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check that the keepalive and request timeouts and the attempt number
+    /// limit are non-zero
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keepalive_interval_ms == 0 {
+            return Err("keepalive_interval_ms must be at least 1".to_string());
+        }
+        if self.keepalive_timeout_ms == 0 {
+            return Err("keepalive_timeout_ms must be at least 1".to_string());
+        }
+        if self.request_timeout_ms == 0 {
+            return Err("request_timeout_ms must be at least 1".to_string());
+        }
+        if self.max_attempt_number == 0 {
+            return Err("max_attempt_number must be at least 1".to_string());
         }
+        Ok(())
     }
 }
 
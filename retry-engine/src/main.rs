@@ -1,15 +1,9 @@
-use retry_engine::{CircuitBreakerConfig, RetryConfig};
-use tonic::transport::Server;
-use tracing::{info, Level};
-use tracing_subscriber;
-
-mod circuit_breaker;
-mod dlq;
-mod retry_policy;
-mod server;
-
-use server::retry::retry_engine_server::RetryEngineServer;
-use server::RetryEngineService;
+use retry_engine::server::retry::retry_engine_server::RetryEngineServer;
+use retry_engine::config::EngineConfig;
+use retry_engine::server::{server_builder, RetryEngineService};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn, Level};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -20,17 +14,76 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let addr = "[::1]:8450".parse()?;
 
-    let retry_config = RetryConfig::default();
-    let circuit_config = CircuitBreakerConfig::default();
+    let config = EngineConfig::load()?;
 
-    let retry_service = RetryEngineService::new(retry_config, circuit_config);
+    let mut retry_service = RetryEngineService::new(config.retry, config.circuit_breaker);
+    if let Some(normalizer) = config.server.psp_naming.normalizer() {
+        retry_service = retry_service.with_psp_normalizer(normalizer);
+    }
+    let mut retry_service = retry_service
+        .with_circuit_overrides(config.psp_overrides)
+        .with_jitter_overrides(config.psp_jitter)
+        .with_psp_access(config.psp_access)
+        .with_psp_quotas(config.dlq.psp_quotas)
+        .with_min_replay_interval(
+            config.dlq.min_replay_interval_ms,
+            config.dlq.replay_throttle,
+        )
+        .with_max_replays(config.dlq.max_replays)
+        .with_health_score(config.health_score)
+        .with_min_schedule_interval(config.server.min_schedule_interval_ms)
+        .with_max_psp_breakers(config.server.max_psp_breakers, config.server.psp_overflow)
+        .with_max_attempt_number(
+            config.server.max_attempt_number,
+            config.server.attempt_overflow,
+        )
+        .with_blank_ids(config.server.blank_ids)
+        .with_admin_token(config.server.admin_token.clone());
+    if let Some(budget) = config.retry_budget.budget() {
+        retry_service = retry_service.with_retry_budget(budget);
+    }
+    if let Some(cooldown_ms) = config.dlq.soft_cooldown_ms {
+        retry_service = retry_service.with_soft_dlq(cooldown_ms);
+    }
+    if let Some(max_age_ms) = config.dlq.max_age_ms {
+        retry_service = retry_service.with_dlq_expiry(max_age_ms);
+    }
+    if let Some(timeout_ms) = config.outcomes.timeout_ms {
+        retry_service =
+            retry_service.with_outcome_timeout(timeout_ms, config.outcomes.timeout_action);
+    }
+    if let Some(wal) = config.persistence.wal() {
+        retry_service = retry_service.with_dlq_wal(wal, config.persistence.mode)?;
+    } else if let Some(store) = config.persistence.store() {
+        retry_service = retry_service.with_dlq_store(store, config.persistence.mode)?;
+    }
+    let reconciled = retry_service.reconcile();
+    if !reconciled.is_empty() {
+        info!("Reconciled recovered state: {:?}", reconciled);
+    }
+    let retry_service = Arc::new(retry_service);
+    RetryEngineService::spawn_circuit_reset_task(retry_service.clone(), Duration::from_secs(1));
+    RetryEngineService::spawn_maintenance_task(retry_service.clone(), Duration::from_secs(1));
+    RetryEngineService::spawn_soft_dlq_task(retry_service.clone(), Duration::from_secs(1));
+    RetryEngineService::spawn_dlq_expiry_task(retry_service.clone(), Duration::from_secs(60));
+    RetryEngineService::spawn_outcome_timeout_task(retry_service.clone(), Duration::from_secs(1));
+    RetryEngineService::spawn_dlq_wal_task(
+        retry_service.clone(),
+        Duration::from_millis(config.persistence.checkpoint_interval_ms),
+    );
 
     info!("Retry Engine starting on {}", addr);
 
-    Server::builder()
-        .add_service(RetryEngineServer::new(retry_service))
-        .serve(addr)
+    server_builder(&config.server)
+        .add_service(RetryEngineServer::from_arc(retry_service.clone()))
+        .serve_with_shutdown(addr, async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
         .await?;
 
+    if let Err(e) = retry_service.persist_dlq() {
+        warn!("Failed to persist DLQ on shutdown: {}", e);
+    }
+
     Ok(())
 }
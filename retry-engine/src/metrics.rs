@@ -0,0 +1,760 @@
+use crate::circuit_breaker::CircuitState;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Counts for one fixed-width slice of time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeBucket {
+    pub start_ms: u64,
+    pub scheduled_retries: u64,
+    pub dlq_adds: u64,
+}
+
+/// Ring of timestamped buckets recording scheduled retries and DLQ adds.
+///
+/// Only the most recent `capacity` buckets are kept; older ones age out as
+/// time moves forward, so memory stays bounded regardless of traffic.
+pub struct RetryTimeSeries {
+    bucket_ms: u64,
+    capacity: usize,
+    buckets: Mutex<VecDeque<TimeBucket>>,
+}
+
+impl RetryTimeSeries {
+    pub fn new(bucket_ms: u64, capacity: usize) -> Self {
+        Self {
+            bucket_ms: bucket_ms.max(1),
+            capacity: capacity.max(1),
+            buckets: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn bucket_ms(&self) -> u64 {
+        self.bucket_ms
+    }
+
+    /// Record a scheduled retry at the given time
+    pub fn record_scheduled_retry(&self, now_ms: u64) {
+        self.record(now_ms, |bucket| bucket.scheduled_retries += 1);
+    }
+
+    /// Record a DLQ add at the given time
+    pub fn record_dlq_add(&self, now_ms: u64) {
+        self.record(now_ms, |bucket| bucket.dlq_adds += 1);
+    }
+
+    /// Get the last `count` buckets ending at the bucket containing `now_ms`,
+    /// oldest first. Buckets with no activity are returned with zero counts.
+    pub fn last_buckets(&self, count: usize, now_ms: u64) -> Vec<TimeBucket> {
+        let count = count.min(self.capacity);
+        let current = self.bucket_start(now_ms);
+        let buckets = self.buckets.lock().unwrap();
+
+        (0..count as u64)
+            .rev()
+            .filter_map(|back| current.checked_sub(back * self.bucket_ms))
+            .map(|start_ms| {
+                buckets
+                    .iter()
+                    .find(|bucket| bucket.start_ms == start_ms)
+                    .copied()
+                    .unwrap_or(TimeBucket {
+                        start_ms,
+                        ..Default::default()
+                    })
+            })
+            .collect()
+    }
+
+    fn bucket_start(&self, now_ms: u64) -> u64 {
+        now_ms - now_ms % self.bucket_ms
+    }
+
+    fn record(&self, now_ms: u64, update: impl FnOnce(&mut TimeBucket)) {
+        let start_ms = self.bucket_start(now_ms);
+        let mut buckets = self.buckets.lock().unwrap();
+
+        match buckets.back().map(|bucket| bucket.start_ms) {
+            Some(latest) if latest == start_ms => {}
+            Some(latest) if latest > start_ms => {
+                // Late sample: count it if its bucket is still retained
+                if let Some(bucket) = buckets.iter_mut().find(|b| b.start_ms == start_ms) {
+                    update(bucket);
+                }
+                return;
+            }
+            _ => buckets.push_back(TimeBucket {
+                start_ms,
+                ..Default::default()
+            }),
+        }
+
+        // Age out buckets that fell outside the window
+        let window_start = start_ms.saturating_sub((self.capacity as u64 - 1) * self.bucket_ms);
+        while buckets
+            .front()
+            .is_some_and(|bucket| bucket.start_ms < window_start)
+        {
+            buckets.pop_front();
+        }
+
+        update(buckets.back_mut().unwrap());
+    }
+}
+
+impl Default for RetryTimeSeries {
+    /// One-second buckets covering the last five minutes
+    fn default() -> Self {
+        Self::new(1000, 300)
+    }
+}
+
+/// Current and peak number of transactions in the retry pipeline for a PSP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InFlightGauge {
+    pub current: u64,
+    pub peak: u64,
+}
+
+/// Per-PSP in-flight retry counts with a high-water mark, for sizing PSP
+/// connection pools
+#[derive(Default)]
+pub struct InFlightTracker {
+    gauges: Mutex<HashMap<String, InFlightGauge>>,
+}
+
+impl InFlightTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A transaction entered the retry pipeline for `psp_name`
+    pub fn start(&self, psp_name: &str) {
+        let mut gauges = self.gauges.lock().unwrap();
+        let gauge = gauges.entry(psp_name.to_string()).or_default();
+        gauge.current += 1;
+        gauge.peak = gauge.peak.max(gauge.current);
+    }
+
+    /// A transaction for `psp_name` left the retry pipeline
+    pub fn finish(&self, psp_name: &str) {
+        let mut gauges = self.gauges.lock().unwrap();
+        if let Some(gauge) = gauges.get_mut(psp_name) {
+            gauge.current = gauge.current.saturating_sub(1);
+        }
+    }
+
+    pub fn get(&self, psp_name: &str) -> InFlightGauge {
+        self.gauges
+            .lock()
+            .unwrap()
+            .get(psp_name)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Latency samples kept per PSP for `psp_reported_latency_seconds`
+pub const MAX_LATENCY_SAMPLES: usize = 1024;
+
+/// Default `psp_reported_latency_seconds` bucket bounds, in seconds
+pub const DEFAULT_LATENCY_BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Outcomes kept per PSP for its recent success rate
+pub const MAX_RECENT_OUTCOMES: usize = 100;
+
+/// Attempt outcomes clients reported for a PSP
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PspOutcomes {
+    pub successes: u64,
+    pub failures: u64,
+    /// Reported latencies in milliseconds, oldest first, capped at
+    /// `MAX_LATENCY_SAMPLES`
+    pub latencies_ms: VecDeque<u64>,
+    /// Whether each reported attempt succeeded, oldest first, capped at
+    /// `MAX_RECENT_OUTCOMES`
+    pub recent: VecDeque<bool>,
+}
+
+impl PspOutcomes {
+    /// Share of the recent outcomes that were successes; `None` before any
+    pub fn recent_success_rate(&self) -> Option<f64> {
+        if self.recent.is_empty() {
+            return None;
+        }
+        let successes = self.recent.iter().filter(|success| **success).count();
+        Some(successes as f64 / self.recent.len() as f64)
+    }
+
+    /// Mean of the kept latencies; `None` before any
+    pub fn mean_latency_ms(&self) -> Option<f64> {
+        if self.latencies_ms.is_empty() {
+            return None;
+        }
+        let total: u64 = self.latencies_ms.iter().sum();
+        Some(total as f64 / self.latencies_ms.len() as f64)
+    }
+}
+
+/// Per-PSP counts and latencies of the outcomes reported through
+/// `ReportOutcome`
+#[derive(Default)]
+pub struct OutcomeTracker {
+    outcomes: Mutex<HashMap<String, PspOutcomes>>,
+}
+
+impl OutcomeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count an outcome for `psp_name`, with its latency if known
+    pub fn record(&self, psp_name: &str, success: bool, latency_ms: Option<u64>) {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let psp = outcomes.entry(psp_name.to_string()).or_default();
+        if success {
+            psp.successes += 1;
+        } else {
+            psp.failures += 1;
+        }
+        if psp.recent.len() == MAX_RECENT_OUTCOMES {
+            psp.recent.pop_front();
+        }
+        psp.recent.push_back(success);
+        if let Some(latency_ms) = latency_ms {
+            if psp.latencies_ms.len() == MAX_LATENCY_SAMPLES {
+                psp.latencies_ms.pop_front();
+            }
+            psp.latencies_ms.push_back(latency_ms);
+        }
+    }
+
+    pub fn get(&self, psp_name: &str) -> PspOutcomes {
+        self.outcomes
+            .lock()
+            .unwrap()
+            .get(psp_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Every PSP with a reported outcome, ordered by name
+    pub fn all(&self) -> Vec<(String, PspOutcomes)> {
+        let mut all: Vec<(String, PspOutcomes)> = self
+            .outcomes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, outcomes)| (psp_name.clone(), outcomes.clone()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+}
+
+/// Default `psp_retry_drift_seconds` bucket bounds, in seconds
+pub const DEFAULT_DRIFT_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0];
+
+/// Drift samples kept per PSP
+pub const MAX_DRIFT_SAMPLES: usize = 1024;
+
+/// How late each PSP's retries were followed up, compared with when they
+/// were due: a backlog in the caller's scheduler shows up as growing drift
+#[derive(Default)]
+pub struct DriftTracker {
+    drifts_ms: Mutex<HashMap<String, VecDeque<u64>>>,
+}
+
+impl DriftTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a follow-up for `psp_name` that came `drift_ms` after its
+    /// attempt was due
+    pub fn record(&self, psp_name: &str, drift_ms: u64) {
+        let mut drifts = self.drifts_ms.lock().unwrap();
+        let psp = drifts.entry(psp_name.to_string()).or_default();
+        if psp.len() == MAX_DRIFT_SAMPLES {
+            psp.pop_front();
+        }
+        psp.push_back(drift_ms);
+    }
+
+    /// Every PSP's kept drifts in milliseconds, oldest first, ordered by PSP
+    pub fn all(&self) -> Vec<(String, Vec<u64>)> {
+        let mut all: Vec<(String, Vec<u64>)> = self
+            .drifts_ms
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, drifts)| (psp_name.clone(), drifts.iter().copied().collect()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+}
+
+/// Width of an `AdmissionTracker` bucket: one minute
+pub const ADMISSION_BUCKET_MS: u64 = 60_000;
+
+/// `AdmissionTracker` buckets kept per PSP: a day's worth
+pub const MAX_ADMISSION_BUCKETS: usize = 1440;
+
+/// A PSP's circuit breaker checks over some window, and how many it turned
+/// away
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Admissions {
+    pub checks: u64,
+    pub rejected: u64,
+}
+
+impl Admissions {
+    /// Share of checks rejected, from 0.0 to 1.0; 0.0 with no checks
+    pub fn rejection_ratio(&self) -> f64 {
+        if self.checks == 0 {
+            return 0.0;
+        }
+        self.rejected as f64 / self.checks as f64
+    }
+}
+
+/// Circuit breaker checks and rejections per PSP in one-minute buckets, for
+/// reporting how much of a PSP's traffic an incident turned away
+#[derive(Default)]
+pub struct AdmissionTracker {
+    buckets: Mutex<HashMap<String, VecDeque<(u64, Admissions)>>>,
+}
+
+impl AdmissionTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a breaker check for `psp_name` at `now_ms`
+    pub fn record(&self, psp_name: &str, allowed: bool, now_ms: u64) {
+        let start_ms = now_ms - now_ms % ADMISSION_BUCKET_MS;
+        let mut all = self.buckets.lock().unwrap();
+        let buckets = all.entry(psp_name.to_string()).or_default();
+        // A check from before the latest bucket (the clock stepped back)
+        // counts toward the latest
+        if buckets.back().is_none_or(|(latest, _)| *latest < start_ms) {
+            if buckets.len() == MAX_ADMISSION_BUCKETS {
+                buckets.pop_front();
+            }
+            buckets.push_back((start_ms, Admissions::default()));
+        }
+        let (_, counts) = buckets.back_mut().unwrap();
+        counts.checks += 1;
+        if !allowed {
+            counts.rejected += 1;
+        }
+    }
+
+    /// Checks for `psp_name` in the buckets overlapping the `window_ms` up to
+    /// `now_ms`, and the start of the first of those buckets. Being whole
+    /// buckets, they can reach up to `ADMISSION_BUCKET_MS` further back.
+    pub fn window(&self, psp_name: &str, window_ms: u64, now_ms: u64) -> (Admissions, u64) {
+        let since_ms = now_ms.saturating_sub(window_ms);
+        let window_start_ms = since_ms - since_ms % ADMISSION_BUCKET_MS;
+        let all = self.buckets.lock().unwrap();
+        let admissions = all
+            .get(psp_name)
+            .into_iter()
+            .flatten()
+            .filter(|(start_ms, _)| *start_ms >= window_start_ms && *start_ms <= now_ms)
+            .fold(Admissions::default(), |total, (_, counts)| Admissions {
+                checks: total.checks + counts.checks,
+                rejected: total.rejected + counts.rejected,
+            });
+        (admissions, window_start_ms)
+    }
+
+    /// Forget `psp_name`'s checks
+    pub fn reset(&self, psp_name: &str) {
+        self.buckets.lock().unwrap().remove(psp_name);
+    }
+}
+
+/// Default `psp_final_attempt` bucket bounds, in attempts
+pub const DEFAULT_ATTEMPT_BUCKETS: &[f64] = &[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0, 10.0, 15.0, 20.0];
+
+/// The attempts a PSP's transactions finished at, counted per attempt number
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FinalAttempts {
+    /// Transactions whose attempt succeeded
+    pub successes: BTreeMap<u32, u64>,
+    /// Transactions that ran out of retries and were dead-lettered
+    pub dead_lettered: BTreeMap<u32, u64>,
+}
+
+/// At which attempt each PSP's transactions ultimately succeed or are
+/// dead-lettered, for judging whether `max_attempts` is too high or too low
+#[derive(Default)]
+pub struct FinalAttemptTracker {
+    attempts: Mutex<HashMap<String, FinalAttempts>>,
+}
+
+impl FinalAttemptTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a transaction on `psp_name` that succeeded at `attempt`
+    pub fn record_success(&self, psp_name: &str, attempt: u32) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let psp = attempts.entry(psp_name.to_string()).or_default();
+        *psp.successes.entry(attempt).or_default() += 1;
+    }
+
+    /// Count a transaction on `psp_name` dead-lettered after `attempt`
+    pub fn record_dead_letter(&self, psp_name: &str, attempt: u32) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let psp = attempts.entry(psp_name.to_string()).or_default();
+        *psp.dead_lettered.entry(attempt).or_default() += 1;
+    }
+
+    /// Every PSP with a finished transaction, ordered by name
+    pub fn all(&self) -> Vec<(String, FinalAttempts)> {
+        let mut all: Vec<(String, FinalAttempts)> = self
+            .attempts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, attempts)| (psp_name.clone(), attempts.clone()))
+            .collect();
+        all.sort_by(|a, b| a.0.cmp(&b.0));
+        all
+    }
+}
+
+/// Most characters of label names and values OpenMetrics allows in one
+/// exemplar
+pub const MAX_EXEMPLAR_LABEL_CHARS: usize = 128;
+
+/// An event behind a counter increment, linking the metric to the
+/// transaction and, if the request carried one, its trace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exemplar {
+    pub transaction_id: String,
+    /// W3C trace ID of the request that caused the increment
+    pub trace_id: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+impl Exemplar {
+    /// The OpenMetrics exemplar to append to a sample line, e.g.
+    /// ` # {transaction_id="txn_1",trace_id="4bf9..."} 1 1700000000.000`;
+    /// `None` if its labels are longer than `MAX_EXEMPLAR_LABEL_CHARS`
+    fn openmetrics_suffix(&self) -> Option<String> {
+        let mut labels = vec![("transaction_id", self.transaction_id.as_str())];
+        if let Some(trace_id) = &self.trace_id {
+            labels.push(("trace_id", trace_id.as_str()));
+        }
+        let chars: usize = labels
+            .iter()
+            .map(|(name, value)| name.len() + value.chars().count())
+            .sum();
+        if chars > MAX_EXEMPLAR_LABEL_CHARS {
+            return None;
+        }
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(name, value)| format!("{}={:?}", name, value))
+            .collect();
+        Some(format!(
+            " # {{{}}} 1 {}.{:03}",
+            labels.join(","),
+            self.timestamp_ms / 1000,
+            self.timestamp_ms % 1000
+        ))
+    }
+}
+
+/// Per-PSP event counts, each keeping its latest event as an exemplar
+#[derive(Debug, Default)]
+pub struct ExemplarCounter {
+    counts: Mutex<HashMap<String, (u64, Exemplar)>>,
+}
+
+impl ExemplarCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn increment(&self, psp_name: &str, exemplar: Exemplar) {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.get_mut(psp_name) {
+            Some((count, latest)) => {
+                *count += 1;
+                *latest = exemplar;
+            }
+            None => {
+                counts.insert(psp_name.to_string(), (1, exemplar));
+            }
+        }
+    }
+
+    pub fn get(&self, psp_name: &str) -> u64 {
+        self.counts
+            .lock()
+            .unwrap()
+            .get(psp_name)
+            .map_or(0, |(count, _)| *count)
+    }
+
+    /// Append a `name{psp="..."}` sample per PSP, ordered by PSP; with
+    /// `exemplars`, each ends in its latest event in the OpenMetrics syntax
+    pub fn write(&self, out: &mut String, name: &str, exemplars: bool) {
+        let counts = self.counts.lock().unwrap();
+        let mut psp_names: Vec<&String> = counts.keys().collect();
+        psp_names.sort();
+        for psp_name in psp_names {
+            let (count, exemplar) = &counts[psp_name];
+            let suffix = exemplars
+                .then(|| exemplar.openmetrics_suffix())
+                .flatten()
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "{}{{psp={:?}}} {}{}\n",
+                name, psp_name, count, suffix
+            ));
+        }
+    }
+}
+
+/// Weights and scales of a PSP's 0-100 health score
+///
+/// Each signal is scaled to 0.0-1.0:
+///
+/// - success rate `S`: the share of the last 100 reported outcomes that
+///   succeeded, 1.0 before any are reported
+/// - latency `L`: `1 - mean latency / latency_ceiling_ms`, floored at 0.0;
+///   1.0 before any latency is reported
+/// - DLQ `D`: `1 - DLQ entries / dlq_ceiling`, floored at 0.0
+///
+/// and the circuit gates the result with `C`: 1.0 closed, 0.5 half-open,
+/// 0.0 open. The score is
+/// `round(100 * C * (ws*S + wl*L + wd*D) / (ws + wl + wd))`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthScoreConfig {
+    pub success_rate_weight: f64,
+    pub latency_weight: f64,
+    pub dlq_weight: f64,
+    /// Mean latency that zeroes the latency signal
+    pub latency_ceiling_ms: u64,
+    /// DLQ entries that zero the DLQ signal
+    pub dlq_ceiling: u64,
+}
+
+impl Default for HealthScoreConfig {
+    fn default() -> Self {
+        Self {
+            success_rate_weight: 0.5,
+            latency_weight: 0.25,
+            dlq_weight: 0.25,
+            latency_ceiling_ms: 5000,
+            dlq_ceiling: 100,
+        }
+    }
+}
+
+impl HealthScoreConfig {
+    /// Check that the weights are usable and the ceilings non-zero
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, weight) in [
+            ("success_rate_weight", self.success_rate_weight),
+            ("latency_weight", self.latency_weight),
+            ("dlq_weight", self.dlq_weight),
+        ] {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!(
+                    "{} must be a finite number of at least 0.0, got {}",
+                    name, weight
+                ));
+            }
+        }
+        if self.success_rate_weight + self.latency_weight + self.dlq_weight == 0.0 {
+            return Err("at least one weight must be above 0.0".to_string());
+        }
+        if self.latency_ceiling_ms == 0 || self.dlq_ceiling == 0 {
+            return Err("latency_ceiling_ms and dlq_ceiling must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// Score a PSP from its circuit state, reported outcomes and DLQ entries
+    pub fn score(&self, circuit: CircuitState, outcomes: &PspOutcomes, dlq_entries: usize) -> u8 {
+        let circuit = match circuit {
+            CircuitState::Closed => 1.0,
+            CircuitState::HalfOpen => 0.5,
+            CircuitState::Open => 0.0,
+        };
+        let success_rate = outcomes.recent_success_rate().unwrap_or(1.0);
+        let latency = outcomes.mean_latency_ms().map_or(1.0, |mean_ms| {
+            (1.0 - mean_ms / self.latency_ceiling_ms as f64).max(0.0)
+        });
+        let dlq = (1.0 - dlq_entries as f64 / self.dlq_ceiling as f64).max(0.0);
+
+        let weighted = self.success_rate_weight * success_rate
+            + self.latency_weight * latency
+            + self.dlq_weight * dlq;
+        let total_weight = self.success_rate_weight + self.latency_weight + self.dlq_weight;
+        (100.0 * circuit * weighted / total_weight).round() as u8
+    }
+}
+
+/// What happens to a scheduled retry whose outcome is never reported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutcomeTimeoutAction {
+    /// Count it as a failure on the PSP's circuit breaker
+    #[default]
+    Fail,
+    /// Only log it
+    Ignore,
+}
+
+impl OutcomeTimeoutAction {
+    /// Parse the config spelling: `fail` or `ignore`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "fail" => Some(Self::Fail),
+            "ignore" => Some(Self::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// The `[outcomes]` config section
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutcomeConfig {
+    /// How long after a scheduled retry is due its outcome must be reported
+    /// (see `RetryEngineService::with_outcome_timeout`); `None` waits forever
+    pub timeout_ms: Option<u64>,
+    pub timeout_action: OutcomeTimeoutAction,
+}
+
+/// Default `psp_failure_interval_seconds` bucket bounds, in seconds
+pub const DEFAULT_FAILURE_INTERVAL_BUCKETS: &[f64] =
+    &[1.0, 5.0, 15.0, 30.0, 60.0, 300.0, 900.0, 3600.0];
+
+/// Prometheus-style histogram: cumulative counts per upper bound, plus the
+/// implicit `+Inf` bucket (`count`) and the sum of all samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// `(le, count of samples <= le)` in ascending `le` order
+    pub buckets: Vec<(f64, u64)>,
+    pub count: u64,
+    pub sum: f64,
+}
+
+impl Histogram {
+    /// Bucket `samples` by `bounds`, which must be ascending
+    pub fn from_samples(bounds: &[f64], samples: impl IntoIterator<Item = f64>) -> Self {
+        Self::from_counts(bounds, samples.into_iter().map(|sample| (sample, 1)))
+    }
+
+    /// Bucket `(sample, times seen)` pairs by `bounds`, which must be
+    /// ascending
+    pub fn from_counts(bounds: &[f64], counts: impl IntoIterator<Item = (f64, u64)>) -> Self {
+        let mut histogram = Self {
+            buckets: bounds.iter().map(|le| (*le, 0)).collect(),
+            count: 0,
+            sum: 0.0,
+        };
+        for (sample, times) in counts {
+            for (le, count) in histogram.buckets.iter_mut() {
+                if sample <= *le {
+                    *count += times;
+                }
+            }
+            histogram.count += times;
+            histogram.sum += sample * times as f64;
+        }
+        histogram
+    }
+
+    /// Append the `_bucket`, `_sum` and `_count` series in the Prometheus text
+    /// format, with `labels` (e.g. `psp="stripe"`) on every line
+    pub fn write_prometheus(&self, out: &mut String, name: &str, labels: &str) {
+        for (le, count) in &self.buckets {
+            out.push_str(&format!(
+                "{}_bucket{{{},le=\"{}\"}} {}\n",
+                name, labels, le, count
+            ));
+        }
+        out.push_str(&format!(
+            "{}_bucket{{{},le=\"+Inf\"}} {}\n",
+            name, labels, self.count
+        ));
+        out.push_str(&format!("{}_sum{{{}}} {}\n", name, labels, self.sum));
+        out.push_str(&format!("{}_count{{{}}} {}\n", name, labels, self.count));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_reflect_rate() {
+        let series = RetryTimeSeries::new(1000, 10);
+
+        // 3 retries/s in the first second, 1/s in the second, a storm of 10 in the fourth
+        for offset in [0, 200, 900] {
+            series.record_scheduled_retry(10_000 + offset);
+        }
+        series.record_scheduled_retry(11_500);
+        for i in 0..10 {
+            series.record_scheduled_retry(13_000 + i * 50);
+        }
+        series.record_dlq_add(13_700);
+
+        let buckets = series.last_buckets(4, 13_999);
+        let counts: Vec<u64> = buckets.iter().map(|b| b.scheduled_retries).collect();
+        assert_eq!(counts, vec![3, 1, 0, 10]);
+        assert_eq!(buckets[0].start_ms, 10_000);
+        assert_eq!(buckets[3].dlq_adds, 1);
+    }
+
+    #[test]
+    fn test_old_buckets_age_out() {
+        let series = RetryTimeSeries::new(1000, 3);
+
+        series.record_scheduled_retry(1_000);
+        series.record_scheduled_retry(5_000);
+
+        let buckets = series.last_buckets(3, 5_000);
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets.iter().map(|b| b.scheduled_retries).sum::<u64>(), 1);
+        assert_eq!(series.buckets.lock().unwrap().len(), 1);
+
+        // A late sample for a bucket that already aged out is dropped
+        series.record_scheduled_retry(1_500);
+        assert_eq!(series.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_in_flight_tracks_peak() {
+        let tracker = InFlightTracker::new();
+        tracker.start("stripe");
+        tracker.start("stripe");
+        tracker.finish("stripe");
+        tracker.start("stripe");
+        tracker.start("stripe");
+        tracker.finish("stripe");
+        tracker.finish("stripe");
+        tracker.finish("stripe");
+        tracker.finish("stripe");
+
+        assert_eq!(
+            tracker.get("stripe"),
+            InFlightGauge {
+                current: 0,
+                peak: 3
+            }
+        );
+        assert_eq!(tracker.get("adyen"), InFlightGauge::default());
+    }
+}
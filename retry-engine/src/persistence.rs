@@ -0,0 +1,463 @@
+use crate::dlq::DLQEntry;
+use crate::wal::DlqWal;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What to do when the DLQ store can't be read or written at startup
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PersistenceMode {
+    /// Fail startup
+    Strict,
+    /// Warn, run with an in-memory DLQ only and report the engine as degraded
+    #[default]
+    Lenient,
+}
+
+impl PersistenceMode {
+    /// Parse the config spelling: `strict` or `lenient`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "strict" => Some(Self::Strict),
+            "lenient" => Some(Self::Lenient),
+            _ => None,
+        }
+    }
+}
+
+/// Where and how the DLQ is persisted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PersistenceConfig {
+    /// Snapshot file; `None` keeps the DLQ in memory only
+    pub path: Option<PathBuf>,
+    pub format: SerializationFormat,
+    pub mode: PersistenceMode,
+    /// Keep a WAL next to the snapshot (`<path>.wal`), fsynced at most this
+    /// often, which bounds the changes lost to a power failure; `None` only
+    /// writes the snapshot on shutdown
+    pub wal_sync_interval_ms: Option<u64>,
+    /// How often the WAL is compacted into the snapshot
+    pub checkpoint_interval_ms: u64,
+    /// Write entry payloads to disk; when off only metadata is persisted and
+    /// reloaded entries need their payload re-supplied to be replayed
+    pub persist_payloads: bool,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            format: SerializationFormat::Json,
+            mode: PersistenceMode::Lenient,
+            wal_sync_interval_ms: None,
+            checkpoint_interval_ms: 60000,
+            persist_payloads: true,
+        }
+    }
+}
+
+impl PersistenceConfig {
+    /// Build from `RETRY_ENGINE_DLQ_PATH`, `RETRY_ENGINE_DLQ_FORMAT` (`json` or
+    /// `bincode`) and `RETRY_ENGINE_PERSISTENCE_MODE` (`strict` or `lenient`),
+    /// using the defaults for any that are unset
+    pub fn from_env() -> Result<Self, String> {
+        Self::default().with_env(|name| std::env::var(name).ok())
+    }
+
+    /// Replace each field whose variable `var` returns a value for
+    pub fn with_env(self, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let format = match var("RETRY_ENGINE_DLQ_FORMAT") {
+            Some(value) => SerializationFormat::parse(&value).ok_or_else(|| {
+                format!(
+                    "RETRY_ENGINE_DLQ_FORMAT must be json or bincode, got {:?}",
+                    value
+                )
+            })?,
+            None => self.format,
+        };
+        let mode = match var("RETRY_ENGINE_PERSISTENCE_MODE") {
+            Some(value) => PersistenceMode::parse(&value).ok_or_else(|| {
+                format!(
+                    "RETRY_ENGINE_PERSISTENCE_MODE must be strict or lenient, got {:?}",
+                    value
+                )
+            })?,
+            None => self.mode,
+        };
+
+        Ok(Self {
+            path: var("RETRY_ENGINE_DLQ_PATH")
+                .map(PathBuf::from)
+                .or(self.path),
+            format,
+            mode,
+            ..self
+        })
+    }
+
+    /// The store to use, if persistence is configured
+    pub fn store(&self) -> Option<DlqStore> {
+        self.path.as_ref().map(|path| {
+            DlqStore::new(path, self.format).with_persist_payloads(self.persist_payloads)
+        })
+    }
+
+    /// The WAL to use, if persistence and a WAL sync interval are configured
+    pub fn wal(&self) -> Option<DlqWal> {
+        let sync_interval_ms = self.wal_sync_interval_ms?;
+        let store = self.store()?;
+        let wal_path = store.path().with_extension("wal");
+        Some(DlqWal::new(
+            store,
+            wal_path,
+            Duration::from_millis(sync_interval_ms),
+        ))
+    }
+}
+
+/// Marks a file written in the bincode format
+const BINCODE_MAGIC: &[u8] = b"RDLQ";
+
+/// Layout of the bincode records that follow `BINCODE_MAGIC`
+///
+/// Bincode writes fields in order with no names or tags, so a record only
+/// reads back into the `DLQEntry` it was written from. Bump this whenever
+/// `DLQEntry`, or a type in it, gains, loses or reorders a field or variant.
+const BINCODE_LAYOUT_VERSION: u8 = 1;
+
+/// On-disk encoding for persisted DLQ entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SerializationFormat {
+    /// One JSON object per line: readable and greppable, but bulky
+    Json,
+    /// Bincode records behind a magic header: compact and fast, but tied to
+    /// the entry layout of the build that wrote them
+    Bincode,
+}
+
+impl SerializationFormat {
+    /// Parse the config spelling: `json` or `bincode`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "json" => Some(Self::Json),
+            "bincode" => Some(Self::Bincode),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for SerializationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerializationFormat::Json => write!(f, "json"),
+            SerializationFormat::Bincode => write!(f, "bincode"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Io(io::Error),
+    /// The file is readable but a record in it is malformed
+    Corrupt(String),
+    /// The file was written in a different format than the store expects
+    FormatMismatch {
+        expected: SerializationFormat,
+        found: SerializationFormat,
+    },
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::Io(e) => write!(f, "persistence I/O error: {}", e),
+            PersistenceError::Corrupt(msg) => write!(f, "corrupt persisted entry: {}", msg),
+            PersistenceError::FormatMismatch { expected, found } => write!(
+                f,
+                "persisted file is in {} format but the store expects {}",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PersistenceError {}
+
+impl From<io::Error> for PersistenceError {
+    fn from(e: io::Error) -> Self {
+        PersistenceError::Io(e)
+    }
+}
+
+/// Encode entries in the given format
+pub fn encode_entries(
+    format: SerializationFormat,
+    entries: &[DLQEntry],
+) -> Result<Vec<u8>, PersistenceError> {
+    let mut buf = Vec::new();
+    match format {
+        SerializationFormat::Json => {
+            for entry in entries {
+                serde_json::to_writer(&mut buf, entry)
+                    .map_err(|e| PersistenceError::Corrupt(e.to_string()))?;
+                buf.push(b'\n');
+            }
+        }
+        SerializationFormat::Bincode => {
+            buf.extend_from_slice(BINCODE_MAGIC);
+            buf.push(BINCODE_LAYOUT_VERSION);
+            for entry in entries {
+                bincode::serialize_into(&mut buf, entry)
+                    .map_err(|e| PersistenceError::Corrupt(e.to_string()))?;
+            }
+        }
+    }
+    Ok(buf)
+}
+
+/// Decode entries, failing if the data was written in the other format
+pub fn decode_entries(
+    format: SerializationFormat,
+    data: &[u8],
+) -> Result<Vec<DLQEntry>, PersistenceError> {
+    let found = if data.starts_with(BINCODE_MAGIC) {
+        SerializationFormat::Bincode
+    } else {
+        SerializationFormat::Json
+    };
+    if !data.is_empty() && found != format {
+        return Err(PersistenceError::FormatMismatch {
+            expected: format,
+            found,
+        });
+    }
+
+    match format {
+        SerializationFormat::Json => data
+            .lines()
+            .map(|line| line.map_err(PersistenceError::Io))
+            .filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+            .map(|line| {
+                serde_json::from_str(&line?).map_err(|e| PersistenceError::Corrupt(e.to_string()))
+            })
+            .collect(),
+        SerializationFormat::Bincode => {
+            let Some((&version, mut buf)) = data
+                .get(BINCODE_MAGIC.len()..)
+                .and_then(|rest| rest.split_first())
+            else {
+                return Ok(Vec::new());
+            };
+            if version != BINCODE_LAYOUT_VERSION {
+                return Err(PersistenceError::Corrupt(format!(
+                    "bincode entry layout {} was written by another build; this one reads layout {}",
+                    version, BINCODE_LAYOUT_VERSION
+                )));
+            }
+            let mut entries = Vec::new();
+            while !buf.is_empty() {
+                entries.push(
+                    bincode::deserialize_from(&mut buf)
+                        .map_err(|e| PersistenceError::Corrupt(e.to_string()))?,
+                );
+            }
+            Ok(entries)
+        }
+    }
+}
+
+/// File-backed snapshot store for DLQ entries
+pub struct DlqStore {
+    path: PathBuf,
+    format: SerializationFormat,
+    persist_payloads: bool,
+}
+
+impl DlqStore {
+    pub fn new(path: impl Into<PathBuf>, format: SerializationFormat) -> Self {
+        Self {
+            path: path.into(),
+            format,
+            persist_payloads: true,
+        }
+    }
+
+    /// Leave payloads out of what's written; in-memory entries keep theirs
+    pub fn with_persist_payloads(mut self, persist_payloads: bool) -> Self {
+        self.persist_payloads = persist_payloads;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> SerializationFormat {
+        self.format
+    }
+
+    pub fn persist_payloads(&self) -> bool {
+        self.persist_payloads
+    }
+
+    /// Replace the stored snapshot with `entries`
+    ///
+    /// Writes to a sibling temp file and renames it over the snapshot, so a
+    /// crash mid-write leaves the previous snapshot intact.
+    pub fn save(&self, entries: &[DLQEntry]) -> Result<(), PersistenceError> {
+        let data = if self.persist_payloads {
+            encode_entries(self.format, entries)?
+        } else {
+            let stripped: Vec<DLQEntry> = entries.iter().map(DLQEntry::without_payload).collect();
+            encode_entries(self.format, &stripped)?
+        };
+        let tmp_path = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Load the stored snapshot; a missing file is an empty DLQ
+    pub fn load(&self) -> Result<Vec<DLQEntry>, PersistenceError> {
+        match fs::read(&self.path) {
+            Ok(data) => decode_entries(self.format, &data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dlq::{DlqEntryStatus, DlqReason};
+    use std::collections::HashMap;
+
+    fn sample_entries() -> Vec<DLQEntry> {
+        (0..20)
+            .map(|i| DLQEntry {
+                transaction_id: format!("txn_{}", i),
+                psp_name: "stripe".to_string(),
+                payload: vec![i as u8; 64],
+                attempt_count: 5,
+                last_error: "Max retry attempts exceeded".to_string(),
+                timestamp_ms: 1_700_000_000_000 + i,
+                metadata: HashMap::from([("severity".to_string(), "high".to_string())]),
+                tags: vec!["fraud-ring".to_string()],
+                rerouted_from: (i % 2 == 0).then(|| "legacy_psp".to_string()),
+                status: if i % 3 == 0 {
+                    DlqEntryStatus::InReview
+                } else {
+                    DlqEntryStatus::New
+                },
+                reason: DlqReason::MaxAttemptsExceeded,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    fn assert_same_entries(left: &[DLQEntry], right: &[DLQEntry]) {
+        assert_eq!(left.len(), right.len());
+        for (a, b) in left.iter().zip(right) {
+            assert_eq!(
+                serde_json::to_value(a).unwrap(),
+                serde_json::to_value(b).unwrap()
+            );
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}", name, uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        let entries = sample_entries();
+
+        for format in [SerializationFormat::Json, SerializationFormat::Bincode] {
+            let store = DlqStore::new(temp_path("dlq-round-trip"), format);
+            store.save(&entries).unwrap();
+            assert_same_entries(&store.load().unwrap(), &entries);
+            fs::remove_file(store.path()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_bincode_is_smaller_than_json() {
+        let entries = sample_entries();
+        let json = encode_entries(SerializationFormat::Json, &entries).unwrap();
+        let bincode = encode_entries(SerializationFormat::Bincode, &entries).unwrap();
+
+        assert!(
+            bincode.len() * 2 < json.len(),
+            "bincode {} bytes vs json {} bytes",
+            bincode.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn test_format_mismatch_fails_clearly() {
+        let entries = sample_entries();
+        let bincode = encode_entries(SerializationFormat::Bincode, &entries).unwrap();
+        let json = encode_entries(SerializationFormat::Json, &entries).unwrap();
+
+        let err = decode_entries(SerializationFormat::Json, &bincode).unwrap_err();
+        assert!(matches!(
+            err,
+            PersistenceError::FormatMismatch {
+                expected: SerializationFormat::Json,
+                found: SerializationFormat::Bincode,
+            }
+        ));
+        assert!(decode_entries(SerializationFormat::Bincode, &json).is_err());
+    }
+
+    #[test]
+    fn test_bincode_from_another_layout_fails_clearly() {
+        let mut data = encode_entries(SerializationFormat::Bincode, &sample_entries()).unwrap();
+        data[BINCODE_MAGIC.len()] = BINCODE_LAYOUT_VERSION + 1;
+
+        let err = decode_entries(SerializationFormat::Bincode, &data).unwrap_err();
+        assert!(
+            matches!(&err, PersistenceError::Corrupt(msg) if msg.contains("written by another build")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_missing_file_loads_empty() {
+        let store = DlqStore::new(temp_path("dlq-missing"), SerializationFormat::Json);
+        assert!(store.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_metadata_only_store_flags_stripped_payloads() {
+        let entries = sample_entries();
+
+        for format in [SerializationFormat::Json, SerializationFormat::Bincode] {
+            let store =
+                DlqStore::new(temp_path("dlq-metadata-only"), format).with_persist_payloads(false);
+            store.save(&entries).unwrap();
+            let loaded = store.load().unwrap();
+
+            assert_eq!(loaded.len(), entries.len());
+            for (reloaded, original) in loaded.iter().zip(&entries) {
+                assert!(reloaded.payload.is_empty());
+                assert!(reloaded.payload_stripped);
+                assert_eq!(reloaded.transaction_id, original.transaction_id);
+                assert_eq!(reloaded.metadata, original.metadata);
+            }
+            assert!(entries
+                .iter()
+                .all(|e| !e.payload.is_empty() && !e.payload_stripped));
+            fs::remove_file(store.path()).unwrap();
+        }
+    }
+}
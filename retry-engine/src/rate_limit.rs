@@ -0,0 +1,96 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Lets each key through at most once per `min_interval_ms`
+///
+/// Only the calls let through start a new interval; rejected ones don't push
+/// it back. Keys are forgotten once their interval has passed, so memory is
+/// bounded by the keys let through in the last `min_interval_ms`.
+pub struct RateLimiter {
+    min_interval_ms: u64,
+    windows: Mutex<Windows>,
+}
+
+#[derive(Default)]
+struct Windows {
+    /// When each key was last let through
+    last_allowed_ms: HashMap<String, u64>,
+    /// The same, oldest first, for evicting keys whose interval has passed
+    order: VecDeque<(u64, String)>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval_ms: u64) -> Self {
+        Self {
+            min_interval_ms,
+            windows: Mutex::new(Windows::default()),
+        }
+    }
+
+    pub fn min_interval_ms(&self) -> u64 {
+        self.min_interval_ms
+    }
+
+    /// Let `key` through at `now_ms`, or return how many milliseconds until
+    /// it may be
+    pub fn try_acquire(&self, key: &str, now_ms: u64) -> Result<(), u64> {
+        let mut windows = self.windows.lock().unwrap();
+        windows.evict(now_ms.saturating_sub(self.min_interval_ms));
+
+        if let Some(last_ms) = windows.last_allowed_ms.get(key) {
+            let allowed_at_ms = last_ms.saturating_add(self.min_interval_ms);
+            if now_ms < allowed_at_ms {
+                return Err(allowed_at_ms - now_ms);
+            }
+        }
+        windows.last_allowed_ms.insert(key.to_string(), now_ms);
+        windows.order.push_back((now_ms, key.to_string()));
+        Ok(())
+    }
+
+    /// Keys currently tracked
+    pub fn len(&self) -> usize {
+        self.windows.lock().unwrap().last_allowed_ms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Windows {
+    /// Forget keys last let through at or before `cutoff_ms`
+    fn evict(&mut self, cutoff_ms: u64) {
+        while let Some((allowed_ms, _)) = self.order.front() {
+            if *allowed_ms > cutoff_ms {
+                break;
+            }
+            let (allowed_ms, key) = self.order.pop_front().unwrap();
+            // A later pass for the key has its own place further back
+            if self.last_allowed_ms.get(&key) == Some(&allowed_ms) {
+                self.last_allowed_ms.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_one_pass_per_interval_and_eviction() {
+        let limiter = RateLimiter::new(1000);
+        assert_eq!(limiter.try_acquire("txn_1", 10_000), Ok(()));
+        assert_eq!(limiter.try_acquire("txn_1", 10_400), Err(600));
+        // Other keys have their own interval
+        assert_eq!(limiter.try_acquire("txn_2", 10_400), Ok(()));
+        // The rejected call didn't restart the interval
+        assert_eq!(limiter.try_acquire("txn_1", 11_000), Ok(()));
+        assert_eq!(limiter.len(), 2);
+
+        // Both intervals have passed, so both keys are forgotten
+        assert_eq!(limiter.try_acquire("txn_3", 12_500), Ok(()));
+        assert_eq!(limiter.len(), 1);
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// The `[retry_budget]` config section
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryBudgetConfig {
+    /// Length of each budget window
+    pub window_ms: u64,
+    /// Retries each priority tier may schedule per window, lowest priority
+    /// first; empty leaves retries unbudgeted
+    pub tier_allowances: Vec<u64>,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: 60000,
+            tier_allowances: Vec::new(),
+        }
+    }
+}
+
+impl RetryBudgetConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.window_ms == 0 {
+            return Err("window_ms must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    /// The budget to enforce, if any tiers are configured
+    pub fn budget(&self) -> Option<RetryBudget> {
+        (!self.tier_allowances.is_empty())
+            .then(|| RetryBudget::new(self.window_ms, self.tier_allowances.clone()))
+    }
+}
+
+/// Retries allowed per fixed window, split into priority tiers
+///
+/// A tier spends its own allowance first. Once that's gone it borrows what
+/// lower tiers haven't used, starting with the tier just below. Lower tiers
+/// never borrow from higher ones, so a flood of low-priority retries can't
+/// use up what high-priority transactions are owed.
+pub struct RetryBudget {
+    window_ms: u64,
+    allowances: Vec<u64>,
+    window: Mutex<Window>,
+}
+
+#[derive(Default)]
+struct Window {
+    start_ms: u64,
+    /// Retries taken from each tier's allowance in this window
+    used: Vec<u64>,
+}
+
+impl RetryBudget {
+    pub fn new(window_ms: u64, allowances: Vec<u64>) -> Self {
+        Self {
+            window_ms: window_ms.max(1),
+            allowances,
+            window: Mutex::new(Window::default()),
+        }
+    }
+
+    pub fn tiers(&self) -> usize {
+        self.allowances.len()
+    }
+
+    /// The tier a transaction priority maps to; priorities past the highest
+    /// tier get it
+    pub fn tier_for(&self, priority: u32) -> usize {
+        (priority as usize).min(self.tiers().saturating_sub(1))
+    }
+
+    /// Spend one retry for `tier` at `now_ms`, or return how many
+    /// milliseconds until the next window
+    pub fn try_acquire(&self, tier: usize, now_ms: u64) -> Result<(), u64> {
+        if self.allowances.is_empty() {
+            return Ok(());
+        }
+        let mut window = self.window.lock().unwrap();
+        let start_ms = now_ms - now_ms % self.window_ms;
+        if window.start_ms != start_ms || window.used.len() != self.allowances.len() {
+            window.start_ms = start_ms;
+            window.used = vec![0; self.allowances.len()];
+        }
+
+        let tier = tier.min(self.allowances.len() - 1);
+        match (0..=tier)
+            .rev()
+            .find(|lender| window.used[*lender] < self.allowances[*lender])
+        {
+            Some(lender) => {
+                window.used[lender] += 1;
+                Ok(())
+            }
+            None => Err(start_ms + self.window_ms - now_ms),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_higher_tiers_borrow_lower_headroom_but_not_the_reverse() {
+        let budget = RetryBudget::new(1000, vec![2, 1]);
+        assert_eq!(budget.tier_for(0), 0);
+        assert_eq!(budget.tier_for(7), 1);
+
+        // The high tier spends its own retry, then borrows one of the low tier's
+        assert_eq!(budget.try_acquire(1, 10_000), Ok(()));
+        assert_eq!(budget.try_acquire(1, 10_100), Ok(()));
+        // The low tier has one left, and can't borrow once it's gone
+        assert_eq!(budget.try_acquire(0, 10_200), Ok(()));
+        assert_eq!(budget.try_acquire(0, 10_300), Err(700));
+        assert_eq!(budget.try_acquire(1, 10_300), Err(700));
+
+        // A new window restores every allowance
+        assert_eq!(budget.try_acquire(0, 11_000), Ok(()));
+        assert_eq!(budget.try_acquire(0, 11_000), Ok(()));
+        assert_eq!(budget.try_acquire(0, 11_000), Err(1000));
+        assert_eq!(budget.try_acquire(1, 11_000), Ok(()));
+    }
+}
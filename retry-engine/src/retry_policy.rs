@@ -1,27 +1,104 @@
-use crate::RetryConfig;
-use rand::Rng;
+use crate::{
+    AttemptNumbering, JitterDistribution, JitterStrategy, RetryConfig, MAX_RETRY_DELAY_MS,
+};
+use rand::{Rng, RngCore};
+use std::sync::{Arc, Mutex};
+
+/// Source of randomness for jitter, injectable so tests can pin the exact values
+pub trait JitterRng: Send {
+    /// Uniform value in `0..=max`
+    fn gen_range_inclusive(&mut self, max: u64) -> u64;
+    /// Fair coin flip deciding whether jitter is added or subtracted
+    fn gen_bool(&mut self) -> bool;
+    /// Standard normal sample, for `JitterDistribution::Normal`
+    fn gen_standard_normal(&mut self) -> f64 {
+        // Box-Muller over two uniforms, the first kept off zero for the log
+        let scale = u64::from(u32::MAX);
+        let u1 = (self.gen_range_inclusive(scale - 1) + 1) as f64 / scale as f64;
+        let u2 = self.gen_range_inclusive(scale) as f64 / scale as f64;
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// Jitter drawn from any `rand` generator, e.g. a seeded `StdRng`
+pub struct RngJitter<R>(pub R);
+
+impl<R: RngCore + Send> JitterRng for RngJitter<R> {
+    fn gen_range_inclusive(&mut self, max: u64) -> u64 {
+        self.0.gen_range(0..=max)
+    }
+
+    fn gen_bool(&mut self) -> bool {
+        self.0.gen_bool(0.5)
+    }
+}
+
+/// Jitter from the calling thread's `ThreadRng`, which is not `Send` itself
+/// and so can't be stored on the policy directly
+pub struct ThreadRngSource;
+
+impl JitterRng for ThreadRngSource {
+    fn gen_range_inclusive(&mut self, max: u64) -> u64 {
+        rand::thread_rng().gen_range(0..=max)
+    }
+
+    fn gen_bool(&mut self) -> bool {
+        rand::thread_rng().gen_bool(0.5)
+    }
+}
+
+/// One generator behind several policies, so a source injected once draws
+/// the jitter of all of them; clones share it
+#[derive(Clone)]
+pub struct SharedJitterRng(Arc<Mutex<Box<dyn JitterRng>>>);
+
+impl SharedJitterRng {
+    pub fn new(rng: Box<dyn JitterRng>) -> Self {
+        Self(Arc::new(Mutex::new(rng)))
+    }
+}
+
+impl JitterRng for SharedJitterRng {
+    fn gen_range_inclusive(&mut self, max: u64) -> u64 {
+        self.0.lock().unwrap().gen_range_inclusive(max)
+    }
+
+    fn gen_bool(&mut self) -> bool {
+        self.0.lock().unwrap().gen_bool()
+    }
+
+    fn gen_standard_normal(&mut self) -> f64 {
+        self.0.lock().unwrap().gen_standard_normal()
+    }
+}
+
+/// Share of the delay `JitterStrategy::Proportional` adds or subtracts at most
+pub const PROPORTIONAL_JITTER: f64 = 0.2;
 
 pub struct RetryPolicy {
     config: RetryConfig,
+    rng: Mutex<Box<dyn JitterRng>>,
 }
 
 impl RetryPolicy {
     pub fn new(config: RetryConfig) -> Self {
-        Self { config }
+        Self::with_rng(config, Box::new(ThreadRngSource))
+    }
+
+    pub fn with_rng(config: RetryConfig, rng: Box<dyn JitterRng>) -> Self {
+        Self {
+            config,
+            rng: Mutex::new(rng),
+        }
     }
 
     /// Calculate the delay for the next retry attempt using exponential backoff
     pub fn calculate_delay(&self, attempt: u32) -> u64 {
-        if attempt == 0 {
+        if self.is_immediate(attempt) {
             return 0;
         }
 
-        // Calculate exponential backoff: initial_delay * (multiplier ^ (attempt - 1))
-        let base_delay = self.config.initial_delay_ms as f64
-            * self.config.backoff_multiplier.powi((attempt - 1) as i32);
-
-        // Cap at max delay
-        let capped_delay = base_delay.min(self.config.max_delay_ms as f64) as u64;
+        let capped_delay = self.backoff(attempt);
 
         // Add jitter if enabled
         let delay_with_jitter = if self.config.jitter {
@@ -29,21 +106,259 @@ impl RetryPolicy {
         } else {
             capped_delay
         };
-        
+
+        // Enforce the minimum gap between attempts, which jitter can't undercut
+        let floored_delay = delay_with_jitter.max(self.config.min_delay_ms);
+
         // Ensure we don't exceed max_delay even with jitter
-        delay_with_jitter.min(self.config.max_delay_ms)
+        floored_delay.min(self.config.max_delay_ms)
+    }
+
+    /// `calculate_delay` stretched for engine load, still capped at
+    /// `max_delay_ms`
+    ///
+    /// `load_factor` runs from 0.0 (idle, no stretch) to 1.0 (saturated, a
+    /// stretch of `1 + load_stretch`); values outside that are clamped.
+    pub fn calculate_delay_loaded(&self, attempt: u32, load_factor: f64) -> u64 {
+        let delay = self.calculate_delay(attempt);
+        let load_factor = if load_factor.is_nan() {
+            0.0
+        } else {
+            load_factor.clamp(0.0, 1.0)
+        };
+        let stretched = delay as f64 * (1.0 + load_factor * self.config.load_stretch);
+        (stretched as u64).max(delay).min(self.config.max_delay_ms)
+    }
+
+    /// How long a client should wait on the retry scheduled after `attempt`
+    /// before counting it failed: `attempt_timeout_ms` for the first retry,
+    /// times `attempt_timeout_multiplier` for each one after, capped at
+    /// `max_attempt_timeout_ms`. 0 when no timeout is configured.
+    pub fn attempt_timeout_ms(&self, attempt: u32) -> u64 {
+        let base_ms = self.config.attempt_timeout_ms;
+        if base_ms == 0 {
+            return 0;
+        }
+        let cap_ms = match self.config.max_attempt_timeout_ms {
+            0 => MAX_RETRY_DELAY_MS,
+            cap_ms => cap_ms,
+        };
+        let exponent = i32::try_from(attempt.saturating_sub(1)).unwrap_or(i32::MAX);
+        let timeout = base_ms as f64 * self.config.attempt_timeout_multiplier.powi(exponent);
+        // As in `backoff`, compare in f64 so an overflowed timeout caps
+        if timeout >= cap_ms as f64 {
+            return cap_ms;
+        }
+        timeout as u64
+    }
+
+    /// The delay `calculate_delay` gives an attempt before jitter
+    pub fn nominal_delay(&self, attempt: u32) -> u64 {
+        if self.is_immediate(attempt) {
+            return 0;
+        }
+        self.backoff(attempt)
+            .max(self.config.min_delay_ms)
+            .min(self.config.max_delay_ms)
+    }
+
+    /// The shortest and longest delay `calculate_delay` can give an attempt,
+    /// after jitter, `min_delay_ms` and `max_delay_ms`; equal without jitter
+    pub fn jitter_bounds(&self, attempt: u32) -> (u64, u64) {
+        if self.is_immediate(attempt) {
+            return (0, 0);
+        }
+        let delay = self.backoff(attempt);
+        let (low, high) = if !self.config.jitter {
+            (delay, delay)
+        } else {
+            match self.config.jitter_strategy {
+                JitterStrategy::Proportional => {
+                    let jitter_range = self.cap_jitter(proportional_range(delay));
+                    (
+                        delay.saturating_sub(jitter_range),
+                        delay.saturating_add(jitter_range),
+                    )
+                }
+                JitterStrategy::EqualJitter => (delay - self.cap_jitter(delay / 2), delay),
+                JitterStrategy::Full => (delay - self.cap_jitter(delay), delay),
+            }
+        };
+        let clamp = |delay: u64| {
+            delay
+                .max(self.config.min_delay_ms)
+                .min(self.config.max_delay_ms)
+        };
+        (clamp(low), clamp(high))
+    }
+
+    /// The longest a transaction can spend waiting between retries: every
+    /// delay at the top of its jitter range and fully stretched for load,
+    /// each capped at `max_delay_ms`, and the total no more than
+    /// `max_elapsed_ms` when that's set
+    pub fn max_total_wait_ms(&self) -> u64 {
+        let total_ms = (1..self.config.max_attempts)
+            .map(|attempt| {
+                let (_, high) = self.jitter_bounds(attempt);
+                let stretched = high as f64 * (1.0 + self.config.load_stretch);
+                (stretched as u64).max(high).min(self.config.max_delay_ms)
+            })
+            .fold(0u64, u64::saturating_add);
+        match self.config.max_elapsed_ms {
+            0 => total_ms,
+            max_elapsed_ms => total_ms.min(max_elapsed_ms),
+        }
+    }
+
+    /// Whether an attempt due at `at_ms` is within `max_elapsed_ms` of the
+    /// transaction's first scheduled retry
+    pub fn within_max_elapsed(&self, first_scheduled_at_ms: u64, at_ms: u64) -> bool {
+        self.config.max_elapsed_ms == 0
+            || at_ms <= first_scheduled_at_ms.saturating_add(self.config.max_elapsed_ms)
+    }
+
+    /// When the last attempt the transaction will get is due, given that the
+    /// one after `attempt` is due at `next_retry_at_ms` and every remaining
+    /// one fails on the nominal schedule
+    pub fn projected_final_attempt_ms(
+        &self,
+        attempt: u32,
+        next_retry_at_ms: u64,
+        first_scheduled_at_ms: u64,
+    ) -> u64 {
+        self.projected_attempts_ms(attempt, next_retry_at_ms, first_scheduled_at_ms)
+            .last()
+            .unwrap_or(next_retry_at_ms)
+    }
+
+    /// When each attempt left to the transaction is due, starting with the
+    /// one after `attempt` at `next_retry_at_ms`, if every one of them fails
+    /// on the nominal schedule
+    pub fn projected_attempts_ms(
+        &self,
+        attempt: u32,
+        next_retry_at_ms: u64,
+        first_scheduled_at_ms: u64,
+    ) -> impl Iterator<Item = u64> + '_ {
+        let later = (attempt + 1..self.config.max_attempts)
+            .scan(next_retry_at_ms, |at_ms, later| {
+                *at_ms = at_ms.saturating_add(self.nominal_delay(later));
+                Some(*at_ms)
+            })
+            .take_while(move |at_ms| self.within_max_elapsed(first_scheduled_at_ms, *at_ms));
+        std::iter::once(next_retry_at_ms).chain(later)
+    }
+
+    /// When each retry of a transaction that fails at time 0 is due, if every
+    /// attempt fails on the nominal schedule (no jitter or load)
+    pub fn nominal_schedule(&self) -> impl Iterator<Item = u64> + '_ {
+        (1..self.config.max_attempts)
+            .scan(0u64, |at_ms, attempt| {
+                *at_ms = at_ms.saturating_add(self.nominal_delay(attempt));
+                Some(*at_ms)
+            })
+            .take_while(|at_ms| self.within_max_elapsed(0, *at_ms))
+    }
+
+    /// Nominal delays of the retries left to a transaction whose last failed
+    /// attempt is `attempt`, if each of them fails in turn
+    pub fn remaining_delays(&self, attempt: u32) -> impl Iterator<Item = u64> + '_ {
+        (attempt + 1..self.config.max_attempts).map(|later| self.nominal_delay(later))
+    }
+
+    /// Attempt 0 and the first `immediate_retries` retries go out with no
+    /// delay, skipping jitter and the `min_delay_ms` floor
+    fn is_immediate(&self, attempt: u32) -> bool {
+        attempt <= self.config.immediate_retries
+    }
+
+    /// Exponential backoff capped at the max delay, counted from the first
+    /// attempt after the immediate ones:
+    /// initial_delay * (multiplier ^ max(attempt - immediate_retries - backoff_offset, 0))
+    fn backoff(&self, attempt: u32) -> u64 {
+        let max_delay_ms = self.config.max_delay_ms;
+        if self.config.initial_delay_ms == 0 {
+            // Rather than 0 * an overflowed (infinite) multiplier, which is NaN
+            return 0;
+        }
+        let exponent =
+            (attempt - self.config.immediate_retries).saturating_sub(self.config.backoff_offset);
+        let exponent = i32::try_from(exponent).unwrap_or(i32::MAX);
+        let base_delay =
+            self.config.initial_delay_ms as f64 * self.config.backoff_multiplier.powi(exponent);
+        // Compare in f64 so a delay too big for u64 (or infinite) caps rather
+        // than relying on the saturating cast
+        if base_delay >= max_delay_ms as f64 {
+            return max_delay_ms;
+        }
+        base_delay as u64
     }
 
     /// Add random jitter to prevent thundering herd
     fn add_jitter(&self, delay: u64) -> u64 {
-        let mut rng = rand::thread_rng();
-        let jitter_range = (delay as f64 * 0.2) as u64; // ±20% jitter
-        let jitter = rng.gen_range(0..=jitter_range);
-        
-        if rng.gen_bool(0.5) {
-            delay.saturating_add(jitter)
-        } else {
-            delay.saturating_sub(jitter)
+        let mut rng = self.rng.lock().unwrap();
+        match self.config.jitter_strategy {
+            JitterStrategy::Proportional => {
+                let jitter_range = self.cap_jitter(proportional_range(delay));
+                let jitter = match self.config.jitter_distribution {
+                    JitterDistribution::Uniform => rng.gen_range_inclusive(jitter_range),
+                    JitterDistribution::Normal { stddev_factor } => {
+                        normal_jitter(&mut **rng, stddev_factor, jitter_range)
+                    }
+                };
+
+                if rng.gen_bool() {
+                    delay.saturating_add(jitter)
+                } else {
+                    delay.saturating_sub(jitter)
+                }
+            }
+            JitterStrategy::EqualJitter => {
+                // delay/2 + rand(0..=delay/2), rounding the fixed half up so
+                // odd delays can still reach the full delay. A capped jitter
+                // grows the fixed part instead.
+                let random_part = self.cap_jitter(delay / 2);
+                match self.config.jitter_distribution {
+                    JitterDistribution::Uniform => {
+                        delay - random_part + rng.gen_range_inclusive(random_part)
+                    }
+                    // Clustered near the full delay, drawn as the amount taken off it
+                    JitterDistribution::Normal { stddev_factor } => {
+                        delay - normal_jitter(&mut **rng, stddev_factor, random_part)
+                    }
+                }
+            }
+            JitterStrategy::Full => {
+                // rand(0..=delay); a capped jitter keeps the rest of the delay
+                let random_part = self.cap_jitter(delay);
+                match self.config.jitter_distribution {
+                    JitterDistribution::Uniform => {
+                        delay - random_part + rng.gen_range_inclusive(random_part)
+                    }
+                    JitterDistribution::Normal { stddev_factor } => {
+                        delay - normal_jitter(&mut **rng, stddev_factor, random_part)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Limit a jitter range to `max_jitter_ms`, if set
+    fn cap_jitter(&self, jitter_range: u64) -> u64 {
+        match self.config.max_jitter_ms {
+            0 => jitter_range,
+            max_jitter_ms => jitter_range.min(max_jitter_ms),
+        }
+    }
+
+    /// The attempt a client's `requested` number counts as, given the
+    /// attempt the transaction's pending retry (if any) was scheduled after
+    pub fn effective_attempt(&self, requested: u32, pending: Option<u32>) -> u32 {
+        match (self.config.attempt_numbering, pending) {
+            (AttemptNumbering::Sequential, Some(pending)) => {
+                requested.min(pending.saturating_add(1))
+            }
+            _ => requested,
         }
     }
 
@@ -54,11 +369,29 @@ impl RetryPolicy {
     pub fn max_attempts(&self) -> u32 {
         self.config.max_attempts
     }
+
+    pub fn config(&self) -> &RetryConfig {
+        &self.config
+    }
+}
+
+/// Size of a `JitterDistribution::Normal` jitter within `0..=range`: the
+/// magnitude of a normal draw with a standard deviation of `stddev_factor`
+/// times the range, clamped to the range
+fn normal_jitter(rng: &mut dyn JitterRng, stddev_factor: f64, range: u64) -> u64 {
+    let jitter = (rng.gen_standard_normal() * stddev_factor * range as f64).abs();
+    (jitter.round() as u64).min(range)
+}
+
+/// How far `JitterStrategy::Proportional` can move `delay` either way
+fn proportional_range(delay: u64) -> u64 {
+    (delay as f64 * PROPORTIONAL_JITTER) as u64
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Preset;
 
     #[test]
     fn test_exponential_backoff_without_jitter() {
@@ -68,6 +401,7 @@ mod tests {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         let policy = RetryPolicy::new(config);
 
@@ -87,6 +421,7 @@ mod tests {
             max_delay_ms: 5000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         let policy = RetryPolicy::new(config);
 
@@ -111,4 +446,591 @@ mod tests {
         assert!(!policy.should_retry(3));
         assert!(!policy.should_retry(4));
     }
+
+    /// Replays fixed jitter values and add/subtract choices
+    struct StubRng {
+        jitters: Vec<u64>,
+        adds: Vec<bool>,
+    }
+
+    impl JitterRng for StubRng {
+        fn gen_range_inclusive(&mut self, max: u64) -> u64 {
+            self.jitters.remove(0).min(max)
+        }
+
+        fn gen_bool(&mut self) -> bool {
+            self.adds.remove(0)
+        }
+    }
+
+    #[test]
+    fn test_jitter_with_stub_rng_is_exact() {
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            ..Default::default()
+        };
+        let rng = StubRng {
+            jitters: vec![150, 300],
+            adds: vec![true, false],
+        };
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
+        // Attempt 1: base 1000, +150
+        assert_eq!(policy.calculate_delay(1), 1150);
+        // Attempt 2: base 2000, -300
+        assert_eq!(policy.calculate_delay(2), 1700);
+    }
+
+    #[test]
+    fn test_shared_rng_feeds_every_policy_in_turn() {
+        let config = RetryConfig {
+            jitter: true,
+            ..Default::default()
+        };
+        let rng = SharedJitterRng::new(Box::new(StubRng {
+            jitters: vec![150, 300],
+            adds: vec![true, false],
+        }));
+        let first = RetryPolicy::with_rng(config.clone(), Box::new(rng.clone()));
+        let second = RetryPolicy::with_rng(config, Box::new(rng));
+
+        assert_eq!(first.calculate_delay(1), 1150);
+        assert_eq!(second.calculate_delay(1), 700);
+    }
+
+    #[test]
+    fn test_min_delay_floors_every_attempt() {
+        let config = RetryConfig {
+            max_attempts: 10,
+            initial_delay_ms: 10,
+            max_delay_ms: 60000,
+            backoff_multiplier: 1.1,
+            jitter: false,
+            min_delay_ms: 500,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(config);
+
+        assert_eq!(policy.calculate_delay(0), 0);
+        for attempt in 1..10 {
+            assert!(policy.calculate_delay(attempt) >= 500);
+        }
+        assert_eq!(policy.calculate_delay(1), 500);
+    }
+
+    #[test]
+    fn test_min_delay_applies_after_jitter() {
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            min_delay_ms: 1000,
+            ..Default::default()
+        };
+        let rng = StubRng {
+            jitters: vec![200],
+            adds: vec![false],
+        };
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
+        assert_eq!(policy.calculate_delay(1), 1000);
+    }
+
+    #[test]
+    fn test_min_delay_above_max_is_invalid() {
+        let config = RetryConfig {
+            max_delay_ms: 1000,
+            min_delay_ms: 2000,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        assert!(RetryConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_equal_jitter_with_stub_rng_is_exact() {
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_delay_ms: 1001,
+            max_delay_ms: 60000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            jitter_strategy: JitterStrategy::EqualJitter,
+            ..Default::default()
+        };
+        let rng = StubRng {
+            jitters: vec![0, 1000],
+            adds: vec![],
+        };
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
+        // Attempt 1: base 1001, fixed half 501 + 0
+        assert_eq!(policy.calculate_delay(1), 501);
+        // Attempt 2: base 2002, fixed half 1001 + 1000
+        assert_eq!(policy.calculate_delay(2), 2001);
+    }
+
+    #[test]
+    fn test_unit_multiplier_is_constant_backoff() {
+        let config = RetryConfig {
+            initial_delay_ms: 1500,
+            backoff_multiplier: 1.0,
+            jitter: false,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+
+        let policy = RetryPolicy::new(config);
+        for attempt in 1..=5 {
+            assert_eq!(policy.calculate_delay(attempt), 1500);
+        }
+    }
+
+    #[test]
+    fn test_shrinking_or_non_finite_multiplier_is_invalid() {
+        for backoff_multiplier in [0.5, 0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let config = RetryConfig {
+                backoff_multiplier,
+                ..Default::default()
+            };
+            let err = config.validate().unwrap_err();
+            assert!(err.contains("backoff_multiplier"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_projected_final_attempt_follows_nominal_schedule() {
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_delay_ms: 1000,
+            max_delay_ms: 5000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(config.clone());
+
+        // Attempts 2, 3 and 4 still to be retried: 2000 + 4000 + 5000 (capped)
+        assert_eq!(policy.projected_final_attempt_ms(1, 10_000, 9_000), 21_000);
+        // Only the last allowed attempt left
+        assert_eq!(policy.projected_final_attempt_ms(4, 10_000, 0), 10_000);
+        assert_eq!(policy.nominal_delay(0), 0);
+
+        // Attempt 4 would be due 15000 after the first scheduled retry
+        let policy = RetryPolicy::new(RetryConfig {
+            max_elapsed_ms: 10_000,
+            ..config
+        });
+        assert_eq!(policy.projected_final_attempt_ms(1, 10_000, 9_000), 16_000);
+        assert!(policy.within_max_elapsed(9_000, 19_000));
+        assert!(!policy.within_max_elapsed(9_000, 19_001));
+    }
+
+    #[test]
+    fn test_load_stretches_delay_up_to_cap() {
+        let config = RetryConfig {
+            initial_delay_ms: 1000,
+            max_delay_ms: 5000,
+            backoff_multiplier: 2.0,
+            jitter: false,
+            load_stretch: 2.0,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(config);
+
+        assert_eq!(policy.calculate_delay_loaded(1, 0.0), 1000);
+        assert_eq!(policy.calculate_delay_loaded(1, 0.5), 2000);
+        assert_eq!(policy.calculate_delay_loaded(1, 1.0), 3000);
+        // Out-of-range load is clamped
+        assert_eq!(policy.calculate_delay_loaded(1, 7.0), 3000);
+        // 4000 * 3 is well past the cap
+        assert_eq!(policy.calculate_delay_loaded(3, 1.0), 5000);
+        assert_eq!(policy.calculate_delay_loaded(0, 1.0), 0);
+    }
+
+    #[test]
+    fn test_huge_initial_delay_caps_at_max() {
+        let config = RetryConfig {
+            max_attempts: 100,
+            initial_delay_ms: u64::MAX,
+            max_delay_ms: 60000,
+            jitter: false,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let policy = RetryPolicy::new(config.clone());
+
+        for attempt in [1, 2, 64, 99, u32::MAX] {
+            assert_eq!(policy.calculate_delay(attempt), 60000);
+            assert_eq!(policy.nominal_delay(attempt), 60000);
+        }
+
+        // Even with no real cap, the delay saturates rather than wrapping
+        let uncapped = RetryPolicy::new(RetryConfig {
+            max_delay_ms: u64::MAX,
+            jitter: true,
+            ..config
+        });
+        assert_eq!(uncapped.nominal_delay(3), u64::MAX);
+        assert!(uncapped.calculate_delay(3) > u64::MAX / 2);
+    }
+
+    #[test]
+    fn test_delays_above_ceiling_are_invalid() {
+        let config = RetryConfig {
+            max_delay_ms: crate::MAX_RETRY_DELAY_MS + 1,
+            ..Default::default()
+        };
+        assert!(config.validate().unwrap_err().contains("max_delay_ms"));
+        let config = RetryConfig {
+            initial_delay_ms: crate::MAX_RETRY_DELAY_MS,
+            max_delay_ms: crate::MAX_RETRY_DELAY_MS,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_immediate_retries_precede_backoff() {
+        let config = RetryConfig {
+            max_attempts: 6,
+            initial_delay_ms: 1000,
+            max_delay_ms: 60000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            min_delay_ms: 500,
+            immediate_retries: 2,
+            ..Default::default()
+        };
+        let rng = StubRng {
+            jitters: vec![0, 0],
+            adds: vec![true, true],
+        };
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
+        // No delay, jitter or floor for the immediate attempts
+        assert_eq!(policy.calculate_delay(1), 0);
+        assert_eq!(policy.calculate_delay(2), 0);
+        assert_eq!(policy.calculate_delay_loaded(2, 1.0), 0);
+        // Backoff starts over from initial_delay_ms
+        assert_eq!(policy.calculate_delay(3), 1000);
+        assert_eq!(policy.calculate_delay(4), 2000);
+        assert_eq!(
+            policy.nominal_schedule().collect::<Vec<_>>(),
+            vec![0, 0, 1000, 3000, 7000]
+        );
+    }
+
+    #[test]
+    fn test_jitter_bounds_at_known_attempt() {
+        let config = RetryConfig {
+            initial_delay_ms: 1000,
+            max_delay_ms: 60000,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            ..Default::default()
+        };
+        // Attempt 3: base 4000
+        let symmetric = RetryPolicy::new(config.clone());
+        assert_eq!(symmetric.jitter_bounds(3), (3200, 4800));
+        let equal = RetryPolicy::new(RetryConfig {
+            jitter_strategy: JitterStrategy::EqualJitter,
+            ..config.clone()
+        });
+        assert_eq!(equal.jitter_bounds(3), (2000, 4000));
+        let full = RetryPolicy::new(RetryConfig {
+            jitter_strategy: JitterStrategy::Full,
+            ..config.clone()
+        });
+        assert_eq!(full.jitter_bounds(3), (0, 4000));
+        for _ in 0..100 {
+            assert!(full.calculate_delay(3) <= 4000);
+        }
+        let none = RetryPolicy::new(RetryConfig {
+            jitter: false,
+            ..config.clone()
+        });
+        assert_eq!(none.jitter_bounds(3), (4000, 4000));
+        assert_eq!(none.jitter_bounds(0), (0, 0));
+
+        // The floor and cap apply to each end
+        let clamped = RetryPolicy::new(RetryConfig {
+            max_delay_ms: 4500,
+            min_delay_ms: 3500,
+            ..config
+        });
+        assert_eq!(clamped.jitter_bounds(3), (3500, 4500));
+        for _ in 0..100 {
+            let delay = clamped.calculate_delay(3);
+            assert!((3500..=4500).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn test_max_jitter_caps_spread_of_large_delays() {
+        let config = RetryConfig {
+            initial_delay_ms: 60000,
+            max_delay_ms: 120000,
+            jitter: true,
+            max_jitter_ms: 500,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new(config.clone());
+        // 20% of 60000 would be 12000 either way
+        assert_eq!(policy.jitter_bounds(1), (59500, 60500));
+        for _ in 0..200 {
+            let delay = policy.calculate_delay(1);
+            assert!(delay.abs_diff(60000) <= 500, "{}", delay);
+        }
+
+        let equal = RetryPolicy::new(RetryConfig {
+            jitter_strategy: JitterStrategy::EqualJitter,
+            ..config.clone()
+        });
+        assert_eq!(equal.jitter_bounds(1), (59500, 60000));
+        for _ in 0..200 {
+            assert!((59500..=60000).contains(&equal.calculate_delay(1)));
+        }
+
+        // The cap only ever narrows the spread
+        let uncapped = RetryPolicy::new(RetryConfig {
+            max_jitter_ms: 0,
+            ..config.clone()
+        });
+        assert_eq!(uncapped.jitter_bounds(1), (48000, 72000));
+        let small = RetryPolicy::new(RetryConfig {
+            initial_delay_ms: 1000,
+            ..config
+        });
+        assert_eq!(small.jitter_bounds(1), (800, 1200));
+    }
+
+    #[test]
+    fn test_sequential_numbering_ignores_skipped_attempts() {
+        let config = RetryConfig {
+            initial_delay_ms: 1000,
+            backoff_multiplier: 2.0,
+            jitter: false,
+            ..Default::default()
+        };
+        let sequential = RetryPolicy::new(config.clone());
+        let literal = RetryPolicy::new(RetryConfig {
+            attempt_numbering: AttemptNumbering::Literal,
+            ..config
+        });
+
+        // Pending retry after attempt 1, client jumps to 3
+        assert_eq!(sequential.effective_attempt(3, Some(1)), 2);
+        assert_eq!(literal.effective_attempt(3, Some(1)), 3);
+        // Resent or in-order numbers, and first contact, are used as sent
+        assert_eq!(sequential.effective_attempt(1, Some(1)), 1);
+        assert_eq!(sequential.effective_attempt(2, Some(1)), 2);
+        assert_eq!(sequential.effective_attempt(4, None), 4);
+    }
+
+    #[test]
+    fn test_normal_jitter_clusters_near_the_base_delay() {
+        use rand::SeedableRng;
+
+        let config = RetryConfig {
+            initial_delay_ms: 10_000,
+            jitter: true,
+            ..Default::default()
+        };
+        let near_base_share = |config: RetryConfig| {
+            let rng = rand::rngs::StdRng::seed_from_u64(42);
+            let policy = RetryPolicy::with_rng(config, Box::new(RngJitter(rng)));
+            let samples = 5_000;
+            let mut near = 0;
+            for _ in 0..samples {
+                let delay = policy.calculate_delay(1);
+                assert!((8_000..=12_000).contains(&delay));
+                if delay.abs_diff(10_000) <= 500 {
+                    near += 1;
+                }
+            }
+            near as f64 / samples as f64
+        };
+
+        // A quarter of the ±2000ms range lies within 500ms of the base delay
+        let uniform = near_base_share(config.clone());
+        assert!((0.2..0.3).contains(&uniform), "uniform share {}", uniform);
+        // With a 600ms standard deviation, about 60% does
+        let normal = near_base_share(RetryConfig {
+            jitter_distribution: JitterDistribution::Normal { stddev_factor: 0.3 },
+            ..config.clone()
+        });
+        assert!((0.55..0.65).contains(&normal), "normal share {}", normal);
+
+        // A wide spread is still clamped to the strategy's range and max_delay_ms
+        let clamped = RetryPolicy::new(RetryConfig {
+            max_delay_ms: 11_000,
+            jitter_distribution: JitterDistribution::Normal { stddev_factor: 5.0 },
+            ..config
+        });
+        for _ in 0..1_000 {
+            assert!((8_000..=11_000).contains(&clamped.calculate_delay(1)));
+        }
+        assert!(RetryConfig {
+            jitter_distribution: JitterDistribution::Normal { stddev_factor: 0.0 },
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_backoff_offset_shifts_the_exponent() {
+        let delays = |backoff_offset| {
+            let policy = RetryPolicy::new(RetryConfig {
+                jitter: false,
+                max_attempts: 10,
+                backoff_offset,
+                ..Default::default()
+            });
+            (1..=5)
+                .map(|attempt| policy.calculate_delay(attempt))
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(delays(1), vec![1000, 2000, 4000, 8000, 16000]);
+        // The first retry already gets one step of growth
+        assert_eq!(delays(0), vec![2000, 4000, 8000, 16000, 32000]);
+        // Exponents that would go negative hold at initial_delay_ms
+        assert_eq!(delays(3), vec![1000, 1000, 1000, 2000, 4000]);
+
+        let immediate = RetryPolicy::new(RetryConfig {
+            jitter: false,
+            immediate_retries: 2,
+            backoff_offset: 0,
+            ..Default::default()
+        });
+        let schedule: Vec<u64> = (1..=4)
+            .map(|attempt| immediate.calculate_delay(attempt))
+            .collect();
+        assert_eq!(schedule, vec![0, 0, 2000, 4000]);
+    }
+
+    #[test]
+    fn test_attempt_timeout_follows_schedule() {
+        let fixed = RetryPolicy::new(RetryConfig::default());
+        for attempt in 1..=5 {
+            assert_eq!(
+                fixed.attempt_timeout_ms(attempt),
+                crate::DEFAULT_ATTEMPT_TIMEOUT_MS
+            );
+        }
+
+        let growing = RetryPolicy::new(RetryConfig {
+            attempt_timeout_ms: 2_000,
+            attempt_timeout_multiplier: 2.0,
+            max_attempt_timeout_ms: 10_000,
+            ..Default::default()
+        });
+        let timeouts: Vec<u64> = (1..=5)
+            .map(|attempt| growing.attempt_timeout_ms(attempt))
+            .collect();
+        assert_eq!(timeouts, vec![2_000, 4_000, 8_000, 10_000, 10_000]);
+        assert_eq!(growing.attempt_timeout_ms(u32::MAX), 10_000);
+
+        let none = RetryPolicy::new(RetryConfig {
+            attempt_timeout_ms: 0,
+            ..Default::default()
+        });
+        assert_eq!(none.attempt_timeout_ms(3), 0);
+
+        let shrinking = RetryConfig {
+            attempt_timeout_multiplier: 0.5,
+            ..Default::default()
+        };
+        assert!(shrinking.validate().is_err());
+    }
+
+    #[test]
+    fn test_max_total_wait_bounds_sampled_schedules() {
+        use rand::SeedableRng;
+
+        let config = RetryConfig {
+            max_attempts: 5,
+            initial_delay_ms: 100,
+            max_delay_ms: 600,
+            backoff_multiplier: 2.0,
+            jitter: true,
+            load_stretch: 0.5,
+            ..Default::default()
+        };
+        let rng = rand::rngs::StdRng::seed_from_u64(7);
+        let policy = RetryPolicy::with_rng(config.clone(), Box::new(RngJitter(rng)));
+        // Nominal 100, 200, 400, 600 (capped); +20% jitter then +50% load,
+        // capped again: 180, 360, 600, 600
+        assert_eq!(policy.max_total_wait_ms(), 1740);
+
+        // No sampled schedule goes past the bound, and each attempt's
+        // sampled worst case reaches its share of it
+        let mut worst = [0u64; 4];
+        for _ in 0..20_000 {
+            let delays: Vec<u64> = (1..5)
+                .map(|attempt| policy.calculate_delay_loaded(attempt, 1.0))
+                .collect();
+            assert!(delays.iter().sum::<u64>() <= policy.max_total_wait_ms());
+            for (worst, delay) in worst.iter_mut().zip(delays) {
+                *worst = (*worst).max(delay);
+            }
+        }
+        assert_eq!(worst.iter().sum::<u64>(), policy.max_total_wait_ms());
+
+        let limited = RetryPolicy::new(RetryConfig {
+            max_elapsed_ms: 1000,
+            ..config.clone()
+        });
+        assert_eq!(limited.max_total_wait_ms(), 1000);
+        let no_jitter = RetryPolicy::new(RetryConfig {
+            jitter: false,
+            load_stretch: 0.0,
+            ..config
+        });
+        assert_eq!(
+            no_jitter.max_total_wait_ms(),
+            no_jitter.nominal_schedule().last().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_presets_validate_and_order_their_delays() {
+        for preset in [Preset::Conservative, Preset::Balanced, Preset::Aggressive] {
+            assert!(RetryConfig::preset(preset).validate().is_ok(), "{preset:?}");
+        }
+        assert_eq!(
+            RetryConfig::preset(Preset::Balanced),
+            RetryConfig::default()
+        );
+
+        let delays = |preset| {
+            let policy = RetryPolicy::new(RetryConfig {
+                jitter: false,
+                ..RetryConfig::preset(preset)
+            });
+            (1..=3)
+                .map(|attempt| policy.calculate_delay(attempt))
+                .collect::<Vec<u64>>()
+        };
+        let conservative = delays(Preset::Conservative);
+        let balanced = delays(Preset::Balanced);
+        let aggressive = delays(Preset::Aggressive);
+        assert_eq!(conservative, vec![2000, 6000, 18000]);
+        assert_eq!(aggressive, vec![200, 300, 450]);
+        for attempt in 0..3 {
+            assert!(aggressive[attempt] < balanced[attempt]);
+            assert!(balanced[attempt] < conservative[attempt]);
+        }
+        let aggressive = RetryConfig::preset(Preset::Aggressive);
+        let conservative = RetryConfig::preset(Preset::Conservative);
+        assert!(aggressive.max_delay_ms < conservative.max_delay_ms);
+        assert!(aggressive.max_attempts > conservative.max_attempts);
+    }
 }
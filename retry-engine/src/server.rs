@@ -1,202 +1,9228 @@
-use crate::circuit_breaker::{CircuitBreaker, CircuitState};
-use crate::dlq::{DLQEntry, DeadLetterQueue};
-use crate::retry_policy::RetryPolicy;
-use crate::{CircuitBreakerConfig, RetryConfig};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{SystemTime, UNIX_EPOCH};
+use crate::circuit_breaker::{
+    CircuitBreaker, CircuitBreakerState, CircuitCanary, CircuitState, OpenActivity, OpenReason,
+    TimeInState,
+};
+use crate::clock::{Clock, SystemClock};
+use crate::dlq::{
+    DLQEntry, DeadLetterQueue, DlqEntryStatus, DlqError, DlqReason, ExpiryHook, FailureComparison,
+    FlushHook, PayloadBudget, PspQuota, ReplayThrottle,
+};
+use crate::event_log::{EngineEvent, EventLog};
+use crate::metrics::{
+    AdmissionTracker, DriftTracker, Exemplar, ExemplarCounter, FinalAttemptTracker,
+    HealthScoreConfig, Histogram, InFlightTracker, OutcomeTimeoutAction, OutcomeTracker,
+    RetryTimeSeries, DEFAULT_ATTEMPT_BUCKETS, DEFAULT_DRIFT_BUCKETS,
+    DEFAULT_FAILURE_INTERVAL_BUCKETS, DEFAULT_LATENCY_BUCKETS, MAX_ADMISSION_BUCKETS,
+};
+use crate::persistence::{DlqStore, PersistenceError, PersistenceMode};
+use crate::rate_limit::RateLimiter;
+use crate::retry_budget::RetryBudget;
+use crate::retry_policy::{JitterRng, RetryPolicy, SharedJitterRng, ThreadRngSource};
+use crate::sharding::StripedLock;
+use crate::single_flight::SingleFlight;
+use crate::wal::{DlqWal, WalRecord};
+use crate::{
+    AttemptNumbering, AttemptOverflow, BlankIds, CircuitBreakerConfig, CircuitResetSchedule,
+    HalfOpenClose, JitterConfig, JitterDistribution, JitterStrategy, PspAccessPolicy,
+    PspNormalizer, PspOverflow, RetryConfig, ServerConfig, DEFAULT_JITTER_STDDEV_FACTOR,
+    DEFAULT_MAX_ATTEMPT_NUMBER,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tonic::metadata::MetadataMap;
+use tonic::transport::Server;
 use tonic::{Request, Response, Status};
-
-fn current_timestamp_ms() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_millis() as u64
-}
+use tracing::{info, warn};
 
 pub mod retry {
     tonic::include_proto!("retry");
 }
 
+/// Server builder with keepalive and request timeouts applied, so dead
+/// connections are reaped and slow requests are cancelled
+pub fn server_builder(config: &ServerConfig) -> Server {
+    Server::builder()
+        .http2_keepalive_interval(Some(Duration::from_millis(config.keepalive_interval_ms)))
+        .http2_keepalive_timeout(Some(Duration::from_millis(config.keepalive_timeout_ms)))
+        .timeout(Duration::from_millis(config.request_timeout_ms))
+}
+
+/// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The deadline a client set with the `grpc-timeout` header, as Unix ms
+/// counted from `now_ms`
+fn grpc_deadline_ms(metadata: &MetadataMap, now_ms: u64) -> Option<u64> {
+    let value = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let unit_at = value.len().checked_sub(1)?;
+    let (amount, unit) = value.split_at(unit_at);
+    let amount: u64 = amount.parse().ok()?;
+    let timeout_ms = match unit {
+        "H" => amount.saturating_mul(60 * 60 * 1000),
+        "M" => amount.saturating_mul(60 * 1000),
+        "S" => amount.saturating_mul(1000),
+        "m" => amount,
+        "u" => amount / 1000,
+        "n" => amount / 1_000_000,
+        _ => return None,
+    };
+    Some(now_ms.saturating_add(timeout_ms))
+}
+
+/// The trace ID in a W3C `traceparent` header
+/// (`<version>-<trace id>-<parent id>-<flags>`), if the request carried a
+/// valid one
+fn traceparent_trace_id(metadata: &MetadataMap) -> Option<String> {
+    let value = metadata.get("traceparent")?.to_str().ok()?;
+    let trace_id = value.split('-').nth(1)?;
+    let valid = trace_id.len() == 32
+        && trace_id
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+        && trace_id.bytes().any(|b| b != b'0');
+    valid.then(|| trace_id.to_string())
+}
+
 use retry::retry_engine_server::RetryEngine;
 use retry::{
-    CircuitRequest, CircuitResponse, CircuitState as ProtoCircuitState, RetryRequest,
-    RetryResponse, RetryStatusRequest, RetryStatusResponse,
+    AddMaintenanceWindowRequest, AttemptNumbering as ProtoAttemptNumbering,
+    BatchGetCircuitStatusRequest, BatchGetCircuitStatusResponse, BatchGetRetryStatusRequest,
+    BatchGetRetryStatusResponse, BulkReplayDlqRequest, BulkReplayDlqResponse, CancelRetryRequest,
+    CancelRetryResponse, CircuitBreakerConfig as ProtoCircuitBreakerConfig, CircuitCanaryRequest,
+    CircuitCanaryResponse, CircuitRejectionsRequest, CircuitRejectionsResponse, CircuitRequest,
+    CircuitResponse, CircuitState as ProtoCircuitState, CompleteDlqLeaseRequest, ConfigChange,
+    DlqChangesSinceRequest, DlqChangesSinceResponse, DlqEntryStatus as ProtoDlqEntryStatus,
+    DlqEntrySummary, DlqPspSummary, DlqReason as ProtoDlqReason, DumpStateRequest,
+    DumpStateResponse, EffectivePspConfigRequest, EffectivePspConfigResponse, EngineHealthRequest,
+    EngineHealthResponse, EvaluateTransactionRequest, EvaluateTransactionResponse,
+    FailureComparison as ProtoFailureComparison, FlushDlqRequest, FlushDlqResponse,
+    ForceToDlqRequest, JitterDistribution as ProtoJitterDistribution,
+    JitterStrategy as ProtoJitterStrategy, LeaseDlqEntryRequest, LeaseDlqEntryResponse,
+    ListCircuitsRequest, ListDlqEntriesRequest, ListDlqEntriesResponse, ListNearExhaustionRequest,
+    ListParkedEntriesRequest, ListRetriesByPspRequest, ListRetriesByPspResponse, MaintenanceWindow,
+    MaintenanceWindowsResponse, MarkResolvedRequest, MarkResolvedResponse, MetricsRequest,
+    MetricsResponse, NextProbeRequest, NextProbeResponse, OpenActivity as ProtoOpenActivity,
+    OpenReason as ProtoOpenReason, ParkDlqEntryRequest, ProjectRetryLoadRequest,
+    ProjectRetryLoadResponse, ProjectedRetryBucket, PspAccessPolicy as ProtoPspAccessPolicy,
+    PspCircuitOverride, PspHealthRequest, PspHealthResponse, PurgeDlqRequest, PurgeDlqResponse,
+    ReleaseDlqLeaseRequest, ReplayDlqEntryRequest, ReplayDlqEntryResponse, ReportOutcomeRequest,
+    ReportOutcomeResponse, RetryConfig as ProtoRetryConfig, RetryEntry, RetryRequest,
+    RetryResponse, RetryStatusRequest, RetryStatusResponse, RetryTimeSeriesBucket,
+    RetryTimeSeriesRequest, RetryTimeSeriesResponse, RetryTimelineRequest, RetryTimelineResponse,
+    ScheduledReplay, SetCircuitCanaryRequest, SetCircuitConfigRequest, SetCircuitConfigResponse,
+    SetCircuitResetScheduleRequest, SetCircuitResetScheduleResponse, SetEnginePausedRequest,
+    SetEnginePausedResponse, SetPspAccessPolicyRequest, SetRetryConfigRequest,
+    SetRetryConfigResponse, TimeInState as ProtoTimeInState, TimelineAttempt,
+    UnparkDlqEntryRequest, UpdateDlqEntryStatusRequest, ValidateConfigRequest,
+    ValidateConfigResponse,
 };
 
-#[derive(Clone)]
+/// Corrections `RetryEngineService::reconcile` made, each list sorted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconcileReport {
+    /// Retry states dropped because the transaction is dead-lettered and not
+    /// being replayed
+    pub removed_retry_states: Vec<String>,
+    /// DLQ entries marked as replaying with no retry behind them, made
+    /// ordinary dead letters again
+    pub cleared_replaying: Vec<String>,
+    /// PSPs whose circuit was Open past its timeout, moved to HalfOpen
+    pub half_opened_circuits: Vec<String>,
+}
+
+impl ReconcileReport {
+    pub fn is_empty(&self) -> bool {
+        self.removed_retry_states.is_empty()
+            && self.cleared_replaying.is_empty()
+            && self.half_opened_circuits.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 struct RetryState {
+    psp_name: String,
     attempt_count: u32,
     last_error: String,
     last_attempt_at_ms: u64,
+    next_retry_at_ms: u64,
+    /// Held so the transaction can be dead-lettered with its payload; counted
+    /// against the DLQ's payload budget
+    payload: Vec<u8>,
+    /// How to interpret `payload`, as the DLQ entry will describe it; empty
+    /// for the default
+    content_type: String,
+    encoding: Option<String>,
+    tags: Vec<String>,
+    /// When the transaction's first retry was scheduled (or it was last
+    /// replayed), the start of `max_elapsed_ms`
+    first_scheduled_at_ms: u64,
+    /// When the attempt counts as unreported if no outcome has arrived, by
+    /// the service clock; 0 once reported, or with no outcome timeout
+    outcome_due_at_ms: u64,
+    /// The attempt's outcome was reported or timed out, so a further report
+    /// for it is only logged
+    outcome_settled: bool,
+    /// The group dead-lettered together with this transaction; empty for
+    /// none
+    group_id: String,
+    /// When the scheduled attempt is due by the service clock, for timing its
+    /// follow-up; 0 once timed, or if unknown
+    due_at_ms: u64,
+    /// How late the last timed follow-up came after its attempt was due
+    drift_ms: u64,
+}
+
+/// Breaker shared by every PSP past `max_psp_breakers` under
+/// `PspOverflow::Shared`
+pub const OVERFLOW_BREAKER: &str = "(overflow)";
+
+/// Most buckets `ProjectRetryLoad` returns; a finer `bucket_ms` is refused
+pub const MAX_PROJECTION_BUCKETS: u64 = 1_000;
+
+/// Most retry states `DumpState` lists; the rest are only counted
+pub const MAX_DUMPED_RETRY_STATES: usize = 1_000;
+
+/// `LeaseDlqEntry` lease length when the request doesn't set one
+pub const DEFAULT_DLQ_LEASE_MS: u64 = 60_000;
+
+/// A replay's place in its PSP's `min_replay_interval_ms` spacing
+struct ReplaySlot {
+    /// Delay the replayed attempt gets
+    delay_ms: u64,
+    /// When the attempt is due
+    due_ms: u64,
+    /// The PSP's slot before this one, restored if the replay fails
+    previous_ms: Option<u64>,
+}
+
+/// A PSP's maintenance windows as `(start_ms, end_ms)`, merged and sorted by
+/// start
+type MaintenanceWindows = Vec<(u64, u64)>;
+
+/// Stripes of the per-transaction lock
+const TRANSACTION_LOCK_STRIPES: usize = 64;
+
+/// Replays allowed per DLQ entry before `force` is required; 0 for no limit
+pub const DEFAULT_MAX_REPLAYS: u32 = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct ScheduledReset {
+    schedule: CircuitResetSchedule,
+    next_reset_at_ms: u64,
 }
 
 pub struct RetryEngineService {
-    retry_policy: Arc<RetryPolicy>,
+    /// Replaced whole by `SetRetryConfig`
+    retry_policy: Arc<Mutex<Arc<RetryPolicy>>>,
+    /// The policies of PSPs with a jitter override, rebuilt along with
+    /// `retry_policy`
+    psp_retry_policies: Arc<Mutex<HashMap<String, Arc<RetryPolicy>>>>,
+    /// Draws the jitter of every retry policy and the spread of bulk replays
+    rng: SharedJitterRng,
+    /// Always locked after `retry_states` when both are needed
     circuit_breakers: Arc<Mutex<HashMap<String, CircuitBreaker>>>,
     dlq: Arc<DeadLetterQueue>,
     retry_states: Arc<Mutex<HashMap<String, RetryState>>>,
     circuit_config: CircuitBreakerConfig,
+    /// Per-PSP circuit configs that replace `circuit_config` for that PSP only.
+    /// Always locked after `circuit_breakers` when both are needed.
+    circuit_overrides: Arc<Mutex<HashMap<String, CircuitBreakerConfig>>>,
+    /// Per-PSP jitter settings that replace those of the retry config for
+    /// that PSP only
+    jitter_overrides: HashMap<String, JitterConfig>,
+    time_series: Arc<RetryTimeSeries>,
+    /// Transactions with a live retry state, per PSP. Always locked after
+    /// `retry_states` when both are needed.
+    in_flight: Arc<InFlightTracker>,
+    /// Attempt outcomes reported by clients, per PSP
+    outcomes: Arc<OutcomeTracker>,
+    /// How late retries were followed up, per PSP
+    retry_drift: Arc<DriftTracker>,
+    /// Attempt each transaction succeeded or was dead-lettered at, per PSP
+    final_attempts: Arc<FinalAttemptTracker>,
+    /// Breaker checks and rejections per PSP over time
+    admissions: Arc<AdmissionTracker>,
+    /// Retries scheduled and DLQ adds per PSP, with exemplars for `GetMetrics`
+    retries_scheduled: Arc<ExemplarCounter>,
+    dlq_adds: Arc<ExemplarCounter>,
+    /// PSPs blocked by policy, whatever their circuit state
+    psp_access: Arc<Mutex<PspAccessPolicy>>,
+    /// Kill switch: while set no new retries are scheduled or replayed
+    paused: Arc<AtomicBool>,
+    /// Engine load from 0.0 to 1.0 as `f64` bits, stretching retry delays
+    load_factor: Arc<AtomicU64>,
+    /// Snapshot store for the DLQ; `None` keeps it in memory only
+    dlq_store: Option<Arc<DlqStore>>,
+    /// Write-ahead log for the DLQ; replaces `dlq_store` when set
+    dlq_wal: Option<Arc<DlqWal>>,
+    /// DLQ revision already written to `dlq_wal`
+    wal_revision: Arc<Mutex<u64>>,
+    /// Persistence was configured but unusable, or the last WAL append
+    /// failed, so DLQ changes are held in memory only
+    persistence_degraded: Arc<AtomicBool>,
+    /// A background retry of a failed WAL append is pending
+    wal_retrying: Arc<AtomicBool>,
+    event_log: Option<Arc<dyn EventLog>>,
+    /// DLQ revision already written to `event_log`
+    dlq_logged_revision: Arc<Mutex<u64>>,
+    /// Time source for scheduled circuit resets
+    clock: Arc<dyn Clock>,
+    /// Per-PSP recurring circuit resets
+    reset_schedules: Arc<Mutex<HashMap<String, ScheduledReset>>>,
+    /// Announced maintenance per PSP
+    maintenance_windows: Arc<Mutex<HashMap<String, MaintenanceWindows>>>,
+    /// Per-transaction critical section for decisions that read and then
+    /// change where a transaction is (retrying or dead-lettered). Always
+    /// taken before any other lock. Only a group being dead-lettered takes
+    /// more than one, through `lock_all`/`try_lock_with`.
+    transaction_locks: Arc<StripedLock>,
+    /// Coalesces concurrent `schedule_retry` calls for the same transaction
+    /// and attempt into one decision
+    schedule_calls: Arc<SingleFlight<(String, i32), RetryResponse>>,
+    /// Replays allowed per DLQ entry before an operator must force one
+    max_replays: u32,
+    /// Bearer token for admin RPCs; `None` refuses them
+    admin_token: Option<String>,
+    /// Soft DLQ cooldown: dead letters replay by themselves this long after
+    /// arriving; `None` leaves every replay to an operator
+    soft_dlq_cooldown_ms: Option<u64>,
+    /// Dead letters older than this are expired; `None` keeps them until
+    /// removed
+    dlq_max_age_ms: Option<u64>,
+    /// Rejects `ScheduleRetry` calls for one transaction that come too close
+    /// together; `None` allows any rate
+    schedule_rate_limit: Option<Arc<RateLimiter>>,
+    /// Retries allowed per window, per priority tier; `None` for no limit
+    retry_budget: Option<Arc<RetryBudget>>,
+    /// How long after a retry is due its outcome must be reported; `None`
+    /// waits forever
+    outcome_timeout_ms: Option<u64>,
+    outcome_timeout_action: OutcomeTimeoutAction,
+    /// Weights of `psp_health_score`
+    health_score: HealthScoreConfig,
+    /// Upper bounds, in seconds, of the `psp_failure_interval_seconds` buckets
+    failure_interval_buckets: Vec<f64>,
+    /// Applied to every PSP name the service is given; `None` uses them as
+    /// sent
+    psp_normalizer: Option<PspNormalizer>,
+    /// Most PSPs with a breaker of their own; 0 for no limit
+    max_psp_breakers: usize,
+    psp_overflow: PspOverflow,
+    /// Set once the cap has turned a PSP away, so it's only logged once
+    psp_cap_reached: Arc<AtomicBool>,
+    /// Least time between the attempts of two replays to one PSP; 0 for no
+    /// limit
+    min_replay_interval_ms: u64,
+    replay_throttle: ReplayThrottle,
+    /// When the latest replayed attempt to each PSP is due
+    replay_slots: Arc<Mutex<HashMap<String, u64>>>,
+    /// Highest attempt number a request may carry
+    max_attempt_number: u32,
+    attempt_overflow: AttemptOverflow,
+    blank_ids: BlankIds,
 }
 
 impl RetryEngineService {
     pub fn new(retry_config: RetryConfig, circuit_config: CircuitBreakerConfig) -> Self {
+        let rng = SharedJitterRng::new(Box::new(ThreadRngSource));
         Self {
-            retry_policy: Arc::new(RetryPolicy::new(retry_config)),
+            retry_policy: Arc::new(Mutex::new(Arc::new(RetryPolicy::with_rng(
+                retry_config,
+                Box::new(rng.clone()),
+            )))),
+            psp_retry_policies: Arc::new(Mutex::new(HashMap::new())),
+            rng,
             circuit_breakers: Arc::new(Mutex::new(HashMap::new())),
             dlq: Arc::new(DeadLetterQueue::new()),
             retry_states: Arc::new(Mutex::new(HashMap::new())),
             circuit_config,
+            circuit_overrides: Arc::new(Mutex::new(HashMap::new())),
+            jitter_overrides: HashMap::new(),
+            time_series: Arc::new(RetryTimeSeries::default()),
+            in_flight: Arc::new(InFlightTracker::new()),
+            outcomes: Arc::new(OutcomeTracker::new()),
+            retry_drift: Arc::new(DriftTracker::new()),
+            final_attempts: Arc::new(FinalAttemptTracker::new()),
+            admissions: Arc::new(AdmissionTracker::new()),
+            retries_scheduled: Arc::new(ExemplarCounter::new()),
+            dlq_adds: Arc::new(ExemplarCounter::new()),
+            psp_access: Arc::new(Mutex::new(PspAccessPolicy::default())),
+            paused: Arc::new(AtomicBool::new(false)),
+            load_factor: Arc::new(AtomicU64::new(0.0f64.to_bits())),
+            dlq_store: None,
+            dlq_wal: None,
+            wal_revision: Arc::new(Mutex::new(0)),
+            persistence_degraded: Arc::new(AtomicBool::new(false)),
+            wal_retrying: Arc::new(AtomicBool::new(false)),
+            event_log: None,
+            dlq_logged_revision: Arc::new(Mutex::new(0)),
+            clock: Arc::new(SystemClock),
+            reset_schedules: Arc::new(Mutex::new(HashMap::new())),
+            maintenance_windows: Arc::new(Mutex::new(HashMap::new())),
+            transaction_locks: Arc::new(StripedLock::new(TRANSACTION_LOCK_STRIPES)),
+            schedule_calls: Arc::new(SingleFlight::new()),
+            max_replays: DEFAULT_MAX_REPLAYS,
+            soft_dlq_cooldown_ms: None,
+            dlq_max_age_ms: None,
+            schedule_rate_limit: None,
+            retry_budget: None,
+            outcome_timeout_ms: None,
+            outcome_timeout_action: OutcomeTimeoutAction::default(),
+            health_score: HealthScoreConfig::default(),
+            admin_token: None,
+            failure_interval_buckets: DEFAULT_FAILURE_INTERVAL_BUCKETS.to_vec(),
+            psp_normalizer: None,
+            max_psp_breakers: 0,
+            psp_overflow: PspOverflow::Shared,
+            psp_cap_reached: Arc::new(AtomicBool::new(false)),
+            min_replay_interval_ms: 0,
+            replay_throttle: ReplayThrottle::Queue,
+            replay_slots: Arc::new(Mutex::new(HashMap::new())),
+            max_attempt_number: DEFAULT_MAX_ATTEMPT_NUMBER,
+            attempt_overflow: AttemptOverflow::Reject,
+            blank_ids: BlankIds::Reject,
         }
     }
 
-    fn get_or_create_circuit_breaker(&self, psp_name: &str) -> CircuitBreaker {
-        let mut breakers = self.circuit_breakers.lock().unwrap();
-        breakers
-            .entry(psp_name.to_string())
-            .or_insert_with(|| CircuitBreaker::new(self.circuit_config.clone()))
-            .clone()
+    /// Key PSPs by `normalizer(psp_name)` rather than the name as sent, so
+    /// variants of one PSP's name share a breaker, overrides, quota and DLQ
+    /// grouping. Apply before the builders that take PSP names.
+    pub fn with_psp_normalizer(mut self, normalizer: PspNormalizer) -> Self {
+        self.psp_normalizer = Some(normalizer);
+        self
     }
 
-    fn convert_circuit_state(state: CircuitState) -> ProtoCircuitState {
-        match state {
-            CircuitState::Closed => ProtoCircuitState::Closed,
-            CircuitState::Open => ProtoCircuitState::Open,
-            CircuitState::HalfOpen => ProtoCircuitState::HalfOpen,
+    /// The key `psp_name` is kept under
+    fn psp_key(&self, psp_name: String) -> String {
+        match &self.psp_normalizer {
+            Some(normalizer) => normalizer(&psp_name),
+            None => psp_name,
         }
     }
-}
 
-#[tonic::async_trait]
-impl RetryEngine for RetryEngineService {
-    async fn schedule_retry(
+    /// Bucket the `psp_failure_interval_seconds` histogram by these upper
+    /// bounds, in seconds
+    pub fn with_failure_interval_buckets(mut self, mut bounds: Vec<f64>) -> Result<Self, String> {
+        if bounds.iter().any(|le| !le.is_finite() || *le <= 0.0) {
+            return Err("failure interval buckets must be positive and finite".to_string());
+        }
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        self.failure_interval_buckets = bounds;
+        Ok(self)
+    }
+
+    /// Start with per-PSP circuit configs, as if set through `SetCircuitConfig`
+    pub fn with_circuit_overrides(self, overrides: HashMap<String, CircuitBreakerConfig>) -> Self {
+        for (psp_name, config) in overrides {
+            let psp_name = self.psp_key(psp_name);
+            if let Err(e) = self.set_psp_circuit_config(&psp_name, config) {
+                warn!("Circuit config for {} not applied: {}", psp_name, e);
+            }
+        }
+        self
+    }
+
+    /// Give PSPs their own jitter settings, in place of the retry config's
+    pub fn with_jitter_overrides(mut self, overrides: HashMap<String, JitterConfig>) -> Self {
+        self.jitter_overrides = overrides
+            .into_iter()
+            .map(|(psp_name, jitter)| (self.psp_key(psp_name), jitter))
+            .collect();
+        self.install_retry_policies(self.retry_policy().config().clone());
+        self
+    }
+
+    /// Draw retry jitter and bulk replay spreads from `rng`, e.g. a seeded
+    /// `RngJitter`, so they repeat from run to run
+    pub fn with_jitter_rng(mut self, rng: Box<dyn JitterRng>) -> Self {
+        self.rng = SharedJitterRng::new(rng);
+        self.install_retry_policies(self.retry_policy().config().clone());
+        self
+    }
+
+    /// Start with a PSP allowlist/denylist, as if set through
+    /// `SetPspAccessPolicy`
+    pub fn with_psp_access(self, policy: PspAccessPolicy) -> Self {
+        self.set_psp_access(policy);
+        self
+    }
+
+    /// Cap how many times a DLQ entry can be replayed without `force`; 0 for
+    /// no limit, the default
+    pub fn with_max_replays(mut self, max_replays: u32) -> Self {
+        self.max_replays = max_replays;
+        self
+    }
+
+    /// Give at most `max` PSPs a breaker of their own (0 for no limit); the
+    /// ones after share `OVERFLOW_BREAKER` or are refused, per `overflow`
+    pub fn with_max_psp_breakers(mut self, max: usize, overflow: PspOverflow) -> Self {
+        self.max_psp_breakers = max;
+        self.psp_overflow = overflow;
+        self
+    }
+
+    /// Limit the retries scheduled per window for each priority tier
+    pub fn with_retry_budget(mut self, budget: RetryBudget) -> Self {
+        self.retry_budget = Some(Arc::new(budget));
+        self
+    }
+
+    /// Accept attempt numbers up to `max`; higher ones are refused or taken as
+    /// `max`, per `overflow`
+    pub fn with_max_attempt_number(mut self, max: u32, overflow: AttemptOverflow) -> Self {
+        self.max_attempt_number = max;
+        self.attempt_overflow = overflow;
+        self
+    }
+
+    /// Refuse or allow requests with a blank transaction ID or PSP name
+    pub fn with_blank_ids(mut self, blank_ids: BlankIds) -> Self {
+        self.blank_ids = blank_ids;
+        self
+    }
+
+    /// Keep replayed attempts to one PSP at least `interval_ms` apart (0 for
+    /// no limit), queueing or refusing replays that come too soon
+    pub fn with_min_replay_interval(mut self, interval_ms: u64, throttle: ReplayThrottle) -> Self {
+        self.min_replay_interval_ms = interval_ms;
+        self.replay_throttle = throttle;
+        self
+    }
+
+    /// Accept admin RPCs (`DumpState`, `ForceOpenCircuit`) carrying
+    /// `authorization: Bearer <token>`
+    pub fn with_admin_token(mut self, token: Option<String>) -> Self {
+        self.admin_token = token;
+        self
+    }
+
+    /// Why an admin RPC carrying `metadata` must be refused, if it must
+    fn admin_rejection(&self, metadata: &MetadataMap) -> Option<Status> {
+        let Some(token) = &self.admin_token else {
+            return Some(Status::permission_denied(
+                "Admin RPCs are disabled: no admin token configured",
+            ));
+        };
+        let presented = metadata
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match presented {
+            Some(presented) if constant_time_eq(presented.as_bytes(), token.as_bytes()) => None,
+            _ => Some(Status::unauthenticated("Missing or invalid admin token")),
+        }
+    }
+
+    /// Cap the DLQ entries each listed PSP can hold
+    pub fn with_psp_quotas(self, quotas: HashMap<String, PspQuota>) -> Self {
+        for (psp_name, quota) in quotas {
+            self.dlq.set_psp_quota(&self.psp_key(psp_name), Some(quota));
+        }
+        self
+    }
+
+    /// Replay dead letters automatically `cooldown_ms` after they arrive, if
+    /// their PSP's circuit has closed by then (see `replay_due_soft_dlq`)
+    ///
+    /// Automatic replays count toward `max_replays`, so with a limit set a
+    /// transaction that keeps failing ends up waiting for an operator after
+    /// all; without one it's replayed after every cooldown.
+    pub fn with_soft_dlq(mut self, cooldown_ms: u64) -> Self {
+        self.soft_dlq_cooldown_ms = Some(cooldown_ms);
+        self
+    }
+
+    /// Expire dead letters `max_age_ms` after they arrive (see `expire_dlq`)
+    pub fn with_dlq_expiry(mut self, max_age_ms: u64) -> Self {
+        self.dlq_max_age_ms = Some(max_age_ms);
+        self
+    }
+
+    /// Hand each expiring dead letter to `hook` first, e.g. to archive it;
+    /// an entry the hook fails on is kept
+    pub fn on_dlq_expire(mut self, hook: ExpiryHook) -> Self {
+        Arc::get_mut(&mut self.dlq)
+            .expect("the DLQ is only shared once the service is running")
+            .set_on_expire(hook);
+        self
+    }
+
+    /// Reject `ScheduleRetry` calls for a transaction within
+    /// `min_interval_ms` of the last one let through, by the service clock,
+    /// so a client stuck in a loop can't churn its state; 0 disables
+    pub fn with_min_schedule_interval(mut self, min_interval_ms: u64) -> Self {
+        self.schedule_rate_limit =
+            (min_interval_ms > 0).then(|| Arc::new(RateLimiter::new(min_interval_ms)));
+        self
+    }
+
+    /// Give up on hearing how a scheduled retry went `timeout_ms` after it's
+    /// due, and apply `action` to it (see `expire_unreported_outcomes`)
+    pub fn with_outcome_timeout(mut self, timeout_ms: u64, action: OutcomeTimeoutAction) -> Self {
+        self.outcome_timeout_ms = Some(timeout_ms);
+        self.outcome_timeout_action = action;
+        self
+    }
+
+    /// Weigh the signals of `psp_health_score` with `config`
+    pub fn with_health_score(mut self, config: HealthScoreConfig) -> Self {
+        self.health_score = config;
+        self
+    }
+
+    /// Use a custom time source for circuit breakers, scheduled circuit
+    /// resets, the soft DLQ and outcome timeouts. Apply before any breakers
+    /// are created.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Set or clear (`None`) a PSP's recurring circuit reset, returning when
+    /// the next reset is due
+    pub fn set_psp_reset_schedule(
         &self,
-        request: Request<RetryRequest>,
-    ) -> Result<Response<RetryResponse>, Status> {
-        let req = request.into_inner();
-        let transaction_id = req.transaction_id.clone();
-        let psp_name = req.psp_name.clone();
-        let attempt = req.attempt_number as u32;
+        psp_name: &str,
+        schedule: Option<CircuitResetSchedule>,
+    ) -> Option<u64> {
+        let mut schedules = self.reset_schedules.lock().unwrap();
+        match schedule {
+            Some(schedule) => {
+                let next_reset_at_ms = schedule.next_after(self.clock.now_ms());
+                schedules.insert(
+                    psp_name.to_string(),
+                    ScheduledReset {
+                        schedule,
+                        next_reset_at_ms,
+                    },
+                );
+                Some(next_reset_at_ms)
+            }
+            None => {
+                schedules.remove(psp_name);
+                None
+            }
+        }
+    }
 
-        // Check if already in DLQ
-        if self.dlq.contains(&transaction_id) {
-            return Ok(Response::new(RetryResponse {
-                retry_id: transaction_id.clone(),
-                scheduled: false,
-                next_retry_at_ms: 0,
-                message: "Transaction already in dead letter queue".to_string(),
-            }));
+    /// Reset every PSP's breaker whose scheduled reset is due, returning the
+    /// PSPs reset. Resets missed while nothing was checking run once.
+    pub fn run_due_circuit_resets(&self) -> Vec<String> {
+        let now = self.clock.now_ms();
+        let mut due: Vec<String> = {
+            let mut schedules = self.reset_schedules.lock().unwrap();
+            schedules
+                .iter_mut()
+                .filter(|(_, reset)| reset.next_reset_at_ms <= now)
+                .map(|(psp_name, reset)| {
+                    reset.next_reset_at_ms = reset.schedule.next_after(now);
+                    psp_name.clone()
+                })
+                .collect()
+        };
+        due.sort();
+
+        for psp_name in &due {
+            if let Some(breaker) = self.get_circuit_breaker(psp_name) {
+                breaker.scheduled_reset();
+                info!("Scheduled circuit reset for PSP: {}", psp_name);
+                self.log_event(|| EngineEvent::CircuitStateChanged {
+                    psp_name: psp_name.clone(),
+                    state: breaker.get_state(),
+                });
+            }
         }
+        due
+    }
 
-        // Check circuit breaker
-        let circuit_breaker = self.get_or_create_circuit_breaker(&psp_name);
-        if !circuit_breaker.can_proceed() {
-            return Ok(Response::new(RetryResponse {
-                retry_id: transaction_id.clone(),
-                scheduled: false,
-                next_retry_at_ms: 0,
-                message: format!("Circuit breaker open for PSP: {}", psp_name),
-            }));
+    /// Run due scheduled circuit resets every `tick` in the background
+    pub fn spawn_circuit_reset_task(
+        service: Arc<Self>,
+        tick: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                service.run_due_circuit_resets();
+            }
+        })
+    }
+
+    /// Announce maintenance for a PSP from `start_ms` up to `end_ms`, merging
+    /// it with any windows it overlaps or touches, and returning the PSP's
+    /// windows afterwards
+    pub fn announce_maintenance(
+        &self,
+        psp_name: &str,
+        start_ms: u64,
+        end_ms: u64,
+    ) -> Result<MaintenanceWindows, String> {
+        if end_ms <= start_ms {
+            return Err("end_ms must be after start_ms".to_string());
+        }
+        if end_ms <= self.clock.now_ms() {
+            return Err("Maintenance window has already ended".to_string());
         }
+        let mut all = self.maintenance_windows.lock().unwrap();
+        let windows = all.entry(psp_name.to_string()).or_default();
+        windows.push((start_ms, end_ms));
+        windows.sort_unstable();
+        let mut merged: MaintenanceWindows = Vec::with_capacity(windows.len());
+        for &(start_ms, end_ms) in windows.iter() {
+            match merged.last_mut() {
+                Some(last) if start_ms <= last.1 => last.1 = last.1.max(end_ms),
+                _ => merged.push((start_ms, end_ms)),
+            }
+        }
+        *windows = merged.clone();
+        Ok(merged)
+    }
 
-        // Check if we should retry
-        if !self.retry_policy.should_retry(attempt) {
-            // Move to DLQ
-            let dlq_entry = DLQEntry {
-                transaction_id: transaction_id.clone(),
-                psp_name: psp_name.clone(),
-                payload: req.payload.clone(),
-                attempt_count: attempt,
-                last_error: "Max retry attempts exceeded".to_string(),
-                timestamp_ms: current_timestamp_ms(),
+    /// Pause the breaker of every PSP inside a maintenance window and resume
+    /// those whose window has ended, returning the PSPs paused or resumed.
+    /// Windows that ended are forgotten.
+    pub fn run_maintenance_windows(&self) -> Vec<String> {
+        let now = self.clock.now_ms();
+        let mut wanted: Vec<(String, bool)> = {
+            let mut all = self.maintenance_windows.lock().unwrap();
+            let wanted = all
+                .iter_mut()
+                .map(|(psp_name, windows)| {
+                    windows.retain(|(_, end_ms)| *end_ms > now);
+                    let in_window = windows
+                        .first()
+                        .is_some_and(|(start_ms, _)| *start_ms <= now);
+                    (psp_name.clone(), in_window)
+                })
+                .collect();
+            all.retain(|_, windows| !windows.is_empty());
+            wanted
+        };
+        wanted.sort();
+
+        let mut changed = Vec::new();
+        for (psp_name, in_window) in wanted {
+            let breaker = if in_window {
+                match self.get_or_create_circuit_breaker(&psp_name) {
+                    // Pausing a shared overflow breaker would pause other PSPs
+                    Ok((name, breaker)) if name == psp_name => breaker,
+                    _ => {
+                        warn!(
+                            "No breaker of its own to pause for {} maintenance",
+                            psp_name
+                        );
+                        continue;
+                    }
+                }
+            } else {
+                match self.get_circuit_breaker(&psp_name) {
+                    Some(breaker) => breaker,
+                    None => continue,
+                }
+            };
+            let toggled = if in_window {
+                breaker.pause()
+            } else {
+                breaker.resume()
             };
-            self.dlq.add_entry(dlq_entry);
+            if toggled {
+                info!(
+                    "Circuit breaker {} for PSP maintenance: {}",
+                    if in_window { "paused" } else { "resumed" },
+                    psp_name
+                );
+                self.log_event(|| EngineEvent::CircuitStateChanged {
+                    psp_name: psp_name.clone(),
+                    state: breaker.get_state(),
+                });
+                changed.push(psp_name);
+            }
+        }
+        changed
+    }
 
-            return Ok(Response::new(RetryResponse {
-                retry_id: transaction_id.clone(),
-                scheduled: false,
-                next_retry_at_ms: 0,
-                message: "Max retries exceeded, moved to DLQ".to_string(),
-            }));
+    /// Start and end maintenance windows every `tick` in the background
+    pub fn spawn_maintenance_task(
+        service: Arc<Self>,
+        tick: Duration,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                service.run_maintenance_windows();
+            }
+        })
+    }
+
+    /// Replay every soft DLQ entry whose cooldown is over and whose PSP's
+    /// circuit is closed, returning the transactions replayed
+    ///
+    /// Entries the replay refuses (paused engine, replay limit reached) stay
+    /// in the DLQ and are tried again on the next call.
+    pub fn replay_due_soft_dlq(&self) -> Vec<String> {
+        let now = self.clock.now_ms();
+        let mut due: Vec<DLQEntry> = self
+            .dlq
+            .get_all_entries()
+            .into_iter()
+            .filter(|entry| entry.retry_after_ms > 0 && entry.retry_after_ms <= now)
+            .filter(|entry| !entry.replaying)
+            .filter(|entry| {
+                self.get_circuit_breaker(&entry.psp_name)
+                    .is_none_or(|breaker| breaker.get_state().state == CircuitState::Closed)
+            })
+            .collect();
+        due.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+
+        let mut replayed = Vec::new();
+        for entry in due {
+            match self.replay_entry(&entry, None, false, 0, None, "") {
+                Ok(_) => {
+                    info!("Soft DLQ replayed transaction: {}", entry.transaction_id);
+                    replayed.push(entry.transaction_id);
+                }
+                Err(reason) => warn!(
+                    "Soft DLQ replay of {} refused: {}",
+                    entry.transaction_id, reason
+                ),
+            }
         }
+        replayed
+    }
 
-        // Calculate next retry delay
-        let delay_ms = self.retry_policy.calculate_delay(attempt);
-        let next_retry_at_ms = current_timestamp_ms() + delay_ms;
+    /// Remove the dead letters older than the `with_dlq_expiry` age, after
+    /// the `on_dlq_expire` hook has seen them, returning their IDs in order.
+    /// Parked, leased and replaying entries are kept. A no-op without expiry.
+    pub fn expire_dlq(&self) -> Vec<String> {
+        let Some(max_age_ms) = self.dlq_max_age_ms else {
+            return Vec::new();
+        };
+        let expired: Vec<String> = self
+            .dlq
+            .purge_expired(max_age_ms, self.clock.now_ms())
+            .into_iter()
+            .map(|entry| entry.transaction_id)
+            .collect();
+        self.log_dlq_changes();
+        if !expired.is_empty() {
+            info!("Expired {} DLQ entries: {:?}", expired.len(), expired);
+        }
+        expired
+    }
 
-        // Update retry state
-        let mut states = self.retry_states.lock().unwrap();
-        states.insert(
-            transaction_id.clone(),
-            RetryState {
-                attempt_count: attempt,
-                last_error: String::new(),
-                last_attempt_at_ms: current_timestamp_ms(),
-            },
-        );
+    /// Expire old dead letters every `tick` in the background; `None` if
+    /// expiry is off. The hook may block, so each pass runs on a blocking
+    /// task.
+    pub fn spawn_dlq_expiry_task(
+        service: Arc<Self>,
+        tick: Duration,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        service.dlq_max_age_ms?;
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                let service = service.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || service.expire_dlq()).await {
+                    warn!("DLQ expiry pass failed: {}", e);
+                }
+            }
+        }))
+    }
 
-        Ok(Response::new(RetryResponse {
-            retry_id: transaction_id,
-            scheduled: true,
-            next_retry_at_ms: next_retry_at_ms as i64,
-            message: format!("Retry scheduled for attempt {}", attempt + 1),
+    /// Run due soft DLQ replays every `tick` in the background; `None` if the
+    /// soft DLQ is off
+    pub fn spawn_soft_dlq_task(
+        service: Arc<Self>,
+        tick: Duration,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        service.soft_dlq_cooldown_ms?;
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                service.replay_due_soft_dlq();
+            }
         }))
     }
 
-    async fn get_circuit_status(
-        &self,
-        request: Request<CircuitRequest>,
-    ) -> Result<Response<CircuitResponse>, Status> {
-        let req = request.into_inner();
-        let circuit_breaker = self.get_or_create_circuit_breaker(&req.psp_name);
-        let state = circuit_breaker.get_state();
+    /// When the outcome of an attempt due in `delay_ms` times out; 0 with no
+    /// outcome timeout
+    fn outcome_due_at_ms(&self, delay_ms: u64) -> u64 {
+        self.outcome_timeout_ms.map_or(0, |timeout_ms| {
+            self.clock
+                .now_ms()
+                .saturating_add(delay_ms)
+                .saturating_add(timeout_ms)
+        })
+    }
 
-        Ok(Response::new(CircuitResponse {
-            psp_name: req.psp_name,
-            state: Self::convert_circuit_state(state.state) as i32,
-            failure_count: state.failure_count as i32,
-            success_count: state.success_count as i32,
-            last_failure_at_ms: state.last_failure_at_ms as i64,
-            next_attempt_at_ms: state.next_attempt_at_ms as i64,
+    /// How healthy a PSP looks from 0 (avoid) to 100 (route freely), from its
+    /// circuit, reported outcomes and dead letters (see `HealthScoreConfig`)
+    pub fn psp_health_score(&self, psp_name: &str) -> u8 {
+        let circuit = self
+            .get_circuit_breaker(psp_name)
+            .map(|breaker| breaker.get_state().state)
+            .unwrap_or(CircuitState::Closed);
+        self.health_score.score(
+            circuit,
+            &self.outcomes.get(&self.circuit_breaker_name(psp_name)),
+            self.dlq.psp_count(psp_name),
+        )
+    }
+
+    /// Apply the outcome timeout action to every scheduled retry whose
+    /// outcome is overdue, returning the transactions that timed out
+    ///
+    /// Each attempt times out once; the retry itself stays scheduled. An
+    /// outcome reported after its timeout is only logged.
+    pub fn expire_unreported_outcomes(&self) -> Vec<String> {
+        let now = self.clock.now_ms();
+        let mut expired: Vec<(String, String)> = {
+            let mut states = self.retry_states.lock().unwrap();
+            states
+                .iter_mut()
+                .filter(|(_, state)| state.outcome_due_at_ms > 0 && state.outcome_due_at_ms <= now)
+                .map(|(transaction_id, state)| {
+                    state.outcome_due_at_ms = 0;
+                    state.outcome_settled = true;
+                    (transaction_id.clone(), state.psp_name.clone())
+                })
+                .collect()
+        };
+        expired.sort();
+
+        for (transaction_id, psp_name) in &expired {
+            warn!(
+                "No outcome reported for {} on PSP {}",
+                transaction_id, psp_name
+            );
+            if self.outcome_timeout_action == OutcomeTimeoutAction::Fail {
+                self.record_on_breaker(psp_name, false, None);
+            }
+        }
+        expired
+            .into_iter()
+            .map(|(transaction_id, _)| transaction_id)
+            .collect()
+    }
+
+    /// Expire unreported outcomes every `tick` in the background; `None` if
+    /// there's no outcome timeout
+    pub fn spawn_outcome_timeout_task(
+        service: Arc<Self>,
+        tick: Duration,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        service.outcome_timeout_ms?;
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            loop {
+                interval.tick().await;
+                service.expire_unreported_outcomes();
+            }
         }))
     }
 
-    async fn get_retry_status(
-        &self,
-        request: Request<RetryStatusRequest>,
-    ) -> Result<Response<RetryStatusResponse>, Status> {
-        let req = request.into_inner();
-        let transaction_id = req.transaction_id;
+    /// Write every state-changing operation to `log`, so `replay_from` can
+    /// rebuild the engine from it
+    ///
+    /// The current DLQ is written first as a baseline. Apply after the other
+    /// builders and before serving, as state changed earlier isn't logged.
+    pub fn with_event_log(mut self, log: Arc<dyn EventLog>) -> Self {
+        let entries = self.dlq.get_all_entries();
+        *self.dlq_logged_revision.lock().unwrap() = self.dlq.revision();
+        if let Err(e) = log.append(&EngineEvent::DlqSnapshot { entries }) {
+            warn!("Failed to write engine event: {}", e);
+        }
+        self.event_log = Some(log);
+        self
+    }
 
-        // Check if in DLQ
-        if let Some(dlq_entry) = self.dlq.get_entry(&transaction_id) {
-            return Ok(Response::new(RetryStatusResponse {
-                transaction_id: transaction_id.clone(),
-                attempt_count: dlq_entry.attempt_count as i32,
-                status: "IN_DLQ".to_string(),
-                last_error: dlq_entry.last_error,
-                in_dlq: true,
-            }));
+    /// Rebuild an engine by applying every event in `log` in order
+    ///
+    /// The rebuilt engine doesn't write to `log`; attach one with
+    /// `with_event_log` to keep logging.
+    pub fn replay_from(
+        retry_config: RetryConfig,
+        circuit_config: CircuitBreakerConfig,
+        log: &dyn EventLog,
+    ) -> std::io::Result<Self> {
+        let service = Self::new(retry_config, circuit_config);
+        for event in log.events()? {
+            service.apply_event(event);
         }
+        Ok(service)
+    }
 
-        // Check retry state
-        let states = self.retry_states.lock().unwrap();
-        if let Some(state) = states.get(&transaction_id) {
-            return Ok(Response::new(RetryStatusResponse {
-                transaction_id: transaction_id.clone(),
-                attempt_count: state.attempt_count as i32,
-                status: "RETRYING".to_string(),
-                last_error: state.last_error.clone(),
-                in_dlq: false,
-            }));
+    /// Resolve inconsistencies in state recovered at startup, logging each
+    /// correction
+    ///
+    /// - A retry state for a transaction in the DLQ that isn't being replayed
+    ///   is dropped: the DLQ entry is the later word on it.
+    /// - A DLQ entry marked as replaying with no retry state is an ordinary
+    ///   dead letter again, as its replay was lost.
+    /// - A circuit left Open past its timeout moves to HalfOpen, so the next
+    ///   request probes the PSP.
+    ///
+    /// Idempotent: a second call finds nothing to correct.
+    pub fn reconcile(&self) -> ReconcileReport {
+        let mut report = ReconcileReport::default();
+
+        let mut transaction_ids: Vec<String> =
+            self.retry_states.lock().unwrap().keys().cloned().collect();
+        transaction_ids.sort();
+        for transaction_id in transaction_ids {
+            let _transaction = self.transaction_locks.lock(&transaction_id);
+            if !self.is_dead_lettered(&transaction_id) {
+                continue;
+            }
+            if self.remove_retry_state(&transaction_id).is_some() {
+                warn!(
+                    "Reconcile: dropped retry state for dead-lettered {}",
+                    transaction_id
+                );
+                report.removed_retry_states.push(transaction_id);
+            }
         }
 
-        Ok(Response::new(RetryStatusResponse {
-            transaction_id: transaction_id.clone(),
-            attempt_count: 0,
-            status: "NOT_FOUND".to_string(),
-            last_error: String::new(),
-            in_dlq: false,
+        let mut replaying: Vec<String> = self
+            .dlq
+            .get_all_entries()
+            .into_iter()
+            .filter(|entry| entry.replaying)
+            .map(|entry| entry.transaction_id)
+            .collect();
+        replaying.sort();
+        for transaction_id in replaying {
+            let _transaction = self.transaction_locks.lock(&transaction_id);
+            if self
+                .retry_states
+                .lock()
+                .unwrap()
+                .contains_key(&transaction_id)
+            {
+                continue;
+            }
+            self.dlq
+                .update_entry(&transaction_id, |entry| entry.replaying = false);
+            warn!(
+                "Reconcile: {} was replaying with no retry scheduled, back to a dead letter",
+                transaction_id
+            );
+            report.cleared_replaying.push(transaction_id);
+        }
+        self.log_dlq_changes();
+
+        let mut breakers: Vec<(String, CircuitBreaker)> = self
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, breaker)| (psp_name.clone(), breaker.clone()))
+            .collect();
+        breakers.sort_by(|a, b| a.0.cmp(&b.0));
+        for (psp_name, breaker) in breakers {
+            if breaker.half_open_if_timed_out() {
+                warn!(
+                    "Reconcile: circuit for {} was open past its timeout, now half-open",
+                    psp_name
+                );
+                self.log_event(|| EngineEvent::CircuitStateChanged {
+                    psp_name: psp_name.clone(),
+                    state: breaker.get_state(),
+                });
+                report.half_opened_circuits.push(psp_name);
+            }
+        }
+
+        report
+    }
+
+    fn apply_event(&self, event: EngineEvent) {
+        match event {
+            EngineEvent::RetryStateStored {
+                transaction_id,
+                psp_name,
+                attempt_count,
+                last_error,
+                last_attempt_at_ms,
+                next_retry_at_ms,
+                payload,
+                payload_stripped,
+                content_type,
+                encoding,
+                tags,
+                first_scheduled_at_ms,
+                group_id,
+                due_at_ms,
+                drift_ms,
+            } => {
+                if payload_stripped {
+                    warn!(
+                        "Replayed retry state for {} was logged without its payload",
+                        transaction_id
+                    );
+                }
+                let state = RetryState {
+                    psp_name,
+                    attempt_count,
+                    last_error,
+                    last_attempt_at_ms,
+                    next_retry_at_ms,
+                    payload,
+                    content_type,
+                    encoding,
+                    tags,
+                    first_scheduled_at_ms,
+                    outcome_due_at_ms: 0,
+                    outcome_settled: false,
+                    group_id,
+                    due_at_ms,
+                    drift_ms,
+                };
+                if let Err(e) = self.store_retry_state(&transaction_id, state) {
+                    warn!("Replayed retry state for {} refused: {}", transaction_id, e);
+                }
+            }
+            EngineEvent::RetryStateRemoved { transaction_id } => {
+                self.remove_retry_state(&transaction_id);
+            }
+            EngineEvent::CircuitStateChanged { psp_name, state } => {
+                let breaker = CircuitBreaker::with_state(self.circuit_config_for(&psp_name), state)
+                    .with_clock(self.clock.clone());
+                self.circuit_breakers
+                    .lock()
+                    .unwrap()
+                    .insert(psp_name, breaker);
+            }
+            EngineEvent::CircuitConfigSet { psp_name, config } => {
+                if let Err(e) = self.set_psp_circuit_config(&psp_name, config) {
+                    warn!("Replayed circuit config for {} refused: {}", psp_name, e);
+                }
+            }
+            // Transactions swept by the change follow as their own events
+            EngineEvent::RetryConfigSet { config } => self.set_retry_policy(config),
+            EngineEvent::DlqEntryStored { entry } => self.dlq.restore_entry(entry),
+            EngineEvent::DlqEntryRemoved { transaction_id } => {
+                self.dlq.remove_entry(&transaction_id);
+            }
+            EngineEvent::DlqSnapshot { entries } => {
+                for entry in self.dlq.get_all_entries() {
+                    self.dlq.remove_entry(&entry.transaction_id);
+                }
+                for entry in entries {
+                    self.dlq.restore_entry(entry);
+                }
+            }
+            EngineEvent::EnginePaused { paused } => self.paused.store(paused, Ordering::SeqCst),
+            EngineEvent::PspAccessSet { policy } => self.set_psp_access(policy),
+        }
+    }
+
+    /// Write an event to the event log, if there is one. The event is only
+    /// built when it will be written.
+    fn log_event(&self, event: impl FnOnce() -> EngineEvent) {
+        if let Some(log) = &self.event_log {
+            if let Err(e) = log.append(&event()) {
+                warn!("Failed to write engine event: {}", e);
+            }
+        }
+    }
+
+    /// Write DLQ changes since the last call to the WAL and event log,
+    /// including any evictions made by the payload budget
+    fn log_dlq_changes(&self) {
+        // A failed write is logged and retried with the next change
+        let _ = self.write_dlq_wal();
+        let Some(log) = &self.event_log else {
+            return;
+        };
+        let mut logged_revision = self.dlq_logged_revision.lock().unwrap();
+        let changes = self.dlq.changes_since(*logged_revision);
+
+        let mut events = Vec::new();
+        if changes.reset {
+            events.push(EngineEvent::DlqSnapshot {
+                entries: changes.added,
+            });
+        } else {
+            events.extend(
+                changes
+                    .removed
+                    .into_iter()
+                    .map(|transaction_id| EngineEvent::DlqEntryRemoved { transaction_id }),
+            );
+            let mut added = changes.added;
+            added.sort_by_key(|entry| entry.last_modified_revision);
+            events.extend(
+                added
+                    .into_iter()
+                    .map(|entry| EngineEvent::DlqEntryStored { entry }),
+            );
+        }
+        for event in &events {
+            if let Err(e) = log.append(event) {
+                warn!("Failed to write engine event: {}", e);
+            }
+        }
+        *logged_revision = changes.revision;
+    }
+
+    /// Append the DLQ changes not yet in the WAL; a no-op without a WAL
+    ///
+    /// This makes a single attempt. On failure the changes stay in the DLQ
+    /// for the next write and, within a Tokio runtime, a blocking task
+    /// retries with the WAL's backoff, so nothing waits while holding a lock.
+    fn write_dlq_wal(&self) -> Result<(), PersistenceError> {
+        let Some(wal) = &self.dlq_wal else {
+            return Ok(());
+        };
+        let written = Self::append_dlq_changes(&self.dlq, wal, &self.wal_revision);
+        match &written {
+            Ok(()) => self.persistence_degraded.store(false, Ordering::SeqCst),
+            Err(e) => {
+                warn!("Failed to write DLQ WAL, holding changes in memory: {}", e);
+                self.persistence_degraded.store(true, Ordering::SeqCst);
+                self.spawn_wal_retry(wal.clone());
+            }
+        }
+        written
+    }
+
+    fn append_dlq_changes(
+        dlq: &DeadLetterQueue,
+        wal: &DlqWal,
+        wal_revision: &Mutex<u64>,
+    ) -> Result<(), PersistenceError> {
+        let mut wal_revision = wal_revision.lock().unwrap();
+        let changes = dlq.changes_since(*wal_revision);
+
+        if changes.reset {
+            // A WAL can't express "replace everything", so checkpoint instead
+            wal.checkpoint(&changes.added)?;
+        } else {
+            let mut added = changes.added;
+            added.sort_by_key(|entry| entry.last_modified_revision);
+            let records: Vec<WalRecord> = changes
+                .removed
+                .into_iter()
+                .map(|transaction_id| WalRecord::Removed { transaction_id })
+                .chain(added.into_iter().map(|entry| WalRecord::Put {
+                    entry: Box::new(entry),
+                }))
+                .collect();
+            if !records.is_empty() {
+                wal.try_append(&records)?;
+            }
+        }
+        *wal_revision = changes.revision;
+        Ok(())
+    }
+
+    /// Retry writing the DLQ changes to `wal` on a blocking task, sleeping
+    /// between attempts with no lock held; one at a time, and only within a
+    /// Tokio runtime. Changes still unwritten go out with the next write.
+    fn spawn_wal_retry(&self, wal: Arc<DlqWal>) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if self.wal_retrying.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let dlq = self.dlq.clone();
+        let wal_revision = self.wal_revision.clone();
+        let degraded = self.persistence_degraded.clone();
+        let retrying = self.wal_retrying.clone();
+        runtime.spawn_blocking(move || {
+            let mut attempts = 1;
+            while let Some(delay) = wal.retry_delay(attempts) {
+                warn!(
+                    "DLQ WAL write attempt {} failed, retrying in {}ms",
+                    attempts,
+                    delay.as_millis()
+                );
+                std::thread::sleep(delay);
+                attempts += 1;
+                match Self::append_dlq_changes(&dlq, &wal, &wal_revision) {
+                    Ok(()) => {
+                        degraded.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                    Err(e) => warn!("Failed to write DLQ WAL: {}", e),
+                }
+            }
+            retrying.store(false, Ordering::SeqCst);
+        });
+    }
+
+    /// Check the PSP's breaker, logging the transition if an expired Open
+    /// circuit moves to HalfOpen
+    fn circuit_allows(&self, psp_name: &str) -> Result<bool, String> {
+        let (name, breaker) = self.get_or_create_circuit_breaker(psp_name)?;
+        let before = breaker.get_state();
+        let allowed = breaker.can_proceed();
+        self.admissions.record(&name, allowed, self.clock.now_ms());
+        let after = breaker.get_state();
+        if after != before {
+            self.log_event(|| EngineEvent::CircuitStateChanged {
+                psp_name: name,
+                state: after,
+            });
+        }
+        Ok(allowed)
+    }
+
+    /// Feed an attempt's outcome to the PSP's breaker, logging any
+    /// transition it causes
+    fn record_outcome(&self, psp_name: &str, success: bool, latency_ms: Option<u64>) {
+        self.record_on_breaker(psp_name, success, latency_ms);
+        self.outcomes
+            .record(&self.circuit_breaker_name(psp_name), success, latency_ms);
+    }
+
+    /// Feed an outcome to the PSP's breaker, logging any state change
+    fn record_on_breaker(&self, psp_name: &str, success: bool, latency_ms: Option<u64>) {
+        // A PSP refused a breaker has no circuit to feed
+        let Ok((name, breaker)) = self.get_or_create_circuit_breaker(psp_name) else {
+            return;
+        };
+        let before = breaker.get_state();
+        match (success, latency_ms) {
+            (true, Some(latency_ms)) => breaker.record_success_with_latency(latency_ms),
+            (true, None) => breaker.record_success(),
+            (false, _) => breaker.record_failure(),
+        }
+        let after = breaker.get_state();
+        if after != before {
+            self.log_event(|| EngineEvent::CircuitStateChanged {
+                psp_name: name,
+                state: after,
+            });
+        }
+    }
+
+    /// Restore the DLQ from `store` and keep persisting to it
+    ///
+    /// The store is checked by loading it and writing the result straight
+    /// back. If either fails, `Strict` returns the error; `Lenient` logs it
+    /// and carries on with an in-memory DLQ, reported as degraded by
+    /// `GetEngineHealth`.
+    pub fn with_dlq_store(
+        mut self,
+        store: DlqStore,
+        mode: PersistenceMode,
+    ) -> Result<Self, PersistenceError> {
+        let opened = self
+            .dlq
+            .load_from(&store)
+            .and_then(|_| self.dlq.save_to(&store));
+        self.log_dlq_changes();
+        match opened {
+            Ok(()) => {
+                self.dlq_store = Some(Arc::new(store));
+                self.persistence_degraded.store(false, Ordering::SeqCst);
+            }
+            Err(e) if mode == PersistenceMode::Lenient => {
+                warn!(
+                    "DLQ persistence at {} unusable, falling back to in-memory DLQ: {}",
+                    store.path().display(),
+                    e
+                );
+                self.dlq_store = None;
+                self.persistence_degraded.store(true, Ordering::SeqCst);
+            }
+            Err(e) => return Err(e),
+        }
+        self.install_dlq_flush();
+        Ok(self)
+    }
+
+    /// Recover the DLQ from `wal` and its snapshot and keep logging to it
+    ///
+    /// Recovery ends with a checkpoint, so the WAL starts out empty. Failures
+    /// are handled per `mode` as in `with_dlq_store`.
+    pub fn with_dlq_wal(
+        mut self,
+        wal: DlqWal,
+        mode: PersistenceMode,
+    ) -> Result<Self, PersistenceError> {
+        let opened = wal.open().map(|entries| {
+            self.dlq.restore_all(entries);
+        });
+        self.log_dlq_changes();
+        let wal = Arc::new(wal);
+        self.dlq_wal = Some(wal.clone());
+        let opened = opened.and_then(|()| self.checkpoint_dlq());
+        match opened {
+            Ok(()) => self.persistence_degraded.store(false, Ordering::SeqCst),
+            Err(e) if mode == PersistenceMode::Lenient => {
+                warn!(
+                    "DLQ WAL at {} unusable, falling back to in-memory DLQ: {}",
+                    wal.path().display(),
+                    e
+                );
+                self.dlq_wal = None;
+                self.persistence_degraded.store(true, Ordering::SeqCst);
+            }
+            Err(e) => return Err(e),
+        }
+        self.install_dlq_flush();
+        Ok(self)
+    }
+
+    /// Compact the WAL into the snapshot; a no-op without a WAL
+    pub fn checkpoint_dlq(&self) -> Result<(), PersistenceError> {
+        let Some(wal) = &self.dlq_wal else {
+            return Ok(());
+        };
+        let mut wal_revision = self.wal_revision.lock().unwrap();
+        // Read the revision first: a change landing in between is then either
+        // in the snapshot or written again by the next `write_dlq_wal`
+        let revision = self.dlq.revision();
+        wal.checkpoint(&self.dlq.get_all_entries())?;
+        *wal_revision = revision;
+        Ok(())
+    }
+
+    /// Fsync the WAL every sync interval, and checkpoint it every
+    /// `checkpoint_every`, in the background
+    pub fn spawn_dlq_wal_task(
+        service: Arc<Self>,
+        checkpoint_every: Duration,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        let wal = service.dlq_wal.clone()?;
+        let tick = wal.sync_interval().max(Duration::from_millis(10));
+        Some(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tick);
+            let mut last_checkpoint = tokio::time::Instant::now();
+            loop {
+                interval.tick().await;
+                let done = if last_checkpoint.elapsed() >= checkpoint_every {
+                    last_checkpoint = tokio::time::Instant::now();
+                    service.checkpoint_dlq()
+                } else {
+                    wal.sync()
+                };
+                if let Err(e) = done {
+                    warn!("Failed to write DLQ WAL: {}", e);
+                }
+            }
         }))
     }
+
+    /// Write the DLQ to its store (checkpointing the WAL if there is one); a
+    /// no-op when running in memory only
+    pub fn persist_dlq(&self) -> Result<(), PersistenceError> {
+        if self.dlq_wal.is_some() {
+            return self.checkpoint_dlq();
+        }
+        match &self.dlq_store {
+            Some(store) => self.dlq.save_to(store),
+            None => Ok(()),
+        }
+    }
+
+    /// Put every DLQ change made so far on disk, fsynced, returning whether
+    /// there's anywhere to put it: the WAL is caught up and synced, or
+    /// without one the snapshot is rewritten. In memory only it's a no-op.
+    /// Blocks on the disk; async callers use `DeadLetterQueue::flush`.
+    pub fn sync_dlq(&self) -> Result<bool, PersistenceError> {
+        if let Some(wal) = &self.dlq_wal {
+            self.write_dlq_wal()?;
+            wal.sync()?;
+            return Ok(true);
+        }
+        match &self.dlq_store {
+            Some(store) => self.dlq.save_to(store).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Point the DLQ's `flush` at the WAL or, without one, the snapshot
+    /// store, so it does what `sync_dlq` does; in memory only it's a no-op
+    fn install_dlq_flush(&mut self) {
+        let hook: Option<FlushHook> = match (self.dlq_wal.clone(), self.dlq_store.clone()) {
+            (Some(wal), _) => {
+                let wal_revision = self.wal_revision.clone();
+                let degraded = self.persistence_degraded.clone();
+                Some(Arc::new(
+                    move |dlq: &DeadLetterQueue| -> Result<bool, PersistenceError> {
+                        let written = Self::append_dlq_changes(dlq, &wal, &wal_revision);
+                        if let Err(e) = &written {
+                            warn!("Failed to write DLQ WAL, holding changes in memory: {}", e);
+                        }
+                        degraded.store(written.is_err(), Ordering::SeqCst);
+                        written?;
+                        wal.sync()?;
+                        Ok(true)
+                    },
+                ))
+            }
+            (None, Some(store)) => Some(Arc::new(move |dlq: &DeadLetterQueue| {
+                dlq.save_to(&store).map(|()| true)
+            })),
+            (None, None) => None,
+        };
+        Arc::get_mut(&mut self.dlq)
+            .expect("the DLQ is only shared once the service is running")
+            .set_on_flush(hook);
+    }
+
+    /// Bound the total payload bytes held across the DLQ and retry states
+    ///
+    /// The budget applies to the existing DLQ, so entries, quotas and
+    /// persistence set up by earlier builders stay, and count against it.
+    pub fn with_payload_budget(mut self, budget: PayloadBudget) -> Self {
+        Arc::get_mut(&mut self.dlq)
+            .expect("the DLQ is only shared once the service is running")
+            .set_payload_budget(Arc::new(budget));
+        self
+    }
+
+    /// Drop a transaction's retry state, returning its payload bytes to the budget
+    fn remove_retry_state(&self, transaction_id: &str) -> Option<RetryState> {
+        let mut states = self.retry_states.lock().unwrap();
+        let removed = states.remove(transaction_id);
+        if let Some(state) = &removed {
+            self.retry_state_removed(transaction_id, state);
+        }
+        removed
+    }
+
+    /// Drop the retry state of a transaction that went through, returning
+    /// whether it had one; the caller holds the transaction's lock
+    ///
+    /// If it was a replay, the entry is kept as a resolved dead letter, with
+    /// the payload its retry held.
+    fn resolve_retry_state(&self, transaction_id: &str) -> bool {
+        let state = self.remove_retry_state(transaction_id);
+        let resolved = state.is_some();
+        if self
+            .dlq
+            .get_entry(transaction_id)
+            .is_some_and(|entry| entry.replaying)
+        {
+            self.dlq.update_entry(transaction_id, |stored| {
+                stored.replaying = false;
+                stored.status = DlqEntryStatus::Resolved;
+                if let Some(state) = state {
+                    stored.payload = state.payload;
+                    stored.payload_stripped = false;
+                }
+            });
+            self.log_dlq_changes();
+        }
+        resolved
+    }
+
+    /// The other transactions of `group_id` still retrying, ordered by ID
+    fn group_members(&self, transaction_id: &str, group_id: &str) -> Vec<String> {
+        if group_id.is_empty() {
+            return Vec::new();
+        }
+        let mut members: Vec<String> = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(member_id, state)| state.group_id == group_id && *member_id != transaction_id)
+            .map(|(member_id, _)| member_id.clone())
+            .collect();
+        members.sort();
+        members
+    }
+
+    /// Drop a transaction's retry state and those of `members` still in
+    /// `group_id`, all under one lock so no member is seen retrying once
+    /// another is gone. The caller holds all their transaction locks.
+    /// Returns the transaction's state and the members'.
+    fn remove_group_retry_states(
+        &self,
+        transaction_id: &str,
+        group_id: &str,
+        members: &[String],
+    ) -> (Option<RetryState>, Vec<(String, RetryState)>) {
+        let mut states = self.retry_states.lock().unwrap();
+        let removed = states.remove(transaction_id);
+        if let Some(state) = &removed {
+            self.retry_state_removed(transaction_id, state);
+        }
+        let members = members
+            .iter()
+            .filter_map(|member_id| {
+                // Only those still in the group; one may have been resolved
+                // before its lock was taken
+                if states.get(member_id)?.group_id != group_id {
+                    return None;
+                }
+                let state = states.remove(member_id)?;
+                self.retry_state_removed(member_id, &state);
+                Some((member_id.clone(), state))
+            })
+            .collect();
+        (removed, members)
+    }
+
+    /// Put back a retry state removed for a DLQ add that the DLQ then
+    /// refused, so the transaction keeps retrying rather than being lost
+    fn restore_retry_state(&self, transaction_id: &str, state: RetryState) {
+        if let Err(e) = self.store_retry_state(transaction_id, state) {
+            warn!(
+                "Retry state for {} lost after the DLQ refused it: {}",
+                transaction_id, e
+            );
+        }
+    }
+
+    /// Return a removed state's payload bytes to the budget and log it gone
+    fn retry_state_removed(&self, transaction_id: &str, state: &RetryState) {
+        self.dlq.release_payload(state.payload.len() as u64);
+        self.in_flight
+            .finish(&self.circuit_breaker_name(&state.psp_name));
+        self.log_event(|| EngineEvent::RetryStateRemoved {
+            transaction_id: transaction_id.to_string(),
+        });
+    }
+
+    /// Insert or replace a transaction's retry state, charging the payload
+    /// budget only for the size difference from any previous state
+    fn store_retry_state(&self, transaction_id: &str, state: RetryState) -> Result<(), DlqError> {
+        let stored = self.store_retry_state_locked(transaction_id, state);
+        // Reserving the payload may have evicted DLQ entries
+        self.log_dlq_changes();
+        stored
+    }
+
+    fn store_retry_state_locked(
+        &self,
+        transaction_id: &str,
+        state: RetryState,
+    ) -> Result<(), DlqError> {
+        let mut states = self.retry_states.lock().unwrap();
+        let previous = states.get(transaction_id);
+        let previous_bytes = previous.map_or(0, |previous| previous.payload.len() as u64);
+        let payload_bytes = state.payload.len() as u64;
+        if payload_bytes > previous_bytes {
+            self.dlq.reserve_payload(payload_bytes - previous_bytes)?;
+        } else {
+            self.dlq.release_payload(previous_bytes - payload_bytes);
+        }
+
+        // A reschedule for the same PSP is still the same in-flight transaction
+        let previous_psp = previous.map(|previous| previous.psp_name.as_str());
+        if previous_psp != Some(state.psp_name.as_str()) {
+            if let Some(previous_psp) = previous_psp {
+                self.in_flight
+                    .finish(&self.circuit_breaker_name(previous_psp));
+            }
+            self.in_flight
+                .start(&self.circuit_breaker_name(&state.psp_name));
+        }
+
+        self.log_event(|| EngineEvent::RetryStateStored {
+            transaction_id: transaction_id.to_string(),
+            psp_name: state.psp_name.clone(),
+            attempt_count: state.attempt_count,
+            last_error: state.last_error.clone(),
+            last_attempt_at_ms: state.last_attempt_at_ms,
+            next_retry_at_ms: state.next_retry_at_ms,
+            payload: state.payload.clone(),
+            payload_stripped: false,
+            content_type: state.content_type.clone(),
+            encoding: state.encoding.clone(),
+            tags: state.tags.clone(),
+            first_scheduled_at_ms: state.first_scheduled_at_ms,
+            group_id: state.group_id.clone(),
+            due_at_ms: state.due_at_ms,
+            drift_ms: state.drift_ms,
+        });
+        states.insert(transaction_id.to_string(), state);
+        Ok(())
+    }
+
+    /// Tags from the request plus any the transaction already carries, so a
+    /// tag set on any attempt sticks to it
+    fn merged_tags(&self, transaction_id: &str, tags: Vec<String>) -> Vec<String> {
+        let mut merged = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(transaction_id)
+            .map(|state| state.tags.clone())
+            .unwrap_or_default();
+        merged.extend(tags);
+        merged.sort();
+        merged.dedup();
+        merged
+    }
+
+    /// DLQ entries carrying `tag` via the tag index, or every entry if it's empty
+    fn dlq_entries_tagged(&self, tag: &str) -> Vec<DLQEntry> {
+        if tag.is_empty() {
+            self.dlq.get_all_entries()
+        } else {
+            self.dlq.entries_with_tag(tag)
+        }
+    }
+
+    /// Put a dead-lettered transaction back into the retry pipeline, returning
+    /// when its first attempt is due.
+    ///
+    /// The entry stays in the DLQ marked `replaying` and `InReview` so its
+    /// history survives, and is overwritten if the transaction exhausts its
+    /// retries again. Its payload moves to the retry state, so it's held once;
+    /// meanwhile the entry reports it as stripped. Resolved and discarded
+    /// entries can't be replayed. With a `target_psp` the transaction is
+    /// re-homed to that PSP, and it's that PSP's breaker that must allow the
+    /// replay; it's only looked at, as the replayed attempt goes through the
+    /// breaker when it's scheduled. Entries replayed `max_replays` times are
+    /// refused unless `force` is set. A `payload` replaces the entry's, and is
+    /// required if the entry's payload wasn't persisted. The replayed attempt
+    /// is due `delay_ms` from now, which is returned. A leased entry is only
+    /// replayed for the holder of `lease_id` ("" for none), and the replay
+    /// ends the lease.
+    fn replay_entry(
+        &self,
+        entry: &DLQEntry,
+        target_psp: Option<&str>,
+        force: bool,
+        delay_ms: u64,
+        payload: Option<&[u8]>,
+        lease_id: &str,
+    ) -> Result<u64, String> {
+        // Work from the entry as it is now, in the transaction's critical section
+        let _transaction = self.transaction_locks.lock(&entry.transaction_id);
+        let entry = &self
+            .dlq
+            .get_entry(&entry.transaction_id)
+            .ok_or_else(|| "Transaction not in dead letter queue".to_string())?;
+        if self.paused.load(Ordering::SeqCst) {
+            return Err("Retry engine paused".to_string());
+        }
+        self.dlq
+            .check_lease(&entry.transaction_id, lease_id, self.clock.now_ms())
+            .map_err(|e| e.to_string())?;
+        if entry.status.is_terminal() {
+            return Err(format!("Entry is {:?} and can't be replayed", entry.status));
+        }
+        if let Some(note) = &entry.parked_note {
+            return Err(format!("Entry is parked ({}), unpark it first", note));
+        }
+        if !force && self.max_replays > 0 && entry.replay_count >= self.max_replays {
+            return Err(format!(
+                "Replay limit reached ({} of {}), set force to replay again",
+                entry.replay_count, self.max_replays
+            ));
+        }
+        let payload = match payload {
+            Some(payload) => payload.to_vec(),
+            None if entry.payload_stripped => {
+                return Err("Payload was not persisted, supply it to replay".to_string())
+            }
+            None => entry.payload.clone(),
+        };
+
+        let psp_name = target_psp.unwrap_or(&entry.psp_name);
+        let now = self.clock.now_ms();
+        // The slot is given back if the replay fails from here on, so a
+        // refused replay doesn't hold up the next one
+        let slot = self.reserve_replay_slot(psp_name, now, delay_ms)?;
+        let replayed = self.start_replay(entry, psp_name, payload, now, slot.delay_ms, lease_id);
+        if replayed.is_err() {
+            self.release_replay_slot(psp_name, &slot);
+        }
+        replayed
+    }
+
+    /// The rest of `replay_entry` once the replay has its slot: check the
+    /// circuit, then move the entry's payload into a new retry state
+    fn start_replay(
+        &self,
+        entry: &DLQEntry,
+        psp_name: &str,
+        payload: Vec<u8>,
+        now: u64,
+        delay_ms: u64,
+        lease_id: &str,
+    ) -> Result<u64, String> {
+        let (_, breaker) = self.get_or_create_circuit_breaker(psp_name)?;
+        if !breaker.would_proceed() {
+            return Err(format!("Circuit breaker open for PSP: {}", psp_name));
+        }
+
+        // Take the payload out of the entry first, so the budget has its
+        // bytes back for the retry state
+        self.dlq
+            .update_leased_entry(
+                &entry.transaction_id,
+                lease_id,
+                self.clock.now_ms(),
+                |stored| {
+                    stored.replaying = true;
+                    stored.replay_count += 1;
+                    stored.status = DlqEntryStatus::InReview;
+                    stored.payload = Vec::new();
+                    stored.payload_stripped = !payload.is_empty();
+                    if stored.psp_name != psp_name {
+                        let previous =
+                            std::mem::replace(&mut stored.psp_name, psp_name.to_string());
+                        stored.rerouted_from = Some(previous);
+                    }
+                },
+            )
+            .map_err(|e| e.to_string())?;
+        let stored = self.store_retry_state(
+            &entry.transaction_id,
+            RetryState {
+                psp_name: psp_name.to_string(),
+                attempt_count: 0,
+                last_error: entry.last_error.clone(),
+                last_attempt_at_ms: now,
+                next_retry_at_ms: now + delay_ms,
+                payload,
+                content_type: entry.content_type.clone(),
+                encoding: entry.encoding.clone(),
+                tags: entry.tags.clone(),
+                first_scheduled_at_ms: now,
+                outcome_due_at_ms: self.outcome_due_at_ms(delay_ms),
+                outcome_settled: false,
+                group_id: String::new(),
+                due_at_ms: self.clock.now_ms().saturating_add(delay_ms),
+                drift_ms: 0,
+            },
+        );
+        if let Err(e) = stored {
+            self.dlq
+                .update_entry(&entry.transaction_id, |stored| *stored = entry.clone());
+            self.log_dlq_changes();
+            return Err(e.to_string());
+        }
+        if !lease_id.is_empty() {
+            // Expired or not, the lease has nothing left to cover
+            let _ = self
+                .dlq
+                .release_lease(&entry.transaction_id, lease_id, self.clock.now_ms());
+        }
+        self.log_dlq_changes();
+        Ok(now + delay_ms)
+    }
+
+    /// Use a custom bucket width/retention for the retry time series
+    pub fn with_time_series(mut self, time_series: RetryTimeSeries) -> Self {
+        self.time_series = Arc::new(time_series);
+        self
+    }
+
+    /// The breaker `psp_name` is tracked under, created if new, and that
+    /// breaker's name. Once `max_psp_breakers` PSPs have one, a new PSP gets
+    /// `OVERFLOW_BREAKER`, or an error under `PspOverflow::Reject`.
+    fn get_or_create_circuit_breaker(
+        &self,
+        psp_name: &str,
+    ) -> Result<(String, CircuitBreaker), String> {
+        let mut breakers = self.circuit_breakers.lock().unwrap();
+        let name = if !self.psp_cap_reached(&breakers) || breakers.contains_key(psp_name) {
+            psp_name
+        } else {
+            if !self.psp_cap_reached.swap(true, Ordering::Relaxed) {
+                warn!(
+                    "{} PSPs have circuit breakers, the most allowed; new PSPs from {} on are {}",
+                    self.max_psp_breakers,
+                    psp_name,
+                    match self.psp_overflow {
+                        PspOverflow::Shared => "sharing the overflow breaker",
+                        PspOverflow::Reject => "refused",
+                    }
+                );
+            }
+            match self.psp_overflow {
+                PspOverflow::Shared => OVERFLOW_BREAKER,
+                PspOverflow::Reject => return Err(self.too_many_psps()),
+            }
+        };
+        let breaker = breakers
+            .entry(name.to_string())
+            .or_insert_with(|| {
+                let breaker = CircuitBreaker::new(self.circuit_config_for(name))
+                    .with_clock(self.clock.clone());
+                self.log_event(|| EngineEvent::CircuitStateChanged {
+                    psp_name: name.to_string(),
+                    state: breaker.get_state(),
+                });
+                breaker
+            })
+            .clone();
+        Ok((name.to_string(), breaker))
+    }
+
+    /// Whether `max_psp_breakers` PSPs already have a breaker of their own
+    fn psp_cap_reached(&self, breakers: &HashMap<String, CircuitBreaker>) -> bool {
+        let tracked = breakers.len() - usize::from(breakers.contains_key(OVERFLOW_BREAKER));
+        self.max_psp_breakers > 0 && tracked >= self.max_psp_breakers
+    }
+
+    fn too_many_psps(&self) -> String {
+        format!(
+            "Too many PSPs: at most {} are tracked",
+            self.max_psp_breakers
+        )
+    }
+
+    /// The name of the breaker `psp_name` is tracked under, or would be,
+    /// without creating it: `OVERFLOW_BREAKER` for a PSP past the cap
+    ///
+    /// Per-PSP metrics are keyed by it, so a flood of PSP names is bounded
+    /// there as well. Under `PspOverflow::Reject` the PSPs past the cap have
+    /// no breaker, but their metrics are still lumped together.
+    fn circuit_breaker_name(&self, psp_name: &str) -> String {
+        let breakers = self.circuit_breakers.lock().unwrap();
+        if self.psp_cap_reached(&breakers) && !breakers.contains_key(psp_name) {
+            OVERFLOW_BREAKER.to_string()
+        } else {
+            psp_name.to_string()
+        }
+    }
+
+    /// Every breaker, ordered by PSP name so listings are stable
+    fn circuit_breakers_by_name(&self) -> Vec<(String, CircuitBreaker)> {
+        let mut breakers: Vec<(String, CircuitBreaker)> = self
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, breaker)| (psp_name.clone(), breaker.clone()))
+            .collect();
+        breakers.sort_by(|a, b| a.0.cmp(&b.0));
+        breakers
+    }
+
+    /// Look up a PSP's breaker without creating one
+    fn get_circuit_breaker(&self, psp_name: &str) -> Option<CircuitBreaker> {
+        self.circuit_breakers.lock().unwrap().get(psp_name).cloned()
+    }
+
+    /// The PSP's pending retry that has been waiting longest, the one to probe
+    /// a recovering circuit with
+    fn longest_waiting_retry(&self, psp_name: &str) -> Option<String> {
+        let states = self.retry_states.lock().unwrap();
+        states
+            .iter()
+            .filter(|(_, state)| state.psp_name == psp_name)
+            .min_by_key(|(transaction_id, state)| (state.first_scheduled_at_ms, *transaction_id))
+            .map(|(transaction_id, _)| transaction_id.clone())
+    }
+
+    /// A request's attempt number, refusing negative ones and handling ones
+    /// above `max_attempt_number` per `attempt_overflow`, so none wrap
+    fn checked_attempt(&self, attempt_number: i32) -> Result<u32, String> {
+        let attempt = u32::try_from(attempt_number)
+            .map_err(|_| "attempt_number must not be negative".to_string())?;
+        if attempt <= self.max_attempt_number {
+            return Ok(attempt);
+        }
+        match self.attempt_overflow {
+            AttemptOverflow::Clamp => Ok(self.max_attempt_number),
+            AttemptOverflow::Reject => Err(format!(
+                "attempt_number {} is above the maximum of {}",
+                attempt, self.max_attempt_number
+            )),
+        }
+    }
+
+    /// Refuse an empty or whitespace-only identifier, which would otherwise
+    /// key a breaker on "" or collide in the DLQ, unless `blank_ids` allows it
+    fn check_identifier(&self, field: &str, value: &str) -> Result<(), String> {
+        if !value.trim().is_empty() {
+            return Ok(());
+        }
+        match self.blank_ids {
+            BlankIds::Reject => Err(format!("{} must not be blank", field)),
+            BlankIds::Allow => {
+                warn!("Accepted a request with a blank {}", field);
+                Ok(())
+            }
+        }
+    }
+
+    /// The attempt a client's number counts as under the policy's
+    /// `attempt_numbering`
+    fn effective_attempt(&self, policy: &RetryPolicy, transaction_id: &str, requested: i32) -> u32 {
+        let pending = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(transaction_id)
+            .map(|state| state.attempt_count);
+        policy.effective_attempt(requested as u32, pending)
+    }
+
+    /// Whether the transaction is dead-lettered (and not being replayed)
+    fn is_dead_lettered(&self, transaction_id: &str) -> bool {
+        self.dlq
+            .get_entry(transaction_id)
+            .is_some_and(|entry| !entry.replaying)
+    }
+
+    /// Resolve the circuit config for a PSP: its override if set, else the global default
+    fn circuit_config_for(&self, psp_name: &str) -> CircuitBreakerConfig {
+        let overrides = self.circuit_overrides.lock().unwrap();
+        overrides
+            .get(psp_name)
+            .cloned()
+            .unwrap_or_else(|| self.circuit_config.clone())
+    }
+
+    /// Replace the circuit config for a single PSP.
+    ///
+    /// An existing breaker is rebuilt with the new config but keeps its current
+    /// state and counts, so changing thresholds never opens or closes it by itself;
+    /// the new thresholds apply from the next recorded outcome.
+    ///
+    /// A PSP without a breaker is refused once `max_psp_breakers` is reached,
+    /// whatever the overflow mode, as it can't have a config of its own.
+    fn set_psp_circuit_config(
+        &self,
+        psp_name: &str,
+        config: CircuitBreakerConfig,
+    ) -> Result<CircuitBreaker, String> {
+        let mut breakers = self.circuit_breakers.lock().unwrap();
+        let breaker = match breakers.get(psp_name) {
+            Some(existing) => existing.reconfigured(config.clone()),
+            None if self.psp_cap_reached(&breakers) => return Err(self.too_many_psps()),
+            None => CircuitBreaker::new(config.clone()).with_clock(self.clock.clone()),
+        };
+        breakers.insert(psp_name.to_string(), breaker.clone());
+        self.log_event(|| EngineEvent::CircuitConfigSet {
+            psp_name: psp_name.to_string(),
+            config: config.clone(),
+        });
+        self.log_event(|| EngineEvent::CircuitStateChanged {
+            psp_name: psp_name.to_string(),
+            state: breaker.get_state(),
+        });
+        self.circuit_overrides
+            .lock()
+            .unwrap()
+            .insert(psp_name.to_string(), config);
+        Ok(breaker)
+    }
+
+    /// Report the engine's load, e.g. from sampled lock contention or CPU,
+    /// so retries back off further while it is busy
+    ///
+    /// 0.0 is idle and 1.0 saturated; values outside that are clamped.
+    /// Delays are stretched by `1 + load_factor * load_stretch`.
+    pub fn set_load_factor(&self, load_factor: f64) {
+        let load_factor = if load_factor.is_nan() {
+            0.0
+        } else {
+            load_factor.clamp(0.0, 1.0)
+        };
+        self.load_factor
+            .store(load_factor.to_bits(), Ordering::SeqCst);
+    }
+
+    pub fn load_factor(&self) -> f64 {
+        f64::from_bits(self.load_factor.load(Ordering::SeqCst))
+    }
+
+    fn retry_policy(&self) -> Arc<RetryPolicy> {
+        self.retry_policy.lock().unwrap().clone()
+    }
+
+    /// The retry policy for attempts against `psp_name`: the engine-wide one,
+    /// with the PSP's own jitter settings if it has any
+    fn retry_policy_for(&self, psp_name: &str) -> Arc<RetryPolicy> {
+        let overridden = self
+            .psp_retry_policies
+            .lock()
+            .unwrap()
+            .get(psp_name)
+            .cloned();
+        overridden.unwrap_or_else(|| self.retry_policy())
+    }
+
+    /// Build the engine-wide retry policy for `config`, and one for each PSP
+    /// with a jitter override, all drawing from the service's RNG
+    fn install_retry_policies(&self, config: RetryConfig) {
+        let policy = |config: RetryConfig| {
+            Arc::new(RetryPolicy::with_rng(config, Box::new(self.rng.clone())))
+        };
+        let psp_policies = self
+            .jitter_overrides
+            .iter()
+            .map(|(psp_name, jitter)| (psp_name.clone(), policy(jitter.apply_to(&config))))
+            .collect();
+        *self.psp_retry_policies.lock().unwrap() = psp_policies;
+        *self.retry_policy.lock().unwrap() = policy(config);
+    }
+
+    fn set_psp_access(&self, policy: PspAccessPolicy) {
+        let key_all = |names: BTreeSet<String>| -> BTreeSet<String> {
+            names.into_iter().map(|name| self.psp_key(name)).collect()
+        };
+        let policy = PspAccessPolicy {
+            allowlist: policy.allowlist.map(key_all),
+            denylist: key_all(policy.denylist),
+        };
+        self.log_event(|| EngineEvent::PspAccessSet {
+            policy: policy.clone(),
+        });
+        *self.psp_access.lock().unwrap() = policy;
+    }
+
+    fn psp_allowed(&self, psp_name: &str) -> bool {
+        self.psp_access.lock().unwrap().allows(psp_name)
+    }
+
+    fn set_retry_policy(&self, config: RetryConfig) {
+        self.log_event(|| EngineEvent::RetryConfigSet {
+            config: config.clone(),
+        });
+        self.install_retry_policies(config);
+    }
+
+    /// Whether this engine would take `config`: valid on its own, and with no
+    /// more attempts than `max_attempt_number` lets clients report
+    fn check_retry_config(&self, config: &RetryConfig) -> Result<(), String> {
+        config.validate()?;
+        if config.max_attempts > self.max_attempt_number {
+            return Err(format!(
+                "max_attempts {} is above the highest attempt number accepted, {}",
+                config.max_attempts, self.max_attempt_number
+            ));
+        }
+        Ok(())
+    }
+
+    /// Switch to a new retry config for every transaction, including those
+    /// already retrying
+    ///
+    /// Lowering `max_attempts` would leave transactions whose last attempt is
+    /// already at the new limit with a retry state that can never be
+    /// scheduled again, so they are moved to the DLQ straight away. Returns
+    /// their IDs.
+    pub fn apply_retry_config(&self, config: RetryConfig) -> Result<Vec<String>, String> {
+        self.check_retry_config(&config)?;
+        let max_attempts = config.max_attempts;
+        self.set_retry_policy(config);
+
+        let stranded = self.stranded_by(max_attempts);
+        let mut dead_lettered = Vec::with_capacity(stranded.len());
+        for transaction_id in stranded {
+            // Recheck in the transaction's critical section, as a concurrent
+            // schedule or resolve may have moved it on
+            let transaction = self.transaction_locks.lock(&transaction_id);
+            let state = self
+                .retry_states
+                .lock()
+                .unwrap()
+                .get(&transaction_id)
+                .filter(|state| state.attempt_count >= max_attempts)
+                .cloned();
+            let Some(state) = state else {
+                continue;
+            };
+            let group_id = state.group_id;
+            let dlq_entry = DLQEntry {
+                transaction_id: transaction_id.clone(),
+                psp_name: state.psp_name,
+                payload: state.payload,
+                attempt_count: state.attempt_count,
+                last_error: format!("Max retry attempts lowered to {}", max_attempts),
+                reason: DlqReason::MaxAttemptsLowered,
+                timestamp_ms: self.clock.now_ms(),
+                tags: state.tags,
+                content_type: state.content_type,
+                encoding: state.encoding,
+                ..Default::default()
+            };
+            let response = self.dead_letter_exhausted(
+                transaction,
+                dlq_entry,
+                &group_id,
+                "Max retry attempts lowered",
+                None,
+            );
+            info!("{}: {}", transaction_id, response.message);
+            dead_lettered.push(transaction_id);
+        }
+        Ok(dead_lettered)
+    }
+
+    /// Retrying transactions whose last attempt is already at `max_attempts`,
+    /// ordered by ID
+    fn stranded_by(&self, max_attempts: u32) -> Vec<String> {
+        let mut stranded: Vec<String> = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, state)| state.attempt_count >= max_attempts)
+            .map(|(transaction_id, _)| transaction_id.clone())
+            .collect();
+        stranded.sort();
+        stranded
+    }
+
+    /// PSPs with a retrying transaction that `proposed` would give different
+    /// remaining retries than the current config, if every one fails
+    fn rescheduled_psps(&self, proposed: &RetryPolicy) -> Vec<String> {
+        let current = self.retry_policy();
+        let mut psp_names: Vec<String> = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|state| state.attempt_count < proposed.max_attempts())
+            .filter(|state| {
+                !current
+                    .remaining_delays(state.attempt_count)
+                    .eq(proposed.remaining_delays(state.attempt_count))
+            })
+            .map(|state| state.psp_name.clone())
+            .collect();
+        psp_names.sort();
+        psp_names.dedup();
+        psp_names
+    }
+
+    /// Make the scheduling decision for a request and apply it, declining if
+    /// the next attempt would land after `deadline_ms`
+    ///
+    /// The whole decision runs in the transaction's critical section: the DLQ
+    /// check and whatever follows it (storing a retry state or dead-lettering)
+    /// happen as one step with respect to other attempts, replays and retry
+    /// config changes for the same transaction. Two exhausted attempts racing
+    /// each other add one DLQ entry, and the loser sees it already there.
+    ///
+    /// `trace_id` is the calling request's trace, recorded with the metrics.
+    fn schedule_retry_now(
+        &self,
+        req: RetryRequest,
+        deadline_ms: Option<u64>,
+        trace_id: Option<&str>,
+    ) -> RetryResponse {
+        let transaction = self.transaction_locks.lock(&req.transaction_id);
+
+        // Too soon after the last call: decline before anything is looked at
+        if let Some(limiter) = &self.schedule_rate_limit {
+            if let Err(wait_ms) = limiter.try_acquire(&req.transaction_id, self.clock.now_ms()) {
+                return RetryResponse {
+                    retry_id: req.transaction_id,
+                    scheduled: false,
+                    next_retry_at_ms: 0,
+                    attempt_timeout_ms: 0,
+                    message: format!(
+                        "Scheduling too frequently: at most one call per {}ms, next allowed in {}ms",
+                        limiter.min_interval_ms(),
+                        wait_ms
+                    ),
+                };
+            }
+        }
+
+        let transaction_id = req.transaction_id.clone();
+        let psp_name = req.psp_name.clone();
+        let tags = self.merged_tags(&transaction_id, req.tags);
+        let content_type = req.content_type;
+        let encoding = Some(req.encoding).filter(|encoding| !encoding.is_empty());
+        let retry_policy = self.retry_policy_for(&psp_name);
+        let attempt = self.effective_attempt(&retry_policy, &transaction_id, req.attempt_number);
+        self.time_follow_up(&transaction_id, attempt);
+        let group_id = match req.group_id.is_empty() {
+            false => req.group_id,
+            true => self
+                .retry_states
+                .lock()
+                .unwrap()
+                .get(&transaction_id)
+                .map(|state| state.group_id.clone())
+                .unwrap_or_default(),
+        };
+
+        // A blocked PSP is declined before anything else is consulted
+        if !self.psp_allowed(&psp_name) {
+            return RetryResponse {
+                retry_id: transaction_id,
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: format!("PSP blocked by policy: {}", psp_name),
+            };
+        }
+
+        // While paused, decline before touching breakers, the DLQ, or retry states
+        if self.paused.load(Ordering::SeqCst) {
+            return RetryResponse {
+                retry_id: transaction_id,
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: "Retry engine paused".to_string(),
+            };
+        }
+
+        // Check if already in DLQ (replayed entries are back in the pipeline)
+        if self.is_dead_lettered(&transaction_id) {
+            return RetryResponse {
+                retry_id: transaction_id.clone(),
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: "Transaction already in dead letter queue".to_string(),
+            };
+        }
+
+        // Check circuit breaker
+        let allowed = match self.circuit_allows(&psp_name) {
+            Ok(allowed) => allowed,
+            Err(message) => {
+                return RetryResponse {
+                    retry_id: transaction_id,
+                    scheduled: false,
+                    next_retry_at_ms: 0,
+                    attempt_timeout_ms: 0,
+                    message,
+                }
+            }
+        };
+        if !allowed {
+            let paused = self
+                .get_circuit_breaker(&psp_name)
+                .is_some_and(|breaker| breaker.is_paused());
+            return RetryResponse {
+                retry_id: transaction_id.clone(),
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: if paused {
+                    format!("Circuit breaker paused for PSP maintenance: {}", psp_name)
+                } else {
+                    format!("Circuit breaker open for PSP: {}", psp_name)
+                },
+            };
+        }
+
+        // Check if we should retry
+        if !retry_policy.should_retry(attempt) {
+            let dlq_entry = DLQEntry {
+                transaction_id,
+                psp_name,
+                payload: req.payload,
+                attempt_count: attempt,
+                last_error: "Max retry attempts exceeded".to_string(),
+                reason: DlqReason::MaxAttemptsExceeded,
+                timestamp_ms: self.clock.now_ms(),
+                tags,
+                content_type,
+                encoding,
+                ..Default::default()
+            };
+            return self.dead_letter_exhausted(
+                transaction,
+                dlq_entry,
+                &group_id,
+                "Max retries exceeded",
+                trace_id,
+            );
+        }
+
+        // Calculate next retry delay
+        let delay_ms = retry_policy.calculate_delay_loaded(attempt, self.load_factor());
+        let next_retry_at_ms = self.clock.now_ms().saturating_add(delay_ms);
+
+        // Out of time for the whole lifecycle, even with attempts left
+        let (first_scheduled_at_ms, drift_ms) = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(&transaction_id)
+            .map_or_else(
+                || (self.clock.now_ms(), 0),
+                |state| (state.first_scheduled_at_ms, state.drift_ms),
+            );
+        if !retry_policy.within_max_elapsed(first_scheduled_at_ms, next_retry_at_ms) {
+            let dlq_entry = DLQEntry {
+                transaction_id,
+                psp_name,
+                payload: req.payload,
+                attempt_count: attempt,
+                last_error: "Max retry time exceeded".to_string(),
+                reason: DlqReason::MaxElapsedExceeded,
+                timestamp_ms: self.clock.now_ms(),
+                tags,
+                content_type,
+                encoding,
+                ..Default::default()
+            };
+            return self.dead_letter_exhausted(
+                transaction,
+                dlq_entry,
+                &group_id,
+                "Max retry time exceeded",
+                trace_id,
+            );
+        }
+
+        // The caller will have given up by then, so don't queue the attempt
+        if let Some(deadline_ms) = deadline_ms.filter(|deadline| next_retry_at_ms > *deadline) {
+            return RetryResponse {
+                retry_id: transaction_id,
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: format!(
+                    "Deadline would be exceeded: next attempt due at {} but the deadline is {}",
+                    next_retry_at_ms, deadline_ms
+                ),
+            };
+        }
+
+        // Over its tier's budget, even after borrowing from lower tiers
+        if let Some(budget) = &self.retry_budget {
+            let tier = budget.tier_for(req.priority as u32);
+            if let Err(wait_ms) = budget.try_acquire(tier, self.clock.now_ms()) {
+                return RetryResponse {
+                    retry_id: transaction_id,
+                    scheduled: false,
+                    next_retry_at_ms: 0,
+                    attempt_timeout_ms: 0,
+                    message: format!(
+                        "Retry budget exhausted for priority tier {}, next window in {}ms",
+                        tier, wait_ms
+                    ),
+                };
+            }
+        }
+
+        // Update retry state
+        let state = RetryState {
+            psp_name: psp_name.clone(),
+            attempt_count: attempt,
+            last_error: String::new(),
+            last_attempt_at_ms: self.clock.now_ms(),
+            next_retry_at_ms,
+            payload: req.payload,
+            content_type,
+            encoding,
+            tags,
+            first_scheduled_at_ms,
+            outcome_due_at_ms: self.outcome_due_at_ms(delay_ms),
+            outcome_settled: false,
+            group_id,
+            due_at_ms: self.clock.now_ms().saturating_add(delay_ms),
+            drift_ms,
+        };
+        if let Err(e) = self.store_retry_state(&transaction_id, state) {
+            return RetryResponse {
+                retry_id: transaction_id.clone(),
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: format!("Retry not scheduled: {}", e),
+            };
+        }
+        self.time_series.record_scheduled_retry(self.clock.now_ms());
+        self.retries_scheduled.increment(
+            &self.circuit_breaker_name(&psp_name),
+            Exemplar {
+                transaction_id: transaction_id.clone(),
+                trace_id: trace_id.map(str::to_string),
+                timestamp_ms: self.clock.now_ms(),
+            },
+        );
+
+        RetryResponse {
+            retry_id: transaction_id,
+            scheduled: true,
+            next_retry_at_ms: next_retry_at_ms as i64,
+            attempt_timeout_ms: retry_policy.attempt_timeout_ms(attempt) as i64,
+            message: format!("Retry scheduled for attempt {}", attempt + 1),
+        }
+    }
+
+    /// Settle the outcome of the attempt scheduled after `failed_attempt`,
+    /// returning false if it already was, or its deadline has passed. A retry
+    /// scheduled for another attempt meanwhile is left alone.
+    fn settle_outcome(&self, transaction_id: &str, failed_attempt: u32) -> bool {
+        let mut states = self.retry_states.lock().unwrap();
+        let Some(state) = states
+            .get_mut(transaction_id)
+            .filter(|state| state.attempt_count == failed_attempt)
+        else {
+            return true;
+        };
+        let overdue = state.outcome_due_at_ms > 0 && state.outcome_due_at_ms <= self.clock.now_ms();
+        if state.outcome_settled || overdue {
+            return false;
+        }
+        state.outcome_settled = true;
+        state.outcome_due_at_ms = 0;
+        true
+    }
+
+    /// Time a follow-up for `attempt` against when the transaction's pending
+    /// attempt was due, once per scheduled attempt; resent and stale
+    /// attempt numbers aren't timed
+    fn time_follow_up(&self, transaction_id: &str, attempt: u32) {
+        let mut states = self.retry_states.lock().unwrap();
+        let Some(state) = states
+            .get_mut(transaction_id)
+            .filter(|state| state.due_at_ms > 0 && attempt > state.attempt_count)
+        else {
+            return;
+        };
+        state.drift_ms = self.clock.now_ms().saturating_sub(state.due_at_ms);
+        state.due_at_ms = 0;
+        self.retry_drift
+            .record(&self.circuit_breaker_name(&state.psp_name), state.drift_ms);
+    }
+
+    /// Move a transaction that has run out of retries to the DLQ, keeping the
+    /// replay count of a replayed entry, along with every other member of
+    /// `group_id` still retrying
+    ///
+    /// If the DLQ refuses the transaction, it and its group keep their retry
+    /// states; a refused member keeps its own.
+    ///
+    /// `transaction` is the caller's hold on the transaction's lock. The
+    /// members' are taken too before their states are touched; if one can't
+    /// be taken in order while holding it, every lock is let go and retaken,
+    /// and the transaction is left alone if it changed meanwhile.
+    fn dead_letter_exhausted(
+        &self,
+        transaction: MutexGuard<'_, ()>,
+        dlq_entry: DLQEntry,
+        group_id: &str,
+        reason: &str,
+        trace_id: Option<&str>,
+    ) -> RetryResponse {
+        let transaction_id = dlq_entry.transaction_id.clone();
+        let psp_name = dlq_entry.psp_name.clone();
+        let attempt_count = dlq_entry.attempt_count;
+        let members = self.group_members(&transaction_id, group_id);
+        let member_ids = members.iter().map(String::as_str);
+        let _group = match self
+            .transaction_locks
+            .try_lock_with(transaction_id.as_str(), member_ids)
+        {
+            Some(group) => group,
+            None => {
+                let before = self
+                    .retry_states
+                    .lock()
+                    .unwrap()
+                    .get(&transaction_id)
+                    .cloned();
+                drop(transaction);
+                let all = std::iter::once(transaction_id.as_str())
+                    .chain(members.iter().map(String::as_str));
+                let group = self.transaction_locks.lock_all(all);
+                if self.retry_states.lock().unwrap().get(&transaction_id) != before.as_ref() {
+                    return RetryResponse {
+                        retry_id: transaction_id,
+                        scheduled: false,
+                        next_retry_at_ms: 0,
+                        attempt_timeout_ms: 0,
+                        message: format!(
+                            "{}, but the transaction changed while group {} was locked",
+                            reason, group_id
+                        ),
+                    };
+                }
+                group
+            }
+        };
+        let (state, members) = self.remove_group_retry_states(&transaction_id, group_id, &members);
+        if let Err(e) = self.add_dead_letter(dlq_entry, trace_id) {
+            let kept = state.map(|state| (transaction_id.clone(), state));
+            for (kept_id, kept_state) in kept.into_iter().chain(members) {
+                self.restore_retry_state(&kept_id, kept_state);
+            }
+            return RetryResponse {
+                retry_id: transaction_id,
+                scheduled: false,
+                next_retry_at_ms: 0,
+                attempt_timeout_ms: 0,
+                message: format!("{}, DLQ refused entry: {}", reason, e),
+            };
+        }
+        self.final_attempts
+            .record_dead_letter(&self.circuit_breaker_name(&psp_name), attempt_count);
+
+        let mut message = format!("{}, moved to DLQ", reason);
+        if !members.is_empty() {
+            message.push_str(&format!(
+                " with {} other member(s) of group {}",
+                members.len(),
+                group_id
+            ));
+        }
+        for (member_id, state) in members {
+            let member_entry = DLQEntry {
+                transaction_id: member_id.clone(),
+                psp_name: state.psp_name.clone(),
+                payload: state.payload.clone(),
+                attempt_count: state.attempt_count,
+                last_error: format!(
+                    "Group {} abandoned: {} {}",
+                    group_id,
+                    transaction_id,
+                    reason.to_lowercase()
+                ),
+                reason: DlqReason::GroupMemberExhausted,
+                timestamp_ms: self.clock.now_ms(),
+                tags: state.tags.clone(),
+                content_type: state.content_type.clone(),
+                encoding: state.encoding.clone(),
+                ..Default::default()
+            };
+            if let Err(e) = self.add_dead_letter(member_entry, trace_id) {
+                warn!(
+                    "Group member {} not dead-lettered, still retrying: {}",
+                    member_id, e
+                );
+                self.restore_retry_state(&member_id, state);
+            }
+        }
+
+        RetryResponse {
+            retry_id: transaction_id,
+            scheduled: false,
+            next_retry_at_ms: 0,
+            attempt_timeout_ms: 0,
+            message,
+        }
+    }
+
+    /// Add a dead letter, keeping the replay count of a replayed entry and
+    /// counting it in the DLQ metrics. The caller removes the retry state
+    /// first, so its payload budget is free for the entry, and puts it back
+    /// with `restore_retry_state` if the DLQ refuses the entry.
+    fn add_dead_letter(
+        &self,
+        mut dlq_entry: DLQEntry,
+        trace_id: Option<&str>,
+    ) -> Result<(), DlqError> {
+        let transaction_id = dlq_entry.transaction_id.clone();
+        let psp_name = dlq_entry.psp_name.clone();
+        dlq_entry.replay_count = self
+            .dlq
+            .get_entry(&transaction_id)
+            .map_or(0, |entry| entry.replay_count);
+        if let Some(cooldown_ms) = self.soft_dlq_cooldown_ms {
+            dlq_entry.retry_after_ms = self.clock.now_ms().saturating_add(cooldown_ms);
+        }
+        let added = self.dlq.try_add_entry(dlq_entry);
+        self.log_dlq_changes();
+        if added.is_ok() {
+            self.time_series.record_dlq_add(self.clock.now_ms());
+            self.dlq_adds.increment(
+                &self.circuit_breaker_name(&psp_name),
+                Exemplar {
+                    transaction_id,
+                    trace_id: trace_id.map(str::to_string),
+                    timestamp_ms: self.clock.now_ms(),
+                },
+            );
+        }
+        added
+    }
+
+    /// Reserve a replay to `psp_name` due `delay_ms` after `now_ms`, at least
+    /// `min_replay_interval_ms` after the PSP's last one, and return the
+    /// slot with the delay it gets: pushed back under `ReplayThrottle::Queue`,
+    /// refused under `Reject` if it would have to be
+    fn reserve_replay_slot(
+        &self,
+        psp_name: &str,
+        now_ms: u64,
+        delay_ms: u64,
+    ) -> Result<ReplaySlot, String> {
+        if self.min_replay_interval_ms == 0 {
+            return Ok(ReplaySlot {
+                delay_ms,
+                due_ms: now_ms.saturating_add(delay_ms),
+                previous_ms: None,
+            });
+        }
+        let key = self.circuit_breaker_name(psp_name);
+        let mut slots = self.replay_slots.lock().unwrap();
+        let due_ms = now_ms.saturating_add(delay_ms);
+        let allowed_at_ms = slots.get(&key).map_or(0, |last_ms| {
+            last_ms.saturating_add(self.min_replay_interval_ms)
+        });
+        let due_ms = match self.replay_throttle {
+            _ if due_ms >= allowed_at_ms => due_ms,
+            ReplayThrottle::Queue => allowed_at_ms,
+            ReplayThrottle::Reject => {
+                return Err(format!(
+                    "Replays to {} are limited to one per {}ms, next allowed in {}ms",
+                    psp_name,
+                    self.min_replay_interval_ms,
+                    allowed_at_ms - due_ms
+                ))
+            }
+        };
+        let previous_ms = slots.insert(key, due_ms);
+        Ok(ReplaySlot {
+            delay_ms: due_ms - now_ms,
+            due_ms,
+            previous_ms,
+        })
+    }
+
+    /// Give back a slot whose replay failed, unless a later replay has been
+    /// queued behind it
+    fn release_replay_slot(&self, psp_name: &str, slot: &ReplaySlot) {
+        if self.min_replay_interval_ms == 0 {
+            return;
+        }
+        let key = self.circuit_breaker_name(psp_name);
+        let mut slots = self.replay_slots.lock().unwrap();
+        if slots.get(&key) != Some(&slot.due_ms) {
+            return;
+        }
+        match slot.previous_ms {
+            Some(previous_ms) => slots.insert(key, previous_ms),
+            None => slots.remove(&key),
+        };
+    }
+
+    /// When a retrying transaction will be dead-lettered if every remaining
+    /// attempt fails
+    fn give_up_at_ms(&self, state: &RetryState) -> u64 {
+        self.retry_policy().projected_final_attempt_ms(
+            state.attempt_count,
+            state.next_retry_at_ms,
+            state.first_scheduled_at_ms,
+        )
+    }
+
+    /// `GetRetryStatus` for one transaction, given the locked retry states
+    fn retry_status(
+        &self,
+        states: &HashMap<String, RetryState>,
+        transaction_id: String,
+    ) -> RetryStatusResponse {
+        // Check if in DLQ (replayed entries report their retry state instead)
+        if let Some(dlq_entry) = self
+            .dlq
+            .get_entry(&transaction_id)
+            .filter(|entry| !entry.replaying)
+        {
+            return RetryStatusResponse {
+                transaction_id,
+                attempt_count: dlq_entry.attempt_count as i32,
+                status: "IN_DLQ".to_string(),
+                failure_comparison: Self::convert_failure_comparison(
+                    dlq_entry.compare_to_previous(),
+                ) as i32,
+                previous_error: dlq_entry
+                    .previous_failures
+                    .last()
+                    .map(|failure| failure.last_error.clone())
+                    .unwrap_or_default(),
+                dlq_reason: Self::convert_dlq_reason(dlq_entry.reason) as i32,
+                last_error: dlq_entry.last_error,
+                in_dlq: true,
+                give_up_at_ms: 0,
+                last_drift_ms: 0,
+            };
+        }
+
+        // Check retry state
+        if let Some(state) = states.get(&transaction_id) {
+            return RetryStatusResponse {
+                transaction_id,
+                attempt_count: state.attempt_count as i32,
+                status: "RETRYING".to_string(),
+                last_error: state.last_error.clone(),
+                in_dlq: false,
+                give_up_at_ms: self.give_up_at_ms(state) as i64,
+                last_drift_ms: state.drift_ms as i64,
+                ..Default::default()
+            };
+        }
+
+        RetryStatusResponse {
+            transaction_id,
+            attempt_count: 0,
+            status: "NOT_FOUND".to_string(),
+            last_error: String::new(),
+            in_dlq: false,
+            give_up_at_ms: 0,
+            ..Default::default()
+        }
+    }
+
+    fn circuit_response(&self, psp_name: String, state: CircuitBreakerState) -> CircuitResponse {
+        let now_ms = self.clock.now_ms();
+        CircuitResponse {
+            psp_name,
+            state: Self::convert_circuit_state(state.state) as i32,
+            failure_count: state.failure_count as i32,
+            success_count: state.success_count as i32,
+            last_failure_at_ms: state.last_failure_at_ms as i64,
+            next_attempt_at_ms: state.next_attempt_at_ms as i64,
+            soft_failure_count: state.soft_failure_count as i32,
+            never_seen: false,
+            open_activity: Self::convert_open_activity(state.open_activity(now_ms)) as i32,
+            rejected_count: state.rejected_count as i32,
+            time_in_state_ms: Some(Self::proto_time_in_state(state.time_in_state_at(now_ms))),
+            time_in_state_since_ms: state.time_in_state_since_ms as i64,
+            open_reason: Self::convert_open_reason(state.open_reason) as i32,
+            paused: state.paused,
+        }
+    }
+
+    fn proto_time_in_state(time_in_state: TimeInState) -> ProtoTimeInState {
+        ProtoTimeInState {
+            closed_ms: time_in_state.closed_ms as i64,
+            open_ms: time_in_state.open_ms as i64,
+            half_open_ms: time_in_state.half_open_ms as i64,
+        }
+    }
+
+    fn canary_response(
+        &self,
+        psp_name: String,
+        canary: Option<CircuitCanary>,
+    ) -> CircuitCanaryResponse {
+        match canary {
+            Some(canary) => CircuitCanaryResponse {
+                psp_name: psp_name.clone(),
+                active: true,
+                config: Some(Self::proto_circuit_config(canary.config())),
+                candidate: Some(self.circuit_response(psp_name, canary.get_state())),
+                would_have_opened: canary.would_have_opened() as i64,
+            },
+            None => CircuitCanaryResponse {
+                psp_name,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn convert_circuit_config(
+        config: ProtoCircuitBreakerConfig,
+    ) -> Result<CircuitBreakerConfig, String> {
+        let failure_threshold = u32::try_from(config.failure_threshold)
+            .map_err(|_| "failure_threshold must not be negative".to_string())?;
+        let success_threshold = u32::try_from(config.success_threshold)
+            .map_err(|_| "success_threshold must not be negative".to_string())?;
+        let timeout_duration_ms = u64::try_from(config.timeout_duration_ms)
+            .map_err(|_| "timeout_duration_ms must not be negative".to_string())?;
+        let latency_threshold_ms = u64::try_from(config.latency_threshold_ms)
+            .map_err(|_| "latency_threshold_ms must not be negative".to_string())?;
+        let probe_window = u32::try_from(config.probe_window)
+            .map_err(|_| "probe_window must not be negative".to_string())?;
+        let min_success_percent = u32::try_from(config.min_success_percent)
+            .map_err(|_| "min_success_percent must not be negative".to_string())?;
+        let reopen_suppression_ms = u64::try_from(config.reopen_suppression_ms)
+            .map_err(|_| "reopen_suppression_ms must not be negative".to_string())?;
+        let min_open_ms = u64::try_from(config.min_open_ms)
+            .map_err(|_| "min_open_ms must not be negative".to_string())?;
+        let success_decay_ms = u64::try_from(config.success_decay_ms)
+            .map_err(|_| "success_decay_ms must not be negative".to_string())?;
+
+        let config = CircuitBreakerConfig {
+            failure_threshold,
+            success_threshold,
+            timeout_duration_ms,
+            latency_threshold_ms,
+            half_open_close: HalfOpenClose::from_parts(probe_window, min_success_percent)?,
+            reopen_suppression_ms,
+            min_open_ms,
+            success_decay_ms,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn convert_dlq_status(status: DlqEntryStatus) -> ProtoDlqEntryStatus {
+        match status {
+            DlqEntryStatus::New => ProtoDlqEntryStatus::New,
+            DlqEntryStatus::InReview => ProtoDlqEntryStatus::InReview,
+            DlqEntryStatus::Resolved => ProtoDlqEntryStatus::Resolved,
+            DlqEntryStatus::Discarded => ProtoDlqEntryStatus::Discarded,
+        }
+    }
+
+    fn convert_dlq_reason(reason: DlqReason) -> ProtoDlqReason {
+        match reason {
+            DlqReason::Unspecified => ProtoDlqReason::Unspecified,
+            DlqReason::MaxAttemptsExceeded => ProtoDlqReason::MaxAttemptsExceeded,
+            DlqReason::MaxElapsedExceeded => ProtoDlqReason::MaxElapsedExceeded,
+            DlqReason::MaxAttemptsLowered => ProtoDlqReason::MaxAttemptsLowered,
+            DlqReason::ForcedByOperator => ProtoDlqReason::ForcedByOperator,
+            DlqReason::GroupMemberExhausted => ProtoDlqReason::GroupMemberExhausted,
+        }
+    }
+
+    fn dlq_status_from_proto(status: i32) -> Option<DlqEntryStatus> {
+        match ProtoDlqEntryStatus::try_from(status).ok()? {
+            ProtoDlqEntryStatus::New => Some(DlqEntryStatus::New),
+            ProtoDlqEntryStatus::InReview => Some(DlqEntryStatus::InReview),
+            ProtoDlqEntryStatus::Resolved => Some(DlqEntryStatus::Resolved),
+            ProtoDlqEntryStatus::Discarded => Some(DlqEntryStatus::Discarded),
+        }
+    }
+
+    fn convert_failure_comparison(comparison: FailureComparison) -> ProtoFailureComparison {
+        match comparison {
+            FailureComparison::FirstFailure => ProtoFailureComparison::FirstFailure,
+            FailureComparison::SameError => ProtoFailureComparison::SameError,
+            FailureComparison::ErrorChanged => ProtoFailureComparison::ErrorChanged,
+        }
+    }
+
+    fn dlq_entry_summary(entry: &DLQEntry) -> DlqEntrySummary {
+        DlqEntrySummary {
+            transaction_id: entry.transaction_id.clone(),
+            psp_name: entry.psp_name.clone(),
+            attempt_count: entry.attempt_count as i32,
+            last_error: entry.last_error.clone(),
+            timestamp_ms: entry.timestamp_ms as i64,
+            status: Self::convert_dlq_status(entry.status) as i32,
+            replaying: entry.replaying,
+            rerouted_from: entry.rerouted_from.clone().unwrap_or_default(),
+            last_modified_revision: entry.last_modified_revision as i64,
+            tags: entry.tags.clone(),
+            replay_count: entry.replay_count as i32,
+            parked_note: entry.parked_note.clone().unwrap_or_default(),
+            retry_after_ms: entry.retry_after_ms as i64,
+            content_type: entry.content_type.clone(),
+            encoding: entry.encoding.clone().unwrap_or_default(),
+            reason: Self::convert_dlq_reason(entry.reason) as i32,
+            payload_stripped: entry.payload_stripped,
+        }
+    }
+
+    fn proto_circuit_config(config: &CircuitBreakerConfig) -> ProtoCircuitBreakerConfig {
+        ProtoCircuitBreakerConfig {
+            failure_threshold: config.failure_threshold as i32,
+            success_threshold: config.success_threshold as i32,
+            timeout_duration_ms: config.timeout_duration_ms as i64,
+            latency_threshold_ms: config.latency_threshold_ms as i64,
+            probe_window: config.half_open_close.parts().0 as i32,
+            min_success_percent: config.half_open_close.parts().1 as i32,
+            reopen_suppression_ms: config.reopen_suppression_ms as i64,
+            min_open_ms: config.min_open_ms as i64,
+            success_decay_ms: config.success_decay_ms as i64,
+        }
+    }
+
+    fn convert_psp_access(policy: ProtoPspAccessPolicy) -> Result<PspAccessPolicy, String> {
+        if policy
+            .allowlist
+            .iter()
+            .chain(&policy.denylist)
+            .any(|psp_name| psp_name.is_empty())
+        {
+            return Err("PSP names must not be empty".to_string());
+        }
+        Ok(PspAccessPolicy {
+            allowlist: policy
+                .allowlist_enabled
+                .then(|| policy.allowlist.into_iter().collect()),
+            denylist: policy.denylist.into_iter().collect(),
+        })
+    }
+
+    fn proto_psp_access(policy: &PspAccessPolicy) -> ProtoPspAccessPolicy {
+        ProtoPspAccessPolicy {
+            allowlist_enabled: policy.allowlist.is_some(),
+            allowlist: policy.allowlist.iter().flatten().cloned().collect(),
+            denylist: policy.denylist.iter().cloned().collect(),
+        }
+    }
+
+    fn convert_retry_config(config: ProtoRetryConfig) -> Result<RetryConfig, String> {
+        let max_attempts = u32::try_from(config.max_attempts)
+            .map_err(|_| "max_attempts must not be negative".to_string())?;
+        let initial_delay_ms = u64::try_from(config.initial_delay_ms)
+            .map_err(|_| "initial_delay_ms must not be negative".to_string())?;
+        let max_delay_ms = u64::try_from(config.max_delay_ms)
+            .map_err(|_| "max_delay_ms must not be negative".to_string())?;
+        let min_delay_ms = u64::try_from(config.min_delay_ms)
+            .map_err(|_| "min_delay_ms must not be negative".to_string())?;
+        let max_elapsed_ms = u64::try_from(config.max_elapsed_ms)
+            .map_err(|_| "max_elapsed_ms must not be negative".to_string())?;
+        let immediate_retries = u32::try_from(config.immediate_retries)
+            .map_err(|_| "immediate_retries must not be negative".to_string())?;
+        let max_jitter_ms = u64::try_from(config.max_jitter_ms)
+            .map_err(|_| "max_jitter_ms must not be negative".to_string())?;
+        let attempt_timeout_ms = u64::try_from(config.attempt_timeout_ms)
+            .map_err(|_| "attempt_timeout_ms must not be negative".to_string())?;
+        let max_attempt_timeout_ms = u64::try_from(config.max_attempt_timeout_ms)
+            .map_err(|_| "max_attempt_timeout_ms must not be negative".to_string())?;
+        let backoff_offset = match config.backoff_offset {
+            None => 1,
+            Some(offset) => u32::try_from(offset)
+                .map_err(|_| "backoff_offset must not be negative".to_string())?,
+        };
+        let attempt_numbering = match ProtoAttemptNumbering::try_from(config.attempt_numbering) {
+            Ok(ProtoAttemptNumbering::Sequential) => AttemptNumbering::Sequential,
+            Ok(ProtoAttemptNumbering::Literal) => AttemptNumbering::Literal,
+            Err(_) => {
+                return Err(format!(
+                    "unknown attempt_numbering {}",
+                    config.attempt_numbering
+                ))
+            }
+        };
+        let jitter_distribution =
+            match ProtoJitterDistribution::try_from(config.jitter_distribution) {
+                Ok(ProtoJitterDistribution::Uniform) if config.jitter_stddev_factor != 0.0 => {
+                    return Err(
+                        "jitter_stddev_factor only applies to the NORMAL jitter distribution"
+                            .to_string(),
+                    )
+                }
+                Ok(ProtoJitterDistribution::Uniform) => JitterDistribution::Uniform,
+                Ok(ProtoJitterDistribution::Normal) => JitterDistribution::Normal {
+                    stddev_factor: if config.jitter_stddev_factor == 0.0 {
+                        DEFAULT_JITTER_STDDEV_FACTOR
+                    } else {
+                        config.jitter_stddev_factor
+                    },
+                },
+                Err(_) => {
+                    return Err(format!(
+                        "unknown jitter_distribution {}",
+                        config.jitter_distribution
+                    ))
+                }
+            };
+        let jitter_strategy = match ProtoJitterStrategy::try_from(config.jitter_strategy) {
+            Ok(ProtoJitterStrategy::Proportional) => JitterStrategy::Proportional,
+            Ok(ProtoJitterStrategy::EqualJitter) => JitterStrategy::EqualJitter,
+            Ok(ProtoJitterStrategy::FullJitter) => JitterStrategy::Full,
+            Err(_) => {
+                return Err(format!(
+                    "unknown jitter_strategy {}",
+                    config.jitter_strategy
+                ))
+            }
+        };
+
+        let config = RetryConfig {
+            max_attempts,
+            initial_delay_ms,
+            max_delay_ms,
+            backoff_multiplier: config.backoff_multiplier,
+            jitter: config.jitter,
+            jitter_strategy,
+            min_delay_ms,
+            max_elapsed_ms,
+            load_stretch: config.load_stretch,
+            immediate_retries,
+            max_jitter_ms,
+            attempt_numbering,
+            jitter_distribution,
+            attempt_timeout_ms,
+            attempt_timeout_multiplier: if config.attempt_timeout_multiplier == 0.0 {
+                1.0
+            } else {
+                config.attempt_timeout_multiplier
+            },
+            max_attempt_timeout_ms,
+            backoff_offset,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn proto_retry_config(config: &RetryConfig) -> ProtoRetryConfig {
+        let jitter_strategy = match config.jitter_strategy {
+            JitterStrategy::Proportional => ProtoJitterStrategy::Proportional,
+            JitterStrategy::EqualJitter => ProtoJitterStrategy::EqualJitter,
+            JitterStrategy::Full => ProtoJitterStrategy::FullJitter,
+        };
+        let (jitter_distribution, jitter_stddev_factor) = match config.jitter_distribution {
+            JitterDistribution::Uniform => (ProtoJitterDistribution::Uniform, 0.0),
+            JitterDistribution::Normal { stddev_factor } => {
+                (ProtoJitterDistribution::Normal, stddev_factor)
+            }
+        };
+        ProtoRetryConfig {
+            max_attempts: config.max_attempts as i32,
+            initial_delay_ms: config.initial_delay_ms as i64,
+            max_delay_ms: config.max_delay_ms as i64,
+            backoff_multiplier: config.backoff_multiplier,
+            jitter: config.jitter,
+            jitter_strategy: jitter_strategy as i32,
+            min_delay_ms: config.min_delay_ms as i64,
+            max_elapsed_ms: config.max_elapsed_ms as i64,
+            load_stretch: config.load_stretch,
+            immediate_retries: config.immediate_retries as i32,
+            max_jitter_ms: config.max_jitter_ms as i64,
+            attempt_numbering: match config.attempt_numbering {
+                AttemptNumbering::Sequential => ProtoAttemptNumbering::Sequential,
+                AttemptNumbering::Literal => ProtoAttemptNumbering::Literal,
+            } as i32,
+            jitter_distribution: jitter_distribution as i32,
+            jitter_stddev_factor,
+            attempt_timeout_ms: config.attempt_timeout_ms as i64,
+            attempt_timeout_multiplier: config.attempt_timeout_multiplier,
+            max_attempt_timeout_ms: config.max_attempt_timeout_ms as i64,
+            backoff_offset: Some(config.backoff_offset as i32),
+        }
+    }
+
+    fn convert_circuit_state(state: CircuitState) -> ProtoCircuitState {
+        match state {
+            CircuitState::Closed => ProtoCircuitState::Closed,
+            CircuitState::Open => ProtoCircuitState::Open,
+            CircuitState::HalfOpen => ProtoCircuitState::HalfOpen,
+        }
+    }
+
+    fn convert_open_reason(reason: Option<OpenReason>) -> ProtoOpenReason {
+        match reason {
+            None => ProtoOpenReason::Unspecified,
+            Some(OpenReason::FailureThreshold) => ProtoOpenReason::FailureThreshold,
+            Some(OpenReason::LatencyThreshold) => ProtoOpenReason::LatencyThreshold,
+            Some(OpenReason::ProbeFailed) => ProtoOpenReason::ProbeFailed,
+            Some(OpenReason::Forced) => ProtoOpenReason::ForcedOpen,
+        }
+    }
+
+    fn convert_open_activity(activity: Option<OpenActivity>) -> ProtoOpenActivity {
+        match activity {
+            None => ProtoOpenActivity::NotOpen,
+            Some(OpenActivity::Rejecting) => ProtoOpenActivity::Rejecting,
+            Some(OpenActivity::CoolingDown) => ProtoOpenActivity::CoolingDown,
+            Some(OpenActivity::ReadyToProbe) => ProtoOpenActivity::ReadyToProbe,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl RetryEngine for RetryEngineService {
+    async fn schedule_retry(
+        &self,
+        request: Request<RetryRequest>,
+    ) -> Result<Response<RetryResponse>, Status> {
+        let client_deadline_ms = grpc_deadline_ms(request.metadata(), self.clock.now_ms());
+        let trace_id = traceparent_trace_id(request.metadata());
+        let mut req = request.into_inner();
+        self.check_identifier("transaction_id", &req.transaction_id)
+            .and_then(|_| self.check_identifier("psp_name", &req.psp_name))
+            .map_err(Status::invalid_argument)?;
+        req.psp_name = self.psp_key(req.psp_name);
+        req.attempt_number = self
+            .checked_attempt(req.attempt_number)
+            .map_err(Status::invalid_argument)? as i32;
+        if req.priority < 0 {
+            return Err(Status::invalid_argument("priority must not be negative"));
+        }
+        let explicit_deadline_ms = u64::try_from(req.deadline_ms)
+            .map_err(|_| Status::invalid_argument("deadline_ms must not be negative"))?;
+        let deadline_ms = [
+            client_deadline_ms,
+            Some(explicit_deadline_ms).filter(|d| *d > 0),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+
+        // The PSP policy and pausing still take precedence, as in
+        // `schedule_retry_now`
+        if req.error_if_dead_lettered
+            && self.psp_allowed(&req.psp_name)
+            && !self.paused.load(Ordering::SeqCst)
+            && self.is_dead_lettered(&req.transaction_id)
+        {
+            return Err(Status::already_exists(format!(
+                "Transaction {} already in dead letter queue",
+                req.transaction_id
+            )));
+        }
+
+        let key = (req.transaction_id.clone(), req.attempt_number);
+        let response = self
+            .schedule_calls
+            .run(key, || async {
+                self.schedule_retry_now(req, deadline_ms, trace_id.as_deref())
+            })
+            .await;
+
+        Ok(Response::new(response))
+    }
+
+    async fn get_circuit_status(
+        &self,
+        request: Request<CircuitRequest>,
+    ) -> Result<Response<CircuitResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let (_, circuit_breaker) = self
+            .get_or_create_circuit_breaker(&req.psp_name)
+            .map_err(Status::resource_exhausted)?;
+        let state = circuit_breaker.get_state();
+
+        Ok(Response::new(self.circuit_response(req.psp_name, state)))
+    }
+
+    async fn reset_circuit_time_in_state(
+        &self,
+        request: Request<CircuitRequest>,
+    ) -> Result<Response<CircuitResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let (name, circuit_breaker) = self
+            .get_or_create_circuit_breaker(&req.psp_name)
+            .map_err(Status::resource_exhausted)?;
+        circuit_breaker.reset_time_in_state();
+        let state = circuit_breaker.get_state();
+        self.log_event(|| EngineEvent::CircuitStateChanged {
+            psp_name: name,
+            state: state.clone(),
+        });
+
+        Ok(Response::new(self.circuit_response(req.psp_name, state)))
+    }
+
+    async fn batch_get_circuit_status(
+        &self,
+        request: Request<BatchGetCircuitStatusRequest>,
+    ) -> Result<Response<BatchGetCircuitStatusResponse>, Status> {
+        let mut psp_names: Vec<String> = request
+            .into_inner()
+            .psp_names
+            .into_iter()
+            .map(|psp_name| self.psp_key(psp_name))
+            .collect();
+        psp_names.sort();
+        psp_names.dedup();
+        let circuits = psp_names
+            .into_iter()
+            .map(|psp_name| match self.get_circuit_breaker(&psp_name) {
+                Some(breaker) => self.circuit_response(psp_name, breaker.get_state()),
+                None => CircuitResponse {
+                    never_seen: true,
+                    ..self.circuit_response(psp_name, CircuitBreakerState::default())
+                },
+            })
+            .collect();
+
+        Ok(Response::new(BatchGetCircuitStatusResponse { circuits }))
+    }
+
+    async fn get_retry_status(
+        &self,
+        request: Request<RetryStatusRequest>,
+    ) -> Result<Response<RetryStatusResponse>, Status> {
+        let req = request.into_inner();
+        let states = self.retry_states.lock().unwrap();
+        Ok(Response::new(
+            self.retry_status(&states, req.transaction_id),
+        ))
+    }
+
+    async fn batch_get_retry_status(
+        &self,
+        request: Request<BatchGetRetryStatusRequest>,
+    ) -> Result<Response<BatchGetRetryStatusResponse>, Status> {
+        let req = request.into_inner();
+        // One pass under the retry state lock rather than a lock per ID
+        let states = self.retry_states.lock().unwrap();
+        let statuses = req
+            .transaction_ids
+            .into_iter()
+            .map(|transaction_id| self.retry_status(&states, transaction_id))
+            .collect();
+
+        Ok(Response::new(BatchGetRetryStatusResponse { statuses }))
+    }
+
+    async fn set_circuit_config(
+        &self,
+        request: Request<SetCircuitConfigRequest>,
+    ) -> Result<Response<SetCircuitConfigResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let config = req
+            .config
+            .ok_or_else(|| Status::invalid_argument("config is required"))?;
+        let config = Self::convert_circuit_config(config).map_err(Status::invalid_argument)?;
+
+        let circuit_breaker = self
+            .set_psp_circuit_config(&req.psp_name, config)
+            .map_err(Status::resource_exhausted)?;
+
+        Ok(Response::new(SetCircuitConfigResponse {
+            psp_name: req.psp_name.clone(),
+            config: Some(Self::proto_circuit_config(circuit_breaker.config())),
+            circuit: Some(self.circuit_response(req.psp_name, circuit_breaker.get_state())),
+        }))
+    }
+
+    async fn get_retry_time_series(
+        &self,
+        request: Request<RetryTimeSeriesRequest>,
+    ) -> Result<Response<RetryTimeSeriesResponse>, Status> {
+        let req = request.into_inner();
+        let count = usize::try_from(req.bucket_count)
+            .map_err(|_| Status::invalid_argument("bucket_count must not be negative"))?;
+
+        let buckets = self
+            .time_series
+            .last_buckets(count, self.clock.now_ms())
+            .into_iter()
+            .map(|bucket| RetryTimeSeriesBucket {
+                start_ms: bucket.start_ms as i64,
+                scheduled_retries: bucket.scheduled_retries as i64,
+                dlq_adds: bucket.dlq_adds as i64,
+            })
+            .collect();
+
+        Ok(Response::new(RetryTimeSeriesResponse {
+            bucket_duration_ms: self.time_series.bucket_ms() as i64,
+            buckets,
+        }))
+    }
+
+    async fn replay_dlq_entry(
+        &self,
+        request: Request<ReplayDlqEntryRequest>,
+    ) -> Result<Response<ReplayDlqEntryResponse>, Status> {
+        let mut req = request.into_inner();
+        req.target_psp = self.psp_key(req.target_psp);
+        let entry = self
+            .dlq
+            .get_entry(&req.transaction_id)
+            .ok_or_else(|| Status::not_found("Transaction not in dead letter queue"))?;
+        let target_psp = Some(req.target_psp.as_str()).filter(|psp| !psp.is_empty());
+        let psp_name = target_psp.unwrap_or(&entry.psp_name).to_string();
+
+        if entry.replaying {
+            return Ok(Response::new(ReplayDlqEntryResponse {
+                transaction_id: req.transaction_id,
+                replayed: false,
+                psp_name,
+                next_retry_at_ms: 0,
+                message: "Transaction is already being replayed".to_string(),
+            }));
+        }
+
+        let payload = Some(req.payload.as_slice()).filter(|payload| !payload.is_empty());
+        let response =
+            match self.replay_entry(&entry, target_psp, req.force, 0, payload, &req.lease_id) {
+                Ok(next_retry_at_ms) => ReplayDlqEntryResponse {
+                    transaction_id: req.transaction_id,
+                    replayed: true,
+                    message: format!("Replay scheduled on PSP: {}", psp_name),
+                    psp_name,
+                    next_retry_at_ms: next_retry_at_ms as i64,
+                },
+                Err(message) => ReplayDlqEntryResponse {
+                    transaction_id: req.transaction_id,
+                    replayed: false,
+                    psp_name,
+                    next_retry_at_ms: 0,
+                    message,
+                },
+            };
+        Ok(Response::new(response))
+    }
+
+    async fn bulk_replay_dlq(
+        &self,
+        request: Request<BulkReplayDlqRequest>,
+    ) -> Result<Response<BulkReplayDlqResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        req.target_psp = self.psp_key(req.target_psp);
+        let target_psp = Some(req.target_psp.as_str()).filter(|psp| !psp.is_empty());
+        let spread_over_ms = u64::try_from(req.spread_over_ms)
+            .map_err(|_| Status::invalid_argument("spread_over_ms must not be negative"))?;
+
+        let mut entries: Vec<DLQEntry> = self
+            .dlq_entries_tagged(&req.has_tag)
+            .into_iter()
+            .filter(|entry| {
+                !entry.replaying
+                    && !entry.status.is_terminal()
+                    && !entry.is_parked()
+                    && (req.psp_name.is_empty() || entry.psp_name == req.psp_name)
+            })
+            .collect();
+        // Oldest first, so the longest-stuck transactions recover first and
+        // runs over the same entries replay them in the same order
+        entries.sort_by(|a, b| {
+            (a.timestamp_ms, &a.transaction_id).cmp(&(b.timestamp_ms, &b.transaction_id))
+        });
+
+        // Each entry gets an equal slot of the window and a random point in it,
+        // so the recovered PSP sees a steady trickle rather than a stampede
+        let slot_ms = spread_over_ms / entries.len().max(1) as u64;
+        let mut rng = self.rng.clone();
+
+        let mut replayed_transaction_ids = Vec::new();
+        let mut schedule = Vec::new();
+        let mut skipped_count = 0;
+        for (slot, entry) in entries.into_iter().enumerate() {
+            let jitter_ms = if slot_ms > 0 {
+                rng.gen_range_inclusive(slot_ms - 1)
+            } else {
+                0
+            };
+            let delay_ms = slot as u64 * slot_ms + jitter_ms;
+            match self.replay_entry(&entry, target_psp, false, delay_ms, None, "") {
+                Ok(next_retry_at_ms) => {
+                    schedule.push(ScheduledReplay {
+                        transaction_id: entry.transaction_id.clone(),
+                        next_retry_at_ms: next_retry_at_ms as i64,
+                    });
+                    replayed_transaction_ids.push(entry.transaction_id);
+                }
+                Err(_) => skipped_count += 1,
+            }
+        }
+
+        Ok(Response::new(BulkReplayDlqResponse {
+            schedule,
+            replayed_count: replayed_transaction_ids.len() as i32,
+            skipped_count,
+            message: format!(
+                "Replayed {} entries, skipped {}",
+                replayed_transaction_ids.len(),
+                skipped_count
+            ),
+            replayed_transaction_ids,
+        }))
+    }
+
+    async fn list_retries_by_psp(
+        &self,
+        request: Request<ListRetriesByPspRequest>,
+    ) -> Result<Response<ListRetriesByPspResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+
+        let mut retries: Vec<RetryEntry> = {
+            let states = self.retry_states.lock().unwrap();
+            states
+                .iter()
+                .filter(|(_, state)| state.psp_name == req.psp_name)
+                .filter(|(_, state)| req.has_tag.is_empty() || state.tags.contains(&req.has_tag))
+                .map(|(transaction_id, state)| RetryEntry {
+                    transaction_id: transaction_id.clone(),
+                    psp_name: state.psp_name.clone(),
+                    attempt_count: state.attempt_count as i32,
+                    next_retry_at_ms: state.next_retry_at_ms as i64,
+                    tags: state.tags.clone(),
+                })
+                .collect()
+        };
+        retries.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+
+        Ok(Response::new(ListRetriesByPspResponse { retries }))
+    }
+
+    /// Dry run of `schedule_retry`: reports the decision it would make without
+    /// touching breakers, retry states, or the DLQ
+    async fn evaluate_transaction(
+        &self,
+        request: Request<EvaluateTransactionRequest>,
+    ) -> Result<Response<EvaluateTransactionResponse>, Status> {
+        let mut req = request.into_inner();
+        self.check_identifier("transaction_id", &req.transaction_id)
+            .and_then(|_| self.check_identifier("psp_name", &req.psp_name))
+            .map_err(Status::invalid_argument)?;
+        req.psp_name = self.psp_key(req.psp_name);
+        req.attempt_number = self
+            .checked_attempt(req.attempt_number)
+            .map_err(Status::invalid_argument)? as i32;
+        let retry_policy = self.retry_policy_for(&req.psp_name);
+        let attempt =
+            self.effective_attempt(&retry_policy, &req.transaction_id, req.attempt_number);
+
+        let in_dlq = self.is_dead_lettered(&req.transaction_id);
+        let (circuit_state, circuit_open) = match self.get_circuit_breaker(&req.psp_name) {
+            Some(breaker) => (breaker.get_state().state, !breaker.would_proceed()),
+            None => (CircuitState::Closed, false),
+        };
+        let retries_left = retry_policy.should_retry(attempt);
+
+        let blocked = !self.psp_allowed(&req.psp_name);
+
+        let retry_allowed = !blocked && !in_dlq && !circuit_open && retries_left;
+        let would_move_to_dlq = !blocked && !in_dlq && !circuit_open && !retries_left;
+        let delay_ms = if retry_allowed {
+            retry_policy.calculate_delay_loaded(attempt, self.load_factor())
+        } else {
+            0
+        };
+        let message = if blocked {
+            format!("PSP blocked by policy: {}", req.psp_name)
+        } else if in_dlq {
+            "Transaction already in dead letter queue".to_string()
+        } else if circuit_open {
+            format!("Circuit breaker open for PSP: {}", req.psp_name)
+        } else if would_move_to_dlq {
+            "Max retries exceeded, would move to DLQ".to_string()
+        } else {
+            format!("Retry would be scheduled for attempt {}", attempt + 1)
+        };
+
+        Ok(Response::new(EvaluateTransactionResponse {
+            in_dlq,
+            circuit_open,
+            circuit_state: Self::convert_circuit_state(circuit_state) as i32,
+            retry_allowed,
+            delay_ms: delay_ms as i64,
+            would_move_to_dlq,
+            message,
+        }))
+    }
+
+    async fn set_engine_paused(
+        &self,
+        request: Request<SetEnginePausedRequest>,
+    ) -> Result<Response<SetEnginePausedResponse>, Status> {
+        let paused = request.into_inner().paused;
+        let was_paused = self.paused.swap(paused, Ordering::SeqCst);
+        if paused != was_paused {
+            warn!("Retry engine {}", if paused { "paused" } else { "resumed" });
+            self.log_event(|| EngineEvent::EnginePaused { paused });
+        }
+
+        Ok(Response::new(SetEnginePausedResponse { paused }))
+    }
+
+    async fn update_dlq_entry_status(
+        &self,
+        request: Request<UpdateDlqEntryStatusRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        let status = Self::dlq_status_from_proto(req.status)
+            .ok_or_else(|| Status::invalid_argument("unknown DLQ entry status"))?;
+
+        let updated =
+            self.dlq
+                .update_leased_status(&req.transaction_id, "", self.clock.now_ms(), status);
+        self.log_dlq_changes();
+        match updated {
+            Ok(entry) => Ok(Response::new(Self::dlq_entry_summary(&entry))),
+            Err(e @ DlqError::NotFound(_)) => Err(Status::not_found(e.to_string())),
+            Err(e) => Err(Status::failed_precondition(e.to_string())),
+        }
+    }
+
+    async fn list_dlq_entries(
+        &self,
+        request: Request<ListDlqEntriesRequest>,
+    ) -> Result<Response<ListDlqEntriesResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let status = match req.status {
+            Some(status) => Some(
+                Self::dlq_status_from_proto(status)
+                    .ok_or_else(|| Status::invalid_argument("unknown DLQ entry status"))?,
+            ),
+            None => None,
+        };
+
+        let mut entries: Vec<DLQEntry> = self
+            .dlq_entries_tagged(&req.has_tag)
+            .into_iter()
+            .filter(|entry| req.psp_name.is_empty() || entry.psp_name == req.psp_name)
+            .filter(|entry| status.is_none_or(|status| entry.status == status))
+            .collect();
+        entries.sort_by(|a, b| {
+            (a.timestamp_ms, &a.transaction_id).cmp(&(b.timestamp_ms, &b.transaction_id))
+        });
+
+        Ok(Response::new(ListDlqEntriesResponse {
+            entries: entries.iter().map(Self::dlq_entry_summary).collect(),
+        }))
+    }
+
+    async fn dlq_changes_since(
+        &self,
+        request: Request<DlqChangesSinceRequest>,
+    ) -> Result<Response<DlqChangesSinceResponse>, Status> {
+        let req = request.into_inner();
+        let revision = u64::try_from(req.revision)
+            .map_err(|_| Status::invalid_argument("revision must not be negative"))?;
+
+        let changes = self.dlq.changes_since(revision);
+        Ok(Response::new(DlqChangesSinceResponse {
+            added: changes.added.iter().map(Self::dlq_entry_summary).collect(),
+            removed_transaction_ids: changes.removed,
+            revision: changes.revision as i64,
+            reset: changes.reset,
+        }))
+    }
+
+    async fn mark_resolved(
+        &self,
+        request: Request<MarkResolvedRequest>,
+    ) -> Result<Response<MarkResolvedResponse>, Status> {
+        let req = request.into_inner();
+        let _transaction = self.transaction_locks.lock(&req.transaction_id);
+        let resolved = self.resolve_retry_state(&req.transaction_id);
+
+        Ok(Response::new(MarkResolvedResponse {
+            transaction_id: req.transaction_id,
+            resolved,
+        }))
+    }
+
+    async fn cancel_retry(
+        &self,
+        request: Request<CancelRetryRequest>,
+    ) -> Result<Response<CancelRetryResponse>, Status> {
+        let req = request.into_inner();
+        let _transaction = self.transaction_locks.lock(&req.transaction_id);
+        let entry = self.dlq.get_entry(&req.transaction_id);
+        if req.remove_from_dlq {
+            if let Some(note) = entry.as_ref().and_then(|entry| entry.parked_note.as_ref()) {
+                return Err(Status::failed_precondition(format!(
+                    "Entry is parked ({}), unpark it first",
+                    note
+                )));
+            }
+            self.dlq
+                .check_lease(&req.transaction_id, "", self.clock.now_ms())
+                .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        }
+
+        let cancelled_state = self.remove_retry_state(&req.transaction_id);
+        let cancelled = cancelled_state.is_some();
+        let removed_from_dlq = match entry {
+            Some(_) if req.remove_from_dlq => self
+                .dlq
+                .remove_leased_entry(&req.transaction_id, "", self.clock.now_ms())
+                .is_ok(),
+            // The replay was abandoned, so the entry is an ordinary dead letter
+            // again, with the payload its retry held
+            Some(entry) if entry.replaying => {
+                self.dlq.update_entry(&req.transaction_id, |stored| {
+                    stored.replaying = false;
+                    if let Some(state) = cancelled_state {
+                        stored.payload = state.payload;
+                        stored.payload_stripped = false;
+                    }
+                });
+                false
+            }
+            _ => false,
+        };
+        self.log_dlq_changes();
+        if cancelled || removed_from_dlq {
+            info!(
+                "Retry for {} cancelled ({}){}",
+                req.transaction_id,
+                if req.reason.is_empty() {
+                    "no reason given"
+                } else {
+                    &req.reason
+                },
+                if removed_from_dlq {
+                    ", removed from DLQ"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        Ok(Response::new(CancelRetryResponse {
+            transaction_id: req.transaction_id,
+            cancelled,
+            removed_from_dlq,
+        }))
+    }
+
+    async fn force_to_dlq(
+        &self,
+        request: Request<ForceToDlqRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        let _transaction = self.transaction_locks.lock(&req.transaction_id);
+        let state = self
+            .remove_retry_state(&req.transaction_id)
+            .ok_or_else(|| Status::not_found("Transaction has no retry in flight"))?;
+        let reason = if req.reason.is_empty() {
+            "no reason given"
+        } else {
+            &req.reason
+        };
+
+        let dlq_entry = DLQEntry {
+            transaction_id: req.transaction_id.clone(),
+            psp_name: state.psp_name.clone(),
+            payload: if req.payload.is_empty() {
+                state.payload.clone()
+            } else {
+                req.payload
+            },
+            attempt_count: state.attempt_count,
+            last_error: format!("Forced to DLQ by operator: {}", reason),
+            reason: DlqReason::ForcedByOperator,
+            timestamp_ms: self.clock.now_ms(),
+            tags: state.tags.clone(),
+            content_type: state.content_type.clone(),
+            encoding: state.encoding.clone(),
+            ..Default::default()
+        };
+        if let Err(e) = self.add_dead_letter(dlq_entry, None) {
+            // Put the retry back rather than lose the transaction
+            self.restore_retry_state(&req.transaction_id, state);
+            return Err(Status::resource_exhausted(format!(
+                "DLQ refused entry: {}",
+                e
+            )));
+        }
+        info!("{} forced to DLQ ({})", req.transaction_id, reason);
+
+        let entry = self
+            .dlq
+            .get_entry(&req.transaction_id)
+            .ok_or_else(|| Status::internal("DLQ entry missing after add"))?;
+        Ok(Response::new(Self::dlq_entry_summary(&entry)))
+    }
+
+    async fn get_psp_health(
+        &self,
+        request: Request<PspHealthRequest>,
+    ) -> Result<Response<PspHealthResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let state = self
+            .get_circuit_breaker(&req.psp_name)
+            .map(|breaker| breaker.get_state())
+            .unwrap_or_default();
+        let in_flight = self
+            .in_flight
+            .get(&self.circuit_breaker_name(&req.psp_name));
+        let health_score = self.psp_health_score(&req.psp_name);
+
+        Ok(Response::new(PspHealthResponse {
+            circuit: Some(self.circuit_response(req.psp_name.clone(), state)),
+            psp_name: req.psp_name,
+            in_flight: in_flight.current as i64,
+            peak_in_flight: in_flight.peak as i64,
+            health_score: health_score as i32,
+        }))
+    }
+
+    async fn get_engine_health(
+        &self,
+        _request: Request<EngineHealthRequest>,
+    ) -> Result<Response<EngineHealthResponse>, Status> {
+        Ok(Response::new(EngineHealthResponse {
+            paused: self.paused.load(Ordering::SeqCst),
+            persistence_degraded: self.persistence_degraded.load(Ordering::SeqCst),
+            dlq_size: self.dlq.count() as i64,
+        }))
+    }
+
+    async fn purge_dlq(
+        &self,
+        request: Request<PurgeDlqRequest>,
+    ) -> Result<Response<PurgeDlqResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        if req.psp_name.is_empty() && req.has_tag.is_empty() {
+            return Err(Status::invalid_argument(
+                "psp_name or has_tag is required to purge",
+            ));
+        }
+        let now = self.clock.now_ms();
+
+        let mut purged_transaction_ids: Vec<String> = self
+            .dlq_entries_tagged(&req.has_tag)
+            .into_iter()
+            .filter(|entry| req.psp_name.is_empty() || entry.psp_name == req.psp_name)
+            .filter(|entry| !entry.is_parked())
+            .filter_map(|entry| {
+                self.dlq
+                    .remove_leased_entry(&entry.transaction_id, "", now)
+                    .ok()
+            })
+            .map(|entry| entry.transaction_id)
+            .collect();
+        self.log_dlq_changes();
+        purged_transaction_ids.sort();
+
+        Ok(Response::new(PurgeDlqResponse {
+            purged_count: purged_transaction_ids.len() as i32,
+            purged_transaction_ids,
+        }))
+    }
+
+    async fn set_circuit_reset_schedule(
+        &self,
+        request: Request<SetCircuitResetScheduleRequest>,
+    ) -> Result<Response<SetCircuitResetScheduleResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let schedule = if req.interval_ms == 0 {
+            None
+        } else {
+            let schedule = CircuitResetSchedule {
+                interval_ms: u64::try_from(req.interval_ms)
+                    .map_err(|_| Status::invalid_argument("interval_ms must not be negative"))?,
+                offset_ms: u64::try_from(req.offset_ms)
+                    .map_err(|_| Status::invalid_argument("offset_ms must not be negative"))?,
+            };
+            schedule.validate().map_err(Status::invalid_argument)?;
+            Some(schedule)
+        };
+
+        let next_reset_at_ms = self.set_psp_reset_schedule(&req.psp_name, schedule);
+        Ok(Response::new(SetCircuitResetScheduleResponse {
+            psp_name: req.psp_name,
+            next_reset_at_ms: next_reset_at_ms.unwrap_or(0) as i64,
+        }))
+    }
+
+    async fn get_metrics(
+        &self,
+        request: Request<MetricsRequest>,
+    ) -> Result<Response<MetricsResponse>, Status> {
+        let openmetrics = request.into_inner().openmetrics;
+        // OpenMetrics names a counter's family without the `_total` suffix
+        let family = |name: &'static str| {
+            if openmetrics {
+                name.trim_end_matches("_total")
+            } else {
+                name
+            }
+        };
+        let breakers = self.circuit_breakers_by_name();
+
+        let mut text = String::new();
+        text.push_str("# HELP psp_failure_interval_seconds Time between consecutive failures recorded for a PSP\n");
+        text.push_str("# TYPE psp_failure_interval_seconds histogram\n");
+        for (psp_name, breaker) in breakers {
+            breaker
+                .failure_interval_histogram(&self.failure_interval_buckets)
+                .write_prometheus(
+                    &mut text,
+                    "psp_failure_interval_seconds",
+                    &format!("psp={:?}", psp_name),
+                );
+        }
+
+        let outcomes = self.outcomes.all();
+        text.push_str(&format!(
+            "# HELP {} Attempt outcomes reported through ReportOutcome\n",
+            family("psp_reported_outcomes_total")
+        ));
+        text.push_str(&format!(
+            "# TYPE {} counter\n",
+            family("psp_reported_outcomes_total")
+        ));
+        for (psp_name, counts) in &outcomes {
+            for (outcome, count) in [("success", counts.successes), ("failure", counts.failures)] {
+                text.push_str(&format!(
+                    "psp_reported_outcomes_total{{psp={:?},outcome=\"{}\"}} {}\n",
+                    psp_name, outcome, count
+                ));
+            }
+        }
+        text.push_str("# HELP psp_reported_latency_seconds Latency of attempts reported through ReportOutcome\n");
+        text.push_str("# TYPE psp_reported_latency_seconds histogram\n");
+        for (psp_name, counts) in &outcomes {
+            Histogram::from_samples(
+                DEFAULT_LATENCY_BUCKETS,
+                counts
+                    .latencies_ms
+                    .iter()
+                    .map(|latency_ms| *latency_ms as f64 / 1000.0),
+            )
+            .write_prometheus(
+                &mut text,
+                "psp_reported_latency_seconds",
+                &format!("psp={:?}", psp_name),
+            );
+        }
+
+        text.push_str("# HELP psp_retry_drift_seconds How late retries were followed up after they were due\n");
+        text.push_str("# TYPE psp_retry_drift_seconds histogram\n");
+        for (psp_name, drifts_ms) in self.retry_drift.all() {
+            Histogram::from_samples(
+                DEFAULT_DRIFT_BUCKETS,
+                drifts_ms.iter().map(|drift_ms| *drift_ms as f64 / 1000.0),
+            )
+            .write_prometheus(
+                &mut text,
+                "psp_retry_drift_seconds",
+                &format!("psp={:?}", psp_name),
+            );
+        }
+
+        text.push_str("# HELP psp_final_attempt Attempt at which transactions succeeded or were dead-lettered\n");
+        text.push_str("# TYPE psp_final_attempt histogram\n");
+        for (psp_name, attempts) in self.final_attempts.all() {
+            for (outcome, counts) in [
+                ("success", &attempts.successes),
+                ("dead_letter", &attempts.dead_lettered),
+            ] {
+                if counts.is_empty() {
+                    continue;
+                }
+                Histogram::from_counts(
+                    DEFAULT_ATTEMPT_BUCKETS,
+                    counts
+                        .iter()
+                        .map(|(attempt, count)| (*attempt as f64, *count)),
+                )
+                .write_prometheus(
+                    &mut text,
+                    "psp_final_attempt",
+                    &format!("psp={:?},outcome=\"{}\"", psp_name, outcome),
+                );
+            }
+        }
+
+        for (name, help, counter) in [
+            (
+                "retries_scheduled_total",
+                "Retries scheduled through ScheduleRetry",
+                &self.retries_scheduled,
+            ),
+            (
+                "dlq_adds_total",
+                "Transactions moved to the DLQ after running out of retries",
+                &self.dlq_adds,
+            ),
+        ] {
+            text.push_str(&format!("# HELP {} {}\n", family(name), help));
+            text.push_str(&format!("# TYPE {} counter\n", family(name)));
+            counter.write(&mut text, name, openmetrics);
+        }
+
+        // Per stripe, so keys skewing onto a few stripes show up
+        let stripes = self.transaction_locks.stats();
+        for (name, help, counts) in [
+            (
+                "transaction_lock_acquisitions_total",
+                "Transaction lock acquisitions per lock stripe",
+                stripes
+                    .iter()
+                    .map(|stats| stats.acquisitions)
+                    .collect::<Vec<_>>(),
+            ),
+            (
+                "transaction_lock_contended_total",
+                "Transaction lock acquisitions that found the stripe held",
+                stripes.iter().map(|stats| stats.contended).collect(),
+            ),
+        ] {
+            text.push_str(&format!("# HELP {} {}\n", family(name), help));
+            text.push_str(&format!("# TYPE {} counter\n", family(name)));
+            for (stripe, count) in counts.iter().enumerate() {
+                text.push_str(&format!("{}{{stripe=\"{}\"}} {}\n", name, stripe, count));
+            }
+        }
+        if openmetrics {
+            text.push_str("# EOF\n");
+        }
+
+        Ok(Response::new(MetricsResponse { text }))
+    }
+
+    async fn park_dlq_entry(
+        &self,
+        request: Request<ParkDlqEntryRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        if req.note.trim().is_empty() {
+            return Err(Status::invalid_argument(
+                "note is required to park an entry",
+            ));
+        }
+        let entry = self
+            .dlq
+            .get_entry(&req.transaction_id)
+            .ok_or_else(|| Status::not_found("Transaction not in dead letter queue"))?;
+        // A replaying entry would be overwritten, note and all, if it exhausts its retries again
+        if entry.replaying {
+            return Err(Status::failed_precondition(
+                "Transaction is being replayed and can't be parked",
+            ));
+        }
+
+        let parked =
+            self.dlq
+                .update_leased_entry(&req.transaction_id, "", self.clock.now_ms(), |entry| {
+                    entry.parked_note = Some(req.note.clone())
+                });
+        self.log_dlq_changes();
+        match parked {
+            Ok(entry) => Ok(Response::new(Self::dlq_entry_summary(&entry))),
+            Err(e @ DlqError::NotFound(_)) => Err(Status::not_found(e.to_string())),
+            Err(e) => Err(Status::failed_precondition(e.to_string())),
+        }
+    }
+
+    async fn unpark_dlq_entry(
+        &self,
+        request: Request<UnparkDlqEntryRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        let unparked =
+            self.dlq
+                .update_leased_entry(&req.transaction_id, "", self.clock.now_ms(), |entry| {
+                    entry.parked_note = None
+                });
+        self.log_dlq_changes();
+        match unparked {
+            Ok(entry) => Ok(Response::new(Self::dlq_entry_summary(&entry))),
+            Err(e @ DlqError::NotFound(_)) => Err(Status::not_found(e.to_string())),
+            Err(e) => Err(Status::failed_precondition(e.to_string())),
+        }
+    }
+
+    async fn list_parked_entries(
+        &self,
+        request: Request<ListParkedEntriesRequest>,
+    ) -> Result<Response<ListDlqEntriesResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let mut entries: Vec<DLQEntry> = self
+            .dlq
+            .get_all_entries()
+            .into_iter()
+            .filter(|entry| entry.is_parked())
+            .filter(|entry| req.psp_name.is_empty() || entry.psp_name == req.psp_name)
+            .collect();
+        entries.sort_by(|a, b| {
+            (a.timestamp_ms, &a.transaction_id).cmp(&(b.timestamp_ms, &b.transaction_id))
+        });
+
+        Ok(Response::new(ListDlqEntriesResponse {
+            entries: entries.iter().map(Self::dlq_entry_summary).collect(),
+        }))
+    }
+
+    /// Start (or restart, resetting its count) a shadow breaker for a PSP
+    /// running a candidate config, or stop it when no config is given
+    async fn set_circuit_canary(
+        &self,
+        request: Request<SetCircuitCanaryRequest>,
+    ) -> Result<Response<CircuitCanaryResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let config = req
+            .config
+            .map(Self::convert_circuit_config)
+            .transpose()
+            .map_err(Status::invalid_argument)?;
+
+        let (_, circuit_breaker) = self
+            .get_or_create_circuit_breaker(&req.psp_name)
+            .map_err(Status::resource_exhausted)?;
+        let canary = circuit_breaker.set_canary(config);
+        Ok(Response::new(self.canary_response(req.psp_name, canary)))
+    }
+
+    async fn get_circuit_canary(
+        &self,
+        request: Request<CircuitCanaryRequest>,
+    ) -> Result<Response<CircuitCanaryResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let canary = self
+            .get_circuit_breaker(&req.psp_name)
+            .and_then(|breaker| breaker.canary());
+        Ok(Response::new(self.canary_response(req.psp_name, canary)))
+    }
+
+    async fn set_retry_config(
+        &self,
+        request: Request<SetRetryConfigRequest>,
+    ) -> Result<Response<SetRetryConfigResponse>, Status> {
+        let config = request
+            .into_inner()
+            .config
+            .ok_or_else(|| Status::invalid_argument("config is required"))?;
+        let config = Self::convert_retry_config(config).map_err(Status::invalid_argument)?;
+
+        let dead_lettered = self
+            .apply_retry_config(config)
+            .map_err(Status::invalid_argument)?;
+        Ok(Response::new(SetRetryConfigResponse {
+            config: Some(Self::proto_retry_config(self.retry_policy().config())),
+            dead_lettered,
+        }))
+    }
+
+    async fn list_circuits(
+        &self,
+        _request: Request<ListCircuitsRequest>,
+    ) -> Result<Response<BatchGetCircuitStatusResponse>, Status> {
+        let circuits = self
+            .circuit_breakers_by_name()
+            .into_iter()
+            .map(|(psp_name, breaker)| self.circuit_response(psp_name, breaker.get_state()))
+            .collect();
+        Ok(Response::new(BatchGetCircuitStatusResponse { circuits }))
+    }
+
+    async fn get_effective_psp_config(
+        &self,
+        request: Request<EffectivePspConfigRequest>,
+    ) -> Result<Response<EffectivePspConfigResponse>, Status> {
+        let psp_name = self.psp_key(request.into_inner().psp_name);
+        let circuit_overridden = self
+            .circuit_overrides
+            .lock()
+            .unwrap()
+            .contains_key(&psp_name);
+        let next_reset_at_ms = self
+            .reset_schedules
+            .lock()
+            .unwrap()
+            .get(&psp_name)
+            .map_or(0, |reset| reset.next_reset_at_ms);
+
+        Ok(Response::new(EffectivePspConfigResponse {
+            retry: Some(Self::proto_retry_config(
+                self.retry_policy_for(&psp_name).config(),
+            )),
+            jitter_overridden: self.jitter_overrides.contains_key(&psp_name),
+            circuit_breaker: Some(Self::proto_circuit_config(
+                &self.circuit_config_for(&psp_name),
+            )),
+            circuit_overridden,
+            next_reset_at_ms: next_reset_at_ms as i64,
+            psp_name,
+        }))
+    }
+
+    async fn report_outcome(
+        &self,
+        request: Request<ReportOutcomeRequest>,
+    ) -> Result<Response<ReportOutcomeResponse>, Status> {
+        let req = request.into_inner();
+        // An empty psp_name is fine here: it means the pending retry's PSP
+        self.check_identifier("transaction_id", &req.transaction_id)
+            .map_err(Status::invalid_argument)?;
+        let latency_ms = u64::try_from(req.latency_ms)
+            .map_err(|_| Status::invalid_argument("latency_ms must not be negative"))?;
+        let attempt = self
+            .checked_attempt(req.attempt_number)
+            .map_err(Status::invalid_argument)?;
+        // Held until the retry state is cleared, so a concurrent schedule or
+        // resolve can't move the transaction on in between
+        let _transaction = self.transaction_locks.lock(&req.transaction_id);
+        let pending = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(&req.transaction_id)
+            .map(|state| (state.psp_name.clone(), state.attempt_count));
+        let psp_name = match (&pending, req.psp_name.is_empty()) {
+            (_, false) => self.psp_key(req.psp_name),
+            (Some((psp_name, _)), true) => psp_name.clone(),
+            (None, true) => {
+                return Err(Status::not_found(
+                    "No retry pending for transaction; psp_name is required",
+                ))
+            }
+        };
+
+        // Only the first report for the pending retry's attempt counts, and
+        // only before its deadline: the timeout has counted a later one
+        let counted = match &pending {
+            Some((_, failed_attempt)) if attempt == 0 || attempt == failed_attempt + 1 => {
+                self.settle_outcome(&req.transaction_id, *failed_attempt)
+            }
+            _ => true,
+        };
+        if counted {
+            self.record_outcome(&psp_name, req.success, Some(latency_ms).filter(|l| *l > 0));
+        } else {
+            warn!(
+                "Ignored outcome for {} reported late or twice (attempt {})",
+                req.transaction_id, attempt
+            );
+        }
+        if attempt > 0 {
+            self.time_follow_up(&req.transaction_id, attempt);
+        }
+
+        // A success for an attempt the pending retry already superseded is
+        // stale, so it leaves the retry in place
+        let retry_state_cleared = req.success
+            && pending
+                .as_ref()
+                .is_some_and(|(_, failed_attempt)| attempt == 0 || attempt > *failed_attempt)
+            && self.resolve_retry_state(&req.transaction_id);
+        // The attempt a transaction finished at: the one reported, or the
+        // pending retry's next one if the client didn't say
+        if retry_state_cleared || (req.success && pending.is_none() && attempt > 0) {
+            let final_attempt = match (attempt, &pending) {
+                (0, Some((_, failed_attempt))) => failed_attempt + 1,
+                _ => attempt,
+            };
+            self.final_attempts
+                .record_success(&self.circuit_breaker_name(&psp_name), final_attempt);
+        }
+
+        let state = self
+            .get_circuit_breaker(&psp_name)
+            .map(|breaker| breaker.get_state())
+            .unwrap_or_default();
+        Ok(Response::new(ReportOutcomeResponse {
+            transaction_id: req.transaction_id,
+            circuit: Some(self.circuit_response(psp_name.clone(), state)),
+            psp_name,
+            retry_state_cleared,
+        }))
+    }
+
+    async fn set_psp_access_policy(
+        &self,
+        request: Request<SetPspAccessPolicyRequest>,
+    ) -> Result<Response<ProtoPspAccessPolicy>, Status> {
+        let policy = request
+            .into_inner()
+            .policy
+            .ok_or_else(|| Status::invalid_argument("policy is required"))?;
+        let policy = Self::convert_psp_access(policy).map_err(Status::invalid_argument)?;
+        self.set_psp_access(policy);
+        let policy = self.psp_access.lock().unwrap().clone();
+        info!("PSP access policy set: {:?}", policy);
+        let response = Self::proto_psp_access(&policy);
+
+        Ok(Response::new(response))
+    }
+
+    async fn project_retry_load(
+        &self,
+        request: Request<ProjectRetryLoadRequest>,
+    ) -> Result<Response<ProjectRetryLoadResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let transaction_count = u64::try_from(req.transaction_count)
+            .map_err(|_| Status::invalid_argument("transaction_count must not be negative"))?;
+        let bucket_ms = match u64::try_from(req.bucket_ms) {
+            Ok(0) => 60_000,
+            Ok(bucket_ms) => bucket_ms,
+            Err(_) => return Err(Status::invalid_argument("bucket_ms must not be negative")),
+        };
+
+        // A blocked PSP never gets a retry scheduled
+        let schedule: Vec<u64> = if self.psp_allowed(&req.psp_name) {
+            self.retry_policy().nominal_schedule().collect()
+        } else {
+            Vec::new()
+        };
+        let end_offset_ms = schedule.last().copied().unwrap_or(0);
+        let bucket_count = end_offset_ms / bucket_ms + 1;
+        if bucket_count > MAX_PROJECTION_BUCKETS {
+            return Err(Status::invalid_argument(format!(
+                "bucket_ms of {} would need {} buckets, more than {}",
+                bucket_ms, bucket_count, MAX_PROJECTION_BUCKETS
+            )));
+        }
+
+        let mut attempts = vec![0u64; bucket_count as usize];
+        for offset_ms in &schedule {
+            attempts[(offset_ms / bucket_ms) as usize] += transaction_count;
+        }
+        let now = self.clock.now_ms();
+        let buckets = attempts
+            .into_iter()
+            .enumerate()
+            .map(|(index, attempts)| ProjectedRetryBucket {
+                start_ms: now.saturating_add(index as u64 * bucket_ms) as i64,
+                attempts: attempts as i64,
+            })
+            .collect();
+
+        Ok(Response::new(ProjectRetryLoadResponse {
+            psp_name: req.psp_name,
+            projected_from_ms: now as i64,
+            total_attempts: (schedule.len() as u64).saturating_mul(transaction_count) as i64,
+            schedule_end_ms: now.saturating_add(end_offset_ms) as i64,
+            bucket_duration_ms: bucket_ms as i64,
+            buckets,
+        }))
+    }
+
+    /// Everything the engine holds, for diagnosis. Locks are taken in the
+    /// engine's usual order (retry states, then the DLQ, then breakers, then
+    /// circuit overrides), each only long enough to copy out of it.
+    async fn dump_state(
+        &self,
+        request: Request<DumpStateRequest>,
+    ) -> Result<Response<DumpStateResponse>, Status> {
+        if let Some(status) = self.admin_rejection(request.metadata()) {
+            return Err(status);
+        }
+
+        let (retry_states, retry_state_count) = {
+            let states = self.retry_states.lock().unwrap();
+            let mut retry_states: Vec<RetryEntry> = states
+                .iter()
+                .map(|(transaction_id, state)| RetryEntry {
+                    transaction_id: transaction_id.clone(),
+                    psp_name: state.psp_name.clone(),
+                    attempt_count: state.attempt_count as i32,
+                    next_retry_at_ms: state.next_retry_at_ms as i64,
+                    tags: state.tags.clone(),
+                })
+                .collect();
+            retry_states.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+            retry_states.truncate(MAX_DUMPED_RETRY_STATES);
+            (retry_states, states.len())
+        };
+        let dlq = self
+            .dlq
+            .summary_by_psp()
+            .into_iter()
+            .map(|(psp_name, summary)| DlqPspSummary {
+                psp_name,
+                entries: summary.entries as i64,
+                parked: summary.parked as i64,
+                replaying: summary.replaying as i64,
+                payload_bytes: summary.payload_bytes as i64,
+            })
+            .collect();
+        let circuits = self
+            .circuit_breakers_by_name()
+            .into_iter()
+            .map(|(psp_name, breaker)| self.circuit_response(psp_name, breaker.get_state()))
+            .collect();
+        let mut circuit_overrides: Vec<PspCircuitOverride> = self
+            .circuit_overrides
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(psp_name, config)| PspCircuitOverride {
+                psp_name: psp_name.clone(),
+                config: Some(Self::proto_circuit_config(config)),
+            })
+            .collect();
+        circuit_overrides.sort_by(|a, b| a.psp_name.cmp(&b.psp_name));
+        let psp_access = Self::proto_psp_access(&self.psp_access.lock().unwrap());
+
+        Ok(Response::new(DumpStateResponse {
+            taken_at_ms: self.clock.now_ms() as i64,
+            paused: self.paused.load(Ordering::SeqCst),
+            load_factor: self.load_factor(),
+            persistence_degraded: self.persistence_degraded.load(Ordering::SeqCst),
+            retry_config: Some(Self::proto_retry_config(self.retry_policy().config())),
+            circuit_config: Some(Self::proto_circuit_config(&self.circuit_config)),
+            circuit_overrides,
+            psp_access: Some(psp_access),
+            circuits,
+            retry_states,
+            retry_state_count: retry_state_count as i64,
+            dlq,
+            dlq_revision: self.dlq.revision() as i64,
+        }))
+    }
+
+    async fn lease_dlq_entry(
+        &self,
+        request: Request<LeaseDlqEntryRequest>,
+    ) -> Result<Response<LeaseDlqEntryResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let lease_ms = match u64::try_from(req.lease_ms) {
+            Ok(0) => DEFAULT_DLQ_LEASE_MS,
+            Ok(lease_ms) => lease_ms,
+            Err(_) => return Err(Status::invalid_argument("lease_ms must not be negative")),
+        };
+        let psp_name = Some(req.psp_name.as_str()).filter(|psp_name| !psp_name.is_empty());
+
+        let Some((entry, lease)) = self.dlq.lease_next(psp_name, self.clock.now_ms(), lease_ms)
+        else {
+            return Ok(Response::new(LeaseDlqEntryResponse::default()));
+        };
+        info!(
+            "Leased DLQ entry {} until {}",
+            entry.transaction_id, lease.expires_at_ms
+        );
+        Ok(Response::new(LeaseDlqEntryResponse {
+            leased: true,
+            entry: Some(Self::dlq_entry_summary(&entry)),
+            payload: entry.payload,
+            lease_id: lease.lease_id,
+            lease_expires_at_ms: lease.expires_at_ms as i64,
+        }))
+    }
+
+    async fn release_dlq_lease(
+        &self,
+        request: Request<ReleaseDlqLeaseRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        self.dlq
+            .release_lease(&req.transaction_id, &req.lease_id, self.clock.now_ms())
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+        info!("Released lease on DLQ entry {}", req.transaction_id);
+        let entry = self
+            .dlq
+            .get_entry(&req.transaction_id)
+            .ok_or_else(|| Status::not_found("Transaction not in dead letter queue"))?;
+        Ok(Response::new(Self::dlq_entry_summary(&entry)))
+    }
+
+    async fn complete_dlq_lease(
+        &self,
+        request: Request<CompleteDlqLeaseRequest>,
+    ) -> Result<Response<DlqEntrySummary>, Status> {
+        let req = request.into_inner();
+        let status = match Self::dlq_status_from_proto(req.status) {
+            Some(status @ (DlqEntryStatus::Resolved | DlqEntryStatus::Discarded)) => status,
+            _ => {
+                return Err(Status::invalid_argument(
+                    "status must be RESOLVED or DISCARDED",
+                ))
+            }
+        };
+
+        let completed = self.dlq.complete_lease(
+            &req.transaction_id,
+            &req.lease_id,
+            self.clock.now_ms(),
+            status,
+        );
+        self.log_dlq_changes();
+        let entry = completed.map_err(|e| Status::failed_precondition(e.to_string()))?;
+        info!(
+            "Lease on DLQ entry {} completed as {:?}",
+            entry.transaction_id, entry.status
+        );
+        Ok(Response::new(Self::dlq_entry_summary(&entry)))
+    }
+
+    async fn next_probe(
+        &self,
+        request: Request<NextProbeRequest>,
+    ) -> Result<Response<NextProbeResponse>, Status> {
+        let psp_name = self.psp_key(request.into_inner().psp_name);
+        let breaker = self.get_circuit_breaker(&psp_name);
+        let state = breaker
+            .as_ref()
+            .map(|breaker| breaker.get_state())
+            .unwrap_or_default();
+        let probe_due = state.state != CircuitState::Closed
+            && breaker.is_some_and(|breaker| breaker.would_proceed());
+        let transaction_id = if probe_due {
+            self.longest_waiting_retry(&psp_name).unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        Ok(Response::new(NextProbeResponse {
+            circuit: Some(self.circuit_response(psp_name.clone(), state)),
+            psp_name,
+            probe_due,
+            transaction_id,
+        }))
+    }
+
+    async fn list_near_exhaustion(
+        &self,
+        request: Request<ListNearExhaustionRequest>,
+    ) -> Result<Response<ListRetriesByPspResponse>, Status> {
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let max_attempts = self.retry_policy().max_attempts();
+
+        let mut retries: Vec<RetryEntry> = {
+            let states = self.retry_states.lock().unwrap();
+            states
+                .iter()
+                .filter(|(_, state)| state.psp_name == req.psp_name)
+                .filter(|(_, state)| state.attempt_count + 1 == max_attempts)
+                .map(|(transaction_id, state)| RetryEntry {
+                    transaction_id: transaction_id.clone(),
+                    psp_name: state.psp_name.clone(),
+                    attempt_count: state.attempt_count as i32,
+                    next_retry_at_ms: state.next_retry_at_ms as i64,
+                    tags: state.tags.clone(),
+                })
+                .collect()
+        };
+        // Soonest to run out first
+        retries.sort_by(|a, b| {
+            a.next_retry_at_ms
+                .cmp(&b.next_retry_at_ms)
+                .then_with(|| a.transaction_id.cmp(&b.transaction_id))
+        });
+
+        Ok(Response::new(ListRetriesByPspResponse { retries }))
+    }
+
+    /// Dry run of `set_retry_config`: validates the proposed config and
+    /// reports what applying it would change, without applying it
+    async fn validate_config(
+        &self,
+        request: Request<ValidateConfigRequest>,
+    ) -> Result<Response<ValidateConfigResponse>, Status> {
+        let config = request
+            .into_inner()
+            .config
+            .ok_or_else(|| Status::invalid_argument("config is required"))?;
+        let config = match Self::convert_retry_config(config)
+            .and_then(|config| self.check_retry_config(&config).map(|()| config))
+        {
+            Ok(config) => config,
+            Err(error) => {
+                return Ok(Response::new(ValidateConfigResponse {
+                    valid: false,
+                    error,
+                    ..Default::default()
+                }))
+            }
+        };
+
+        let current = serde_json::to_value(self.retry_policy().config())
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let proposed =
+            serde_json::to_value(&config).map_err(|e| Status::internal(e.to_string()))?;
+        let mut changes: Vec<ConfigChange> = match (current, proposed) {
+            (serde_json::Value::Object(current), serde_json::Value::Object(proposed)) => proposed
+                .iter()
+                .filter(|(field, value)| current.get(*field) != Some(*value))
+                .map(|(field, value)| ConfigChange {
+                    field: field.clone(),
+                    current: current
+                        .get(field)
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                    proposed: value.to_string(),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        changes.sort_by(|a, b| a.field.cmp(&b.field));
+
+        let would_dead_letter = self.stranded_by(config.max_attempts);
+        let rescheduled_psps = self.rescheduled_psps(&RetryPolicy::new(config));
+        Ok(Response::new(ValidateConfigResponse {
+            valid: true,
+            error: String::new(),
+            changes,
+            rescheduled_psps,
+            would_dead_letter_count: would_dead_letter.len() as i32,
+            would_dead_letter,
+        }))
+    }
+
+    async fn flush_dlq(
+        &self,
+        _request: Request<FlushDlqRequest>,
+    ) -> Result<Response<FlushDlqResponse>, Status> {
+        // Everything up to this revision is covered once the flush returns
+        let revision = self.dlq.revision();
+        let persisted = self
+            .dlq
+            .flush()
+            .await
+            .map_err(|e| Status::unavailable(format!("DLQ flush failed: {}", e)))?;
+
+        Ok(Response::new(FlushDlqResponse {
+            persisted,
+            revision: revision as i64,
+        }))
+    }
+
+    async fn get_retry_timeline(
+        &self,
+        request: Request<RetryTimelineRequest>,
+    ) -> Result<Response<RetryTimelineResponse>, Status> {
+        let req = request.into_inner();
+        let state = self
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(&req.transaction_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found("Transaction has no retry in flight"))?;
+
+        let attempts = self
+            .retry_policy()
+            .projected_attempts_ms(
+                state.attempt_count,
+                state.next_retry_at_ms,
+                state.first_scheduled_at_ms,
+            )
+            .zip(state.attempt_count + 1..)
+            .map(|(due_at_ms, attempt_number)| TimelineAttempt {
+                attempt_number: attempt_number as i32,
+                due_at_ms: due_at_ms as i64,
+            })
+            .collect();
+
+        Ok(Response::new(RetryTimelineResponse {
+            transaction_id: req.transaction_id,
+            estimated: self.retry_policy_for(&state.psp_name).config().jitter,
+            psp_name: state.psp_name,
+            attempts,
+        }))
+    }
+
+    async fn add_maintenance_window(
+        &self,
+        request: Request<AddMaintenanceWindowRequest>,
+    ) -> Result<Response<MaintenanceWindowsResponse>, Status> {
+        let req = request.into_inner();
+        self.check_identifier("psp_name", &req.psp_name)
+            .map_err(Status::invalid_argument)?;
+        let psp_name = self.psp_key(req.psp_name);
+        let start_ms = u64::try_from(req.start_ms)
+            .map_err(|_| Status::invalid_argument("start_ms must not be negative"))?;
+        let end_ms = u64::try_from(req.end_ms)
+            .map_err(|_| Status::invalid_argument("end_ms must not be negative"))?;
+        let windows = self
+            .announce_maintenance(&psp_name, start_ms, end_ms)
+            .map_err(Status::invalid_argument)?;
+        // A window that has already started pauses the breaker straight away
+        self.run_maintenance_windows();
+
+        Ok(Response::new(MaintenanceWindowsResponse {
+            windows: windows
+                .into_iter()
+                .map(|(start_ms, end_ms)| MaintenanceWindow {
+                    start_ms: start_ms as i64,
+                    end_ms: end_ms as i64,
+                })
+                .collect(),
+            psp_name,
+        }))
+    }
+
+    async fn get_circuit_rejections(
+        &self,
+        request: Request<CircuitRejectionsRequest>,
+    ) -> Result<Response<CircuitRejectionsResponse>, Status> {
+        let req = request.into_inner();
+        let psp_name = self.psp_key(req.psp_name);
+        if !(1..=MAX_ADMISSION_BUCKETS as i32).contains(&req.window_minutes) {
+            return Err(Status::invalid_argument(format!(
+                "window_minutes must be from 1 to {}",
+                MAX_ADMISSION_BUCKETS
+            )));
+        }
+        let window_ms = req.window_minutes as u64 * 60_000;
+        // Checks are counted per breaker, so PSPs sharing one share counts
+        let breaker_name = self.circuit_breaker_name(&psp_name);
+        let (admissions, window_start_ms) =
+            self.admissions
+                .window(&breaker_name, window_ms, self.clock.now_ms());
+        if req.reset {
+            self.admissions.reset(&breaker_name);
+        }
+
+        Ok(Response::new(CircuitRejectionsResponse {
+            psp_name,
+            checks: admissions.checks as i64,
+            rejected: admissions.rejected as i64,
+            rejection_ratio: admissions.rejection_ratio(),
+            window_start_ms: window_start_ms as i64,
+        }))
+    }
+
+    async fn force_open_circuit(
+        &self,
+        request: Request<CircuitRequest>,
+    ) -> Result<Response<CircuitResponse>, Status> {
+        if let Some(status) = self.admin_rejection(request.metadata()) {
+            return Err(status);
+        }
+        let mut req = request.into_inner();
+        req.psp_name = self.psp_key(req.psp_name);
+        let (name, breaker) = self
+            .get_or_create_circuit_breaker(&req.psp_name)
+            .map_err(Status::resource_exhausted)?;
+        // Opening a shared overflow breaker would block other PSPs too
+        if name != req.psp_name {
+            return Err(Status::failed_precondition(format!(
+                "PSP {} has no breaker of its own to force open",
+                req.psp_name
+            )));
+        }
+        breaker.force_open();
+        let state = breaker.get_state();
+        warn!("Circuit breaker forced open for PSP: {}", name);
+        self.log_event(|| EngineEvent::CircuitStateChanged {
+            psp_name: name,
+            state: state.clone(),
+        });
+
+        Ok(Response::new(self.circuit_response(req.psp_name, state)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::current_timestamp_ms;
+
+    fn service() -> RetryEngineService {
+        RetryEngineService::new(RetryConfig::default(), CircuitBreakerConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_set_circuit_config_opens_psp_sooner() {
+        let service = service();
+
+        let response = service
+            .set_circuit_config(Request::new(SetCircuitConfigRequest {
+                psp_name: "stripe".to_string(),
+                config: Some(ProtoCircuitBreakerConfig {
+                    failure_threshold: 2,
+                    success_threshold: 1,
+                    timeout_duration_ms: 10000,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.config.unwrap().failure_threshold, 2);
+
+        for _ in 0..2 {
+            service
+                .get_or_create_circuit_breaker("stripe")
+                .unwrap()
+                .1
+                .record_failure();
+            service
+                .get_or_create_circuit_breaker("adyen")
+                .unwrap()
+                .1
+                .record_failure();
+        }
+
+        assert_eq!(
+            service
+                .get_or_create_circuit_breaker("stripe")
+                .unwrap()
+                .1
+                .get_state()
+                .state,
+            CircuitState::Open
+        );
+        assert_eq!(
+            service
+                .get_or_create_circuit_breaker("adyen")
+                .unwrap()
+                .1
+                .get_state()
+                .state,
+            CircuitState::Closed
+        );
+
+        // Breakers created after the override still get the global default
+        let checkout = service.get_or_create_circuit_breaker("checkout").unwrap().1;
+        assert_eq!(checkout.config().failure_threshold, 5);
+    }
+
+    #[tokio::test]
+    async fn test_set_circuit_config_preserves_counts() {
+        let service = service();
+        service
+            .get_or_create_circuit_breaker("stripe")
+            .unwrap()
+            .1
+            .record_failure();
+
+        service
+            .set_circuit_config(Request::new(SetCircuitConfigRequest {
+                psp_name: "stripe".to_string(),
+                config: Some(ProtoCircuitBreakerConfig {
+                    failure_threshold: 2,
+                    success_threshold: 1,
+                    timeout_duration_ms: 10000,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap();
+
+        let breaker = service.get_or_create_circuit_breaker("stripe").unwrap().1;
+        assert_eq!(breaker.get_state().failure_count, 1);
+        breaker.record_failure();
+        assert_eq!(breaker.get_state().state, CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_set_circuit_config_rejects_invalid_config() {
+        let service = service();
+
+        let status = service
+            .set_circuit_config(Request::new(SetCircuitConfigRequest {
+                psp_name: "stripe".to_string(),
+                config: Some(ProtoCircuitBreakerConfig {
+                    failure_threshold: 0,
+                    success_threshold: 1,
+                    timeout_duration_ms: 10000,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            service
+                .get_or_create_circuit_breaker("stripe")
+                .unwrap()
+                .1
+                .config()
+                .failure_threshold,
+            5
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retry_time_series_counts_scheduled_retries() {
+        let service = service();
+
+        for attempt in 0..2 {
+            service
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: format!("txn_{}", attempt),
+                    psp_name: "stripe".to_string(),
+                    attempt_number: 1,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+        }
+
+        let response = service
+            .get_retry_time_series(Request::new(RetryTimeSeriesRequest { bucket_count: 5 }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.bucket_duration_ms, 1000);
+        assert_eq!(response.buckets.len(), 5);
+        let total: i64 = response.buckets.iter().map(|b| b.scheduled_retries).sum();
+        assert_eq!(total, 2);
+    }
+
+    #[tokio::test]
+    async fn test_payload_budget_covers_retry_states_and_dlq() {
+        let service = service()
+            .with_payload_budget(PayloadBudget::new(10, crate::dlq::OverflowPolicy::Reject));
+        let request = |transaction_id: &str, attempt_number: i32| {
+            Request::new(RetryRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                payload: vec![0; 8],
+                attempt_number,
+                ..Default::default()
+            })
+        };
+
+        let first = service.schedule_retry(request("txn_1", 1)).await.unwrap();
+        assert!(first.into_inner().scheduled);
+
+        // Rescheduling the same transaction does not double count its payload
+        let again = service.schedule_retry(request("txn_1", 2)).await.unwrap();
+        assert!(again.into_inner().scheduled);
+        assert_eq!(service.dlq.payload_budget().used_bytes(), 8);
+
+        let second = service
+            .schedule_retry(request("txn_2", 1))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!second.scheduled);
+        assert!(second.message.contains("byte ceiling"));
+
+        // Dead-lettering moves the bytes from the retry state to the DLQ
+        for attempt in 2..=5 {
+            service
+                .schedule_retry(request("txn_1", attempt))
+                .await
+                .unwrap();
+        }
+        assert!(service.dlq.contains("txn_1"));
+        assert_eq!(service.dlq.payload_budget().used_bytes(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_refused_dead_letter_keeps_retry_state() {
+        // The budget comes after the quota, and must not drop it
+        let service = no_jitter_service()
+            .with_psp_quotas(HashMap::from([(
+                "stripe".to_string(),
+                PspQuota {
+                    max_entries: 1,
+                    overflow_policy: crate::dlq::OverflowPolicy::Reject,
+                },
+            )]))
+            .with_payload_budget(PayloadBudget::new(
+                100,
+                crate::dlq::OverflowPolicy::EvictOldest,
+            ));
+        dead_letter(&service, "txn_0", "stripe");
+        assert_eq!(service.dlq.payload_budget().used_bytes(), 3);
+
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+        let response = exhaust(&service, "txn_1", "stripe").await;
+        assert!(response.message.contains("DLQ refused entry"));
+        assert!(!service.dlq.contains("txn_1"));
+        let states = service.retry_states.lock().unwrap().clone();
+        assert!(states.contains_key("txn_1"));
+    }
+
+    fn dead_letter(service: &RetryEngineService, transaction_id: &str, psp_name: &str) {
+        service.dlq.add_entry(DLQEntry {
+            transaction_id: transaction_id.to_string(),
+            psp_name: psp_name.to_string(),
+            payload: vec![1, 2, 3],
+            attempt_count: 5,
+            last_error: "Max retry attempts exceeded".to_string(),
+            timestamp_ms: current_timestamp_ms(),
+            ..Default::default()
+        });
+    }
+
+    fn open_circuit(service: &RetryEngineService, psp_name: &str) {
+        let breaker = service.get_or_create_circuit_breaker(psp_name).unwrap().1;
+        for _ in 0..breaker.config().failure_threshold {
+            breaker.record_failure();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_to_target_psp_checks_new_psp_circuit() {
+        let service = service();
+        dead_letter(&service, "txn_1", "legacy_psp");
+        open_circuit(&service, "legacy_psp");
+
+        let declined = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                target_psp: String::new(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!declined.replayed);
+
+        let replayed = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                target_psp: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(replayed.replayed);
+        assert_eq!(replayed.psp_name, "stripe");
+
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert_eq!(entry.psp_name, "stripe");
+        assert_eq!(entry.rerouted_from.as_deref(), Some("legacy_psp"));
+        assert!(entry.replaying);
+
+        let status = service
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.status, "RETRYING");
+    }
+
+    #[tokio::test]
+    async fn test_replay_holds_payload_once_and_only_looks_at_breaker() {
+        let service = service()
+            .with_payload_budget(PayloadBudget::new(100, crate::dlq::OverflowPolicy::Reject));
+        dead_letter(&service, "txn_1", "stripe");
+        assert_eq!(service.dlq.payload_budget().used_bytes(), 3);
+
+        let replayed = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(replayed.replayed);
+        assert_eq!(service.dlq.payload_budget().used_bytes(), 3);
+
+        let (admissions, _) = service
+            .admissions
+            .window("stripe", 60_000, service.clock.now_ms());
+        assert_eq!(admissions.checks, 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_unknown_entry_is_not_found() {
+        let status = service()
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "missing".to_string(),
+                target_psp: String::new(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_replay_reroutes_matching_entries() {
+        let service = service();
+        dead_letter(&service, "txn_1", "legacy_psp");
+        dead_letter(&service, "txn_2", "legacy_psp");
+        dead_letter(&service, "txn_3", "adyen");
+        open_circuit(&service, "legacy_psp");
+
+        let response = service
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                psp_name: "legacy_psp".to_string(),
+                target_psp: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(response.replayed_count, 2);
+        assert_eq!(response.skipped_count, 0);
+        assert_eq!(service.dlq.get_entry("txn_1").unwrap().psp_name, "stripe");
+        assert_eq!(service.dlq.get_entry("txn_3").unwrap().psp_name, "adyen");
+        assert!(!service.dlq.get_entry("txn_3").unwrap().replaying);
+    }
+
+    #[tokio::test]
+    async fn test_list_retries_by_psp_filters_on_psp() {
+        let service = service();
+        for (transaction_id, psp_name, attempt_number) in [
+            ("txn_1", "stripe", 1),
+            ("txn_2", "adyen", 1),
+            ("txn_3", "stripe", 2),
+        ] {
+            service
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    psp_name: psp_name.to_string(),
+                    attempt_number,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap();
+        }
+
+        let response = service
+            .list_retries_by_psp(Request::new(ListRetriesByPspRequest {
+                psp_name: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let listed: Vec<(&str, i32)> = response
+            .retries
+            .iter()
+            .map(|retry| (retry.transaction_id.as_str(), retry.attempt_count))
+            .collect();
+        assert_eq!(listed, vec![("txn_1", 1), ("txn_3", 2)]);
+        assert!(response
+            .retries
+            .iter()
+            .all(|retry| retry.next_retry_at_ms > 0));
+    }
+
+    /// Fail each remaining attempt in turn until the transaction is no longer
+    /// scheduled, as sequential attempt numbering won't skip ahead
+    async fn exhaust(
+        service: &RetryEngineService,
+        transaction_id: &str,
+        psp_name: &str,
+    ) -> RetryResponse {
+        let mut attempt = service
+            .retry_states
+            .lock()
+            .unwrap()
+            .get(transaction_id)
+            .map_or(1, |state| state.attempt_count + 1);
+        loop {
+            let response = schedule(service, transaction_id, psp_name, attempt as i32).await;
+            if !response.scheduled {
+                return response;
+            }
+            attempt += 1;
+        }
+    }
+
+    fn no_jitter_service() -> RetryEngineService {
+        RetryEngineService::new(
+            RetryConfig {
+                jitter: false,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        )
+    }
+
+    async fn evaluate(
+        service: &RetryEngineService,
+        transaction_id: &str,
+        psp_name: &str,
+        attempt_number: i32,
+    ) -> EvaluateTransactionResponse {
+        service
+            .evaluate_transaction(Request::new(EvaluateTransactionRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: psp_name.to_string(),
+                attempt_number,
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    async fn schedule(
+        service: &RetryEngineService,
+        transaction_id: &str,
+        psp_name: &str,
+        attempt_number: i32,
+    ) -> RetryResponse {
+        service
+            .schedule_retry(Request::new(RetryRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: psp_name.to_string(),
+                attempt_number,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_matches_separate_calls() {
+        let service = no_jitter_service();
+        open_circuit(&service, "legacy_psp");
+
+        // Retry allowed
+        let evaluation = evaluate(&service, "txn_1", "stripe", 2).await;
+        assert!(evaluation.retry_allowed && !evaluation.circuit_open);
+        let before = current_timestamp_ms() as i64;
+        let scheduled = schedule(&service, "txn_1", "stripe", 2).await;
+        assert!(scheduled.scheduled);
+        assert!(scheduled.next_retry_at_ms >= before + evaluation.delay_ms);
+        assert_eq!(evaluation.delay_ms, 2000);
+
+        // Circuit open
+        let evaluation = evaluate(&service, "txn_2", "legacy_psp", 1).await;
+        let circuit = service
+            .get_circuit_status(Request::new(CircuitRequest {
+                psp_name: "legacy_psp".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(evaluation.circuit_open && !evaluation.retry_allowed);
+        assert_eq!(evaluation.circuit_state, circuit.state);
+        assert!(!schedule(&service, "txn_2", "legacy_psp", 1).await.scheduled);
+
+        // Exhausted
+        let evaluation = evaluate(&service, "txn_3", "stripe", 5).await;
+        assert!(evaluation.would_move_to_dlq && !evaluation.retry_allowed);
+        assert!(!service.dlq.contains("txn_3"));
+        schedule(&service, "txn_3", "stripe", 5).await;
+        assert!(service.dlq.contains("txn_3"));
+        assert!(evaluate(&service, "txn_3", "stripe", 5).await.in_dlq);
+    }
+
+    #[tokio::test]
+    async fn test_evaluate_does_not_create_breakers() {
+        let service = service();
+        let evaluation = evaluate(&service, "txn_1", "new_psp", 1).await;
+
+        assert!(evaluation.retry_allowed);
+        assert!(service.get_circuit_breaker("new_psp").is_none());
+        assert!(service.retry_states.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pause_blocks_scheduling_until_resumed() {
+        let service = service();
+        let set_paused =
+            |paused| service.set_engine_paused(Request::new(SetEnginePausedRequest { paused }));
+
+        set_paused(true).await.unwrap();
+        let declined = schedule(&service, "txn_1", "stripe", 1).await;
+        assert!(!declined.scheduled);
+        assert_eq!(declined.message, "Retry engine paused");
+
+        // Exhausted transactions are not dead-lettered while paused either
+        schedule(&service, "txn_2", "stripe", 5).await;
+        assert!(!service.dlq.contains("txn_2"));
+        assert!(service.get_circuit_breaker("stripe").is_none());
+        assert!(service.retry_states.lock().unwrap().is_empty());
+
+        // Queries keep working
+        let status = service
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.status, "NOT_FOUND");
+
+        set_paused(false).await.unwrap();
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+    }
+
+    async fn update_status(
+        service: &RetryEngineService,
+        transaction_id: &str,
+        status: ProtoDlqEntryStatus,
+    ) -> Result<DlqEntrySummary, Status> {
+        service
+            .update_dlq_entry_status(Request::new(UpdateDlqEntryStatusRequest {
+                transaction_id: transaction_id.to_string(),
+                status: status as i32,
+            }))
+            .await
+            .map(Response::into_inner)
+    }
+
+    #[tokio::test]
+    async fn test_dlq_status_transitions_and_filtering() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        dead_letter(&service, "txn_2", "stripe");
+        dead_letter(&service, "txn_3", "stripe");
+
+        let entry = update_status(&service, "txn_1", ProtoDlqEntryStatus::InReview)
+            .await
+            .unwrap();
+        assert_eq!(entry.status, ProtoDlqEntryStatus::InReview as i32);
+        update_status(&service, "txn_1", ProtoDlqEntryStatus::Resolved)
+            .await
+            .unwrap();
+        update_status(&service, "txn_2", ProtoDlqEntryStatus::Discarded)
+            .await
+            .unwrap();
+
+        let rejected = update_status(&service, "txn_1", ProtoDlqEntryStatus::New)
+            .await
+            .unwrap_err();
+        assert_eq!(rejected.code(), tonic::Code::FailedPrecondition);
+        let missing = update_status(&service, "missing", ProtoDlqEntryStatus::Resolved)
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let new_entries = service
+            .list_dlq_entries(Request::new(ListDlqEntriesRequest {
+                status: Some(ProtoDlqEntryStatus::New as i32),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let ids: Vec<&str> = new_entries
+            .entries
+            .iter()
+            .map(|entry| entry.transaction_id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["txn_3"]);
+    }
+
+    #[tokio::test]
+    async fn test_replay_marks_entry_in_review() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        dead_letter(&service, "txn_2", "stripe");
+        update_status(&service, "txn_2", ProtoDlqEntryStatus::Discarded)
+            .await
+            .unwrap();
+
+        let replay = |transaction_id: &str| {
+            service.replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: transaction_id.to_string(),
+                target_psp: String::new(),
+                ..Default::default()
+            }))
+        };
+        assert!(replay("txn_1").await.unwrap().into_inner().replayed);
+        assert_eq!(
+            service.dlq.get_entry("txn_1").unwrap().status,
+            DlqEntryStatus::InReview
+        );
+
+        // Discarded entries stay put
+        assert!(!replay("txn_2").await.unwrap().into_inner().replayed);
+        assert!(!service.dlq.get_entry("txn_2").unwrap().replaying);
+    }
+
+    #[tokio::test]
+    async fn test_mark_resolved_closes_out_a_replay() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        assert!(
+            service
+                .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                    transaction_id: "txn_1".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .replayed
+        );
+        assert!(service.dlq.get_entry("txn_1").unwrap().payload.is_empty());
+
+        let response = service
+            .mark_resolved(Request::new(MarkResolvedRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.resolved);
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert!(!entry.replaying);
+        assert_eq!(entry.status, DlqEntryStatus::Resolved);
+        assert_eq!(entry.payload, vec![1, 2, 3]);
+        assert!(!entry.payload_stripped);
+    }
+
+    #[tokio::test]
+    async fn test_reported_success_closes_out_a_replay() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        assert!(
+            service
+                .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                    transaction_id: "txn_1".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .replayed
+        );
+
+        let response = service
+            .report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: "txn_1".to_string(),
+                success: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(response.retry_state_cleared);
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert!(!entry.replaying);
+        assert_eq!(entry.status, DlqEntryStatus::Resolved);
+        assert_eq!(entry.payload, vec![1, 2, 3]);
+        assert!(!entry.payload_stripped);
+    }
+
+    #[tokio::test]
+    async fn test_dlq_changes_since() {
+        let service = service();
+        let changes_since = |revision: i64| {
+            service.dlq_changes_since(Request::new(DlqChangesSinceRequest { revision }))
+        };
+
+        dead_letter(&service, "txn_1", "stripe");
+        let first = changes_since(0).await.unwrap().into_inner();
+        assert_eq!(first.added.len(), 1);
+
+        service.dlq.remove_entry("txn_1");
+        dead_letter(&service, "txn_2", "stripe");
+        let second = changes_since(first.revision).await.unwrap().into_inner();
+        assert!(!second.reset);
+        assert_eq!(second.added[0].transaction_id, "txn_2");
+        assert_eq!(second.removed_transaction_ids, vec!["txn_1".to_string()]);
+        assert!(second.revision > first.revision);
+
+        let err = changes_since(-1).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_psp_health_tracks_peak_in_flight() {
+        let service = service();
+        let health = || async {
+            service
+                .get_psp_health(Request::new(PspHealthRequest {
+                    psp_name: "stripe".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        };
+        let resolve = |transaction_id: &str| {
+            service.mark_resolved(Request::new(MarkResolvedRequest {
+                transaction_id: transaction_id.to_string(),
+            }))
+        };
+
+        schedule(&service, "txn_1", "stripe", 1).await;
+        schedule(&service, "txn_2", "stripe", 1).await;
+        // A reschedule is the same transaction still in flight
+        schedule(&service, "txn_1", "stripe", 2).await;
+        schedule(&service, "txn_3", "stripe", 1).await;
+        schedule(&service, "txn_4", "adyen", 1).await;
+        assert_eq!(health().await.in_flight, 3);
+
+        assert!(resolve("txn_1").await.unwrap().into_inner().resolved);
+        assert!(!resolve("txn_1").await.unwrap().into_inner().resolved);
+        // Exhausting retries moves txn_2 to the DLQ
+        exhaust(&service, "txn_2", "stripe").await;
+        schedule(&service, "txn_5", "stripe", 1).await;
+
+        let health = health().await;
+        assert_eq!(health.in_flight, 2);
+        assert_eq!(health.peak_in_flight, 3);
+        assert_eq!(
+            health.circuit.unwrap().state,
+            ProtoCircuitState::Closed as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unusable_dlq_store_falls_back_in_lenient_mode() {
+        use crate::persistence::SerializationFormat;
+
+        let path = std::env::temp_dir()
+            .join(format!("missing-dir-{}", uuid::Uuid::new_v4()))
+            .join("dlq.json");
+        let strict = service().with_dlq_store(
+            DlqStore::new(&path, SerializationFormat::Json),
+            PersistenceMode::Strict,
+        );
+        assert!(strict.is_err());
+
+        let service = service()
+            .with_dlq_store(
+                DlqStore::new(&path, SerializationFormat::Json),
+                PersistenceMode::Lenient,
+            )
+            .unwrap();
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+        schedule(&service, "txn_2", "stripe", 10).await;
+        assert!(service.dlq.contains("txn_2"));
+        assert!(service.persist_dlq().is_ok());
+
+        let health = service
+            .get_engine_health(Request::new(EngineHealthRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(health.persistence_degraded);
+        assert_eq!(health.dlq_size, 1);
+    }
+
+    #[tokio::test]
+    async fn test_tag_filtered_operations_only_touch_tagged() {
+        let service = service();
+        let schedule_tagged = |transaction_id: &str, attempt_number: i32, tags: &[&str]| {
+            service.schedule_retry(Request::new(RetryRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number,
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+                ..Default::default()
+            }))
+        };
+
+        // Tags set on an earlier attempt carry through to the DLQ
+        schedule_tagged("txn_1", 1, &["fraud-ring"]).await.unwrap();
+        for attempt in 2..=5 {
+            schedule_tagged("txn_1", attempt, &[]).await.unwrap();
+        }
+        for (transaction_id, tags) in [("txn_2", &["fraud-ring"][..]), ("txn_3", &[][..])] {
+            schedule_tagged(transaction_id, 10, tags).await.unwrap();
+        }
+        schedule_tagged("txn_4", 1, &["fraud-ring"]).await.unwrap();
+        schedule_tagged("txn_5", 1, &[]).await.unwrap();
+
+        let retries = service
+            .list_retries_by_psp(Request::new(ListRetriesByPspRequest {
+                psp_name: "stripe".to_string(),
+                has_tag: "fraud-ring".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(retries.retries.len(), 1);
+        assert_eq!(retries.retries[0].transaction_id, "txn_4");
+
+        let listed = service
+            .list_dlq_entries(Request::new(ListDlqEntriesRequest {
+                has_tag: "fraud-ring".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let mut ids: Vec<String> = listed
+            .entries
+            .into_iter()
+            .map(|e| e.transaction_id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["txn_1", "txn_2"]);
+
+        let replayed = service
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                has_tag: "fraud-ring".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replayed.replayed_count, 2);
+        assert!(!service.dlq.get_entry("txn_3").unwrap().replaying);
+
+        let purged = service
+            .purge_dlq(Request::new(PurgeDlqRequest {
+                has_tag: "fraud-ring".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(purged.purged_transaction_ids, vec!["txn_1", "txn_2"]);
+        assert!(service.dlq.contains("txn_3"));
+
+        let unfiltered = service
+            .purge_dlq(Request::new(PurgeDlqRequest::default()))
+            .await
+            .unwrap_err();
+        assert_eq!(unfiltered.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_replay_from_event_log_rebuilds_state() {
+        use crate::event_log::InMemoryEventLog;
+
+        let log = Arc::new(InMemoryEventLog::new());
+        let service = service().with_event_log(log.clone());
+
+        // Retries, a DLQ move and a replay
+        schedule(&service, "txn_1", "stripe", 1).await;
+        schedule(&service, "txn_1", "stripe", 2).await;
+        schedule(&service, "txn_2", "stripe", 10).await;
+        schedule(&service, "txn_3", "adyen", 10).await;
+        schedule(&service, "txn_4", "adyen", 1).await;
+        service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_3".to_string(),
+                target_psp: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        update_status(&service, "txn_2", ProtoDlqEntryStatus::Discarded)
+            .await
+            .unwrap();
+        service
+            .mark_resolved(Request::new(MarkResolvedRequest {
+                transaction_id: "txn_4".to_string(),
+            }))
+            .await
+            .unwrap();
+
+        // A config override and a circuit transition
+        service
+            .set_circuit_config(Request::new(SetCircuitConfigRequest {
+                psp_name: "adyen".to_string(),
+                config: Some(ProtoCircuitBreakerConfig {
+                    failure_threshold: 1,
+                    success_threshold: 1,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap();
+        open_circuit(&service, "adyen");
+        schedule(&service, "txn_5", "adyen", 1).await;
+        service
+            .set_engine_paused(Request::new(SetEnginePausedRequest { paused: true }))
+            .await
+            .unwrap();
+
+        let rebuilt = RetryEngineService::replay_from(
+            RetryConfig::default(),
+            CircuitBreakerConfig::default(),
+            log.as_ref(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            *rebuilt.retry_states.lock().unwrap(),
+            *service.retry_states.lock().unwrap()
+        );
+        let breaker_states = |service: &RetryEngineService| {
+            let breakers = service.circuit_breakers.lock().unwrap();
+            let mut states: Vec<(String, CircuitBreakerState)> = breakers
+                .iter()
+                .map(|(psp_name, breaker)| (psp_name.clone(), breaker.get_state()))
+                .collect();
+            states.sort_by(|a, b| a.0.cmp(&b.0));
+            states
+        };
+        assert_eq!(breaker_states(&rebuilt), breaker_states(&service));
+        assert_eq!(breaker_states(&rebuilt)[0].1.state, CircuitState::HalfOpen);
+        assert_eq!(
+            *rebuilt.circuit_overrides.lock().unwrap(),
+            *service.circuit_overrides.lock().unwrap()
+        );
+        let dlq_entries = |service: &RetryEngineService| {
+            let mut entries = service.dlq.get_all_entries();
+            entries.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+            entries
+        };
+        assert_eq!(dlq_entries(&rebuilt), dlq_entries(&service));
+        assert_eq!(dlq_entries(&rebuilt).len(), 2);
+        assert!(rebuilt.paused.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_circuit_reset_with_manual_clock() {
+        use crate::clock::ManualClock;
+
+        // 2024-01-01 00:00 UTC, reset daily at 03:00
+        let clock = Arc::new(ManualClock::new(1_704_067_200_000));
+        let service = service().with_clock(clock.clone());
+        let three_am = 3 * 60 * 60 * 1000;
+        let response = service
+            .set_circuit_reset_schedule(Request::new(SetCircuitResetScheduleRequest {
+                psp_name: "stripe".to_string(),
+                interval_ms: 24 * 60 * 60 * 1000,
+                offset_ms: three_am,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.next_reset_at_ms, 1_704_067_200_000 + three_am);
+
+        open_circuit(&service, "stripe");
+        open_circuit(&service, "adyen");
+        clock.advance(three_am as u64 - 1);
+        assert!(service.run_due_circuit_resets().is_empty());
+
+        clock.advance(1);
+        assert_eq!(service.run_due_circuit_resets(), vec!["stripe".to_string()]);
+        let stripe = service.get_circuit_breaker("stripe").unwrap().get_state();
+        assert_eq!(stripe.state, CircuitState::HalfOpen);
+        assert_eq!(stripe.failure_count, 0);
+        assert_eq!(
+            service
+                .get_circuit_breaker("adyen")
+                .unwrap()
+                .get_state()
+                .state,
+            CircuitState::Open
+        );
+
+        // Not due again until the next day
+        assert!(service.run_due_circuit_resets().is_empty());
+
+        let invalid = service
+            .set_circuit_reset_schedule(Request::new(SetCircuitResetScheduleRequest {
+                psp_name: "stripe".to_string(),
+                interval_ms: 1000,
+                offset_ms: 1000,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_expire_dlq_runs_the_hook_and_logs_the_removals() {
+        use crate::clock::ManualClock;
+        use crate::event_log::InMemoryEventLog;
+
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let log = Arc::new(InMemoryEventLog::new());
+        let archived = Arc::new(Mutex::new(Vec::new()));
+        let hook_archived = archived.clone();
+        let expiring = service()
+            .with_clock(clock.clone())
+            .with_event_log(log.clone())
+            .with_dlq_expiry(60_000)
+            .on_dlq_expire(Arc::new(move |entry: &DLQEntry| {
+                if entry.transaction_id == "txn_unarchivable" {
+                    return Err("archive unavailable".to_string());
+                }
+                hook_archived
+                    .lock()
+                    .unwrap()
+                    .push(entry.transaction_id.clone());
+                Ok(())
+            }));
+        // Dead-lettered on their last attempt, stamped by the service clock
+        for transaction_id in ["txn_old", "txn_unarchivable"] {
+            assert!(
+                !schedule(&expiring, transaction_id, "stripe", 5)
+                    .await
+                    .scheduled
+            );
+        }
+        clock.advance(60_000);
+        assert!(expiring.expire_dlq().is_empty());
+        clock.advance(30_000);
+        assert!(!schedule(&expiring, "txn_new", "stripe", 5).await.scheduled);
+        clock.advance(10_000);
+
+        assert_eq!(expiring.expire_dlq(), vec!["txn_old".to_string()]);
+        assert_eq!(*archived.lock().unwrap(), vec!["txn_old".to_string()]);
+        assert!(expiring.dlq.get_entry("txn_old").is_none());
+        assert!(expiring.dlq.get_entry("txn_unarchivable").is_some());
+        assert!(expiring.dlq.get_entry("txn_new").is_some());
+        let removed: Vec<EngineEvent> = log
+            .events()
+            .unwrap()
+            .into_iter()
+            .filter(|event| matches!(event, EngineEvent::DlqEntryRemoved { .. }))
+            .collect();
+        assert_eq!(
+            removed,
+            vec![EngineEvent::DlqEntryRemoved {
+                transaction_id: "txn_old".to_string()
+            }]
+        );
+
+        // Without expiry nothing is touched, and no task is spawned
+        let keep = Arc::new(service());
+        dead_letter(&keep, "txn_1", "stripe");
+        assert!(keep.expire_dlq().is_empty());
+        assert!(RetryEngineService::spawn_dlq_expiry_task(keep, Duration::from_secs(1)).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_circuit_rejections_report_share_of_blocked_checks() {
+        use crate::clock::ManualClock;
+
+        let clock = Arc::new(ManualClock::new(1_800_000));
+        let service = no_jitter_service().with_clock(clock.clone());
+        let rejections = |window_minutes, reset| {
+            service.get_circuit_rejections(Request::new(CircuitRejectionsRequest {
+                psp_name: "stripe".to_string(),
+                window_minutes,
+                reset,
+            }))
+        };
+
+        for i in 0..6 {
+            assert!(
+                schedule(&service, &format!("txn_ok_{}", i), "stripe", 1)
+                    .await
+                    .scheduled
+            );
+        }
+        clock.advance(120_000);
+        open_circuit(&service, "stripe");
+        for i in 0..2 {
+            assert!(
+                !schedule(&service, &format!("txn_blocked_{}", i), "stripe", 1)
+                    .await
+                    .scheduled
+            );
+        }
+
+        let report = rejections(5, false).await.unwrap().into_inner();
+        assert_eq!((report.checks, report.rejected), (8, 2));
+        assert_eq!(report.rejection_ratio, 0.25);
+        assert_eq!(report.window_start_ms, 1_620_000);
+        // The last minute only holds the blocked checks
+        let report = rejections(1, false).await.unwrap().into_inner();
+        assert_eq!((report.checks, report.rejected), (2, 2));
+        assert_eq!(report.rejection_ratio, 1.0);
+
+        // Reset clears the counts once they're read
+        let report = rejections(5, true).await.unwrap().into_inner();
+        assert_eq!(report.checks, 8);
+        let report = rejections(5, false).await.unwrap().into_inner();
+        assert_eq!((report.checks, report.rejected), (0, 0));
+        assert_eq!(report.rejection_ratio, 0.0);
+
+        let invalid = rejections(0, false).await.unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_window_pauses_then_resumes_breaker() {
+        use crate::clock::ManualClock;
+
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let service = service().with_clock(clock.clone());
+        let add = |start_ms: i64, end_ms: i64| {
+            service.add_maintenance_window(Request::new(AddMaintenanceWindowRequest {
+                psp_name: "stripe".to_string(),
+                start_ms,
+                end_ms,
+            }))
+        };
+        add(1_010_000, 1_020_000).await.unwrap();
+        // Overlapping windows merge into one
+        let windows: Vec<(i64, i64)> = add(1_015_000, 1_030_000)
+            .await
+            .unwrap()
+            .into_inner()
+            .windows
+            .iter()
+            .map(|window| (window.start_ms, window.end_ms))
+            .collect();
+        assert_eq!(windows, vec![(1_010_000, 1_030_000)]);
+        let ended = add(900_000, 999_000).await.unwrap_err();
+        assert_eq!(ended.code(), tonic::Code::InvalidArgument);
+
+        assert!(service.run_maintenance_windows().is_empty());
+        assert!(
+            schedule(&service, "txn_before", "stripe", 1)
+                .await
+                .scheduled
+        );
+
+        clock.advance(10_000);
+        assert_eq!(
+            service.run_maintenance_windows(),
+            vec!["stripe".to_string()]
+        );
+        let breaker = service.get_circuit_breaker("stripe").unwrap();
+        assert!(breaker.is_paused());
+        let declined = schedule(&service, "txn_during", "stripe", 1).await;
+        assert!(!declined.scheduled);
+        assert!(declined.message.contains("maintenance"));
+        // Failures reported during maintenance don't count against the PSP
+        for _ in 0..10 {
+            service.record_outcome("stripe", false, None);
+        }
+
+        // Still inside the merged window after the first one would have ended
+        clock.advance(15_000);
+        assert!(service.run_maintenance_windows().is_empty());
+        assert!(breaker.is_paused());
+
+        clock.advance(5_000);
+        assert_eq!(
+            service.run_maintenance_windows(),
+            vec!["stripe".to_string()]
+        );
+        let state = breaker.get_state();
+        assert!(!state.paused);
+        assert_eq!(state.state, CircuitState::Closed);
+        assert_eq!(state.failure_count, 0);
+        assert!(schedule(&service, "txn_after", "stripe", 1).await.scheduled);
+        assert!(service.run_maintenance_windows().is_empty());
+    }
+
+    /// Event log that holds up each stored retry state, so a schedule call
+    /// is still running when identical ones arrive
+    struct SlowEventLog {
+        inner: crate::event_log::InMemoryEventLog,
+        delay: Duration,
+    }
+
+    impl EventLog for SlowEventLog {
+        fn append(&self, event: &EngineEvent) -> std::io::Result<()> {
+            if matches!(event, EngineEvent::RetryStateStored { .. }) {
+                std::thread::sleep(self.delay);
+            }
+            self.inner.append(event)
+        }
+
+        fn events(&self) -> std::io::Result<Vec<EngineEvent>> {
+            self.inner.events()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_identical_concurrent_schedules_apply_once() {
+        let log = Arc::new(SlowEventLog {
+            inner: crate::event_log::InMemoryEventLog::new(),
+            delay: Duration::from_millis(200),
+        });
+        let service = Arc::new(service().with_event_log(log.clone()));
+        let barrier = Arc::new(tokio::sync::Barrier::new(16));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..16 {
+            let service = service.clone();
+            let barrier = barrier.clone();
+            tasks.spawn(async move {
+                barrier.wait().await;
+                schedule(&service, "txn_1", "stripe", 1).await
+            });
+        }
+        let mut responses = Vec::new();
+        while let Some(response) = tasks.join_next().await {
+            responses.push(response.unwrap());
+        }
+
+        // The work ran once, and every caller got its response
+        let stored = log
+            .events()
+            .unwrap()
+            .into_iter()
+            .filter(|event| matches!(event, EngineEvent::RetryStateStored { .. }))
+            .count();
+        assert_eq!(stored, 1);
+        assert_eq!(service.retries_scheduled.get("stripe"), 1);
+        assert!(responses[0].scheduled);
+        assert!(responses.iter().all(|response| *response == responses[0]));
+
+        let states = service.retry_states.lock().unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states["txn_1"].attempt_count, 1);
+        assert_eq!(service.in_flight.get("stripe").peak, 1);
+        assert_eq!(service.schedule_calls.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_limit_requires_force() {
+        let service = service().with_max_replays(2);
+        dead_letter(&service, "txn_1", "stripe");
+        let replay = |force: bool| {
+            service.replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                target_psp: String::new(),
+                force,
+                ..Default::default()
+            }))
+        };
+
+        for replay_count in 1..=2 {
+            let response = replay(false).await.unwrap().into_inner();
+            assert!(response.replayed);
+            assert_eq!(
+                service.dlq.get_entry("txn_1").unwrap().replay_count,
+                replay_count
+            );
+
+            // Exhausting the retries again dead-letters it with the count intact
+            let exhausted = exhaust(&service, "txn_1", "stripe").await;
+            assert!(!exhausted.scheduled);
+            let entry = service.dlq.get_entry("txn_1").unwrap();
+            assert!(!entry.replaying);
+            assert_eq!(entry.replay_count, replay_count);
+        }
+
+        let limited = replay(false).await.unwrap().into_inner();
+        assert!(!limited.replayed);
+        assert!(limited.message.contains("Replay limit reached"));
+        assert!(!service.dlq.get_entry("txn_1").unwrap().replaying);
+
+        let forced = replay(true).await.unwrap().into_inner();
+        assert!(forced.replayed);
+        assert_eq!(service.dlq.get_entry("txn_1").unwrap().replay_count, 3);
+
+        // Without a limit, the default, replays never need force
+        let unlimited = no_jitter_service();
+        dead_letter(&unlimited, "txn_1", "stripe");
+        for _ in 0..5 {
+            let replayed = unlimited
+                .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                    transaction_id: "txn_1".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(replayed.replayed, "{}", replayed.message);
+            exhaust(&unlimited, "txn_1", "stripe").await;
+        }
+        assert_eq!(unlimited.dlq.get_entry("txn_1").unwrap().replay_count, 5);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_expose_failure_interval_histogram() {
+        let service = service()
+            .with_failure_interval_buckets(vec![60.0, 10.0])
+            .unwrap();
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold: 100,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        service
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .insert("stripe".to_string(), breaker.clone());
+
+        breaker.record_failure();
+        for interval_ms in [2_000, 30_000] {
+            clock.advance(interval_ms);
+            breaker.record_failure();
+        }
+
+        let text = service
+            .get_metrics(Request::new(MetricsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .text;
+        assert!(text.contains("# TYPE psp_failure_interval_seconds histogram\n"));
+        assert!(text.contains("psp_failure_interval_seconds_bucket{psp=\"stripe\",le=\"10\"} 1\n"));
+        assert!(text.contains("psp_failure_interval_seconds_bucket{psp=\"stripe\",le=\"60\"} 2\n"));
+        assert!(
+            text.contains("psp_failure_interval_seconds_bucket{psp=\"stripe\",le=\"+Inf\"} 2\n")
+        );
+        assert!(text.contains("psp_failure_interval_seconds_sum{psp=\"stripe\"} 32\n"));
+        assert!(text.contains("psp_failure_interval_seconds_count{psp=\"stripe\"} 2\n"));
+
+        assert!(no_jitter_service()
+            .with_failure_interval_buckets(vec![f64::NAN])
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_expose_transaction_lock_stripes() {
+        let service = service();
+        for i in 0..10 {
+            schedule(&service, &format!("txn_{}", i), "stripe", 1).await;
+        }
+        let stats = service.transaction_locks.stats();
+        assert!(stats.iter().map(|stats| stats.acquisitions).sum::<u64>() >= 10);
+
+        let text = service
+            .get_metrics(Request::new(MetricsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .text;
+        assert!(text.contains("# TYPE transaction_lock_acquisitions_total counter\n"));
+        assert!(text.contains("# TYPE transaction_lock_contended_total counter\n"));
+        for (stripe, stats) in stats.iter().enumerate() {
+            assert!(text.contains(&format!(
+                "transaction_lock_acquisitions_total{{stripe=\"{}\"}} {}\n",
+                stripe, stats.acquisitions
+            )));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_parked_entries_skip_bulk_replay_and_purge() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        dead_letter(&service, "txn_2", "stripe");
+
+        let parked = service
+            .park_dlq_entry(Request::new(ParkDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                note: "billing address truncated by merchant".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(parked.parked_note, "billing address truncated by merchant");
+
+        let listed = service
+            .list_parked_entries(Request::new(ListParkedEntriesRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(listed.entries.len(), 1);
+        assert_eq!(listed.entries[0].transaction_id, "txn_1");
+
+        let replayed = service
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replayed.replayed_transaction_ids, vec!["txn_2"]);
+        assert_eq!(replayed.skipped_count, 0);
+
+        let single = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!single.replayed);
+        assert!(single.message.contains("parked"));
+
+        let purged = service
+            .purge_dlq(Request::new(PurgeDlqRequest {
+                psp_name: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(purged.purged_transaction_ids, vec!["txn_2"]);
+        assert!(service.dlq.contains("txn_1"));
+
+        let unparked = service
+            .unpark_dlq_entry(Request::new(UnparkDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(unparked.parked_note.is_empty());
+        assert!(service
+            .list_parked_entries(Request::new(ListParkedEntriesRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .entries
+            .is_empty());
+
+        let replayed = service
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(replayed.replayed_transaction_ids, vec!["txn_1"]);
+    }
+
+    #[tokio::test]
+    async fn test_park_requires_a_note() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+
+        let status = service
+            .park_dlq_entry(Request::new(ParkDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                note: "  ".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+        let status = service
+            .park_dlq_entry(Request::new(ParkDlqEntryRequest {
+                transaction_id: "missing".to_string(),
+                note: "bad data".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    }
+
+    #[test]
+    fn test_grpc_timeout_header_parsing() {
+        let deadline = |value: &str| {
+            let mut metadata = MetadataMap::new();
+            metadata.insert("grpc-timeout", value.parse().unwrap());
+            grpc_deadline_ms(&metadata, 1_000)
+        };
+        assert_eq!(deadline("2H"), Some(1_000 + 7_200_000));
+        assert_eq!(deadline("3M"), Some(1_000 + 180_000));
+        assert_eq!(deadline("5S"), Some(6_000));
+        assert_eq!(deadline("250m"), Some(1_250));
+        assert_eq!(deadline("2500u"), Some(1_002));
+        assert_eq!(deadline("3000000n"), Some(1_003));
+        assert_eq!(deadline("10x"), None);
+        assert_eq!(deadline("S"), None);
+        assert_eq!(grpc_deadline_ms(&MetadataMap::new(), 1_000), None);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_declines_past_client_deadline() {
+        let service = no_jitter_service();
+        let request = |transaction_id: &str, grpc_timeout: &str, deadline_ms: i64| {
+            let mut request = Request::new(RetryRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number: 1,
+                deadline_ms,
+                ..Default::default()
+            });
+            request
+                .metadata_mut()
+                .insert("grpc-timeout", grpc_timeout.parse().unwrap());
+            request
+        };
+
+        // The first retry is due in 1s, after the client's 200ms deadline
+        let declined = service
+            .schedule_retry(request("txn_1", "200m", 0))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!declined.scheduled);
+        assert!(declined.message.contains("Deadline would be exceeded"));
+        assert!(!service.retry_states.lock().unwrap().contains_key("txn_1"));
+
+        // A generous gRPC deadline still loses to a tighter explicit one
+        let soon = current_timestamp_ms() as i64 + 200;
+        let declined = service
+            .schedule_retry(request("txn_2", "1H", soon))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!declined.scheduled);
+
+        let later = current_timestamp_ms() as i64 + 60_000;
+        let scheduled = service
+            .schedule_retry(request("txn_3", "1H", later))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(scheduled.scheduled);
+
+        let negative = service
+            .schedule_retry(request("txn_4", "1H", -1))
+            .await
+            .unwrap_err();
+        assert_eq!(negative.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_batch_circuit_status_reports_unknown_psps_without_creating_them() {
+        let service = service();
+        open_circuit(&service, "stripe");
+        service.get_or_create_circuit_breaker("adyen").unwrap();
+
+        let circuits = service
+            .batch_get_circuit_status(Request::new(BatchGetCircuitStatusRequest {
+                psp_names: vec![
+                    "stripe".to_string(),
+                    "unknown_psp".to_string(),
+                    "adyen".to_string(),
+                ],
+            }))
+            .await
+            .unwrap()
+            .into_inner()
+            .circuits;
+
+        let names: Vec<&str> = circuits.iter().map(|c| c.psp_name.as_str()).collect();
+        assert_eq!(names, vec!["adyen", "stripe", "unknown_psp"]);
+
+        assert_eq!(circuits[0].state, ProtoCircuitState::Closed as i32);
+        assert!(!circuits[0].never_seen);
+
+        assert_eq!(circuits[1].state, ProtoCircuitState::Open as i32);
+        assert_eq!(circuits[1].failure_count, 5);
+        assert!(!circuits[1].never_seen);
+
+        assert_eq!(circuits[2].state, ProtoCircuitState::Closed as i32);
+        assert_eq!(circuits[2].failure_count, 0);
+        assert!(circuits[2].never_seen);
+
+        assert!(service.get_circuit_breaker("unknown_psp").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_status_reports_give_up_time() {
+        let svc = no_jitter_service();
+        let scheduled = schedule(&svc, "txn_give_up", "stripe", 1).await;
+
+        let status = svc
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_give_up".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        // Attempts 3, 4 and 5 follow attempt 2 after 2s, 4s and 8s
+        assert_eq!(status.give_up_at_ms, scheduled.next_retry_at_ms + 14_000);
+
+        let missing = svc
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_unknown".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(missing.give_up_at_ms, 0);
+    }
+
+    #[tokio::test]
+    async fn test_blank_identifiers_rejected_unless_allowed() {
+        let strict = service();
+        for (transaction_id, psp_name, field) in [
+            ("", "stripe", "transaction_id"),
+            ("txn_blank", "  ", "psp_name"),
+        ] {
+            let err = strict
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    psp_name: psp_name.to_string(),
+                    attempt_number: 1,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+            assert!(err.message().contains(field));
+        }
+        let err = strict
+            .report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: " ".to_string(),
+                success: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        // Nothing was keyed on the blank PSP
+        assert!(strict.get_circuit_breaker("  ").is_none());
+
+        let lenient = service().with_blank_ids(BlankIds::Allow);
+        let response = schedule(&lenient, "", "", 1).await;
+        assert!(response.scheduled);
+        assert!(lenient.get_circuit_breaker("").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_retry_timeline_follows_projected_schedule() {
+        let svc = no_jitter_service();
+        schedule(&svc, "txn_timeline", "stripe", 1).await;
+        let scheduled = schedule(&svc, "txn_timeline", "stripe", 2).await;
+
+        let timeline = svc
+            .get_retry_timeline(Request::new(RetryTimelineRequest {
+                transaction_id: "txn_timeline".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(timeline.psp_name, "stripe");
+        assert!(!timeline.estimated);
+        // Attempt 3 is pending; 4 and 5 follow it after 4s and 8s
+        let due = scheduled.next_retry_at_ms;
+        let attempts: Vec<(i32, i64)> = timeline
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.attempt_number, attempt.due_at_ms))
+            .collect();
+        assert_eq!(
+            attempts,
+            vec![(3, due), (4, due + 4_000), (5, due + 12_000)]
+        );
+        let status = svc
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_timeline".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.give_up_at_ms, due + 12_000);
+
+        let jittered = service();
+        schedule(&jittered, "txn_jittered", "stripe", 1).await;
+        let timeline = jittered
+            .get_retry_timeline(Request::new(RetryTimelineRequest {
+                transaction_id: "txn_jittered".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(timeline.estimated);
+        assert_eq!(timeline.attempts.len(), 4);
+
+        let missing = svc
+            .get_retry_timeline(Request::new(RetryTimelineRequest {
+                transaction_id: "txn_unknown".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_max_elapsed_dead_letters_with_attempts_left() {
+        let svc = RetryEngineService::new(
+            RetryConfig {
+                jitter: false,
+                max_elapsed_ms: 2500,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+        assert!(schedule(&svc, "txn_slow", "stripe", 1).await.scheduled);
+        assert!(schedule(&svc, "txn_slow", "stripe", 2).await.scheduled);
+
+        // Attempt 3 would be due 4s after the first scheduled retry
+        let response = schedule(&svc, "txn_slow", "stripe", 3).await;
+        assert!(!response.scheduled);
+        assert_eq!(response.message, "Max retry time exceeded, moved to DLQ");
+        let entry = svc.dlq.get_entry("txn_slow").unwrap();
+        assert_eq!(entry.last_error, "Max retry time exceeded");
+        assert_eq!(entry.attempt_count, 3);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_canary_records_candidate_openings() {
+        let svc = service();
+        let candidate = ProtoCircuitBreakerConfig {
+            failure_threshold: 2,
+            success_threshold: 1,
+            timeout_duration_ms: 0,
+            ..Default::default()
+        };
+        let started = svc
+            .set_circuit_canary(Request::new(SetCircuitCanaryRequest {
+                psp_name: "stripe".to_string(),
+                config: Some(candidate),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(started.active);
+        assert_eq!(started.would_have_opened, 0);
+
+        // Below the live threshold of 5, but the candidate opens at 2 and its
+        // zero timeout lets every later failure reopen it from half-open
+        let breaker = svc.get_or_create_circuit_breaker("stripe").unwrap().1;
+        for _ in 0..4 {
+            assert!(svc.circuit_allows("stripe").unwrap());
+            breaker.record_failure();
+        }
+
+        let canary = svc
+            .get_circuit_canary(Request::new(CircuitCanaryRequest {
+                psp_name: "stripe".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(canary.active);
+        assert_eq!(canary.would_have_opened, 3);
+        assert_eq!(canary.config.unwrap().failure_threshold, 2);
+        assert_eq!(
+            canary.candidate.unwrap().state,
+            ProtoCircuitState::Open as i32
+        );
+        let live = breaker.get_state();
+        assert_eq!(live.state, CircuitState::Closed);
+        assert_eq!(live.failure_count, 4);
+
+        let stopped = svc
+            .set_circuit_canary(Request::new(SetCircuitCanaryRequest {
+                psp_name: "stripe".to_string(),
+                config: None,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!stopped.active);
+        let unknown = svc
+            .get_circuit_canary(Request::new(CircuitCanaryRequest {
+                psp_name: "adyen".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!unknown.active);
+    }
+
+    #[tokio::test]
+    async fn test_lowering_max_attempts_sweeps_stranded_retries() {
+        let svc = no_jitter_service();
+        assert!(schedule(&svc, "txn_late", "stripe", 3).await.scheduled);
+        assert!(schedule(&svc, "txn_early", "stripe", 1).await.scheduled);
+
+        let response = svc
+            .set_retry_config(Request::new(SetRetryConfigRequest {
+                config: Some(ProtoRetryConfig {
+                    max_attempts: 3,
+                    initial_delay_ms: 1000,
+                    max_delay_ms: 60000,
+                    backoff_multiplier: 2.0,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.dead_lettered, vec!["txn_late".to_string()]);
+        assert_eq!(response.config.unwrap().max_attempts, 3);
+
+        let entry = svc.dlq.get_entry("txn_late").unwrap();
+        assert_eq!(entry.attempt_count, 3);
+        assert_eq!(entry.last_error, "Max retry attempts lowered to 3");
+        {
+            let states = svc.retry_states.lock().unwrap();
+            assert!(!states.contains_key("txn_late"));
+            assert!(states.contains_key("txn_early"));
+        }
+
+        let invalid = svc
+            .set_retry_config(Request::new(SetRetryConfigRequest {
+                config: Some(ProtoRetryConfig {
+                    backoff_multiplier: 0.5,
+                    ..Default::default()
+                }),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(invalid.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_dead_lettered_schedule_soft_or_error() {
+        let svc = service();
+        dead_letter(&svc, "txn_dead", "stripe");
+
+        let soft = schedule(&svc, "txn_dead", "stripe", 1).await;
+        assert!(!soft.scheduled);
+        assert_eq!(soft.message, "Transaction already in dead letter queue");
+
+        let err = svc
+            .schedule_retry(Request::new(RetryRequest {
+                transaction_id: "txn_dead".to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number: 1,
+                error_if_dead_lettered: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), tonic::Code::AlreadyExists);
+
+        // Only dead-lettered transactions are turned into errors
+        let live = svc
+            .schedule_retry(Request::new(RetryRequest {
+                transaction_id: "txn_live".to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number: 1,
+                error_if_dead_lettered: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(live.scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_load_factor_stretches_scheduled_delay() {
+        let svc = no_jitter_service();
+        svc.set_load_factor(0.5);
+        assert_eq!(svc.load_factor(), 0.5);
+
+        let response = evaluate(&svc, "txn_loaded", "stripe", 1).await;
+        assert_eq!(response.delay_ms, 1500);
+
+        svc.set_load_factor(f64::NAN);
+        assert_eq!(svc.load_factor(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_list_circuits_sorted_by_psp() {
+        let svc = service();
+        for psp_name in ["worldpay", "adyen", "stripe", "braintree", "checkout"] {
+            svc.get_or_create_circuit_breaker(psp_name).unwrap();
+        }
+        open_circuit(&svc, "stripe");
+
+        let circuits = svc
+            .list_circuits(Request::new(ListCircuitsRequest {}))
+            .await
+            .unwrap()
+            .into_inner()
+            .circuits;
+
+        let names: Vec<&str> = circuits.iter().map(|c| c.psp_name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["adyen", "braintree", "checkout", "stripe", "worldpay"]
+        );
+        assert_eq!(circuits[3].state, ProtoCircuitState::Open as i32);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_replay_spreads_attempts_over_window() {
+        let svc = service();
+        for i in 0..10 {
+            dead_letter(&svc, &format!("txn_{}", i), "stripe");
+        }
+
+        let before = current_timestamp_ms() as i64;
+        let response = svc
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                spread_over_ms: 60_000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let after = current_timestamp_ms() as i64;
+
+        assert_eq!(response.replayed_count, 10);
+        let mut due: Vec<i64> = response
+            .schedule
+            .iter()
+            .map(|scheduled| scheduled.next_retry_at_ms)
+            .collect();
+        due.sort();
+        // One due in each 6s slot of the minute
+        for (slot, due_at) in due.iter().enumerate() {
+            let offset = slot as i64 * 6_000;
+            assert!(*due_at >= before + offset, "{:?}", due);
+            assert!(*due_at < after + offset + 6_000, "{:?}", due);
+        }
+
+        let states = svc.retry_states.lock().unwrap();
+        for scheduled in &response.schedule {
+            let state = &states[&scheduled.transaction_id];
+            assert_eq!(state.next_retry_at_ms as i64, scheduled.next_retry_at_ms);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bulk_replay_spread_follows_the_injected_rng() {
+        use crate::clock::ManualClock;
+        use crate::retry_policy::RngJitter;
+        use rand::SeedableRng;
+
+        const NOW_MS: u64 = 1_704_067_200_000;
+
+        let replay_offsets = || async {
+            let svc = service()
+                .with_clock(Arc::new(ManualClock::new(NOW_MS)))
+                .with_jitter_rng(Box::new(RngJitter(rand::rngs::StdRng::seed_from_u64(11))));
+            for i in 0..10 {
+                dead_letter(&svc, &format!("txn_{}", i), "stripe");
+            }
+            let response = svc
+                .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                    spread_over_ms: 60_000,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            response
+                .schedule
+                .iter()
+                .map(|scheduled| scheduled.next_retry_at_ms - NOW_MS as i64)
+                .collect::<Vec<_>>()
+        };
+
+        // Each offset lies in its own slot, and a seed lands it at the same
+        // point of that slot
+        let offsets = replay_offsets().await;
+        assert_eq!(offsets.len(), 10);
+        for (slot, offset) in offsets.iter().enumerate() {
+            let start = slot as i64 * 6_000;
+            assert!((start..start + 6_000).contains(offset), "{:?}", offsets);
+        }
+        assert_eq!(offsets, replay_offsets().await);
+    }
+
+    #[tokio::test]
+    async fn test_effective_psp_config_reports_override_or_global() {
+        let mut overrides = HashMap::new();
+        overrides.insert(
+            "stripe".to_string(),
+            CircuitBreakerConfig {
+                failure_threshold: 10,
+                ..Default::default()
+            },
+        );
+        let svc = no_jitter_service().with_circuit_overrides(overrides);
+
+        let effective = |psp_name: &str| {
+            svc.get_effective_psp_config(Request::new(EffectivePspConfigRequest {
+                psp_name: psp_name.to_string(),
+            }))
+        };
+        let stripe = effective("stripe").await.unwrap().into_inner();
+        assert!(stripe.circuit_overridden);
+        assert_eq!(stripe.circuit_breaker.unwrap().failure_threshold, 10);
+        assert!(!stripe.retry.unwrap().jitter);
+
+        let adyen = effective("adyen").await.unwrap().into_inner();
+        assert!(!adyen.circuit_overridden);
+        assert_eq!(adyen.circuit_breaker.unwrap().failure_threshold, 5);
+        assert_eq!(adyen.retry.unwrap().max_attempts, 5);
+        assert_eq!(adyen.next_reset_at_ms, 0);
+    }
+
+    #[tokio::test]
+    async fn test_dlq_wal_recovers_changes_after_checkpoint() {
+        use crate::persistence::SerializationFormat;
+
+        let dir = std::env::temp_dir().join(format!("wal-service-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let wal = || {
+            DlqWal::new(
+                DlqStore::new(dir.join("dlq"), SerializationFormat::Json),
+                dir.join("dlq.wal"),
+                Duration::ZERO,
+            )
+        };
+
+        let svc = service()
+            .with_dlq_wal(wal(), PersistenceMode::Strict)
+            .unwrap();
+        schedule(&svc, "txn_1", "stripe", 10).await;
+        schedule(&svc, "txn_2", "stripe", 10).await;
+        svc.checkpoint_dlq().unwrap();
+
+        // Past the checkpoint: a new entry, a replay and a purge
+        schedule(&svc, "txn_3", "stripe", 10).await;
+        svc.replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+            transaction_id: "txn_1".to_string(),
+            ..Default::default()
+        }))
+        .await
+        .unwrap();
+        svc.update_dlq_entry_status(Request::new(UpdateDlqEntryStatusRequest {
+            transaction_id: "txn_2".to_string(),
+            status: ProtoDlqEntryStatus::Discarded as i32,
+        }))
+        .await
+        .unwrap();
+        let expected = {
+            let mut entries = svc.dlq.get_all_entries();
+            entries.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+            serde_json::to_value(entries).unwrap()
+        };
+        assert!(std::fs::metadata(dir.join("dlq.wal")).unwrap().len() > 0);
+        // Crash: no persist_dlq
+        drop(svc);
+
+        let recovered = service()
+            .with_dlq_wal(wal(), PersistenceMode::Strict)
+            .unwrap();
+        let mut entries = recovered.dlq.get_all_entries();
+        entries.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+        assert_eq!(serde_json::to_value(entries).unwrap(), expected);
+        assert_eq!(recovered.dlq.get_entry("txn_1").unwrap().replay_count, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A WAL file whose first `failures` writes fail
+    struct FailingWalFile {
+        file: std::fs::File,
+        failures: usize,
+    }
+
+    impl std::io::Write for FailingWalFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(std::io::Error::other("disk hiccup"));
+            }
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl crate::wal::WalFile for FailingWalFile {
+        fn sync_data(&self) -> std::io::Result<()> {
+            self.file.sync_data()
+        }
+
+        fn set_len(&self, size: u64) -> std::io::Result<()> {
+            self.file.set_len(size)
+        }
+
+        fn size(&self) -> std::io::Result<u64> {
+            Ok(self.file.metadata()?.len())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_wal_write_is_retried_in_the_background() {
+        use crate::persistence::SerializationFormat;
+
+        let dir = std::env::temp_dir().join(format!("wal-service-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let wal = || {
+            DlqWal::new(
+                DlqStore::new(dir.join("dlq"), SerializationFormat::Json),
+                dir.join("dlq.wal"),
+                Duration::ZERO,
+            )
+        };
+        let svc = service()
+            .with_dlq_wal(wal(), PersistenceMode::Strict)
+            .unwrap();
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("dlq.wal"))
+            .unwrap();
+        svc.dlq_wal
+            .as_ref()
+            .unwrap()
+            .open_with(Box::new(FailingWalFile { file, failures: 2 }))
+            .unwrap();
+
+        // The change is held in memory and the caller isn't kept waiting
+        dead_letter(&svc, "txn_1", "stripe");
+        svc.log_dlq_changes();
+        assert!(svc.persistence_degraded.load(Ordering::SeqCst));
+        assert!(wal().recover().unwrap().is_empty());
+
+        // The background retry gets it on disk on the third attempt
+        for _ in 0..100 {
+            if !svc.persistence_degraded.load(Ordering::SeqCst) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(!svc.persistence_degraded.load(Ordering::SeqCst));
+        let recovered = wal().recover().unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].transaction_id, "txn_1");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reported_success_clears_retry_and_records_success() {
+        let service = service();
+        let breaker = service.get_or_create_circuit_breaker("stripe").unwrap().1;
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+
+        let report = |attempt_number, success| {
+            service.report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: "txn_1".to_string(),
+                psp_name: String::new(),
+                attempt_number,
+                success,
+                latency_ms: 180,
+            }))
+        };
+
+        // A success for the attempt that already failed is stale
+        let stale = report(1, true).await.unwrap().into_inner();
+        assert!(!stale.retry_state_cleared);
+        assert!(service.retry_states.lock().unwrap().contains_key("txn_1"));
+
+        let response = report(2, true).await.unwrap().into_inner();
+        assert_eq!(response.psp_name, "stripe");
+        assert!(response.retry_state_cleared);
+        assert!(!service.retry_states.lock().unwrap().contains_key("txn_1"));
+        // A success on a closed circuit clears its failures
+        assert_eq!(breaker.get_state().failure_count, 0);
+        assert_eq!(response.circuit.unwrap().failure_count, 0);
+        assert_eq!(service.outcomes.get("stripe").successes, 2);
+
+        // Nothing pending any more, so the PSP must be named
+        let status = report(2, false).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let metrics = service
+            .get_metrics(Request::new(MetricsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .text;
+        assert!(
+            metrics.contains("psp_reported_outcomes_total{psp=\"stripe\",outcome=\"success\"} 2")
+        );
+        assert!(
+            metrics.contains("psp_reported_latency_seconds_bucket{psp=\"stripe\",le=\"0.25\"} 2")
+        );
+    }
+
+    async fn set_psp_access(service: &RetryEngineService, policy: ProtoPspAccessPolicy) {
+        service
+            .set_psp_access_policy(Request::new(SetPspAccessPolicyRequest {
+                policy: Some(policy),
+            }))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_denylisted_psp_is_blocked() {
+        let service = service();
+        set_psp_access(
+            &service,
+            ProtoPspAccessPolicy {
+                denylist: vec!["sanctioned".to_string()],
+                ..Default::default()
+            },
+        )
+        .await;
+
+        // Even an exhausted transaction never reaches the breaker or DLQ
+        let response = schedule(&service, "txn_1", "sanctioned", 10).await;
+        assert!(!response.scheduled);
+        assert_eq!(response.message, "PSP blocked by policy: sanctioned");
+        assert!(service.get_circuit_breaker("sanctioned").is_none());
+        assert_eq!(service.dlq.count(), 0);
+        assert!(
+            !evaluate(&service, "txn_1", "sanctioned", 1)
+                .await
+                .retry_allowed
+        );
+
+        assert!(schedule(&service, "txn_2", "stripe", 1).await.scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_excludes_unlisted_psp() {
+        let service = service();
+        assert!(schedule(&service, "txn_1", "adyen", 1).await.scheduled);
+
+        set_psp_access(
+            &service,
+            ProtoPspAccessPolicy {
+                allowlist_enabled: true,
+                allowlist: vec!["stripe".to_string()],
+                denylist: vec![],
+            },
+        )
+        .await;
+
+        let response = schedule(&service, "txn_1", "adyen", 2).await;
+        assert!(!response.scheduled);
+        assert_eq!(response.message, "PSP blocked by policy: adyen");
+        assert!(schedule(&service, "txn_2", "stripe", 1).await.scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_soft_dlq_replays_after_cooldown_once_circuit_closes() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = service().with_clock(clock.clone()).with_soft_dlq(60_000);
+        schedule(&service, "txn_1", "stripe", 5).await;
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert_eq!(entry.retry_after_ms, 1_060_000);
+        open_circuit(&service, "stripe");
+
+        // Cooling down, then waiting on the open circuit
+        assert!(service.replay_due_soft_dlq().is_empty());
+        clock.advance(60_000);
+        assert!(service.replay_due_soft_dlq().is_empty());
+
+        service
+            .get_or_create_circuit_breaker("stripe")
+            .unwrap()
+            .1
+            .reset();
+        assert_eq!(service.replay_due_soft_dlq(), vec!["txn_1".to_string()]);
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert!(entry.replaying);
+        assert_eq!(entry.replay_count, 1);
+        assert_eq!(
+            service.retry_states.lock().unwrap()["txn_1"].attempt_count,
+            0
+        );
+        // Already back in the pipeline
+        assert!(service.replay_due_soft_dlq().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_project_retry_load_matches_hand_computed_schedule() {
+        let service = RetryEngineService::new(
+            RetryConfig {
+                max_attempts: 4,
+                jitter: false,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+        let project = |bucket_ms| {
+            service.project_retry_load(Request::new(ProjectRetryLoadRequest {
+                psp_name: "stripe".to_string(),
+                transaction_count: 10,
+                bucket_ms,
+            }))
+        };
+
+        // Delays of 1s, 2s and 4s put the retries 1s, 3s and 7s after the failure
+        let projection = project(2_000).await.unwrap().into_inner();
+        let from = projection.projected_from_ms;
+        assert_eq!(projection.total_attempts, 30);
+        assert_eq!(projection.schedule_end_ms, from + 7_000);
+        let buckets: Vec<(i64, i64)> = projection
+            .buckets
+            .iter()
+            .map(|bucket| (bucket.start_ms - from, bucket.attempts))
+            .collect();
+        assert_eq!(buckets, vec![(0, 10), (2_000, 10), (4_000, 0), (6_000, 10)]);
+        assert_eq!(service.retry_states.lock().unwrap().len(), 0);
+
+        let status = project(1).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_racing_exhausted_attempts_dead_letter_once() {
+        let service = Arc::new(service());
+
+        for i in 0..20 {
+            let transaction_id = format!("txn_{}", i);
+            let barrier = Arc::new(tokio::sync::Barrier::new(2));
+            // Different attempts, so single-flight doesn't coalesce them
+            let mut tasks = tokio::task::JoinSet::new();
+            for attempt in [5, 6] {
+                let service = service.clone();
+                let barrier = barrier.clone();
+                let transaction_id = transaction_id.clone();
+                tasks.spawn(async move {
+                    barrier.wait().await;
+                    let response = schedule(&service, &transaction_id, "stripe", attempt).await;
+                    (attempt, response)
+                });
+            }
+
+            let mut moved = Vec::new();
+            while let Some(result) = tasks.join_next().await {
+                let (attempt, response) = result.unwrap();
+                assert!(!response.scheduled);
+                if response.message == "Max retries exceeded, moved to DLQ" {
+                    moved.push(attempt);
+                } else {
+                    assert_eq!(response.message, "Transaction already in dead letter queue");
+                }
+            }
+            assert_eq!(moved.len(), 1, "{}", transaction_id);
+            let entry = service.dlq.get_entry(&transaction_id).unwrap();
+            assert_eq!(entry.attempt_count, moved[0] as u32);
+        }
+        assert_eq!(service.dlq.count(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_dump_state_reflects_driven_state() {
+        let svc = service().with_admin_token(Some("s3cret".to_string()));
+        let dump = |token: &str| {
+            let mut request = Request::new(DumpStateRequest {});
+            if !token.is_empty() {
+                request.metadata_mut().insert(
+                    "authorization",
+                    format!("Bearer {}", token).parse().unwrap(),
+                );
+            }
+            svc.dump_state(request)
+        };
+
+        assert!(schedule(&svc, "txn_2", "stripe", 1).await.scheduled);
+        assert!(schedule(&svc, "txn_1", "adyen", 2).await.scheduled);
+        schedule(&svc, "txn_3", "stripe", 5).await;
+        dead_letter(&svc, "txn_4", "stripe");
+        svc.dlq.park("txn_4", "bad card number").unwrap();
+        open_circuit(&svc, "adyen");
+        svc.set_psp_circuit_config(
+            "adyen",
+            CircuitBreakerConfig {
+                failure_threshold: 9,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let state = dump("s3cret").await.unwrap().into_inner();
+        assert!(!state.paused);
+        assert_eq!(state.retry_config.unwrap().max_attempts, 5);
+        assert_eq!(state.retry_state_count, 2);
+        let retries: Vec<(&str, &str, i32)> = state
+            .retry_states
+            .iter()
+            .map(|r| {
+                (
+                    r.transaction_id.as_str(),
+                    r.psp_name.as_str(),
+                    r.attempt_count,
+                )
+            })
+            .collect();
+        assert_eq!(retries, vec![("txn_1", "adyen", 2), ("txn_2", "stripe", 1)]);
+        let circuits: Vec<(&str, i32)> = state
+            .circuits
+            .iter()
+            .map(|c| (c.psp_name.as_str(), c.state))
+            .collect();
+        assert_eq!(
+            circuits,
+            vec![
+                ("adyen", ProtoCircuitState::Open as i32),
+                ("stripe", ProtoCircuitState::Closed as i32)
+            ]
+        );
+        assert_eq!(state.circuit_overrides.len(), 1);
+        assert_eq!(
+            state.circuit_overrides[0]
+                .config
+                .as_ref()
+                .unwrap()
+                .failure_threshold,
+            9
+        );
+        assert_eq!(
+            state.dlq,
+            vec![DlqPspSummary {
+                psp_name: "stripe".to_string(),
+                entries: 2,
+                parked: 1,
+                replaying: 0,
+                payload_bytes: 3,
+            }]
+        );
+
+        assert_eq!(
+            dump("wrong").await.unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+        assert_eq!(
+            dump("").await.unwrap_err().code(),
+            tonic::Code::Unauthenticated
+        );
+        let locked = service()
+            .dump_state(Request::new(DumpStateRequest {}))
+            .await;
+        assert_eq!(locked.unwrap_err().code(), tonic::Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_force_open_circuit_is_admin_only() {
+        async fn force_open(
+            svc: &RetryEngineService,
+            psp_name: &str,
+            token: &str,
+        ) -> Result<Response<CircuitResponse>, Status> {
+            let mut request = Request::new(CircuitRequest {
+                psp_name: psp_name.to_string(),
+            });
+            request.metadata_mut().insert(
+                "authorization",
+                format!("Bearer {}", token).parse().unwrap(),
+            );
+            svc.force_open_circuit(request).await
+        }
+
+        let svc = service()
+            .with_admin_token(Some("s3cret".to_string()))
+            .with_max_psp_breakers(1, PspOverflow::Shared);
+        let unauthenticated = force_open(&svc, "stripe", "wrong").await.unwrap_err();
+        assert_eq!(unauthenticated.code(), tonic::Code::Unauthenticated);
+        let disabled = force_open(&service(), "stripe", "s3cret")
+            .await
+            .unwrap_err();
+        assert_eq!(disabled.code(), tonic::Code::PermissionDenied);
+        assert!(schedule(&svc, "txn_1", "stripe", 1).await.scheduled);
+
+        let circuit = force_open(&svc, "stripe", "s3cret")
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(circuit.state, ProtoCircuitState::Open as i32);
+        assert_eq!(circuit.open_reason, ProtoOpenReason::ForcedOpen as i32);
+        assert!(!schedule(&svc, "txn_2", "stripe", 1).await.scheduled);
+
+        // PSPs on the shared overflow breaker can't open it for the others
+        let shared = force_open(&svc, "adyen", "s3cret").await.unwrap_err();
+        assert_eq!(shared.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(
+            svc.get_circuit_breaker(OVERFLOW_BREAKER)
+                .unwrap()
+                .get_state()
+                .state,
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redead_lettered_replay_flags_changed_error() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        let status = || {
+            service.get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+        };
+        let replay = || {
+            service.replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                ..Default::default()
+            }))
+        };
+        let first = status().await.unwrap().into_inner();
+        assert_eq!(
+            first.failure_comparison,
+            ProtoFailureComparison::FirstFailure as i32
+        );
+        assert_eq!(first.previous_error, "");
+
+        // The replay dies the same way
+        assert!(replay().await.unwrap().into_inner().replayed);
+        exhaust(&service, "txn_1", "stripe").await;
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert_eq!(entry.compare_to_previous(), FailureComparison::SameError);
+
+        // Then differently, after a fix
+        assert!(replay().await.unwrap().into_inner().replayed);
+        service.dlq.add_entry(DLQEntry {
+            transaction_id: "txn_1".to_string(),
+            psp_name: "stripe".to_string(),
+            attempt_count: 2,
+            last_error: "Card declined".to_string(),
+            ..Default::default()
+        });
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert_eq!(entry.compare_to_previous(), FailureComparison::ErrorChanged);
+        let history: Vec<(u32, &str)> = entry
+            .previous_failures
+            .iter()
+            .map(|failure| (failure.attempt_count, failure.last_error.as_str()))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (5, "Max retry attempts exceeded"),
+                (5, "Max retry attempts exceeded")
+            ]
+        );
+
+        let changed = status().await.unwrap().into_inner();
+        assert_eq!(
+            changed.failure_comparison,
+            ProtoFailureComparison::ErrorChanged as i32
+        );
+        assert_eq!(changed.last_error, "Card declined");
+        assert_eq!(changed.previous_error, "Max retry attempts exceeded");
+    }
+
+    #[tokio::test]
+    async fn test_unreported_outcome_times_out_as_failure() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = no_jitter_service()
+            .with_clock(clock.clone())
+            .with_outcome_timeout(5_000, OutcomeTimeoutAction::Fail);
+        let failures = || {
+            service
+                .get_circuit_breaker("stripe")
+                .map_or(0, |breaker| breaker.get_state().failure_count)
+        };
+
+        // Attempt 2 is due 2000ms out, so its outcome is overdue at 7000ms
+        assert!(schedule(&service, "txn_1", "stripe", 2).await.scheduled);
+        assert!(schedule(&service, "txn_2", "stripe", 2).await.scheduled);
+        service
+            .report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: "txn_2".to_string(),
+                attempt_number: 3,
+                success: false,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(failures(), 1);
+
+        clock.advance(6_999);
+        assert!(service.expire_unreported_outcomes().is_empty());
+        clock.advance(1);
+        assert_eq!(service.expire_unreported_outcomes(), vec!["txn_1"]);
+        assert_eq!(failures(), 2);
+        // Once per attempt, and the retry stays scheduled
+        clock.advance(60_000);
+        assert!(service.expire_unreported_outcomes().is_empty());
+        assert_eq!(failures(), 2);
+        assert!(service.retry_states.lock().unwrap().contains_key("txn_1"));
+
+        let ignoring = no_jitter_service()
+            .with_clock(clock.clone())
+            .with_outcome_timeout(0, OutcomeTimeoutAction::Ignore);
+        schedule(&ignoring, "txn_1", "stripe", 1).await;
+        clock.advance(1_000);
+        assert_eq!(ignoring.expire_unreported_outcomes(), vec!["txn_1"]);
+        assert_eq!(
+            ignoring
+                .get_or_create_circuit_breaker("stripe")
+                .unwrap()
+                .1
+                .get_state()
+                .failure_count,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_late_repeated_and_stale_outcomes_are_counted_once() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = no_jitter_service()
+            .with_clock(clock.clone())
+            .with_outcome_timeout(5_000, OutcomeTimeoutAction::Fail);
+        let failures = || {
+            service
+                .get_circuit_breaker("stripe")
+                .map_or(0, |breaker| breaker.get_state().failure_count)
+        };
+        let report = |transaction_id: &str, attempt_number: i32, success: bool| {
+            service.report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: transaction_id.to_string(),
+                attempt_number,
+                success,
+                ..Default::default()
+            }))
+        };
+        let outcome_due_at_ms = |transaction_id: &str| {
+            service.retry_states.lock().unwrap()[transaction_id].outcome_due_at_ms
+        };
+
+        // A report for an earlier attempt counts, but leaves the pending
+        // attempt's deadline alone
+        schedule(&service, "txn_1", "stripe", 2).await;
+        report("txn_1", 2, false).await.unwrap();
+        assert_eq!(failures(), 1);
+        assert_eq!(outcome_due_at_ms("txn_1"), 1_007_000);
+
+        // Once timed out, a late report is only logged
+        clock.advance(7_000);
+        assert_eq!(service.expire_unreported_outcomes(), vec!["txn_1"]);
+        assert_eq!(failures(), 2);
+        report("txn_1", 3, false).await.unwrap();
+        assert_eq!(failures(), 2);
+        // As is one past its deadline that the timeout hasn't seen yet
+        schedule(&service, "txn_2", "stripe", 2).await;
+        clock.advance(7_000);
+        report("txn_2", 0, false).await.unwrap();
+        assert_eq!(failures(), 2);
+        assert_eq!(service.expire_unreported_outcomes(), vec!["txn_2"]);
+        assert_eq!(failures(), 3);
+
+        // A second report for the same attempt doesn't count again
+        schedule(&service, "txn_3", "stripe", 2).await;
+        report("txn_3", 3, false).await.unwrap();
+        report("txn_3", 3, false).await.unwrap();
+        assert_eq!(failures(), 4);
+
+        // A late success still ends the transaction's retry
+        let late = report("txn_1", 3, true).await.unwrap().into_inner();
+        assert!(late.retry_state_cleared);
+        assert_eq!(failures(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_concurrent_leases_get_distinct_entries() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = Arc::new(service().with_clock(clock.clone()));
+        for (transaction_id, timestamp_ms) in [("txn_1", 1), ("txn_2", 2), ("txn_3", 3)] {
+            service.dlq.add_entry(DLQEntry {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                timestamp_ms,
+                ..Default::default()
+            });
+        }
+        // Parked entries are never handed out
+        service.dlq.park("txn_3", "needs a new card").unwrap();
+        let lease = |service: Arc<RetryEngineService>| async move {
+            service
+                .lease_dlq_entry(Request::new(LeaseDlqEntryRequest {
+                    lease_ms: 1_000,
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        };
+
+        let (a, b) = tokio::join!(
+            tokio::spawn(lease(service.clone())),
+            tokio::spawn(lease(service.clone()))
+        );
+        let mut leased: Vec<String> = [a.unwrap(), b.unwrap()]
+            .into_iter()
+            .map(|response| {
+                assert!(response.leased);
+                assert_eq!(response.lease_expires_at_ms, 1_001_000);
+                response.entry.unwrap().transaction_id
+            })
+            .collect();
+        leased.sort();
+        assert_eq!(leased, vec!["txn_1", "txn_2"]);
+        assert!(!lease(service.clone()).await.leased);
+
+        // An expired lease puts its entry back, oldest first
+        clock.advance(999);
+        assert!(!lease(service.clone()).await.leased);
+        clock.advance(1);
+        let released = lease(service.clone()).await;
+        assert_eq!(released.entry.unwrap().transaction_id, "txn_1");
+        assert_eq!(
+            service.dlq.lease("txn_1", clock.now_ms()).unwrap().lease_id,
+            released.lease_id
+        );
+
+        let negative = service
+            .lease_dlq_entry(Request::new(LeaseDlqEntryRequest {
+                lease_ms: -1,
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(negative.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[tokio::test]
+    async fn test_leased_entry_is_only_touched_by_its_holder() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = service().with_clock(clock.clone());
+        dead_letter(&service, "txn_1", "stripe");
+        dead_letter(&service, "txn_2", "stripe");
+        dead_letter(&service, "txn_3", "stripe");
+        let lease = || async {
+            service
+                .lease_dlq_entry(Request::new(LeaseDlqEntryRequest {
+                    psp_name: "stripe".to_string(),
+                    lease_ms: 1_000,
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        };
+        let replay = |transaction_id: &str, lease_id: &str| {
+            service.replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: transaction_id.to_string(),
+                lease_id: lease_id.to_string(),
+                ..Default::default()
+            }))
+        };
+        let leased = lease().await;
+        let leased_id = leased.entry.unwrap().transaction_id;
+
+        // Nobody else replays, re-triages, parks, cancels or purges it
+        let refused = replay(&leased_id, "").await.unwrap().into_inner();
+        assert!(!refused.replayed);
+        assert!(refused.message.contains("leased"));
+        assert!(
+            !replay(&leased_id, "not-the-lease")
+                .await
+                .unwrap()
+                .into_inner()
+                .replayed
+        );
+        let status = service
+            .update_dlq_entry_status(Request::new(UpdateDlqEntryStatusRequest {
+                transaction_id: leased_id.clone(),
+                status: ProtoDlqEntryStatus::Discarded as i32,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = service
+            .park_dlq_entry(Request::new(ParkDlqEntryRequest {
+                transaction_id: leased_id.clone(),
+                note: "bad card".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let status = service
+            .cancel_retry(Request::new(CancelRetryRequest {
+                transaction_id: leased_id.clone(),
+                remove_from_dlq: true,
+                ..Default::default()
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        // Releasing takes the lease itself
+        let status = service
+            .release_dlq_lease(Request::new(ReleaseDlqLeaseRequest {
+                transaction_id: leased_id.clone(),
+                lease_id: "not-the-lease".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        service
+            .release_dlq_lease(Request::new(ReleaseDlqLeaseRequest {
+                transaction_id: leased_id.clone(),
+                lease_id: leased.lease_id,
+            }))
+            .await
+            .unwrap();
+        assert!(service.dlq.lease(&leased_id, clock.now_ms()).is_none());
+
+        // The holder completes one lease and replays another, ending both
+        let first = lease().await;
+        let completed = service
+            .complete_dlq_lease(Request::new(CompleteDlqLeaseRequest {
+                transaction_id: first.entry.as_ref().unwrap().transaction_id.clone(),
+                lease_id: first.lease_id.clone(),
+                status: ProtoDlqEntryStatus::Resolved as i32,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(completed.status, ProtoDlqEntryStatus::Resolved as i32);
+        let status = service
+            .complete_dlq_lease(Request::new(CompleteDlqLeaseRequest {
+                transaction_id: completed.transaction_id.clone(),
+                lease_id: first.lease_id,
+                status: ProtoDlqEntryStatus::Discarded as i32,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+        let second = lease().await;
+        let second_id = second.entry.unwrap().transaction_id;
+        assert!(
+            replay(&second_id, &second.lease_id)
+                .await
+                .unwrap()
+                .into_inner()
+                .replayed
+        );
+        assert!(service.dlq.lease(&second_id, clock.now_ms()).is_none());
+
+        // A purge keeps the last one while it's leased
+        let third = lease().await;
+        let third_id = third.entry.unwrap().transaction_id;
+        let purged = service
+            .purge_dlq(Request::new(PurgeDlqRequest {
+                psp_name: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!purged.purged_transaction_ids.contains(&third_id));
+        assert!(service.dlq.contains(&third_id));
+    }
+
+    #[tokio::test]
+    async fn test_next_probe_picks_longest_waiting_retry() {
+        let service = service();
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            timeout_duration_ms: 5_000,
+            ..Default::default()
+        })
+        .with_clock(clock.clone());
+        service
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .insert("stripe".to_string(), breaker.clone());
+        let next_probe = || async {
+            service
+                .next_probe(Request::new(NextProbeRequest {
+                    psp_name: "stripe".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        };
+
+        // Closed, so there's nothing to probe
+        for transaction_id in ["txn_1", "txn_2", "txn_3"] {
+            assert!(
+                schedule(&service, transaction_id, "stripe", 1)
+                    .await
+                    .scheduled
+            );
+        }
+        schedule(&service, "txn_other", "adyen", 1).await;
+        let closed = next_probe().await;
+        assert!(!closed.probe_due);
+        assert_eq!(closed.transaction_id, "");
+
+        // txn_2 has been waiting longest
+        service
+            .retry_states
+            .lock()
+            .unwrap()
+            .get_mut("txn_2")
+            .unwrap()
+            .first_scheduled_at_ms -= 1_000;
+        open_circuit(&service, "stripe");
+        assert!(!next_probe().await.probe_due);
+        clock.advance(5_000);
+        let due = next_probe().await;
+        assert!(due.probe_due);
+        assert_eq!(due.transaction_id, "txn_2");
+        assert_eq!(due.circuit.unwrap().state, ProtoCircuitState::Open as i32);
+
+        // Nothing waiting: the probe is due but has no transaction
+        for transaction_id in ["txn_1", "txn_2", "txn_3"] {
+            service.remove_retry_state(transaction_id);
+        }
+        let empty = next_probe().await;
+        assert!(empty.probe_due);
+        assert_eq!(empty.transaction_id, "");
+    }
+
+    #[tokio::test]
+    async fn test_skipped_attempt_numbers_keep_sequential_schedule() {
+        let sequential = no_jitter_service();
+        assert!(schedule(&sequential, "txn_1", "stripe", 1).await.scheduled);
+        // The response to attempt 2 was lost, so the client sends 3
+        let before = current_timestamp_ms() as i64;
+        let jumped = schedule(&sequential, "txn_1", "stripe", 3).await;
+        assert_eq!(jumped.message, "Retry scheduled for attempt 3");
+        let delay_ms = jumped.next_retry_at_ms - before;
+        assert!((2000..2100).contains(&delay_ms), "{}", delay_ms);
+        assert_eq!(
+            sequential.retry_states.lock().unwrap()["txn_1"].attempt_count,
+            2
+        );
+        let evaluated = evaluate(&sequential, "txn_1", "stripe", 9).await;
+        assert_eq!(evaluated.delay_ms, 4000);
+
+        let literal = RetryEngineService::new(
+            RetryConfig {
+                jitter: false,
+                attempt_numbering: AttemptNumbering::Literal,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+        schedule(&literal, "txn_1", "stripe", 1).await;
+        let jumped = schedule(&literal, "txn_1", "stripe", 3).await;
+        assert_eq!(jumped.message, "Retry scheduled for attempt 4");
+        assert_eq!(
+            literal.retry_states.lock().unwrap()["txn_1"].attempt_count,
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_score_tracks_failures_latency_and_circuit() {
+        let service = service();
+        assert_eq!(service.psp_health_score("stripe"), 100);
+
+        for _ in 0..10 {
+            service.outcomes.record("stripe", true, Some(100));
+            service.outcomes.record("adyen", true, Some(4000));
+        }
+        for i in 0..10 {
+            service.outcomes.record("worldpay", i % 2 == 0, Some(100));
+        }
+        let healthy = service.psp_health_score("stripe");
+        assert!(healthy >= 99, "fast successes scored {}", healthy);
+        // Slower or flakier PSPs score lower
+        assert!(service.psp_health_score("adyen") < healthy);
+        assert!(service.psp_health_score("worldpay") < healthy);
+
+        for i in 0..10 {
+            dead_letter(&service, &format!("txn_{}", i), "stripe");
+        }
+        let with_dead_letters = service.psp_health_score("stripe");
+        assert!(with_dead_letters < healthy);
+
+        let health = service
+            .get_psp_health(Request::new(PspHealthRequest {
+                psp_name: "stripe".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(health.health_score, with_dead_letters as i32);
+
+        open_circuit(&service, "stripe");
+        assert_eq!(service.psp_health_score("stripe"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_near_exhaustion_lists_last_attempts_only() {
+        let service = no_jitter_service();
+        for (transaction_id, psp_name, attempt_number) in [
+            ("txn_1", "stripe", 4),
+            ("txn_2", "stripe", 2),
+            ("txn_3", "adyen", 4),
+            ("txn_4", "stripe", 3),
+            ("txn_5", "stripe", 4),
+        ] {
+            schedule(&service, transaction_id, psp_name, attempt_number).await;
+        }
+        // Exhausted: dead-lettered rather than retried
+        schedule(&service, "txn_6", "stripe", 5).await;
+
+        let response = service
+            .list_near_exhaustion(Request::new(ListNearExhaustionRequest {
+                psp_name: "stripe".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let listed: Vec<(&str, i32)> = response
+            .retries
+            .iter()
+            .map(|retry| (retry.transaction_id.as_str(), retry.attempt_count))
+            .collect();
+        assert_eq!(listed, vec![("txn_1", 4), ("txn_5", 4)]);
+        assert!(response
+            .retries
+            .iter()
+            .all(|retry| retry.next_retry_at_ms > 0));
+    }
+
+    #[tokio::test]
+    async fn test_openmetrics_exemplars_link_counters_to_transactions() {
+        let service = service();
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        for attempt_number in 1..=5 {
+            let mut request = Request::new(RetryRequest {
+                transaction_id: "txn_1".to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number,
+                ..Default::default()
+            });
+            request.metadata_mut().insert(
+                "traceparent",
+                format!("00-{}-00f067aa0ba902b7-01", trace_id)
+                    .parse()
+                    .unwrap(),
+            );
+            service.schedule_retry(request).await.unwrap();
+        }
+        assert_eq!(service.retries_scheduled.get("stripe"), 4);
+        assert_eq!(service.dlq_adds.get("stripe"), 1);
+
+        let scrape =
+            |openmetrics| service.get_metrics(Request::new(MetricsRequest { openmetrics }));
+        let text = scrape(true).await.unwrap().into_inner().text;
+        let exemplar = format!(
+            "# {{transaction_id=\"txn_1\",trace_id=\"{}\"}} 1 ",
+            trace_id
+        );
+        let dlq_line = text
+            .lines()
+            .find(|line| line.starts_with("dlq_adds_total{psp=\"stripe\"} 1 "))
+            .unwrap();
+        assert!(dlq_line.contains(&exemplar), "{}", dlq_line);
+        let retries_line = text
+            .lines()
+            .find(|line| line.starts_with("retries_scheduled_total{psp=\"stripe\"} 4 "))
+            .unwrap();
+        assert!(retries_line.contains(&exemplar), "{}", retries_line);
+        assert!(text.contains("# TYPE dlq_adds counter\n"));
+        assert!(text.ends_with("# EOF\n"));
+
+        // The Prometheus format has no exemplars
+        let text = scrape(false).await.unwrap().into_inner().text;
+        assert!(text.contains("dlq_adds_total{psp=\"stripe\"} 1\n"));
+        assert!(!text.contains("transaction_id"));
+    }
+
+    #[tokio::test]
+    async fn test_validate_config_previews_lowered_max_attempts() {
+        let service = service();
+        for (transaction_id, psp_name, attempt_number) in [
+            ("txn_1", "stripe", 1),
+            ("txn_2", "stripe", 3),
+            ("txn_3", "adyen", 4),
+            ("txn_4", "adyen", 2),
+        ] {
+            schedule(&service, transaction_id, psp_name, attempt_number).await;
+        }
+        let proposed = ProtoRetryConfig {
+            max_attempts: 3,
+            ..RetryEngineService::proto_retry_config(&RetryConfig::default())
+        };
+
+        let preview = service
+            .validate_config(Request::new(ValidateConfigRequest {
+                config: Some(proposed.clone()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(preview.valid);
+        assert_eq!(
+            preview.changes,
+            vec![ConfigChange {
+                field: "max_attempts".to_string(),
+                current: "5".to_string(),
+                proposed: "3".to_string(),
+            }]
+        );
+        // The rest lose their later retries
+        assert_eq!(preview.rescheduled_psps, vec!["adyen", "stripe"]);
+        assert_eq!(preview.would_dead_letter, vec!["txn_2", "txn_3"]);
+        // Nothing was applied
+        assert_eq!(service.retry_policy().max_attempts(), 5);
+        assert_eq!(service.dlq.count(), 0);
+
+        let applied = service
+            .set_retry_config(Request::new(SetRetryConfigRequest {
+                config: Some(proposed.clone()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(preview.would_dead_letter_count, 2);
+        assert_eq!(applied.dead_lettered, preview.would_dead_letter);
+
+        let invalid = service
+            .validate_config(Request::new(ValidateConfigRequest {
+                config: Some(ProtoRetryConfig {
+                    max_delay_ms: -1,
+                    ..proposed
+                }),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!invalid.valid);
+        assert_eq!(invalid.error, "max_delay_ms must not be negative");
+        assert!(invalid.changes.is_empty());
+
+        // A config SetRetryConfig would refuse isn't reported valid either
+        let capped = no_jitter_service().with_max_attempt_number(10, AttemptOverflow::Reject);
+        let over_limit = ProtoRetryConfig {
+            max_attempts: 11,
+            ..proposed
+        };
+        let preview = capped
+            .validate_config(Request::new(ValidateConfigRequest {
+                config: Some(over_limit.clone()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!preview.valid);
+        assert_eq!(
+            preview.error,
+            "max_attempts 11 is above the highest attempt number accepted, 10"
+        );
+        let refused = capped
+            .set_retry_config(Request::new(SetRetryConfigRequest {
+                config: Some(over_limit),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.message(), preview.error);
+    }
+
+    #[tokio::test]
+    async fn test_rapid_schedule_calls_are_rate_limited_per_transaction() {
+        let clock = Arc::new(crate::clock::ManualClock::new(1_000_000));
+        let service = service()
+            .with_clock(clock.clone())
+            .with_min_schedule_interval(1_000);
+        let attempt_count = |service: &RetryEngineService| {
+            service.retry_states.lock().unwrap()["txn_1"].attempt_count
+        };
+
+        // A tight loop: only the first call in the interval is honored, and
+        // the rest don't touch the transaction, not even to dead-letter it
+        let mut honored = 0;
+        for attempt_number in 1..=10 {
+            let response = schedule(&service, "txn_1", "stripe", attempt_number).await;
+            if response.scheduled {
+                honored += 1;
+            } else {
+                assert!(response.message.starts_with("Scheduling too frequently"));
+            }
+        }
+        assert_eq!(honored, 1);
+        assert_eq!(attempt_count(&service), 1);
+        assert!(!service.is_dead_lettered("txn_1"));
+
+        // Other transactions have their own interval
+        assert!(schedule(&service, "txn_2", "stripe", 1).await.scheduled);
+
+        clock.advance(999);
+        assert!(!schedule(&service, "txn_1", "stripe", 2).await.scheduled);
+        clock.advance(1);
+        assert!(schedule(&service, "txn_1", "stripe", 2).await.scheduled);
+        assert!(!schedule(&service, "txn_1", "stripe", 3).await.scheduled);
+        assert_eq!(attempt_count(&service), 2);
+    }
+
+    #[tokio::test]
+    async fn test_payload_content_type_round_trips_through_dlq() {
+        use crate::dlq::DEFAULT_CONTENT_TYPE;
+        use crate::persistence::SerializationFormat;
+
+        let service = service();
+        for (transaction_id, content_type, encoding) in [
+            ("txn_json", "application/json", "gzip"),
+            ("txn_opaque", "", ""),
+        ] {
+            let response = service
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    psp_name: "stripe".to_string(),
+                    payload: b"{}".to_vec(),
+                    attempt_number: 10,
+                    content_type: content_type.to_string(),
+                    encoding: encoding.to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(!response.scheduled);
+        }
+
+        let json = service.dlq.get_entry("txn_json").unwrap();
+        assert_eq!(json.content_type, "application/json");
+        assert_eq!(json.encoding.as_deref(), Some("gzip"));
+        let opaque = service.dlq.get_entry("txn_opaque").unwrap();
+        assert_eq!(opaque.content_type, DEFAULT_CONTENT_TYPE);
+        assert_eq!(opaque.encoding, None);
+
+        for format in [SerializationFormat::Json, SerializationFormat::Bincode] {
+            let path = std::env::temp_dir().join(format!("dlq-{}", uuid::Uuid::new_v4()));
+            let store = DlqStore::new(&path, format);
+            service.dlq.save_to(&store).unwrap();
+            let restored = DeadLetterQueue::new();
+            restored.load_from(&store).unwrap();
+            assert_eq!(restored.get_entry("txn_json"), Some(json.clone()));
+            assert_eq!(restored.get_entry("txn_opaque"), Some(opaque.clone()));
+            let _ = std::fs::remove_file(&path);
+        }
+
+        let listed = service
+            .list_dlq_entries(Request::new(ListDlqEntriesRequest::default()))
+            .await
+            .unwrap()
+            .into_inner();
+        let types: Vec<(&str, &str, &str)> = listed
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.transaction_id.as_str(),
+                    entry.content_type.as_str(),
+                    entry.encoding.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("txn_json", "application/json", "gzip"),
+                ("txn_opaque", DEFAULT_CONTENT_TYPE, ""),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_reports_whether_it_is_rejecting_or_idle() {
+        async fn status(service: &RetryEngineService, psp_name: &str) -> CircuitResponse {
+            service
+                .get_circuit_status(Request::new(CircuitRequest {
+                    psp_name: psp_name.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+
+        let service = service();
+        let closed = status(&service, "stripe").await;
+        assert_eq!(closed.open_activity, ProtoOpenActivity::NotOpen as i32);
+
+        open_circuit(&service, "stripe");
+        let quiet = status(&service, "stripe").await;
+        assert_eq!(quiet.open_activity, ProtoOpenActivity::CoolingDown as i32);
+        assert_eq!(quiet.rejected_count, 0);
+        assert_eq!(quiet.open_reason, ProtoOpenReason::FailureThreshold as i32);
+
+        assert!(!schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+        let rejecting = status(&service, "stripe").await;
+        assert_eq!(rejecting.open_activity, ProtoOpenActivity::Rejecting as i32);
+        assert_eq!(rejecting.rejected_count, 1);
+
+        // Timed out long ago and nothing has come along to probe since
+        let idle = CircuitBreakerState {
+            state: CircuitState::Open,
+            failure_count: 5,
+            next_attempt_at_ms: current_timestamp_ms() - 60_000,
+            ..Default::default()
+        };
+        service.circuit_breakers.lock().unwrap().insert(
+            "adyen".to_string(),
+            CircuitBreaker::with_state(CircuitBreakerConfig::default(), idle),
+        );
+        let ready = status(&service, "adyen").await;
+        assert_eq!(ready.state, ProtoCircuitState::Open as i32);
+        assert_eq!(ready.open_activity, ProtoOpenActivity::ReadyToProbe as i32);
+
+        // The next request is let through as the probe
+        assert!(schedule(&service, "txn_2", "adyen", 1).await.scheduled);
+        let probing = status(&service, "adyen").await;
+        assert_eq!(probing.state, ProtoCircuitState::HalfOpen as i32);
+        assert_eq!(probing.open_activity, ProtoOpenActivity::NotOpen as i32);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_replay_goes_oldest_first() {
+        let service = service();
+        for (transaction_id, timestamp_ms) in [
+            ("txn_c", 3_000),
+            ("txn_a", 5_000),
+            ("txn_d", 1_000),
+            ("txn_b", 3_000),
+        ] {
+            service.dlq.add_entry(DLQEntry {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                timestamp_ms,
+                ..Default::default()
+            });
+        }
+
+        let response = service
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                spread_over_ms: 4_000,
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let expected = vec!["txn_d", "txn_b", "txn_c", "txn_a"];
+        assert_eq!(response.replayed_transaction_ids, expected);
+        let scheduled: Vec<&str> = response
+            .schedule
+            .iter()
+            .map(|replay| replay.transaction_id.as_str())
+            .collect();
+        assert_eq!(scheduled, expected);
+        // The oldest entry gets the earliest slot of the spread
+        let due: Vec<i64> = response
+            .schedule
+            .iter()
+            .map(|replay| replay.next_retry_at_ms)
+            .collect();
+        assert!(due.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn test_batch_retry_status_reports_each_transaction() {
+        let service = service();
+        assert!(
+            schedule(&service, "txn_retrying", "stripe", 1)
+                .await
+                .scheduled
+        );
+        dead_letter(&service, "txn_dead", "stripe");
+
+        let transaction_ids = ["txn_dead", "txn_unknown", "txn_retrying", "txn_dead"];
+        let response = service
+            .batch_get_retry_status(Request::new(BatchGetRetryStatusRequest {
+                transaction_ids: transaction_ids.iter().map(|id| id.to_string()).collect(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        let statuses: Vec<(&str, &str)> = response
+            .statuses
+            .iter()
+            .map(|status| (status.transaction_id.as_str(), status.status.as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("txn_dead", "IN_DLQ"),
+                ("txn_unknown", "NOT_FOUND"),
+                ("txn_retrying", "RETRYING"),
+                ("txn_dead", "IN_DLQ"),
+            ]
+        );
+
+        // Each status is what GetRetryStatus returns for the transaction
+        for (transaction_id, status) in transaction_ids.iter().zip(&response.statuses) {
+            let single = service
+                .get_retry_status(Request::new(RetryStatusRequest {
+                    transaction_id: transaction_id.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(&single, status);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cancel_retry_forgets_the_transaction() {
+        async fn cancel(
+            service: &RetryEngineService,
+            transaction_id: &str,
+            remove_from_dlq: bool,
+        ) -> Result<CancelRetryResponse, Status> {
+            service
+                .cancel_retry(Request::new(CancelRetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    reason: "order cancelled".to_string(),
+                    remove_from_dlq,
+                }))
+                .await
+                .map(Response::into_inner)
+        }
+        async fn status(service: &RetryEngineService, transaction_id: &str) -> String {
+            service
+                .get_retry_status(Request::new(RetryStatusRequest {
+                    transaction_id: transaction_id.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .status
+        }
+
+        let service = service();
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+        assert_eq!(status(&service, "txn_1").await, "RETRYING");
+        let response = cancel(&service, "txn_1", false).await.unwrap();
+        assert!(response.cancelled && !response.removed_from_dlq);
+        assert_eq!(status(&service, "txn_1").await, "NOT_FOUND");
+        assert!(!cancel(&service, "txn_1", false).await.unwrap().cancelled);
+
+        // Dead letters stay unless asked for, and parked ones must be unparked
+        dead_letter(&service, "txn_2", "stripe");
+        assert!(
+            !cancel(&service, "txn_2", false)
+                .await
+                .unwrap()
+                .removed_from_dlq
+        );
+        assert_eq!(status(&service, "txn_2").await, "IN_DLQ");
+        service
+            .dlq
+            .update_entry("txn_2", |entry| entry.parked_note = Some("fix".to_string()));
+        let parked = cancel(&service, "txn_2", true).await.unwrap_err();
+        assert_eq!(parked.code(), tonic::Code::FailedPrecondition);
+        service
+            .dlq
+            .update_entry("txn_2", |entry| entry.parked_note = None);
+        assert!(
+            cancel(&service, "txn_2", true)
+                .await
+                .unwrap()
+                .removed_from_dlq
+        );
+        assert_eq!(status(&service, "txn_2").await, "NOT_FOUND");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_repairs_inconsistent_recovered_state() {
+        let service = service();
+        // Retrying and dead-lettered at once
+        assert!(schedule(&service, "txn_both", "stripe", 1).await.scheduled);
+        dead_letter(&service, "txn_both", "stripe");
+        // A replay whose retry was lost
+        service.dlq.add_entry(DLQEntry {
+            transaction_id: "txn_lost_replay".to_string(),
+            psp_name: "stripe".to_string(),
+            replaying: true,
+            ..Default::default()
+        });
+        // A consistent replay and retry, left alone
+        assert!(
+            schedule(&service, "txn_retrying", "stripe", 1)
+                .await
+                .scheduled
+        );
+        dead_letter(&service, "txn_replayed", "stripe");
+        assert!(service
+            .replay_entry(
+                &service.dlq.get_entry("txn_replayed").unwrap(),
+                None,
+                false,
+                0,
+                None,
+                ""
+            )
+            .is_ok());
+        // Open long past its timeout
+        let stale = CircuitBreakerState {
+            state: CircuitState::Open,
+            failure_count: 5,
+            next_attempt_at_ms: current_timestamp_ms() - 60_000,
+            ..Default::default()
+        };
+        service.circuit_breakers.lock().unwrap().insert(
+            "adyen".to_string(),
+            CircuitBreaker::with_state(CircuitBreakerConfig::default(), stale),
+        );
+        open_circuit(&service, "worldpay");
+
+        let report = service.reconcile();
+        assert_eq!(
+            report,
+            ReconcileReport {
+                removed_retry_states: vec!["txn_both".to_string()],
+                cleared_replaying: vec!["txn_lost_replay".to_string()],
+                half_opened_circuits: vec!["adyen".to_string()],
+            }
+        );
+        let states = service.retry_states.lock().unwrap();
+        assert!(!states.contains_key("txn_both"));
+        assert!(states.contains_key("txn_retrying") && states.contains_key("txn_replayed"));
+        drop(states);
+        assert!(service.is_dead_lettered("txn_both"));
+        assert!(service.is_dead_lettered("txn_lost_replay"));
+        assert!(service.dlq.get_entry("txn_replayed").unwrap().replaying);
+        let circuit_state = |psp_name: &str| {
+            service
+                .get_circuit_breaker(psp_name)
+                .unwrap()
+                .get_state()
+                .state
+        };
+        assert_eq!(circuit_state("adyen"), CircuitState::HalfOpen);
+        assert_eq!(circuit_state("worldpay"), CircuitState::Open);
+
+        assert!(service.reconcile().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_psp_jitter_override_changes_only_that_psps_spread() {
+        use crate::retry_policy::RngJitter;
+        use rand::SeedableRng;
+
+        let overridden = || {
+            service()
+                .with_jitter_rng(Box::new(RngJitter(rand::rngs::StdRng::seed_from_u64(7))))
+                .with_jitter_overrides(HashMap::from([
+                    (
+                        "stripe".to_string(),
+                        JitterConfig {
+                            jitter_strategy: JitterStrategy::Full,
+                            ..JitterConfig::of(&RetryConfig::default())
+                        },
+                    ),
+                    (
+                        "adyen".to_string(),
+                        JitterConfig {
+                            jitter: false,
+                            ..JitterConfig::of(&RetryConfig::default())
+                        },
+                    ),
+                ]))
+        };
+        let service = overridden();
+        let delays = |service: &RetryEngineService, psp_name: &str| -> Vec<u64> {
+            let policy = service.retry_policy_for(psp_name);
+            (0..200).map(|_| policy.calculate_delay(1)).collect()
+        };
+        let spread = |psp_name: &str| {
+            let delays = delays(&service, psp_name);
+            (*delays.iter().min().unwrap(), *delays.iter().max().unwrap())
+        };
+
+        // Full jitter draws from the whole of 0..=1000
+        let (low, high) = spread("stripe");
+        assert!(low < 500 && high > 500 && high <= 1000);
+        assert_eq!(spread("adyen"), (1000, 1000));
+        // The global ±20% jitter still applies to PSPs without an override
+        let (low, high) = spread("paypal");
+        assert!(low >= 800 && high <= 1200 && low < high);
+
+        // Every policy draws from the one injected RNG, so a seed repeats them
+        assert_eq!(
+            delays(&overridden(), "stripe"),
+            delays(&overridden(), "stripe")
+        );
+        assert!(Arc::ptr_eq(
+            &service.retry_policy_for("stripe"),
+            &service.retry_policy_for("stripe")
+        ));
+
+        let effective = |psp_name: &str| {
+            let service = &service;
+            let psp_name = psp_name.to_string();
+            async move {
+                service
+                    .get_effective_psp_config(Request::new(EffectivePspConfigRequest { psp_name }))
+                    .await
+                    .unwrap()
+                    .into_inner()
+            }
+        };
+        let stripe = effective("stripe").await;
+        assert!(stripe.jitter_overridden);
+        assert_eq!(
+            stripe.retry.unwrap().jitter_strategy,
+            ProtoJitterStrategy::FullJitter as i32
+        );
+        let adyen = effective("adyen").await;
+        assert!(adyen.jitter_overridden && !adyen.retry.unwrap().jitter);
+        let paypal = effective("paypal").await;
+        assert!(!paypal.jitter_overridden && paypal.retry.unwrap().jitter);
+    }
+
+    #[tokio::test]
+    async fn test_reset_circuit_time_in_state_keeps_the_circuit() {
+        let service = service();
+        let started_ms = current_timestamp_ms() - 60_000;
+        let tracked = CircuitBreakerState {
+            state: CircuitState::Open,
+            failure_count: 5,
+            next_attempt_at_ms: current_timestamp_ms() + 60_000,
+            state_entered_at_ms: started_ms + 50_000,
+            time_in_state: TimeInState {
+                closed_ms: 50_000,
+                ..Default::default()
+            },
+            time_in_state_since_ms: started_ms,
+            ..Default::default()
+        };
+        service.circuit_breakers.lock().unwrap().insert(
+            "stripe".to_string(),
+            CircuitBreaker::with_state(CircuitBreakerConfig::default(), tracked),
+        );
+        let request = || {
+            Request::new(CircuitRequest {
+                psp_name: "stripe".to_string(),
+            })
+        };
+
+        let before = service
+            .get_circuit_status(request())
+            .await
+            .unwrap()
+            .into_inner();
+        let time_in_state = before.time_in_state_ms.unwrap();
+        assert_eq!(time_in_state.closed_ms, 50_000);
+        assert!(time_in_state.open_ms >= 10_000);
+        assert_eq!(before.time_in_state_since_ms, started_ms as i64);
+
+        let after = service
+            .reset_circuit_time_in_state(request())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(after.state, ProtoCircuitState::Open as i32);
+        assert_eq!(after.failure_count, 5);
+        assert!(after.time_in_state_since_ms >= started_ms as i64 + 60_000);
+        let time_in_state = after.time_in_state_ms.unwrap();
+        assert_eq!(time_in_state.closed_ms, 0);
+        assert!(time_in_state.open_ms < 1_000);
+    }
+
+    #[tokio::test]
+    async fn test_force_to_dlq_dead_letters_a_retrying_transaction() {
+        async fn force(
+            service: &RetryEngineService,
+            transaction_id: &str,
+        ) -> Result<DlqEntrySummary, Status> {
+            service
+                .force_to_dlq(Request::new(ForceToDlqRequest {
+                    transaction_id: transaction_id.to_string(),
+                    reason: "card reported stolen".to_string(),
+                    payload: Vec::new(),
+                }))
+                .await
+                .map(Response::into_inner)
+        }
+
+        let service = service();
+        assert!(schedule(&service, "txn_1", "stripe", 2).await.scheduled);
+        let entry = force(&service, "txn_1").await.unwrap();
+        assert_eq!(entry.psp_name, "stripe");
+        assert_eq!(entry.attempt_count, 2);
+        assert!(entry.last_error.contains("card reported stolen"));
+
+        let status = service
+            .get_retry_status(Request::new(RetryStatusRequest {
+                transaction_id: "txn_1".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.status, "IN_DLQ");
+        assert!(service.retry_states.lock().unwrap().is_empty());
+
+        // Nothing left retrying, and unknown transactions aren't invented
+        let code = |result: Result<DlqEntrySummary, Status>| result.unwrap_err().code();
+        assert_eq!(code(force(&service, "txn_1").await), tonic::Code::NotFound);
+        assert_eq!(code(force(&service, "txn_2").await), tonic::Code::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_returns_growing_attempt_timeouts() {
+        let service = RetryEngineService::new(
+            RetryConfig {
+                attempt_timeout_ms: 1_000,
+                attempt_timeout_multiplier: 3.0,
+                max_attempt_timeout_ms: 5_000,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+        let mut timeouts = Vec::new();
+        for attempt in 1..=4 {
+            let response = schedule(&service, "txn_1", "stripe", attempt).await;
+            assert!(response.scheduled);
+            timeouts.push(response.attempt_timeout_ms);
+        }
+        assert_eq!(timeouts, vec![1_000, 3_000, 5_000, 5_000]);
+
+        // Declined calls suggest nothing
+        let exhausted = exhaust(&service, "txn_1", "stripe").await;
+        assert_eq!(exhausted.attempt_timeout_ms, 0);
+        let fixed = schedule(&no_jitter_service(), "txn_2", "stripe", 3).await;
+        assert_eq!(
+            fixed.attempt_timeout_ms,
+            crate::DEFAULT_ATTEMPT_TIMEOUT_MS as i64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_every_dlq_path_records_its_reason() {
+        async fn reason(service: &RetryEngineService, transaction_id: &str) -> i32 {
+            let status = service
+                .get_retry_status(Request::new(RetryStatusRequest {
+                    transaction_id: transaction_id.to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(status.in_dlq);
+            let summary = RetryEngineService::dlq_entry_summary(
+                &service.dlq.get_entry(transaction_id).unwrap(),
+            );
+            assert_eq!(summary.reason, status.dlq_reason);
+            status.dlq_reason
+        }
+
+        let service = no_jitter_service();
+        exhaust(&service, "txn_exhausted", "stripe").await;
+        assert_eq!(
+            reason(&service, "txn_exhausted").await,
+            ProtoDlqReason::MaxAttemptsExceeded as i32
+        );
+
+        assert!(
+            schedule(&service, "txn_forced", "stripe", 1)
+                .await
+                .scheduled
+        );
+        service
+            .force_to_dlq(Request::new(ForceToDlqRequest {
+                transaction_id: "txn_forced".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(
+            reason(&service, "txn_forced").await,
+            ProtoDlqReason::ForcedByOperator as i32
+        );
+
+        assert!(
+            schedule(&service, "txn_lowered", "stripe", 3)
+                .await
+                .scheduled
+        );
+        service
+            .apply_retry_config(RetryConfig {
+                max_attempts: 3,
+                jitter: false,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            reason(&service, "txn_lowered").await,
+            ProtoDlqReason::MaxAttemptsLowered as i32
+        );
+
+        let slow = RetryEngineService::new(
+            RetryConfig {
+                jitter: false,
+                max_elapsed_ms: 2500,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        );
+        exhaust(&slow, "txn_slow", "stripe").await;
+        assert_eq!(
+            reason(&slow, "txn_slow").await,
+            ProtoDlqReason::MaxElapsedExceeded as i32
+        );
+
+        // Entries added some other way say so rather than guess
+        dead_letter(&service, "txn_direct", "stripe");
+        assert_eq!(
+            reason(&service, "txn_direct").await,
+            ProtoDlqReason::Unspecified as i32
+        );
+    }
+
+    #[tokio::test]
+    async fn test_normalized_psp_names_share_one_breaker() {
+        async fn fail_on(service: &RetryEngineService, psp_names: &[&str]) {
+            for (i, psp_name) in psp_names.iter().enumerate() {
+                let transaction_id = format!("txn_{}", i);
+                schedule(service, &transaction_id, psp_name, 1).await;
+                service
+                    .report_outcome(Request::new(ReportOutcomeRequest {
+                        transaction_id,
+                        psp_name: psp_name.to_string(),
+                        attempt_number: 2,
+                        success: false,
+                        ..Default::default()
+                    }))
+                    .await
+                    .unwrap();
+            }
+        }
+        let variants = ["Stripe", "stripe ", " STRIPE"];
+
+        let normalized =
+            service().with_psp_normalizer(crate::PspNaming::TrimLowercase.normalizer().unwrap());
+        fail_on(&normalized, &variants).await;
+        {
+            let breakers = normalized.circuit_breakers.lock().unwrap();
+            assert_eq!(breakers.keys().collect::<Vec<_>>(), vec!["stripe"]);
+            assert_eq!(breakers["stripe"].get_state().failure_count, 3);
+        }
+        let status = normalized
+            .get_circuit_status(Request::new(CircuitRequest {
+                psp_name: "  Stripe".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.psp_name, "stripe");
+        assert_eq!(status.failure_count, 3);
+
+        // Raw names keep every variant apart
+        let raw = service();
+        fail_on(&raw, &variants).await;
+        let breakers = raw.circuit_breakers.lock().unwrap();
+        assert_eq!(breakers.len(), 3);
+        assert!(breakers
+            .values()
+            .all(|breaker| breaker.get_state().failure_count == 1));
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_group_member_dead_letters_whole_group() {
+        let service = no_jitter_service();
+        for (transaction_id, psp_name) in [
+            ("txn_auth", "stripe"),
+            ("txn_fx", "adyen"),
+            ("txn_fee", "stripe"),
+        ] {
+            let response = service
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    psp_name: psp_name.to_string(),
+                    payload: transaction_id.as_bytes().to_vec(),
+                    attempt_number: 1,
+                    group_id: "payment_1".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.scheduled);
+        }
+        assert!(schedule(&service, "txn_other", "stripe", 1).await.scheduled);
+
+        // Later attempts leave the group out; it sticks from the first
+        let response = exhaust(&service, "txn_auth", "stripe").await;
+        assert_eq!(
+            response.message,
+            "Max retries exceeded, moved to DLQ with 2 other member(s) of group payment_1"
+        );
+
+        let reasons: Vec<(String, i32)> = ["txn_auth", "txn_fee", "txn_fx"]
+            .into_iter()
+            .map(|transaction_id| {
+                let entry = service.dlq.get_entry(transaction_id).unwrap();
+                // Swept members keep the payload of their retry state
+                if transaction_id != "txn_auth" {
+                    assert_eq!(entry.payload, transaction_id.as_bytes());
+                }
+                let summary = RetryEngineService::dlq_entry_summary(&entry);
+                (summary.psp_name, summary.reason)
+            })
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (
+                    "stripe".to_string(),
+                    ProtoDlqReason::MaxAttemptsExceeded as i32
+                ),
+                (
+                    "stripe".to_string(),
+                    ProtoDlqReason::GroupMemberExhausted as i32
+                ),
+                (
+                    "adyen".to_string(),
+                    ProtoDlqReason::GroupMemberExhausted as i32
+                ),
+            ]
+        );
+        assert_eq!(
+            service.dlq.get_entry("txn_fx").unwrap().last_error,
+            "Group payment_1 abandoned: txn_auth max retries exceeded"
+        );
+
+        // The rest of the group stops retrying; others are untouched
+        let states = service.retry_states.lock().unwrap().clone();
+        assert_eq!(states.keys().collect::<Vec<_>>(), vec!["txn_other"]);
+        assert!(!schedule(&service, "txn_fx", "adyen", 2).await.scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_group_dead_letter_waits_for_member_locks() {
+        let service = Arc::new(no_jitter_service());
+        for transaction_id in ["txn_auth", "txn_fee"] {
+            let response = service
+                .schedule_retry(Request::new(RetryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    psp_name: "stripe".to_string(),
+                    attempt_number: 1,
+                    group_id: "payment_1".to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner();
+            assert!(response.scheduled);
+        }
+
+        // Someone is mid-decision on a member, so the group can't be swept yet
+        let contended = || {
+            let stats = service.transaction_locks.stats();
+            stats.iter().map(|stripe| stripe.contended).sum::<u64>()
+        };
+        let contended_before = contended();
+        let member = service.transaction_locks.lock("txn_fee");
+        let (swept_tx, swept) = std::sync::mpsc::channel();
+        let sweep = std::thread::spawn({
+            let service = service.clone();
+            move || {
+                swept_tx
+                    .send(service.apply_retry_config(RetryConfig {
+                        max_attempts: 1,
+                        jitter: false,
+                        ..Default::default()
+                    }))
+                    .unwrap();
+            }
+        });
+        // Once the sweep has found the member's stripe taken, it can only
+        // finish after the lock is released
+        while contended() == contended_before {
+            std::thread::yield_now();
+        }
+        assert_eq!(swept.try_recv(), Err(std::sync::mpsc::TryRecvError::Empty));
+        assert_eq!(service.retry_states.lock().unwrap().len(), 2);
+        assert_eq!(service.dlq.count(), 0);
+
+        drop(member);
+        assert_eq!(swept.recv().unwrap().unwrap(), vec!["txn_auth"]);
+        sweep.join().unwrap();
+        assert!(service.retry_states.lock().unwrap().is_empty());
+        assert_eq!(
+            service.dlq.get_entry("txn_fee").unwrap().reason,
+            DlqReason::GroupMemberExhausted
+        );
+    }
+
+    #[tokio::test]
+    async fn test_psp_breaker_cap_bounds_unknown_psps() {
+        async fn flood(service: &RetryEngineService) -> Vec<RetryResponse> {
+            let mut responses = Vec::new();
+            for i in 0..50 {
+                let psp_name = format!("psp_{}", i);
+                responses.push(schedule(service, &format!("txn_{}", i), &psp_name, 1).await);
+            }
+            responses
+        }
+
+        let shared = service().with_max_psp_breakers(3, PspOverflow::Shared);
+        assert!(flood(&shared)
+            .await
+            .iter()
+            .all(|response| response.scheduled));
+        let mut names: Vec<String> = shared
+            .circuit_breakers
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        assert_eq!(names, vec![OVERFLOW_BREAKER, "psp_0", "psp_1", "psp_2"]);
+        // Every PSP past the cap feeds and reads the one overflow breaker
+        shared.record_outcome("psp_10", false, None);
+        shared.record_outcome("psp_11", false, None);
+        let overflow = shared.get_circuit_breaker(OVERFLOW_BREAKER).unwrap();
+        assert_eq!(overflow.get_state().failure_count, 2);
+        let status = shared
+            .get_circuit_status(Request::new(CircuitRequest {
+                psp_name: "psp_99".to_string(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(status.failure_count, 2);
+        // As do their breaker checks
+        let rejections = shared
+            .get_circuit_rejections(Request::new(CircuitRejectionsRequest {
+                psp_name: "psp_99".to_string(),
+                window_minutes: 1,
+                reset: false,
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(rejections.checks, 47);
+        let (unshared, _) = shared
+            .admissions
+            .window("psp_10", 60_000, shared.clock.now_ms());
+        assert_eq!(unshared.checks, 0);
+
+        let reject = service().with_max_psp_breakers(3, PspOverflow::Reject);
+        let responses = flood(&reject).await;
+        assert!(responses[..3].iter().all(|response| response.scheduled));
+        for response in &responses[3..] {
+            assert!(!response.scheduled);
+            assert_eq!(response.message, "Too many PSPs: at most 3 are tracked");
+        }
+        assert_eq!(reject.circuit_breakers.lock().unwrap().len(), 3);
+        let refused = reject
+            .get_circuit_status(Request::new(CircuitRequest {
+                psp_name: "psp_99".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(refused.code(), tonic::Code::ResourceExhausted);
+        // PSPs already tracked carry on as before
+        assert!(schedule(&reject, "txn_again", "psp_0", 1).await.scheduled);
+    }
+
+    #[tokio::test]
+    async fn test_psp_breaker_cap_bounds_set_circuit_config() {
+        async fn set_config(
+            service: &RetryEngineService,
+            psp_name: &str,
+        ) -> Result<SetCircuitConfigResponse, Status> {
+            service
+                .set_circuit_config(Request::new(SetCircuitConfigRequest {
+                    psp_name: psp_name.to_string(),
+                    config: Some(ProtoCircuitBreakerConfig {
+                        failure_threshold: 2,
+                        success_threshold: 1,
+                        timeout_duration_ms: 10000,
+                        ..Default::default()
+                    }),
+                }))
+                .await
+                .map(Response::into_inner)
+        }
+
+        for overflow in [PspOverflow::Shared, PspOverflow::Reject] {
+            let capped = service().with_max_psp_breakers(3, overflow);
+            for i in 0..50 {
+                let result = set_config(&capped, &format!("psp_{}", i)).await;
+                if i < 3 {
+                    assert!(result.is_ok());
+                } else {
+                    let refused = result.unwrap_err();
+                    assert_eq!(refused.code(), tonic::Code::ResourceExhausted);
+                    assert_eq!(refused.message(), "Too many PSPs: at most 3 are tracked");
+                }
+            }
+            assert_eq!(capped.circuit_breakers.lock().unwrap().len(), 3);
+            assert_eq!(capped.circuit_overrides.lock().unwrap().len(), 3);
+            // PSPs already tracked can still be reconfigured
+            assert!(set_config(&capped, "psp_0").await.is_ok());
+        }
+
+        // Per-PSP metrics of the PSPs past the cap are kept under the
+        // overflow breaker, not one entry per name
+        let shared = service().with_max_psp_breakers(3, PspOverflow::Shared);
+        for i in 0..50 {
+            let psp_name = format!("psp_{}", i);
+            schedule(&shared, &format!("txn_{}", i), &psp_name, 1).await;
+            shared.record_outcome(&psp_name, true, Some(100));
+        }
+        assert_eq!(shared.retries_scheduled.get("psp_0"), 1);
+        assert_eq!(shared.retries_scheduled.get(OVERFLOW_BREAKER), 47);
+        assert_eq!(shared.retries_scheduled.get("psp_10"), 0);
+        assert_eq!(shared.in_flight.get(OVERFLOW_BREAKER).current, 47);
+        assert_eq!(shared.outcomes.all().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_min_replay_interval_spaces_or_rejects_replays() {
+        async fn replay(
+            service: &RetryEngineService,
+            transaction_id: &str,
+        ) -> ReplayDlqEntryResponse {
+            service
+                .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                    transaction_id: transaction_id.to_string(),
+                    ..Default::default()
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+        }
+        let ids = ["txn_1", "txn_2", "txn_3"];
+
+        // Queued: each replay's attempt lands a full interval after the last
+        let queued = service().with_min_replay_interval(60_000, ReplayThrottle::Queue);
+        for id in ids {
+            dead_letter(&queued, id, "stripe");
+        }
+        dead_letter(&queued, "txn_adyen", "adyen");
+        let mut due: Vec<i64> = Vec::new();
+        for id in ids {
+            let response = replay(&queued, id).await;
+            assert!(response.replayed, "{}", response.message);
+            due.push(response.next_retry_at_ms);
+        }
+        assert!(
+            due.windows(2).all(|pair| pair[1] - pair[0] == 60_000),
+            "{:?}",
+            due
+        );
+        // Other PSPs have their own interval
+        let adyen = replay(&queued, "txn_adyen").await;
+        assert!(adyen.next_retry_at_ms < due[1]);
+
+        // Rejected: only the first of a rapid burst gets through
+        let rejecting = service().with_min_replay_interval(60_000, ReplayThrottle::Reject);
+        for id in ids.into_iter().chain(["txn_4", "txn_5"]) {
+            dead_letter(&rejecting, id, "stripe");
+        }
+        assert!(replay(&rejecting, "txn_1").await.replayed);
+        let refused = replay(&rejecting, "txn_2").await;
+        assert!(!refused.replayed);
+        assert!(
+            refused
+                .message
+                .starts_with("Replays to stripe are limited to one per 60000ms"),
+            "{}",
+            refused.message
+        );
+
+        // Bulk replays are held to the same interval
+        let bulk = rejecting
+            .bulk_replay_dlq(Request::new(BulkReplayDlqRequest {
+                psp_name: "stripe".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(bulk.replayed_count, 0);
+        assert_eq!(bulk.skipped_count, 4);
+
+        // A replay that fails after taking its slot gives it back
+        let releasing = service().with_min_replay_interval(60_000, ReplayThrottle::Reject);
+        for id in ids {
+            dead_letter(&releasing, id, "stripe");
+        }
+        open_circuit(&releasing, "stripe");
+        let refused = replay(&releasing, "txn_1").await;
+        assert!(
+            refused.message.starts_with("Circuit breaker open"),
+            "{}",
+            refused.message
+        );
+        releasing.get_circuit_breaker("stripe").unwrap().reset();
+        let replayed = replay(&releasing, "txn_2").await;
+        assert!(replayed.replayed, "{}", replayed.message);
+        assert!(!replay(&releasing, "txn_3").await.replayed);
+    }
+
+    #[tokio::test]
+    async fn test_late_follow_ups_record_retry_drift() {
+        use crate::clock::ManualClock;
+
+        async fn last_drift_ms(service: &RetryEngineService) -> i64 {
+            service
+                .get_retry_status(Request::new(RetryStatusRequest {
+                    transaction_id: "txn_1".to_string(),
+                }))
+                .await
+                .unwrap()
+                .into_inner()
+                .last_drift_ms
+        }
+
+        let clock = Arc::new(ManualClock::new(1_704_067_200_000));
+        let service = RetryEngineService::new(
+            RetryConfig {
+                initial_delay_ms: 1000,
+                backoff_multiplier: 2.0,
+                jitter: false,
+                ..Default::default()
+            },
+            CircuitBreakerConfig::default(),
+        )
+        .with_clock(clock.clone());
+
+        // Attempt 2 is due 1s after scheduling but asked for 2.5s late
+        assert!(schedule(&service, "txn_1", "stripe", 1).await.scheduled);
+        assert_eq!(last_drift_ms(&service).await, 0);
+        clock.advance(1000 + 2500);
+        assert!(schedule(&service, "txn_1", "stripe", 2).await.scheduled);
+        assert_eq!(last_drift_ms(&service).await, 2500);
+
+        // Attempt 3 is reported 400ms late; scheduling after it, or resending
+        // the same attempt, doesn't time it again
+        clock.advance(2000 + 400);
+        service
+            .report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: "txn_1".to_string(),
+                attempt_number: 3,
+                success: false,
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        assert_eq!(last_drift_ms(&service).await, 400);
+        clock.advance(10_000);
+        assert!(schedule(&service, "txn_1", "stripe", 3).await.scheduled);
+        assert!(schedule(&service, "txn_1", "stripe", 3).await.scheduled);
+        assert_eq!(last_drift_ms(&service).await, 400);
+
+        let text = service
+            .get_metrics(Request::new(MetricsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .text;
+        assert!(text.contains("# TYPE psp_retry_drift_seconds histogram\n"));
+        assert!(text.contains("psp_retry_drift_seconds_bucket{psp=\"stripe\",le=\"0.1\"} 0\n"));
+        assert!(text.contains("psp_retry_drift_seconds_bucket{psp=\"stripe\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("psp_retry_drift_seconds_bucket{psp=\"stripe\",le=\"5\"} 2\n"));
+        assert!(text.contains("psp_retry_drift_seconds_count{psp=\"stripe\"} 2\n"));
+    }
+
+    #[tokio::test]
+    async fn test_stripped_payload_must_be_resupplied_to_replay() {
+        let service = service();
+        dead_letter(&service, "txn_1", "stripe");
+        // As reloaded from a store that doesn't persist payloads
+        service
+            .dlq
+            .update_entry("txn_1", |entry| *entry = entry.without_payload());
+        let summary =
+            RetryEngineService::dlq_entry_summary(&service.dlq.get_entry("txn_1").unwrap());
+        assert!(summary.payload_stripped);
+
+        let refused = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!refused.replayed);
+        assert!(refused.message.contains("Payload was not persisted"));
+        assert!(!service.retry_states.lock().unwrap().contains_key("txn_1"));
+
+        let replayed = service
+            .replay_dlq_entry(Request::new(ReplayDlqEntryRequest {
+                transaction_id: "txn_1".to_string(),
+                payload: vec![4, 5, 6],
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(replayed.replayed);
+        assert_eq!(
+            service
+                .retry_states
+                .lock()
+                .unwrap()
+                .get("txn_1")
+                .unwrap()
+                .payload,
+            vec![4, 5, 6]
+        );
+        // The payload is held by the retry, not the entry too
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert!(entry.payload.is_empty());
+        assert!(entry.payload_stripped);
+
+        // Abandoning the replay hands it back to the entry
+        service
+            .cancel_retry(Request::new(CancelRetryRequest {
+                transaction_id: "txn_1".to_string(),
+                ..Default::default()
+            }))
+            .await
+            .unwrap();
+        let entry = service.dlq.get_entry("txn_1").unwrap();
+        assert_eq!(entry.payload, vec![4, 5, 6]);
+        assert!(!entry.payload_stripped);
+    }
+
+    #[tokio::test]
+    async fn test_final_attempt_histogram_counts_successes_and_dead_letters() {
+        let service = no_jitter_service();
+        let succeed = |transaction_id: &str, attempt_number: i32| {
+            service.report_outcome(Request::new(ReportOutcomeRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number,
+                success: true,
+                ..Default::default()
+            }))
+        };
+
+        // First try, second try, and a third the client didn't number
+        succeed("txn_first", 1).await.unwrap();
+        assert!(
+            schedule(&service, "txn_second", "stripe", 1)
+                .await
+                .scheduled
+        );
+        succeed("txn_second", 2).await.unwrap();
+        for attempt in 1..=2 {
+            assert!(
+                schedule(&service, "txn_third", "stripe", attempt)
+                    .await
+                    .scheduled
+            );
+        }
+        succeed("txn_third", 0).await.unwrap();
+        // A stale success for an attempt already retried isn't the final one
+        for attempt in 1..=2 {
+            assert!(
+                schedule(&service, "txn_stale", "stripe", attempt)
+                    .await
+                    .scheduled
+            );
+        }
+        succeed("txn_stale", 1).await.unwrap();
+        assert!(!exhaust(&service, "txn_dead", "adyen").await.scheduled);
+
+        let text = service
+            .get_metrics(Request::new(MetricsRequest::default()))
+            .await
+            .unwrap()
+            .into_inner()
+            .text;
+        assert!(text.contains("# TYPE psp_final_attempt histogram\n"));
+        let success = "psp=\"stripe\",outcome=\"success\"";
+        for (le, count) in [("1", 1), ("2", 2), ("3", 3), ("20", 3)] {
+            assert!(text.contains(&format!(
+                "psp_final_attempt_bucket{{{},le=\"{}\"}} {}\n",
+                success, le, count
+            )));
+        }
+        assert!(text.contains(&format!("psp_final_attempt_sum{{{}}} 6\n", success)));
+        assert!(!text.contains("psp=\"stripe\",outcome=\"dead_letter\""));
+        let dead_letter = "psp=\"adyen\",outcome=\"dead_letter\"";
+        assert!(text.contains(&format!(
+            "psp_final_attempt_bucket{{{},le=\"4\"}} 0\n",
+            dead_letter
+        )));
+        assert!(text.contains(&format!(
+            "psp_final_attempt_bucket{{{},le=\"5\"}} 1\n",
+            dead_letter
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_out_of_range_attempt_numbers_are_refused_not_wrapped() {
+        let request = |attempt_number: i32| {
+            Request::new(RetryRequest {
+                transaction_id: "txn_1".to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number,
+                ..Default::default()
+            })
+        };
+        let service = no_jitter_service();
+
+        let negative = service.schedule_retry(request(-1)).await.unwrap_err();
+        assert_eq!(negative.code(), tonic::Code::InvalidArgument);
+        assert_eq!(negative.message(), "attempt_number must not be negative");
+        let huge = service.schedule_retry(request(i32::MAX)).await.unwrap_err();
+        assert_eq!(huge.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            huge.message(),
+            format!(
+                "attempt_number {} is above the maximum of {}",
+                i32::MAX,
+                DEFAULT_MAX_ATTEMPT_NUMBER
+            )
+        );
+        let evaluated = service
+            .evaluate_transaction(Request::new(EvaluateTransactionRequest {
+                transaction_id: "txn_1".to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number: i32::MIN,
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(evaluated.code(), tonic::Code::InvalidArgument);
+        assert!(service.retry_states.lock().unwrap().is_empty());
+
+        // Clamped, a huge attempt counts as the maximum, which is exhausted
+        let clamping = no_jitter_service().with_max_attempt_number(50, AttemptOverflow::Clamp);
+        let response = clamping
+            .schedule_retry(request(i32::MAX))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!response.scheduled);
+        assert_eq!(clamping.dlq.get_entry("txn_1").unwrap().attempt_count, 50);
+
+        // A retry config whose last attempt a clamped number can't reach is refused
+        let unreachable = clamping
+            .apply_retry_config(RetryConfig {
+                max_attempts: 51,
+                ..Default::default()
+            })
+            .unwrap_err();
+        assert!(unreachable.contains("max_attempts 51"), "{}", unreachable);
+        assert!(clamping
+            .apply_retry_config(RetryConfig {
+                max_attempts: 50,
+                ..Default::default()
+            })
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flush_dlq_puts_new_entries_on_disk() {
+        use crate::persistence::SerializationFormat;
+
+        let in_memory = service();
+        dead_letter(&in_memory, "txn_1", "stripe");
+        let flushed = in_memory
+            .flush_dlq(Request::new(FlushDlqRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(!flushed.persisted);
+        assert!(!in_memory.dlq.flush().await.unwrap());
+
+        let dir = std::env::temp_dir().join(format!("flush-dlq-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let store = || DlqStore::new(dir.join("dlq"), SerializationFormat::Json);
+        let on_disk = || {
+            let mut ids: Vec<String> = store()
+                .load()
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.transaction_id)
+                .collect();
+            ids.sort();
+            ids
+        };
+        let svc = service()
+            .with_dlq_store(store(), PersistenceMode::Strict)
+            .unwrap();
+        dead_letter(&svc, "txn_1", "stripe");
+        dead_letter(&svc, "txn_2", "adyen");
+        // Without a WAL nothing is written until shutdown or a flush
+        assert!(on_disk().is_empty());
+
+        let flushed = svc
+            .flush_dlq(Request::new(FlushDlqRequest {}))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(flushed.persisted);
+        assert_eq!(flushed.revision, svc.dlq.revision() as i64);
+        assert_eq!(on_disk(), vec!["txn_1", "txn_2"]);
+
+        // With a WAL the flush covers what's been appended since the snapshot
+        let wal = DlqWal::new(store(), dir.join("dlq.wal"), Duration::from_secs(3600));
+        let svc = service()
+            .with_dlq_wal(wal, PersistenceMode::Strict)
+            .unwrap();
+        dead_letter(&svc, "txn_3", "stripe");
+        assert!(
+            svc.flush_dlq(Request::new(FlushDlqRequest {}))
+                .await
+                .unwrap()
+                .into_inner()
+                .persisted
+        );
+        let recovered = DlqWal::new(store(), dir.join("dlq.wal"), Duration::ZERO)
+            .recover()
+            .unwrap();
+        assert!(recovered
+            .iter()
+            .any(|entry| entry.transaction_id == "txn_3"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_high_priority_retries_outlast_exhausted_low_tier() {
+        let service = no_jitter_service()
+            .with_clock(Arc::new(crate::clock::ManualClock::new(1_704_067_200_000)))
+            .with_retry_budget(RetryBudget::new(60_000, vec![2, 1]));
+        let schedule_at = |transaction_id: &str, priority: i32| {
+            service.schedule_retry(Request::new(RetryRequest {
+                transaction_id: transaction_id.to_string(),
+                psp_name: "stripe".to_string(),
+                attempt_number: 1,
+                priority,
+                ..Default::default()
+            }))
+        };
+
+        for transaction_id in ["txn_low_1", "txn_low_2"] {
+            assert!(
+                schedule_at(transaction_id, 0)
+                    .await
+                    .unwrap()
+                    .into_inner()
+                    .scheduled
+            );
+        }
+        let throttled = schedule_at("txn_low_3", 0).await.unwrap().into_inner();
+        assert!(!throttled.scheduled);
+        assert!(throttled
+            .message
+            .starts_with("Retry budget exhausted for priority tier 0"));
+        assert!(!service
+            .retry_states
+            .lock()
+            .unwrap()
+            .contains_key("txn_low_3"));
+
+        // The high tier still has its own allowance; priorities past it share it
+        assert!(
+            schedule_at("txn_high", 5)
+                .await
+                .unwrap()
+                .into_inner()
+                .scheduled
+        );
+        assert!(
+            !schedule_at("txn_high_2", 1)
+                .await
+                .unwrap()
+                .into_inner()
+                .scheduled
+        );
+
+        let negative = schedule_at("txn_bad", -1).await.unwrap_err();
+        assert_eq!(negative.code(), tonic::Code::InvalidArgument);
+    }
 }
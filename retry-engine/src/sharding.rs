@@ -0,0 +1,392 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// The standard library's SipHash with fixed keys, so a key maps to the same
+/// shard in every process
+pub type DefaultShardHasher = BuildHasherDefault<DefaultHasher>;
+
+/// SipHash salted with a secret seed, so keys can't be picked to pile onto
+/// one shard
+///
+/// With `DefaultShardHasher` anyone can work out which shard an ID lands on
+/// and mint IDs that all share one. Salting with a seed the client doesn't
+/// know spreads such a set like any other, at the cost of a key landing on
+/// different shards in different processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeededShardHasher {
+    seed: u64,
+}
+
+impl SeededShardHasher {
+    /// Salt with a fresh random seed
+    pub fn random() -> Self {
+        Self::with_seed(rand::random())
+    }
+
+    /// Salt with `seed`, e.g. to reproduce a routing in a test
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl Default for SeededShardHasher {
+    fn default() -> Self {
+        Self::random()
+    }
+}
+
+impl BuildHasher for SeededShardHasher {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
+
+/// Picks the shard for a key by hashing it with a pluggable `BuildHasher`
+///
+/// Meant for splitting a keyed map (the DLQ, the breaker registry) into
+/// independently locked shards; so far only `StripedLock` routes through it.
+/// `load` shows how a set of keys would spread, e.g. to check a hasher
+/// against transaction IDs sharing a long prefix.
+#[derive(Debug, Clone)]
+pub struct ShardRouter<S = DefaultShardHasher> {
+    shard_count: usize,
+    hasher: S,
+}
+
+impl ShardRouter {
+    pub fn new(shard_count: usize) -> Self {
+        Self::with_hasher(shard_count, DefaultShardHasher::default())
+    }
+}
+
+impl<S: BuildHasher> ShardRouter<S> {
+    /// Route with a custom hash, e.g. one that distributes a skewed key set
+    /// better than the default
+    pub fn with_hasher(shard_count: usize, hasher: S) -> Self {
+        assert!(shard_count > 0, "shard_count must be at least 1");
+        Self {
+            shard_count,
+            hasher,
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shard_count
+    }
+
+    pub fn shard_for<K: Hash + ?Sized>(&self, key: &K) -> usize {
+        (self.hasher.hash_one(key) % self.shard_count as u64) as usize
+    }
+
+    /// How many of `keys` land on each shard
+    pub fn load<'a, K: Hash + ?Sized + 'a>(
+        &self,
+        keys: impl IntoIterator<Item = &'a K>,
+    ) -> ShardLoad {
+        let mut counts = vec![0; self.shard_count];
+        for key in keys {
+            counts[self.shard_for(key)] += 1;
+        }
+        ShardLoad { counts }
+    }
+}
+
+/// A fixed set of mutexes with each key routed to one, giving a critical
+/// section per key without a lock per key
+///
+/// Keys sharing a stripe also exclude each other, which only costs
+/// throughput as long as nothing holds two guards at once.
+pub struct StripedLock<S = DefaultShardHasher> {
+    router: ShardRouter<S>,
+    stripes: Vec<Mutex<()>>,
+    acquisitions: Vec<AtomicU64>,
+    contended: Vec<AtomicU64>,
+}
+
+impl StripedLock {
+    pub fn new(stripe_count: usize) -> Self {
+        Self::with_router(ShardRouter::new(stripe_count))
+    }
+}
+
+impl<S: BuildHasher> StripedLock<S> {
+    pub fn with_router(router: ShardRouter<S>) -> Self {
+        let count = router.shard_count();
+        Self {
+            router,
+            stripes: (0..count).map(|_| Mutex::new(())).collect(),
+            acquisitions: (0..count).map(|_| AtomicU64::new(0)).collect(),
+            contended: (0..count).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// Block until no one else holds `key`'s stripe
+    pub fn lock<K: Hash + ?Sized>(&self, key: &K) -> MutexGuard<'_, ()> {
+        self.lock_stripe(self.router.shard_for(key))
+    }
+
+    /// Acquisitions and waits so far on each stripe, to spot keys skewing
+    /// onto a few stripes
+    pub fn stats(&self) -> Vec<StripeStats> {
+        self.acquisitions
+            .iter()
+            .zip(&self.contended)
+            .map(|(acquisitions, contended)| StripeStats {
+                acquisitions: acquisitions.load(Ordering::Relaxed),
+                contended: contended.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    fn lock_stripe(&self, stripe: usize) -> MutexGuard<'_, ()> {
+        let guard = match self.stripes[stripe].try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                self.contended[stripe].fetch_add(1, Ordering::Relaxed);
+                self.stripes[stripe].lock().unwrap()
+            }
+        };
+        self.acquisitions[stripe].fetch_add(1, Ordering::Relaxed);
+        guard
+    }
+
+    /// Lock the stripes of `keys`, each once and in ascending order, so two
+    /// callers locking overlapping sets can't deadlock
+    pub fn lock_all<'k, K: Hash + ?Sized + 'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k K>,
+    ) -> Vec<MutexGuard<'_, ()>> {
+        self.sorted_stripes(keys, None)
+            .into_iter()
+            .map(|stripe| self.lock_stripe(stripe))
+            .collect()
+    }
+
+    /// Also lock the stripes of `keys` for a caller already holding `held`'s,
+    /// each once and in ascending order. Whoever holds a stripe below
+    /// `held`'s may be waiting for `held`'s, so those are only taken if free:
+    /// `None`, with nothing more locked, if one isn't.
+    pub fn try_lock_with<'k, K: Hash + ?Sized + 'k>(
+        &self,
+        held: &K,
+        keys: impl IntoIterator<Item = &'k K>,
+    ) -> Option<Vec<MutexGuard<'_, ()>>> {
+        let held = self.router.shard_for(held);
+        self.sorted_stripes(keys, Some(held))
+            .into_iter()
+            .map(|stripe| {
+                if stripe < held {
+                    let guard = self.stripes[stripe].try_lock().ok();
+                    let counter = match guard {
+                        Some(_) => &self.acquisitions[stripe],
+                        None => &self.contended[stripe],
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    guard
+                } else {
+                    Some(self.lock_stripe(stripe))
+                }
+            })
+            .collect()
+    }
+
+    fn sorted_stripes<'k, K: Hash + ?Sized + 'k>(
+        &self,
+        keys: impl IntoIterator<Item = &'k K>,
+        held: Option<usize>,
+    ) -> Vec<usize> {
+        let mut stripes: Vec<usize> = keys
+            .into_iter()
+            .map(|key| self.router.shard_for(key))
+            .filter(|&stripe| Some(stripe) != held)
+            .collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+    }
+}
+
+/// Lock traffic on one stripe of a `StripedLock`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StripeStats {
+    pub acquisitions: u64,
+    /// Acquisitions that found the stripe held and had to wait, or gave up
+    pub contended: u64,
+}
+
+/// Keys per shard, for spotting a hash that piles keys onto a few shards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShardLoad {
+    pub counts: Vec<usize>,
+}
+
+impl ShardLoad {
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Busiest shard's count over the mean; 1.0 is perfectly balanced and
+    /// `shard_count` means every key is on one shard
+    pub fn imbalance(&self) -> f64 {
+        let total = self.total();
+        if total == 0 {
+            return 1.0;
+        }
+        let max = self.counts.iter().copied().max().unwrap_or(0);
+        max as f64 * self.counts.len() as f64 / total as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_hasher_spreads_adversarial_ids() {
+        let router = ShardRouter::new(16);
+        let key_sets: Vec<(&str, Vec<String>)> = vec![
+            // A long shared prefix, as merchant-scoped IDs have
+            (
+                "shared prefix",
+                (0..10_000)
+                    .map(|i| format!("txn_2024_merchant_0042_{:08}", i))
+                    .collect(),
+            ),
+            // A long shared suffix
+            (
+                "shared suffix",
+                (0..10_000)
+                    .map(|i| format!("{:08}_stripe_eu_west_1_settlement", i))
+                    .collect(),
+            ),
+            // Numbers stepping by the shard count, which a modulo hash would
+            // send to a single shard
+            (
+                "stride",
+                (0..10_000).map(|i| (i * 16).to_string()).collect(),
+            ),
+            // IDs differing in one byte only
+            (
+                "one byte",
+                (0..=255u8)
+                    .flat_map(|byte| (0..40).map(move |i| format!("txn_{:02}_{}", i, byte as char)))
+                    .collect(),
+            ),
+        ];
+
+        for (name, ids) in key_sets {
+            let load = router.load(ids.iter().map(String::as_str));
+            assert_eq!(load.total(), ids.len());
+            assert!(load.imbalance() < 1.2, "{}: {:?}", name, load);
+        }
+    }
+
+    #[test]
+    fn test_seeded_hasher_spreads_ids_minted_for_one_shard() {
+        // IDs chosen so the default hasher puts every one on shard 0, as a
+        // client knowing the fixed keys could
+        let default = ShardRouter::new(16);
+        let ids: Vec<String> = (0..)
+            .map(|i| format!("txn_{}", i))
+            .filter(|id| default.shard_for(id.as_str()) == 0)
+            .take(4_000)
+            .collect();
+        let seeded = ShardRouter::with_hasher(16, SeededShardHasher::with_seed(0x5eed));
+
+        let default_load = default.load(ids.iter().map(String::as_str));
+        let seeded_load = seeded.load(ids.iter().map(String::as_str));
+        assert_eq!(default_load.imbalance(), 16.0);
+        assert!(seeded_load.imbalance() < 1.2, "{:?}", seeded_load);
+        // The seed is what moves them
+        let reseeded = ShardRouter::with_hasher(16, SeededShardHasher::with_seed(0x5eed + 1));
+        assert_ne!(seeded_load, reseeded.load(ids.iter().map(String::as_str)));
+    }
+
+    #[test]
+    fn test_striped_lock_counts_acquisitions_and_waits() {
+        let lock = std::sync::Arc::new(StripedLock::new(4));
+        let stripe = lock.router.shard_for("txn_1");
+        drop(lock.lock("txn_1"));
+
+        let held = lock.lock("txn_1");
+        let waiter = {
+            let lock = lock.clone();
+            std::thread::spawn(move || drop(lock.lock("txn_1")))
+        };
+        while lock.stats()[stripe].contended == 0 {
+            std::thread::yield_now();
+        }
+        drop(held);
+        waiter.join().unwrap();
+
+        let stats = lock.stats();
+        assert_eq!(
+            stats[stripe],
+            StripeStats {
+                acquisitions: 3,
+                contended: 1,
+            }
+        );
+        // Nothing else was locked
+        let total: u64 = stats.iter().map(|stats| stats.acquisitions).sum();
+        assert_eq!(total, 3);
+    }
+
+    #[test]
+    fn test_striped_lock_takes_each_stripe_once() {
+        let lock = StripedLock::new(4);
+        let keys: Vec<String> = (0..16).map(|i| format!("txn_{}", i)).collect();
+        // Keys sharing a stripe don't deadlock on themselves
+        let guards = lock.lock_all(keys.iter().map(String::as_str));
+        assert_eq!(guards.len(), 4);
+        drop(guards);
+
+        let held = lock.lock(keys[0].as_str());
+        let guards = lock
+            .try_lock_with(keys[0].as_str(), keys.iter().map(String::as_str))
+            .unwrap();
+        assert_eq!(guards.len(), 3);
+        drop(guards);
+        drop(held);
+    }
+
+    #[test]
+    fn test_striped_lock_only_tries_lower_stripes() {
+        let lock = StripedLock::new(4);
+        let key_on = |stripe: usize| {
+            (0..)
+                .map(|i| format!("txn_{}", i))
+                .find(|key| lock.router.shard_for(key.as_str()) == stripe)
+                .unwrap()
+        };
+        let (low, high) = (key_on(0), key_on(3));
+
+        // Someone else holds the lower stripe, and may be waiting for ours
+        let other = lock.lock(low.as_str());
+        let held = lock.lock(high.as_str());
+        assert!(lock.try_lock_with(high.as_str(), [low.as_str()]).is_none());
+        drop(other);
+        assert_eq!(
+            lock.try_lock_with(high.as_str(), [low.as_str()])
+                .unwrap()
+                .len(),
+            1
+        );
+        drop(held);
+    }
+
+    #[test]
+    fn test_routing_is_stable() {
+        let router = ShardRouter::new(8);
+        let shard = router.shard_for("txn_1");
+        assert!(shard < 8);
+        assert_eq!(ShardRouter::new(8).shard_for("txn_1"), shard);
+        assert_eq!(ShardLoad { counts: vec![0; 4] }.imbalance(), 1.0);
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Coalesces concurrent calls for the same key: the first caller runs the
+/// work and every caller that arrives while it is running gets a clone of its
+/// result. Calls after it finishes run the work again.
+pub struct SingleFlight<K, V> {
+    calls: Mutex<HashMap<K, Arc<OnceCell<V>>>>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> SingleFlight<K, V> {
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub async fn run<F, Fut>(&self, key: K, work: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let call = self
+            .calls
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| Arc::new(OnceCell::new()))
+            .clone();
+
+        // If the running caller is cancelled, a waiting one takes over the work
+        let value = call.get_or_init(work).await.clone();
+
+        let mut calls = self.calls.lock().unwrap();
+        if calls
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &call))
+        {
+            calls.remove(&key);
+        }
+        value
+    }
+
+    /// Number of keys with a call running
+    pub fn in_flight(&self) -> usize {
+        self.calls.lock().unwrap().len()
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> Default for SingleFlight<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_concurrent_calls_share_one_run() {
+        let flight = Arc::new(SingleFlight::new());
+        let runs = Arc::new(AtomicUsize::new(0));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            let flight = flight.clone();
+            let runs = runs.clone();
+            tasks.spawn(async move {
+                flight
+                    .run(("txn_1".to_string(), 1), || async move {
+                        let run = runs.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        run
+                    })
+                    .await
+            });
+        }
+
+        let mut results = Vec::new();
+        while let Some(result) = tasks.join_next().await {
+            results.push(result.unwrap());
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(results.iter().all(|result| *result == 0));
+        assert_eq!(flight.in_flight(), 0);
+
+        // A call after the flight landed runs again
+        let again = flight
+            .run(("txn_1".to_string(), 1), || async {
+                runs.fetch_add(1, Ordering::SeqCst)
+            })
+            .await;
+        assert_eq!(again, 1);
+    }
+}
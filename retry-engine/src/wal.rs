@@ -0,0 +1,454 @@
+use crate::dlq::DLQEntry;
+use crate::persistence::{DlqStore, PersistenceError};
+use crate::retry_policy::RetryPolicy;
+use crate::RetryConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// A DLQ change, as written to the WAL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WalRecord {
+    /// An entry was added or modified
+    Put {
+        entry: Box<DLQEntry>,
+    },
+    Removed {
+        transaction_id: String,
+    },
+}
+
+/// The file a WAL appends to
+pub trait WalFile: Write + Send {
+    fn sync_data(&self) -> io::Result<()>;
+    fn set_len(&self, size: u64) -> io::Result<()>;
+    /// Current length in bytes
+    fn size(&self) -> io::Result<u64>;
+}
+
+impl WalFile for File {
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        File::set_len(self, size)
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+/// Backoff between attempts at an append that failed with an I/O error:
+/// three attempts, 10ms then 20ms apart
+fn write_retry_config() -> RetryConfig {
+    RetryConfig {
+        max_attempts: 3,
+        initial_delay_ms: 10,
+        max_delay_ms: 100,
+        jitter: false,
+        ..Default::default()
+    }
+}
+
+struct WalWriter {
+    file: Box<dyn WalFile>,
+    /// Length of the file up to the last complete append
+    len: u64,
+    /// Records written since the last fsync
+    unsynced: bool,
+    last_sync: Instant,
+}
+
+/// Write-ahead log of DLQ changes on top of a `DlqStore` snapshot
+///
+/// Every change is appended as a JSON line and handed to the OS straight
+/// away, so a crash of the process alone loses nothing. The file is fsynced
+/// at most every `sync_interval`, so a power or kernel failure loses at most
+/// the changes made in the last `sync_interval` (given `sync` runs at least
+/// that often while idle). A checkpoint writes the whole DLQ to the snapshot
+/// and empties the WAL; recovery loads the snapshot and replays the WAL over
+/// it.
+pub struct DlqWal {
+    snapshot: DlqStore,
+    path: PathBuf,
+    sync_interval: Duration,
+    /// `None` until `open`
+    writer: Mutex<Option<WalWriter>>,
+    /// Paces retries of an append that failed with an I/O error
+    write_retry: RetryPolicy,
+}
+
+impl DlqWal {
+    /// A WAL at `path` over `snapshot`, fsyncing at most every
+    /// `sync_interval` (zero fsyncs every append)
+    pub fn new(snapshot: DlqStore, path: impl Into<PathBuf>, sync_interval: Duration) -> Self {
+        Self {
+            snapshot,
+            path: path.into(),
+            sync_interval,
+            writer: Mutex::new(None),
+            write_retry: RetryPolicy::new(write_retry_config()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn snapshot(&self) -> &DlqStore {
+        &self.snapshot
+    }
+
+    pub fn sync_interval(&self) -> Duration {
+        self.sync_interval
+    }
+
+    /// Recover the DLQ from the snapshot and WAL, then open the WAL for
+    /// appending
+    pub fn open(&self) -> Result<Vec<DLQEntry>, PersistenceError> {
+        let entries = self.recover()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.open_writer(Box::new(file))?;
+        Ok(entries)
+    }
+
+    /// Like `open`, but append to `file`, which must already hold the WAL
+    /// at `path`
+    pub fn open_with(&self, file: Box<dyn WalFile>) -> Result<Vec<DLQEntry>, PersistenceError> {
+        let entries = self.recover()?;
+        self.open_writer(file)?;
+        Ok(entries)
+    }
+
+    fn open_writer(&self, file: Box<dyn WalFile>) -> Result<(), PersistenceError> {
+        let len = file.size()?;
+        *self.writer.lock().unwrap() = Some(WalWriter {
+            file,
+            len,
+            unsynced: false,
+            last_sync: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// The snapshot with every WAL record applied over it, ordered by
+    /// transaction ID
+    ///
+    /// A torn last line, from a crash mid-append, is skipped: that change
+    /// was never acknowledged as durable.
+    pub fn recover(&self) -> Result<Vec<DLQEntry>, PersistenceError> {
+        let mut entries: HashMap<String, DLQEntry> = self
+            .snapshot
+            .load()?
+            .into_iter()
+            .map(|entry| (entry.transaction_id.clone(), entry))
+            .collect();
+
+        let lines = match File::open(&self.path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .collect::<io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let last = lines.len().saturating_sub(1);
+        for (index, line) in lines.iter().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str(line) {
+                Ok(WalRecord::Put { entry }) => {
+                    entries.insert(entry.transaction_id.clone(), *entry);
+                }
+                Ok(WalRecord::Removed { transaction_id }) => {
+                    entries.remove(&transaction_id);
+                }
+                Err(_) if index == last => {}
+                Err(e) => {
+                    return Err(PersistenceError::Corrupt(format!(
+                        "WAL line {}: {}",
+                        index + 1,
+                        e
+                    )))
+                }
+            }
+        }
+
+        let mut entries: Vec<DLQEntry> = entries.into_values().collect();
+        entries.sort_by(|a, b| a.transaction_id.cmp(&b.transaction_id));
+        Ok(entries)
+    }
+
+    /// Append records, fsyncing if the last fsync is `sync_interval` old
+    ///
+    /// A write that fails with an I/O error is cut back off the file and
+    /// tried again, up to three times in all with a short backoff. The
+    /// writer isn't locked while waiting, so this only blocks the calling
+    /// thread; keep it off the async runtime and out of other locks. If the
+    /// last try fails too, the file is left as it was before the call.
+    pub fn append(&self, records: &[WalRecord]) -> Result<(), PersistenceError> {
+        let mut attempt = 1;
+        loop {
+            let Err(e) = self.try_append(records) else {
+                return Ok(());
+            };
+            let Some(delay) = self.retry_delay(attempt) else {
+                return Err(e);
+            };
+            warn!(
+                "DLQ WAL append attempt {} failed, retrying in {}ms: {}",
+                attempt,
+                delay.as_millis(),
+                e
+            );
+            std::thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    /// `append` in a single attempt, never waiting: a failed write is cut
+    /// back off the file, leaving it as it was before the call
+    pub fn try_append(&self, records: &[WalRecord]) -> Result<(), PersistenceError> {
+        let mut data = Vec::new();
+        for record in records {
+            match record {
+                WalRecord::Put { entry } if !self.snapshot.persist_payloads() => {
+                    let stripped = WalRecord::Put {
+                        entry: Box::new(entry.without_payload()),
+                    };
+                    serde_json::to_writer(&mut data, &stripped)
+                }
+                _ => serde_json::to_writer(&mut data, record),
+            }
+            .map_err(io::Error::from)?;
+            data.push(b'\n');
+        }
+        let mut guard = self.writer.lock().unwrap();
+        let writer = guard.as_mut().ok_or_else(not_open)?;
+        if let Err(e) = writer.write(&data) {
+            // A partial line would make the records after it unreadable
+            writer.file.set_len(writer.len)?;
+            return Err(e.into());
+        }
+        writer.unsynced = true;
+        if writer.last_sync.elapsed() >= self.sync_interval {
+            Self::sync_writer(writer)?;
+        }
+        Ok(())
+    }
+
+    /// How long to wait after `attempts` failed tries at an append before
+    /// the next, or `None` once it should be given up
+    pub fn retry_delay(&self, attempts: u32) -> Option<Duration> {
+        self.write_retry
+            .should_retry(attempts)
+            .then(|| Duration::from_millis(self.write_retry.calculate_delay(attempts)))
+    }
+
+    /// Fsync anything appended since the last fsync
+    pub fn sync(&self) -> Result<(), PersistenceError> {
+        let mut guard = self.writer.lock().unwrap();
+        match guard.as_mut() {
+            Some(writer) if writer.unsynced => Self::sync_writer(writer),
+            _ => Ok(()),
+        }
+    }
+
+    fn sync_writer(writer: &mut WalWriter) -> Result<(), PersistenceError> {
+        writer.file.sync_data()?;
+        writer.unsynced = false;
+        writer.last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Write `entries` as the new snapshot and empty the WAL
+    ///
+    /// `entries` must include every change already appended. A crash between
+    /// the two steps leaves a WAL whose records are already in the snapshot,
+    /// which replays harmlessly.
+    pub fn checkpoint(&self, entries: &[DLQEntry]) -> Result<(), PersistenceError> {
+        let mut guard = self.writer.lock().unwrap();
+        let writer = guard.as_mut().ok_or_else(not_open)?;
+        self.snapshot.save(entries)?;
+        writer.file.set_len(0)?;
+        writer.len = 0;
+        Self::sync_writer(writer)
+    }
+}
+
+impl WalWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.file.write_all(data)?;
+        self.file.flush()?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+}
+
+fn not_open() -> PersistenceError {
+    io::Error::new(io::ErrorKind::NotConnected, "DLQ WAL is not open").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::SerializationFormat;
+    use std::fs;
+
+    fn entry(transaction_id: &str, attempt_count: u32) -> DLQEntry {
+        DLQEntry {
+            transaction_id: transaction_id.to_string(),
+            psp_name: "stripe".to_string(),
+            payload: vec![1, 2, 3],
+            attempt_count,
+            last_error: "Max retry attempts exceeded".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn put(entry: DLQEntry) -> WalRecord {
+        WalRecord::Put {
+            entry: Box::new(entry),
+        }
+    }
+
+    #[test]
+    fn test_recovers_wal_tail_past_checkpoint() {
+        let dir = std::env::temp_dir().join(format!("dlq-wal-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let open_wal = || {
+            DlqWal::new(
+                DlqStore::new(dir.join("dlq"), SerializationFormat::Bincode),
+                dir.join("dlq.wal"),
+                Duration::from_secs(1),
+            )
+        };
+
+        let wal = open_wal();
+        assert!(wal.open().unwrap().is_empty());
+        wal.append(&[put(entry("txn_1", 5)), put(entry("txn_2", 5))])
+            .unwrap();
+        wal.checkpoint(&[entry("txn_1", 5), entry("txn_2", 5)])
+            .unwrap();
+        assert_eq!(fs::metadata(wal.path()).unwrap().len(), 0);
+
+        // Changes after the checkpoint, then a crash partway through a write
+        wal.append(&[
+            put(entry("txn_3", 5)),
+            WalRecord::Removed {
+                transaction_id: "txn_1".to_string(),
+            },
+            put(entry("txn_2", 7)),
+        ])
+        .unwrap();
+        drop(wal);
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(dir.join("dlq.wal"))
+            .unwrap();
+        file.write_all(br#"{"Put":{"entry":{"transaction_id":"txn_4""#)
+            .unwrap();
+
+        let recovered = open_wal().open().unwrap();
+        let summary: Vec<(&str, u32)> = recovered
+            .iter()
+            .map(|entry| (entry.transaction_id.as_str(), entry.attempt_count))
+            .collect();
+        assert_eq!(summary, vec![("txn_2", 7), ("txn_3", 5)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A WAL file whose next `failures` writes fail partway through
+    struct FlakyFile {
+        file: File,
+        failures: usize,
+    }
+
+    impl Write for FlakyFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                self.file.write_all(&buf[..buf.len() / 2])?;
+                return Err(io::Error::other("disk hiccup"));
+            }
+            self.file.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl WalFile for FlakyFile {
+        fn sync_data(&self) -> io::Result<()> {
+            self.file.sync_data()
+        }
+
+        fn set_len(&self, size: u64) -> io::Result<()> {
+            self.file.set_len(size)
+        }
+
+        fn size(&self) -> io::Result<u64> {
+            self.file.size()
+        }
+    }
+
+    #[test]
+    fn test_append_retries_failed_writes() {
+        let dir = std::env::temp_dir().join(format!("dlq-wal-{}", uuid::Uuid::new_v4()));
+        fs::create_dir(&dir).unwrap();
+        let wal = DlqWal::new(
+            DlqStore::new(dir.join("dlq"), SerializationFormat::Bincode),
+            dir.join("dlq.wal"),
+            Duration::from_secs(1),
+        );
+        let flaky = |failures| {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(dir.join("dlq.wal"))
+                .unwrap();
+            Box::new(FlakyFile { file, failures })
+        };
+
+        // The first two writes fail; the third gets the entry on disk
+        wal.open_with(flaky(2)).unwrap();
+        wal.append(&[put(entry("txn_1", 5))]).unwrap();
+        assert_eq!(wal.recover().unwrap().len(), 1);
+
+        // Every write fails: the append errors without tearing the file
+        wal.open_with(flaky(3)).unwrap();
+        assert!(wal.append(&[put(entry("txn_2", 5))]).is_err());
+        let recovered = wal.recover().unwrap();
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].transaction_id, "txn_1");
+
+        // A later append lands after the last complete one
+        wal.open_with(flaky(0)).unwrap();
+        wal.append(&[put(entry("txn_2", 5))]).unwrap();
+        assert_eq!(wal.recover().unwrap().len(), 2);
+
+        // A single try leaves the retrying, and the waiting, to the caller
+        wal.open_with(flaky(1)).unwrap();
+        assert!(wal.try_append(&[put(entry("txn_3", 5))]).is_err());
+        assert_eq!(wal.recover().unwrap().len(), 2);
+        wal.try_append(&[put(entry("txn_3", 5))]).unwrap();
+        assert_eq!(wal.recover().unwrap().len(), 3);
+        assert_eq!(wal.retry_delay(1), Some(Duration::from_millis(10)));
+        assert_eq!(wal.retry_delay(2), Some(Duration::from_millis(20)));
+        assert_eq!(wal.retry_delay(3), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,10 @@
 use proptest::prelude::*;
-use retry_engine::{RetryConfig, CircuitBreakerConfig};
+use retry_engine::RetryConfig;
 use retry_engine::retry_policy::RetryPolicy;
 use retry_engine::dlq::{DeadLetterQueue, DLQEntry};
 use retry_engine::current_timestamp_ms;
 
-/**
+/*
  * Feature: payment-acquiring-gateway, Property 19: DLQ After Max Retries
  * 
  * For any transaction that fails after maximum retry attempts, the transaction 
@@ -27,6 +27,7 @@ proptest! {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -56,6 +57,7 @@ proptest! {
             attempt_count: max_attempts,
             last_error: "Max retries exceeded".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            ..Default::default()
         };
         
         dlq.add_entry(dlq_entry);
@@ -94,6 +96,7 @@ proptest! {
             attempt_count,
             last_error: last_error.clone(),
             timestamp_ms: timestamp,
+            ..Default::default()
         };
         
         dlq.add_entry(entry);
@@ -128,6 +131,7 @@ proptest! {
                 attempt_count: max_attempts,
                 last_error: format!("Error {}", i),
                 timestamp_ms: current_timestamp_ms() + i as u64,
+                ..Default::default()
             };
             
             dlq.add_entry(entry);
@@ -168,6 +172,7 @@ proptest! {
                 attempt_count: 5,
                 last_error: "Test error".to_string(),
                 timestamp_ms: current_timestamp_ms(),
+                ..Default::default()
             };
             
             dlq.add_entry(entry);
@@ -218,6 +223,7 @@ proptest! {
             attempt_count: attempt_count_1,
             last_error: "Error 1".to_string(),
             timestamp_ms: 1000,
+            ..Default::default()
         };
         dlq.add_entry(entry1);
         
@@ -231,6 +237,7 @@ proptest! {
             attempt_count: attempt_count_2,
             last_error: "Error 2".to_string(),
             timestamp_ms: 2000,
+            ..Default::default()
         };
         dlq.add_entry(entry2);
         
@@ -256,6 +263,7 @@ proptest! {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -275,6 +283,7 @@ proptest! {
                     attempt_count: attempt,
                     last_error: "Max retries exceeded".to_string(),
                     timestamp_ms: current_timestamp_ms(),
+                    ..Default::default()
                 };
                 dlq.add_entry(entry);
                 should_be_in_dlq = true;
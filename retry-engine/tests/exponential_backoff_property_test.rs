@@ -1,7 +1,7 @@
 use proptest::prelude::*;
-use retry_engine::{RetryConfig, retry_policy::RetryPolicy};
+use retry_engine::{JitterStrategy, RetryConfig, retry_policy::RetryPolicy};
 
-/**
+/*
  * Feature: payment-acquiring-gateway, Property 17: Exponential Backoff Timing
  * 
  * For any retry sequence, the delay between retry attempts should increase 
@@ -27,6 +27,7 @@ proptest! {
             max_delay_ms: max_delay,
             backoff_multiplier: multiplier,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -89,6 +90,7 @@ proptest! {
             max_delay_ms: max_delay,
             backoff_multiplier: multiplier,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -117,6 +119,7 @@ proptest! {
             max_delay_ms: max_delay,
             backoff_multiplier: multiplier,
             jitter: true,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -137,6 +140,7 @@ proptest! {
             let jitter_range = (base_delay as f64 * 0.2) as u64;
             let min_expected = base_delay.saturating_sub(jitter_range);
             let max_expected = (base_delay + jitter_range).min(max_delay);
+            prop_assert_eq!(policy.jitter_bounds(attempt), (min_expected, max_expected));
             
             prop_assert!(
                 delay >= min_expected && delay <= max_expected,
@@ -146,11 +150,49 @@ proptest! {
         }
     }
     
+    #[test]
+    fn equal_jitter_stays_between_half_and_full_base_delay(
+        initial_delay in 1u64..1000u64,
+        max_delay in 5000u64..20000u64,
+        multiplier in 1.5f64..2.5f64,
+        attempt in 1u32..10u32,
+    ) {
+        let config = RetryConfig {
+            max_attempts: 10,
+            initial_delay_ms: initial_delay,
+            max_delay_ms: max_delay,
+            backoff_multiplier: multiplier,
+            jitter: true,
+            jitter_strategy: JitterStrategy::EqualJitter,
+            ..Default::default()
+        };
+        
+        let policy = RetryPolicy::new(config);
+        
+        let exp_delay = initial_delay as f64 * multiplier.powi((attempt - 1) as i32);
+        let base_delay = exp_delay.min(max_delay as f64) as u64;
+        let (low, high) = policy.jitter_bounds(attempt);
+        prop_assert_eq!(high, base_delay);
+        prop_assert!(low * 2 >= base_delay && low * 2 <= base_delay + 1);
+        
+        // Property: Equal jitter never waits less than half the base delay or
+        // more than the base delay, across many draws
+        for _ in 0..50 {
+            let delay = policy.calculate_delay(attempt);
+            prop_assert!(
+                delay >= low && delay <= high,
+                "Equal jitter delay out of range: attempt={}, delay={}, base={}",
+                attempt, delay, base_delay
+            );
+        }
+    }
+    
     #[test]
     fn first_retry_uses_initial_delay(
         initial_delay in 100u64..10000u64,
         max_delay in 10000u64..100000u64,
         multiplier in 1.5f64..3.0f64,
+        immediate_retries in 0u32..4u32,
     ) {
         let config = RetryConfig {
             max_attempts: 10,
@@ -158,12 +200,18 @@ proptest! {
             max_delay_ms: max_delay,
             backoff_multiplier: multiplier,
             jitter: false,
+            immediate_retries,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
         
-        // Property: First retry (attempt 1) should always use initial_delay
-        let delay = policy.calculate_delay(1);
+        // Property: The immediate retries have no delay, and the first retry
+        // after them should always use initial_delay
+        for attempt in 1..=immediate_retries {
+            prop_assert_eq!(policy.calculate_delay(attempt), 0);
+        }
+        let delay = policy.calculate_delay(immediate_retries + 1);
         prop_assert_eq!(delay, initial_delay);
     }
     
@@ -180,6 +228,7 @@ proptest! {
             max_delay_ms: max_delay,
             backoff_multiplier: multiplier,
             jitter,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
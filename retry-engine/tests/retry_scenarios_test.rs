@@ -1,7 +1,8 @@
 use retry_engine::{RetryConfig, CircuitBreakerConfig, current_timestamp_ms};
-use retry_engine::retry_policy::RetryPolicy;
+use retry_engine::retry_policy::{JitterRng, RetryPolicy};
 use retry_engine::circuit_breaker::{CircuitBreaker, CircuitState};
 use retry_engine::dlq::{DeadLetterQueue, DLQEntry};
+use std::sync::{Arc, Mutex};
 
 #[cfg(test)]
 mod retry_exhaustion_tests {
@@ -15,6 +16,7 @@ mod retry_exhaustion_tests {
             max_delay_ms: 10000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -37,6 +39,7 @@ mod retry_exhaustion_tests {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -61,6 +64,7 @@ mod retry_exhaustion_tests {
             attempt_count: attempt,
             last_error: "Max retries exceeded".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            ..Default::default()
         };
         
         dlq.add_entry(entry);
@@ -79,6 +83,7 @@ mod retry_exhaustion_tests {
             max_delay_ms: 10000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -99,6 +104,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 3,
             success_threshold: 2,
             timeout_duration_ms: 5000,
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -124,6 +130,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 2,
             success_threshold: 2,
             timeout_duration_ms: 100, // Short timeout for testing
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -150,6 +157,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 2,
             success_threshold: 3,
             timeout_duration_ms: 0, // Immediate timeout
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -159,7 +167,6 @@ mod circuit_breaker_state_transition_tests {
         cb.record_failure();
         
         // Transition to half-open
-        std::thread::sleep(std::time::Duration::from_millis(10));
         cb.can_proceed();
         assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
         
@@ -181,6 +188,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 2,
             success_threshold: 3,
             timeout_duration_ms: 0,
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -190,7 +198,6 @@ mod circuit_breaker_state_transition_tests {
         cb.record_failure();
         
         // Transition to half-open
-        std::thread::sleep(std::time::Duration::from_millis(10));
         cb.can_proceed();
         assert_eq!(cb.get_state().state, CircuitState::HalfOpen);
         
@@ -209,6 +216,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 5,
             success_threshold: 2,
             timeout_duration_ms: 5000,
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -232,6 +240,7 @@ mod circuit_breaker_state_transition_tests {
             failure_threshold: 2,
             success_threshold: 2,
             timeout_duration_ms: 50,
+            ..Default::default()
         };
         
         let cb = CircuitBreaker::new(config);
@@ -258,36 +267,49 @@ mod circuit_breaker_state_transition_tests {
 mod jitter_calculation_tests {
     use super::*;
 
+    /// Replays fixed jitter values and add/subtract choices, recording the
+    /// range each jitter was drawn from
+    struct FixedRng {
+        jitters: Vec<u64>,
+        adds: Vec<bool>,
+        ranges: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl JitterRng for FixedRng {
+        fn gen_range_inclusive(&mut self, max: u64) -> u64 {
+            self.ranges.lock().unwrap().push(max);
+            self.jitters.remove(0)
+        }
+
+        fn gen_bool(&mut self) -> bool {
+            self.adds.remove(0)
+        }
+    }
+
     #[test]
-    fn test_jitter_adds_randomness() {
+    fn test_jitter_applies_the_drawn_offset() {
         let config = RetryConfig {
             max_attempts: 10,
             initial_delay_ms: 1000,
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: true,
+            ..Default::default()
         };
-        
-        let policy = RetryPolicy::new(config);
-        
-        // Calculate delay multiple times for the same attempt
-        let mut delays = Vec::new();
-        for _ in 0..10 {
-            delays.push(policy.calculate_delay(3));
-        }
-        
-        // With jitter, we should see some variation
-        // (though there's a small chance all values are the same)
-        let first = delays[0];
-        let has_variation = delays.iter().any(|&d| d != first);
-        
-        // At least check that delays are in a reasonable range
-        // For attempt 3: base = 1000 * 2^2 = 4000
-        // With ±20% jitter: range is [3200, 4800]
-        for delay in delays {
-            assert!(delay >= 3200 && delay <= 4800,
-                "Delay {} should be in range [3200, 4800]", delay);
-        }
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let rng = FixedRng {
+            jitters: vec![800, 800, 0, 350],
+            adds: vec![true, false, true, false],
+            ranges: ranges.clone(),
+        };
+
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
+        // For attempt 3: base = 1000 * 2^2 = 4000, drawn from ±20% = ±800
+        let delays: Vec<u64> = (0..4).map(|_| policy.calculate_delay(3)).collect();
+        assert_eq!(delays, vec![4800, 3200, 4000, 3650]);
+        assert_eq!(*ranges.lock().unwrap(), vec![800; 4]);
+        assert_eq!(policy.jitter_bounds(3), (3200, 4800));
     }
 
     #[test]
@@ -298,6 +320,7 @@ mod jitter_calculation_tests {
             max_delay_ms: 60000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -320,6 +343,7 @@ mod jitter_calculation_tests {
             max_delay_ms: 5000,
             backoff_multiplier: 2.0,
             jitter: true,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(config);
@@ -332,36 +356,33 @@ mod jitter_calculation_tests {
     }
 
     #[test]
-    fn test_jitter_range_is_reasonable() {
+    fn test_jitter_range_grows_with_the_delay() {
         let config = RetryConfig {
             max_attempts: 10,
             initial_delay_ms: 10000,
             max_delay_ms: 100000,
             backoff_multiplier: 2.0,
             jitter: true,
+            ..Default::default()
         };
-        
-        let policy = RetryPolicy::new(config);
-        
+        let ranges = Arc::new(Mutex::new(Vec::new()));
+        let rng = FixedRng {
+            jitters: vec![4000, 4000, 1234],
+            adds: vec![false, true, true],
+            ranges: ranges.clone(),
+        };
+
+        let policy = RetryPolicy::with_rng(config, Box::new(rng));
+
         // For attempt 2: base = 10000 * 2^1 = 20000
         // Jitter range is ±20% = ±4000
         // So range is [16000, 24000]
-        let mut min_seen = u64::MAX;
-        let mut max_seen = 0u64;
-        
-        for _ in 0..100 {
-            let delay = policy.calculate_delay(2);
-            min_seen = min_seen.min(delay);
-            max_seen = max_seen.max(delay);
-        }
-        
-        // Should see values across the range
-        assert!(min_seen >= 16000, "Min delay {} should be >= 16000", min_seen);
-        assert!(max_seen <= 24000, "Max delay {} should be <= 24000", max_seen);
-        
-        // With 100 samples, we should see some spread
-        assert!(max_seen - min_seen > 1000, 
-            "Should see variation in jitter: min={}, max={}", min_seen, max_seen);
+        assert_eq!(policy.jitter_bounds(2), (16000, 24000));
+        assert_eq!(policy.calculate_delay(2), 16000);
+        assert_eq!(policy.calculate_delay(2), 24000);
+        // Attempt 3: base 40000, ±8000
+        assert_eq!(policy.calculate_delay(3), 41234);
+        assert_eq!(*ranges.lock().unwrap(), vec![4000, 4000, 8000]);
     }
 }
 
@@ -377,12 +398,14 @@ mod integration_tests {
             max_delay_ms: 1000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let circuit_config = CircuitBreakerConfig {
             failure_threshold: 3,
             success_threshold: 2,
             timeout_duration_ms: 200,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(retry_config);
@@ -420,6 +443,7 @@ mod integration_tests {
             max_delay_ms: 1000,
             backoff_multiplier: 2.0,
             jitter: false,
+            ..Default::default()
         };
         
         let policy = RetryPolicy::new(retry_config);
@@ -449,6 +473,7 @@ mod integration_tests {
             attempt_count: attempt,
             last_error: "All retries failed".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            ..Default::default()
         };
         
         dlq.add_entry(entry);
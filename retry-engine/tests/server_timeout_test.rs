@@ -0,0 +1,65 @@
+use retry_engine::server::retry::retry_engine_client::RetryEngineClient;
+use retry_engine::server::retry::retry_engine_server::RetryEngineServer;
+use retry_engine::server::retry::CircuitRequest;
+use retry_engine::server::{server_builder, RetryEngineService};
+use retry_engine::{CircuitBreakerConfig, RetryConfig, ServerConfig};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tower::util::MapFutureLayer;
+
+/// Serve the engine with every request delayed by `delay`, returning its address
+async fn serve_with_delay(config: ServerConfig, delay: Duration) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let service = RetryEngineService::new(RetryConfig::default(), CircuitBreakerConfig::default());
+
+    tokio::spawn(async move {
+        server_builder(&config)
+            .layer(MapFutureLayer::new(move |response| async move {
+                tokio::time::sleep(delay).await;
+                response.await
+            }))
+            .add_service(RetryEngineServer::new(service))
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+            .unwrap();
+    });
+
+    format!("http://{}", addr)
+}
+
+fn circuit_request() -> CircuitRequest {
+    CircuitRequest {
+        psp_name: "stripe".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn test_slow_request_is_cancelled_at_timeout() {
+    let config = ServerConfig {
+        request_timeout_ms: 50,
+        ..Default::default()
+    };
+    let addr = serve_with_delay(config, Duration::from_millis(500)).await;
+    let mut client = RetryEngineClient::connect(addr).await.unwrap();
+
+    let status = client
+        .get_circuit_status(circuit_request())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Cancelled);
+}
+
+#[tokio::test]
+async fn test_request_within_timeout_succeeds() {
+    let config = ServerConfig {
+        request_timeout_ms: 2000,
+        ..Default::default()
+    };
+    let addr = serve_with_delay(config, Duration::from_millis(10)).await;
+    let mut client = RetryEngineClient::connect(addr).await.unwrap();
+
+    let response = client.get_circuit_status(circuit_request()).await.unwrap();
+    assert_eq!(response.into_inner().psp_name, "stripe");
+}